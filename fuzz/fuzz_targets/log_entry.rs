@@ -0,0 +1,9 @@
+#![no_main]
+
+use codecrafters_kafka::LogEntry;
+use libfuzzer_sys::fuzz_target;
+
+// Produce payloads and log segments, record batches and legacy messages.
+fuzz_target!(|data: &[u8]| {
+    let _ = LogEntry::split(data.to_vec());
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use codecrafters_kafka::Request;
+use libfuzzer_sys::fuzz_target;
+
+// The input is a request as it follows its size on the wire, so every
+// input reaches the header and body parsers.
+fuzz_target!(|data: &[u8]| {
+    let mut frame = (data.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(data);
+    let _ = Request::read(&mut frame.as_slice());
+});
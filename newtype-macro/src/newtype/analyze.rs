@@ -1,10 +1,4 @@
 use crate::parse::Ast;
-use syn::{
-    parenthesized,
-    parse::{Parse, ParseStream},
-    spanned::Spanned,
-    Expr, ItemStruct,
-};
 
 pub struct Model {
     pub item: Ast,
@@ -1,7 +1,7 @@
 use crate::lower::Ir;
 use proc_macro2::{Ident, TokenStream};
 use quote::quote;
-use syn::{Field, Fields, ItemStruct, Type, Visibility};
+use syn::{Field, ItemStruct, Type, Visibility};
 pub type Rust = TokenStream;
 
 pub fn codegen(ir: Ir) -> Rust {
@@ -10,13 +10,9 @@ pub fn codegen(ir: Ir) -> Rust {
         field,
     } = ir;
     let ItemStruct {
-        attrs,
         vis,
-        struct_token,
         ident,
-        generics,
-        fields,
-        semi_token,
+        ..
     } = item;
     let t = field.ty.clone();
 
@@ -91,16 +87,11 @@ fn is_string(ty: &Type) -> bool {
 mod tests {
     use super::*;
     use proc_macro_error::abort;
-    use syn::{parse_quote, Field};
+    use syn::{parse_quote, Field, Fields};
     #[test]
     fn output_is_newtype() {
         let item: ItemStruct = parse_quote!(
             pub struct MyNewtype(u32);
-            impl MyNewtype {
-                pub fn new(v:u32) -> Self {
-                    Self(v)
-                }
-            }
         );
         let field = match item.fields.clone() {
             Fields::Unnamed(f) if f.unnamed.len() == 1 =>
@@ -114,6 +105,6 @@ mod tests {
         };
         let rust = codegen(ir);
         println!("{:?}", rust);
-        assert!(syn::parse2::<ItemStruct>(rust).is_ok());
+        assert!(syn::parse2::<syn::File>(rust).is_ok());
     }
 }
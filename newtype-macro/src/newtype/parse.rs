@@ -1,9 +1,7 @@
 use proc_macro2::TokenStream;
 use proc_macro_error::abort;
 use syn::parse::{Parse, ParseStream};
-use syn::{
-    parse2, Error, Field, Fields, FieldsUnnamed, Item, ItemStruct, Result,
-};
+use syn::{parse2, Field, Fields, ItemStruct, Result};
 
 pub struct Ast {
     pub item: ItemStruct,
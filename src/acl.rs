@@ -0,0 +1,486 @@
+use std::sync::Arc;
+
+use crate::{
+    broker_config, BytesOps, Meta, MetaCache, Principal, RequestHeader, Result,
+    ToCompactString, ToKafkaString, Version,
+};
+use bytes::BufMut;
+use newtype_macro::newtype;
+use uuid::Uuid;
+
+#[newtype]
+pub struct AclResourceType(u8);
+
+impl AclResourceType {
+    pub const ANY: u8 = 1;
+    pub const TOPIC: u8 = 2;
+    pub const GROUP: u8 = 3;
+    pub const CLUSTER: u8 = 4;
+    pub const TRANSACTIONAL_ID: u8 = 5;
+    pub const DELEGATION_TOKEN: u8 = 6;
+    pub const USER: u8 = 7;
+}
+
+#[newtype]
+pub struct PatternType(u8);
+
+impl PatternType {
+    pub const ANY: u8 = 1;
+    pub const MATCH: u8 = 2;
+    pub const LITERAL: u8 = 3;
+    pub const PREFIXED: u8 = 4;
+}
+
+#[newtype]
+pub struct AclOperation(u8);
+
+impl AclOperation {
+    pub const ANY: u8 = 1;
+    pub const ALL: u8 = 2;
+    pub const READ: u8 = 3;
+    pub const WRITE: u8 = 4;
+    pub const CREATE: u8 = 5;
+    pub const DELETE: u8 = 6;
+    pub const ALTER: u8 = 7;
+    pub const DESCRIBE: u8 = 8;
+    pub const CLUSTER_ACTION: u8 = 9;
+    pub const DESCRIBE_CONFIGS: u8 = 10;
+    pub const ALTER_CONFIGS: u8 = 11;
+    pub const IDEMPOTENT_WRITE: u8 = 12;
+
+    /// What a topic can be authorized for, TopicAuthorizedOperations.
+    pub const TOPIC: &[u8] = &[
+        Self::READ,
+        Self::WRITE,
+        Self::CREATE,
+        Self::DELETE,
+        Self::ALTER,
+        Self::DESCRIBE,
+        Self::DESCRIBE_CONFIGS,
+        Self::ALTER_CONFIGS,
+    ];
+
+    /// Allowing `self` allows `operation` too.
+    fn implies(&self, operation: u8) -> bool {
+        match (self.0, operation) {
+            (a, b) if a == b => true,
+            (Self::ALL, _) => true,
+            (
+                Self::READ | Self::WRITE | Self::DELETE | Self::ALTER,
+                Self::DESCRIBE,
+            ) => true,
+            (Self::ALTER_CONFIGS, Self::DESCRIBE_CONFIGS) => true,
+            _ => false,
+        }
+    }
+}
+
+#[newtype]
+pub struct AclPermissionType(u8);
+
+impl AclPermissionType {
+    pub const ANY: u8 = 1;
+    pub const DENY: u8 = 2;
+    pub const ALLOW: u8 = 3;
+}
+
+/// The name of the one cluster resource.
+pub const CLUSTER_NAME: &str = "kafka-cluster";
+pub const WILDCARD: &str = "*";
+
+// CreateAcls, DescribeAcls and DeleteAcls are all flexible from v2
+const FLEXIBLE: Version = Version::V2;
+
+/// Which principal from which host may or may not do an operation on the
+/// resources matching a pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AclBinding {
+    pub resource_type: AclResourceType,
+    pub resource_name: String,
+    pub pattern_type: PatternType,
+    pub principal: String,
+    pub host: String,
+    pub operation: AclOperation,
+    pub permission_type: AclPermissionType,
+}
+
+impl AclBinding {
+    /// A CreateAcls creation, v0 only has literal patterns.
+    pub fn extract(value: &[u8], version: Version) -> Result<(Self, &[u8])> {
+        let flexible = version >= FLEXIBLE;
+        let (resource_type, rest) =
+            value.extract_u8_into(AclResourceType::new)?;
+        let (resource_name, rest) = rest.extract_flexible_string(flexible)?;
+        let (pattern_type, rest) = match version {
+            Version::V0 => (PatternType::new(PatternType::LITERAL), rest),
+            _ => rest.extract_u8_into(PatternType::new)?,
+        };
+        let (principal, rest) = rest.extract_flexible_string(flexible)?;
+        let (host, rest) = rest.extract_flexible_string(flexible)?;
+        let (operation, rest) = rest.extract_u8_into(AclOperation::new)?;
+        let (permission_type, rest) =
+            rest.extract_u8_into(AclPermissionType::new)?;
+        let rest = rest.drop_tag_buffer(flexible)?;
+        Ok((
+            Self {
+                resource_type,
+                resource_name,
+                pattern_type,
+                principal,
+                host,
+                operation,
+                permission_type,
+            },
+            rest,
+        ))
+    }
+    /// Filter values such as ANY cannot be stored.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        let invalid = |what: &str, v: u8| Err(format!("Invalid {what} {v}"));
+        match *self.resource_type {
+            AclResourceType::TOPIC..=AclResourceType::USER => {}
+            v => return invalid("resource type", v),
+        }
+        match *self.pattern_type {
+            PatternType::LITERAL | PatternType::PREFIXED => {}
+            v => return invalid("pattern type", v),
+        }
+        match *self.operation {
+            AclOperation::ALL..=AclOperation::IDEMPOTENT_WRITE => {}
+            v => return invalid("operation", v),
+        }
+        match *self.permission_type {
+            AclPermissionType::DENY | AclPermissionType::ALLOW => {}
+            v => return invalid("permission type", v),
+        }
+        match self.principal.split_once(':') {
+            Some((_, name)) if !name.is_empty() => Ok(()),
+            _ => Err(format!("Invalid principal {}", self.principal)),
+        }
+    }
+    fn matches_resource(&self, resource_type: u8, resource_name: &str) -> bool {
+        *self.resource_type == resource_type
+            && match *self.pattern_type {
+                PatternType::LITERAL =>
+                    self.resource_name == resource_name
+                        || self.resource_name == WILDCARD,
+                PatternType::PREFIXED =>
+                    resource_name.starts_with(&self.resource_name),
+                _ => false,
+            }
+    }
+    /// Resource type, name and pattern, as in DescribeAcls and DeleteAcls.
+    pub fn encode_resource(&self, version: Version) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.put_u8(*self.resource_type);
+        bytes.extend(match version >= FLEXIBLE {
+            true => self.resource_name.to_compact_string(),
+            false => self.resource_name.to_kafka_string(),
+        });
+        if version >= Version::V1 {
+            bytes.put_u8(*self.pattern_type);
+        }
+        bytes
+    }
+    /// Principal, host, operation and permission.
+    pub fn encode_entry(&self, version: Version) -> Vec<u8> {
+        let flexible = version >= FLEXIBLE;
+        let mut bytes = vec![];
+        [&self.principal, &self.host].into_iter().for_each(|v| {
+            bytes.extend(match flexible {
+                true => v.to_compact_string(),
+                false => v.to_kafka_string(),
+            })
+        });
+        bytes.put_u8(*self.operation);
+        bytes.put_u8(*self.permission_type);
+        bytes
+    }
+}
+
+/// Selects bindings for DescribeAcls and DeleteAcls, ANY and null match
+/// everything, MATCH selects the patterns that apply to a resource name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AclBindingFilter {
+    pub resource_type: AclResourceType,
+    pub resource_name: Option<String>,
+    pub pattern_type: PatternType,
+    pub principal: Option<String>,
+    pub host: Option<String>,
+    pub operation: AclOperation,
+    pub permission_type: AclPermissionType,
+}
+
+impl AclBindingFilter {
+    /// Without the tagged fields, DescribeAcls has the filter at the top
+    /// level of the request.
+    pub fn extract(value: &[u8], version: Version) -> Result<(Self, &[u8])> {
+        let flexible = version >= FLEXIBLE;
+        let (resource_type, rest) =
+            value.extract_u8_into(AclResourceType::new)?;
+        let (resource_name, rest) = rest.extract_nullable_string(flexible)?;
+        let (pattern_type, rest) = match version {
+            Version::V0 => (PatternType::new(PatternType::LITERAL), rest),
+            _ => rest.extract_u8_into(PatternType::new)?,
+        };
+        let (principal, rest) = rest.extract_nullable_string(flexible)?;
+        let (host, rest) = rest.extract_nullable_string(flexible)?;
+        let (operation, rest) = rest.extract_u8_into(AclOperation::new)?;
+        let (permission_type, rest) =
+            rest.extract_u8_into(AclPermissionType::new)?;
+        Ok((
+            Self {
+                resource_type,
+                resource_name,
+                pattern_type,
+                principal,
+                host,
+                operation,
+                permission_type,
+            },
+            rest,
+        ))
+    }
+    pub fn matches(&self, binding: &AclBinding) -> bool {
+        let any_or = |filter: &Option<String>, v: &String| {
+            filter.as_ref().map_or(true, |filter| filter == v)
+        };
+        let pattern = match (*self.pattern_type, &self.resource_name) {
+            (PatternType::MATCH, Some(name)) =>
+                binding.matches_resource(*binding.resource_type, name),
+            (PatternType::ANY | PatternType::MATCH, name) =>
+                any_or(name, &binding.resource_name),
+            (v, name) =>
+                v == *binding.pattern_type
+                    && any_or(name, &binding.resource_name),
+        };
+        (*self.resource_type == AclResourceType::ANY
+            || self.resource_type == binding.resource_type)
+            && pattern
+            && any_or(&self.principal, &binding.principal)
+            && any_or(&self.host, &binding.host)
+            && (*self.operation == AclOperation::ANY
+                || self.operation == binding.operation)
+            && (*self.permission_type == AclPermissionType::ANY
+                || self.permission_type == binding.permission_type)
+    }
+}
+
+/// On when `authorizer.class.name` is set, as with Kafka.
+pub fn authorizer_enabled() -> bool {
+    broker_config().get("authorizer.class.name").is_some_and(|v| !v.is_empty())
+}
+
+/// Decides from the ACLs of the metadata log. A DENY wins over any ALLOW,
+/// resources without ACLs are only open with
+/// `allow.everyone.if.no.acl.found=true` and `super.users` may do anything.
+#[derive(Debug, Clone, Default)]
+pub struct Authorizer {
+    enabled: bool,
+    acls: Arc<Vec<(Uuid, AclBinding)>>,
+}
+
+static ACLS: MetaCache<Vec<(Uuid, AclBinding)>> = MetaCache::new(Meta::acls);
+
+impl Authorizer {
+    /// The ACL bindings are read from the metadata log once it has grown
+    /// since the last load.
+    pub fn load(path: &str) -> Self {
+        match authorizer_enabled() {
+            false => Self::default(),
+            true => Self::from_log(path),
+        }
+    }
+    /// Enabled whatever `authorizer.class.name`, with the bindings of the
+    /// metadata log at `path`.
+    pub fn from_log(path: &str) -> Self {
+        Self {
+            enabled: true,
+            acls: ACLS.get(path),
+        }
+    }
+    /// After CreateAcls or DeleteAcls, the next load reads the bindings.
+    pub fn invalidate() {
+        ACLS.invalidate();
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn acls(&self) -> &Vec<(Uuid, AclBinding)> {
+        &self.acls
+    }
+    pub fn authorize(
+        &self,
+        header: &RequestHeader,
+        operation: u8,
+        resource_type: u8,
+        resource_name: &str,
+    ) -> bool {
+        if !self.enabled {
+            return true;
+        }
+        let anonymous = Principal::anonymous();
+        let principal = header.principal().unwrap_or(&anonymous);
+        let super_users = broker_config()
+            .get("super.users")
+            .map(String::as_str)
+            .unwrap_or_default();
+        if super_users.split(';').any(|v| v.trim() == **principal) {
+            return true;
+        }
+        let host = header.client_host().map(String::as_str);
+        let acls: Vec<&AclBinding> = self
+            .acls
+            .iter()
+            .map(|(_, acl)| acl)
+            .filter(|acl| acl.matches_resource(resource_type, resource_name))
+            .collect();
+        if acls.is_empty() {
+            return broker_config()
+                .get("allow.everyone.if.no.acl.found")
+                .is_some_and(|v| v == "true");
+        }
+        let applies = |acl: &&&AclBinding| {
+            (acl.principal == **principal || acl.principal == "User:*")
+                && (acl.host == WILDCARD || Some(acl.host.as_str()) == host)
+        };
+        let permitted = |permission: u8| {
+            acls.iter()
+                .filter(applies)
+                .filter(move |acl| *acl.permission_type == permission)
+        };
+        let denied = permitted(AclPermissionType::DENY).any(|acl| {
+            *acl.operation == operation || *acl.operation == AclOperation::ALL
+        });
+        !denied
+            && permitted(AclPermissionType::ALLOW)
+                .any(|acl| acl.operation.implies(operation))
+    }
+    /// Bit field of the topic operations allowed, bit n for operation n.
+    pub fn topic_authorized_operations(
+        &self,
+        header: &RequestHeader,
+        topic_name: &str,
+    ) -> u32 {
+        AclOperation::TOPIC
+            .iter()
+            .filter(|op| {
+                self.authorize(header, **op, AclResourceType::TOPIC, topic_name)
+            })
+            .fold(0, |acc, op| acc | 1 << op)
+    }
+}
+
+/// Lets every user do anything on the cluster, written to the metadata log
+/// at `path`, for handler tests.
+#[cfg(test)]
+pub(crate) fn allow_cluster(path: &str) -> Result<()> {
+    let binding = AclBinding {
+        resource_type: AclResourceType::new(AclResourceType::CLUSTER),
+        resource_name: CLUSTER_NAME.to_string(),
+        pattern_type: PatternType::new(PatternType::LITERAL),
+        principal: "User:*".to_string(),
+        host: WILDCARD.to_string(),
+        operation: AclOperation::new(AclOperation::ALL),
+        permission_type: AclPermissionType::new(AclPermissionType::ALLOW),
+    };
+    let value = crate::AccessControlEntryRecordValue(
+        crate::FrameVersion::new(1),
+        crate::ValueVersion::new(0),
+        Uuid::new_v4(),
+        binding,
+    );
+    Meta::append(
+        path,
+        vec![crate::RecordValue::AccessControlEntryRecord(value)],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Request;
+
+    fn binding(
+        name: &str,
+        pattern: u8,
+        operation: u8,
+        permission: u8,
+    ) -> AclBinding {
+        AclBinding {
+            resource_type: AclResourceType::new(AclResourceType::TOPIC),
+            resource_name: name.to_string(),
+            pattern_type: PatternType::new(pattern),
+            principal: "User:alice".to_string(),
+            host: WILDCARD.to_string(),
+            operation: AclOperation::new(operation),
+            permission_type: AclPermissionType::new(permission),
+        }
+    }
+
+    #[test]
+    fn test_authorize() -> Result<()> {
+        // ApiVersions v0 with client id "t"
+        let bytes = [0, 0, 0, 11, 0, 18, 0, 0, 0, 0, 0, 7, 0, 1, b't'];
+        let request = Request::read(&mut &bytes[..])?
+            .with_principal(Principal::user("alice"))
+            .with_client_host("10.0.0.1".to_string());
+        let authorizer = Authorizer {
+            enabled: true,
+            acls: Arc::new(
+                vec![
+                    binding(
+                        "orders-",
+                        PatternType::PREFIXED,
+                        AclOperation::READ,
+                        AclPermissionType::ALLOW,
+                    ),
+                    binding(
+                        "orders-secret",
+                        PatternType::LITERAL,
+                        AclOperation::ALL,
+                        AclPermissionType::DENY,
+                    ),
+                ]
+                .into_iter()
+                .map(|acl| (Uuid::new_v4(), acl))
+                .collect(),
+            ),
+        };
+        let allowed = |operation, name| {
+            authorizer.authorize(
+                &request.header,
+                operation,
+                AclResourceType::TOPIC,
+                name,
+            )
+        };
+        assert!(allowed(AclOperation::READ, "orders-eu"));
+        assert!(allowed(AclOperation::DESCRIBE, "orders-eu"));
+        assert!(!allowed(AclOperation::WRITE, "orders-eu"));
+        assert!(!allowed(AclOperation::READ, "orders-secret"));
+        assert!(!allowed(AclOperation::READ, "payments"));
+        assert_eq!(
+            authorizer
+                .topic_authorized_operations(&request.header, "orders-eu"),
+            1 << AclOperation::READ | 1 << AclOperation::DESCRIBE
+        );
+
+        let filter = AclBindingFilter {
+            resource_type: AclResourceType::new(AclResourceType::ANY),
+            resource_name: Some("orders-eu".to_string()),
+            pattern_type: PatternType::new(PatternType::MATCH),
+            principal: None,
+            host: None,
+            operation: AclOperation::new(AclOperation::ANY),
+            permission_type: AclPermissionType::new(AclPermissionType::ANY),
+        };
+        let matched: Vec<_> = authorizer
+            .acls()
+            .iter()
+            .filter(|(_, acl)| filter.matches(acl))
+            .collect();
+        assert_eq!(matched.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,651 @@
+use crate::{
+    array_length, response_header, with_message_size, AclOperation,
+    AclResourceType, ApiKey, BytesOps, ClientQuotaRecordValue, CorrelationId,
+    Error, ErrorCode, FrameVersion, HandlerContext, MapTupleTwo, Meta,
+    QuotaManager, RecordValue, Request, RequestBody, Response, ResponseBody,
+    Result, TagBuffer, ThrottleTime, ToCompactString, ToKafkaString,
+    ToNullableString, ValueVersion, Version, CLUSTER_NAME,
+};
+use bytes::BufMut;
+
+pub const USER: &str = "user";
+pub const CLIENT_ID: &str = "client-id";
+
+pub const PRODUCER_BYTE_RATE: &str = "producer_byte_rate";
+pub const CONSUMER_BYTE_RATE: &str = "consumer_byte_rate";
+/// Share of one handler thread's time, in percent.
+pub const REQUEST_PERCENTAGE: &str = "request_percentage";
+pub const QUOTA_KEYS: &[&str] =
+    &[PRODUCER_BYTE_RATE, CONSUMER_BYTE_RATE, REQUEST_PERCENTAGE];
+
+// DescribeClientQuotas and AlterClientQuotas are both flexible from v1
+const FLEXIBLE: Version = Version::V1;
+
+/// Who a quota applies to, entity types in order with `None` naming the
+/// default entity, e.g. user alice with the default client id.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ClientQuotaEntity(Vec<(String, Option<String>)>);
+
+impl ClientQuotaEntity {
+    pub fn new(mut entries: Vec<(String, Option<String>)>) -> Self {
+        entries.sort();
+        Self(entries)
+    }
+    pub fn entries(&self) -> &[(String, Option<String>)] {
+        &self.0
+    }
+    pub fn name(&self, entity_type: &str) -> Option<&Option<String>> {
+        self.0.iter().find(|(t, _)| t == entity_type).map(|(_, name)| name)
+    }
+    /// Only users and client ids, each at most once.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.0.is_empty() {
+            return Err("Invalid empty client quota entity".to_string());
+        }
+        if let Some((t, _)) =
+            self.0.iter().find(|(t, _)| t != USER && t != CLIENT_ID)
+        {
+            return Err(format!("Unhandled client quota entity type {t}"));
+        }
+        match self.0.windows(2).any(|w| w[0].0 == w[1].0) {
+            true => Err("Duplicate client quota entity type".to_string()),
+            false => Ok(()),
+        }
+    }
+    pub fn extract(value: &[u8], version: Version) -> Result<(Self, &[u8])> {
+        let flexible = version >= FLEXIBLE;
+        value
+            .extract_array_with(flexible, |v| {
+                let (entity_type, rest) =
+                    v.extract_flexible_string(flexible)?;
+                let (name, rest) = rest.extract_nullable_string(flexible)?;
+                Ok(((entity_type, name), rest.drop_tag_buffer(flexible)?))
+            })
+            .map_tuple(Self::new)
+    }
+    pub fn encode(&self, version: Version) -> Vec<u8> {
+        let flexible = version >= FLEXIBLE;
+        let mut bytes = vec![];
+        bytes.extend(array_length(flexible, self.0.len()));
+        self.0.iter().for_each(|(entity_type, name)| {
+            bytes.extend(match flexible {
+                true => entity_type.to_compact_string(),
+                false => entity_type.to_kafka_string(),
+            });
+            bytes.extend(name.to_nullable_string(flexible));
+            if flexible {
+                bytes.put_u8(*TagBuffer::zero());
+            }
+        });
+        bytes
+    }
+}
+
+/// How a DescribeClientQuotas component matches an entity name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientQuotaFilterComponent {
+    pub entity_type: String,
+    pub match_type: u8,
+    pub name: Option<String>,
+}
+
+impl ClientQuotaFilterComponent {
+    pub const MATCH_EXACT: u8 = 0;
+    pub const MATCH_DEFAULT: u8 = 1;
+    pub const MATCH_ANY: u8 = 2;
+
+    fn extract(value: &[u8], version: Version) -> Result<(Self, &[u8])> {
+        let flexible = version >= FLEXIBLE;
+        let (entity_type, rest) = value.extract_flexible_string(flexible)?;
+        let (match_type, rest) = rest.extract_u8()?;
+        let (name, rest) = rest.extract_nullable_string(flexible)?;
+        Ok((
+            Self {
+                entity_type,
+                match_type,
+                name,
+            },
+            rest.drop_tag_buffer(flexible)?,
+        ))
+    }
+    fn matches(&self, entity: &ClientQuotaEntity) -> bool {
+        match (self.match_type, entity.name(&self.entity_type)) {
+            (_, None) => false,
+            (Self::MATCH_EXACT, Some(name)) => *name == self.name,
+            (Self::MATCH_DEFAULT, Some(name)) => name.is_none(),
+            (_, Some(_)) => true,
+        }
+    }
+}
+
+/// The DescribeClientQuotas request, with `strict` entities may not have
+/// entity types that are not in a component.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientQuotaFilter {
+    pub components: Vec<ClientQuotaFilterComponent>,
+    pub strict: bool,
+}
+
+impl ClientQuotaFilter {
+    pub fn extract(value: &[u8], version: Version) -> Result<(Self, &[u8])> {
+        let (components, rest) = value
+            .extract_array_with(version >= FLEXIBLE, |v| {
+                ClientQuotaFilterComponent::extract(v, version)
+            })?;
+        let (strict, rest) = rest.extract_u8().map_tuple(|v| v != 0)?;
+        Ok((
+            Self {
+                components,
+                strict,
+            },
+            rest,
+        ))
+    }
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        let mut types: Vec<&String> =
+            self.components.iter().map(|c| &c.entity_type).collect();
+        types.sort();
+        if types.windows(2).any(|w| w[0] == w[1]) {
+            return Err("Duplicate filter component entity type".to_string());
+        }
+        match self.components.iter().find(|c| {
+            c.match_type > ClientQuotaFilterComponent::MATCH_ANY
+                || (c.match_type == ClientQuotaFilterComponent::MATCH_EXACT
+                    && c.name.is_none())
+        }) {
+            Some(c) => Err(format!(
+                "Invalid match type {} for entity type {}",
+                c.match_type, c.entity_type
+            )),
+            None => Ok(()),
+        }
+    }
+    pub fn matches(&self, entity: &ClientQuotaEntity) -> bool {
+        self.components.iter().all(|c| c.matches(entity))
+            && (!self.strict
+                || entity.entries().iter().all(|(t, _)| {
+                    self.components.iter().any(|c| c.entity_type == *t)
+                }))
+    }
+}
+
+/// Sets or removes one quota of an entity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientQuotaOp {
+    pub key: String,
+    pub value: f64,
+    pub remove: bool,
+}
+
+impl ClientQuotaOp {
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if !QUOTA_KEYS.contains(&self.key.as_str()) {
+            return Err(format!("Invalid configuration key {}", self.key));
+        }
+        match self.remove || self.value > 0.0 {
+            true => Ok(()),
+            false => Err(format!(
+                "Illegal value {} for quota {}",
+                self.value, self.key
+            )),
+        }
+    }
+}
+
+/// One entry of an AlterClientQuotas request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientQuotaAlteration {
+    pub entity: ClientQuotaEntity,
+    pub ops: Vec<ClientQuotaOp>,
+}
+
+impl ClientQuotaAlteration {
+    pub fn extract(value: &[u8], version: Version) -> Result<(Self, &[u8])> {
+        let flexible = version >= FLEXIBLE;
+        let (entity, rest) = ClientQuotaEntity::extract(value, version)?;
+        let (ops, rest) = rest.extract_array_with(flexible, |v| {
+            let (key, rest) = v.extract_flexible_string(flexible)?;
+            let (value, rest) = rest.extract_u64().map_tuple(f64::from_bits)?;
+            let (remove, rest) = rest.extract_u8().map_tuple(|v| v != 0)?;
+            Ok((
+                ClientQuotaOp {
+                    key,
+                    value,
+                    remove,
+                },
+                rest.drop_tag_buffer(flexible)?,
+            ))
+        })?;
+        Ok((
+            Self {
+                entity,
+                ops,
+            },
+            rest.drop_tag_buffer(flexible)?,
+        ))
+    }
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        self.entity.validate()?;
+        self.ops.iter().try_for_each(ClientQuotaOp::validate)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientQuotaEntry {
+    pub entity: ClientQuotaEntity,
+    pub values: Vec<(String, f64)>,
+}
+
+impl ClientQuotaEntry {
+    pub fn encode(&self, version: Version) -> Vec<u8> {
+        let flexible = version >= FLEXIBLE;
+        let mut bytes = self.entity.encode(version);
+        bytes.extend(array_length(flexible, self.values.len()));
+        self.values.iter().for_each(|(key, value)| {
+            bytes.extend(match flexible {
+                true => key.to_compact_string(),
+                false => key.to_kafka_string(),
+            });
+            bytes.put_f64(*value);
+            if flexible {
+                bytes.put_u8(*TagBuffer::zero());
+            }
+        });
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AlterClientQuotasEntryResult {
+    pub error_code: ErrorCode,
+    pub error_message: Option<String>,
+    pub entity: ClientQuotaEntity,
+}
+
+impl AlterClientQuotasEntryResult {
+    pub fn new(entity: ClientQuotaEntity) -> Self {
+        Self {
+            error_code: ErrorCode::NoError,
+            error_message: None,
+            entity,
+        }
+    }
+    pub fn error(
+        entity: ClientQuotaEntity,
+        error_code: ErrorCode,
+        message: String,
+    ) -> Self {
+        Self {
+            error_code,
+            error_message: Some(message),
+            entity,
+        }
+    }
+    pub fn encode(&self, version: Version) -> Vec<u8> {
+        let flexible = version >= FLEXIBLE;
+        let mut bytes = vec![];
+        bytes.put_i16(*self.error_code);
+        bytes.extend(self.error_message.to_nullable_string(flexible));
+        bytes.extend(self.entity.encode(version));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+impl RequestBody {
+    pub(crate) fn describe_client_quotas(
+        body: &[u8],
+        version: Version,
+    ) -> Result<Self> {
+        let (filter, _rest) = ClientQuotaFilter::extract(body, version)?;
+        Ok(RequestBody::DescribeClientQuotas {
+            filter,
+        })
+    }
+    pub(crate) fn alter_client_quotas(
+        body: &[u8],
+        version: Version,
+    ) -> Result<Self> {
+        let flexible = ApiKey::AlterClientQuotas.is_flexible(version);
+        let (entries, rest) = body.extract_array_with(flexible, |v| {
+            ClientQuotaAlteration::extract(v, version)
+        })?;
+        let (validate_only, _rest) = rest.extract_u8().map_tuple(|v| v != 0)?;
+        Ok(RequestBody::AlterClientQuotas {
+            entries,
+            validate_only,
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_describe_client_quotas(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let allowed = cx.allowed(request);
+        let RequestBody::DescribeClientQuotas {
+            filter,
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        Ok(
+            match Self::client_quotas_authorization(
+                &allowed,
+                AclOperation::DESCRIBE_CONFIGS,
+            )
+            .or(filter
+                .validate()
+                .err()
+                .map(|message| (ErrorCode::InvalidRequest, message)))
+            {
+                Some((error_code, message)) =>
+                    ResponseBody::DescribeClientQuotas {
+                        error_code,
+                        error_message: Some(message),
+                        entries: None,
+                        throttle_time: ThrottleTime::zero(),
+                    },
+                None => ResponseBody::DescribeClientQuotas {
+                    error_code: ErrorCode::NoError,
+                    error_message: None,
+                    entries: Some(
+                        QuotaManager::quotas(cx.metadata_log)
+                            .iter()
+                            .filter(|(entity, _)| filter.matches(entity))
+                            .map(|(entity, values)| ClientQuotaEntry {
+                                entity: entity.clone(),
+                                values: values.clone().into_iter().collect(),
+                            })
+                            .collect(),
+                    ),
+                    throttle_time: ThrottleTime::zero(),
+                },
+            },
+        )
+    }
+
+    pub(crate) fn handle_alter_client_quotas(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let allowed = cx.allowed(request);
+        let RequestBody::AlterClientQuotas {
+            entries,
+            validate_only,
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        Ok(ResponseBody::AlterClientQuotas {
+            entries: match Self::client_quotas_authorization(
+                &allowed,
+                AclOperation::ALTER_CONFIGS,
+            ) {
+                Some((code, message)) => entries
+                    .iter()
+                    .map(|e| {
+                        AlterClientQuotasEntryResult::error(
+                            e.entity.clone(),
+                            code,
+                            message.clone(),
+                        )
+                    })
+                    .collect(),
+                None => Self::alter_client_quotas(
+                    cx.metadata_log,
+                    entries,
+                    *validate_only,
+                ),
+            },
+            throttle_time: ThrottleTime::zero(),
+        })
+    }
+
+    pub(crate) fn error_describe_client_quotas(
+        _body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        ResponseBody::DescribeClientQuotas {
+            error_code: error.error_code(),
+            error_message: Some(error.to_string()),
+            entries: None,
+            throttle_time: ThrottleTime::zero(),
+        }
+    }
+
+    pub(crate) fn error_alter_client_quotas(
+        body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        let entries = match body {
+            Some(RequestBody::AlterClientQuotas {
+                entries,
+                ..
+            }) => entries
+                .iter()
+                .map(|e| {
+                    AlterClientQuotasEntryResult::error(
+                        e.entity.clone(),
+                        error.error_code(),
+                        error.to_string(),
+                    )
+                })
+                .collect(),
+            _ => vec![],
+        };
+        ResponseBody::AlterClientQuotas {
+            entries,
+            throttle_time: ThrottleTime::zero(),
+        }
+    }
+
+    pub(crate) fn encode_describe_client_quotas(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::DescribeClientQuotas {
+            error_code,
+            error_message,
+            entries,
+            throttle_time,
+        } = body
+        else {
+            unreachable!("not a DescribeClientQuotas");
+        };
+        let (mut bytes, flexible) = response_header(
+            ApiKey::DescribeClientQuotas,
+            version,
+            correlation_id,
+        );
+        bytes.put_u32(*throttle_time);
+        bytes.put_i16(*error_code);
+        bytes.extend(error_message.to_nullable_string(flexible));
+        match entries {
+            Some(entries) => {
+                bytes.extend(array_length(flexible, entries.len()));
+                entries
+                    .into_iter()
+                    .for_each(|e| bytes.extend(e.encode(version)));
+            }
+            None if flexible => bytes.put_u8(0),
+            None => bytes.put_i32(-1),
+        }
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+
+    pub(crate) fn encode_alter_client_quotas(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::AlterClientQuotas {
+            entries,
+            throttle_time,
+        } = body
+        else {
+            unreachable!("not a AlterClientQuotas");
+        };
+        let (mut bytes, flexible) =
+            response_header(ApiKey::AlterClientQuotas, version, correlation_id);
+        bytes.put_u32(*throttle_time);
+        bytes.extend(array_length(flexible, entries.len()));
+        entries.into_iter().for_each(|e| bytes.extend(e.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+
+    /// Quotas are broker configs, authorized on the cluster.
+    fn client_quotas_authorization(
+        allowed: &impl Fn(u8, u8, &str) -> bool,
+        operation: u8,
+    ) -> Option<(ErrorCode, String)> {
+        match allowed(operation, AclResourceType::CLUSTER, CLUSTER_NAME) {
+            true => None,
+            false => Some((
+                ErrorCode::ClusterAuthorizationFailed,
+                "Not authorized on the cluster".to_string(),
+            )),
+        }
+    }
+
+    /// Valid entries are written as ClientQuotaRecords in one batch.
+    fn alter_client_quotas(
+        metadata_log: &str,
+        entries: &[ClientQuotaAlteration],
+        validate_only: bool,
+    ) -> Vec<AlterClientQuotasEntryResult> {
+        let results: Vec<_> =
+            entries.iter().map(ClientQuotaAlteration::validate).collect();
+        let values = entries
+            .iter()
+            .zip(&results)
+            .filter(|(_, result)| result.is_ok() && !validate_only)
+            .flat_map(|(entry, _)| {
+                entry.ops.iter().map(|op| {
+                    RecordValue::ClientQuotaRecord(ClientQuotaRecordValue(
+                        FrameVersion::new(1),
+                        ValueVersion::new(0),
+                        entry.entity.clone(),
+                        op.key.clone(),
+                        op.value,
+                        op.remove,
+                    ))
+                })
+            })
+            .collect::<Vec<_>>();
+        let written = match values.is_empty() {
+            true => Ok(()),
+            false => Meta::append(metadata_log, values)
+                .inspect(|_| QuotaManager::invalidate()),
+        };
+        entries
+            .iter()
+            .zip(results)
+            .map(|(entry, result)| {
+                let entity = entry.entity.clone();
+                match (result, &written) {
+                    (Err(message), _) => AlterClientQuotasEntryResult::error(
+                        entity,
+                        ErrorCode::InvalidRequest,
+                        message,
+                    ),
+                    (Ok(()), Err(e)) => AlterClientQuotasEntryResult::error(
+                        entity,
+                        ErrorCode::UnknownServerError,
+                        e.to_string(),
+                    ),
+                    (Ok(()), Ok(())) =>
+                        AlterClientQuotasEntryResult::new(entity),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Authorizer, TestLogs};
+
+    #[test]
+    fn test_handle_client_quotas() -> Result<()> {
+        let logs = TestLogs::new("client-quotas");
+        let handle = |frame: &str| {
+            logs.handle(frame, Authorizer::default()).map(|(_, v)| v)
+        };
+        // AlterClientQuotas v0 setting producer_byte_rate=100 for client-id
+        // "test", validate only, then for real
+        let alter = |validate_only| {
+            format!(
+                "0031 0000 00000007 0001 74 00000001 \
+                 00000001 0009 636c69656e742d6964 0004 74657374 \
+                 00000001 0012 70726f64756365725f627974655f72617465 \
+                 4059000000000000 00 {validate_only}"
+            )
+        };
+        // DescribeClientQuotas v0 of client-id "test" exactly
+        let describe = "0030 0000 00000008 0001 74 00000001 \
+                        0009 636c69656e742d6964 00 0004 74657374 00";
+        let altered = [
+            "00000025 00000007 00000000 00000001 0000 ffff",
+            "00000001 0009 636c69656e742d6964 0004 74657374",
+        ]
+        .join("")
+        .replace(" ", "");
+        assert_eq!(hex::encode(handle(&alter("01"))?), altered);
+        assert_eq!(
+            hex::encode(handle(describe)?),
+            "00000010 00000008 00000000 0000 ffff 00000000".replace(" ", "")
+        );
+        assert_eq!(hex::encode(handle(&alter("00"))?), altered);
+        assert_eq!(
+            hex::encode(handle(describe)?),
+            [
+                "00000045 00000008 00000000 0000 ffff 00000001",
+                "00000001 0009 636c69656e742d6964 0004 74657374",
+                "00000001 0012 70726f64756365725f627974655f72617465",
+                "4059000000000000",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        // v1 removing producer_byte_rate again, and describing in v1
+        let removed = handle(
+            "0031 0001 0000000c 0001 74 00 02 \
+             02 0a 636c69656e742d6964 05 74657374 00 \
+             02 13 70726f64756365725f627974655f72617465 0000000000000000 01 \
+             00 00 00 00",
+        )?;
+        assert_eq!(
+            hex::encode(removed),
+            [
+                "00000020 0000000c 00 00000000 02",
+                "0000 00 02 0a 636c69656e742d6964 05 74657374 00 00 00",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        let described = handle(
+            "0030 0001 0000000d 0001 74 00 \
+             02 0a 636c69656e742d6964 00 05 74657374 00 00 00",
+        )?;
+        assert_eq!(
+            hex::encode(described),
+            "0000000e 0000000d 00 00000000 0000 00 01 00".replace(" ", "")
+        );
+        Ok(())
+    }
+}
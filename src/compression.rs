@@ -0,0 +1,110 @@
+use std::io::{Read, Write};
+
+use crate::{BytesOps, Context, Error, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+// Java clients frame snappy the way snappy-java does:
+// magic, version, compatible version, then length prefixed raw blocks.
+const XERIAL_MAGIC: [u8; 8] = [0x82, b'S', b'N', b'A', b'P', b'P', b'Y', 0];
+
+/// Compression codec stored in the low three bits of the attributes of a
+/// legacy message or a v2 record batch.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_attributes(attributes: u16) -> Result<Self> {
+        match attributes & 0x07 {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Gzip),
+            2 => Ok(Compression::Snappy),
+            3 => Ok(Compression::Lz4),
+            4 => Ok(Compression::Zstd),
+            v => Err(Error::UnknownCompression(v as u8)),
+        }
+    }
+
+    pub fn decompress(&self, v: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(v.to_vec()),
+            Compression::Gzip => {
+                let mut result = vec![];
+                GzDecoder::new(v)
+                    .read_to_end(&mut result)
+                    .context("gzip decompress")?;
+                Ok(result)
+            }
+            Compression::Snappy if v.starts_with(&XERIAL_MAGIC) =>
+                xerial_blocks(v.drop(16)?.1),
+            Compression::Snappy => snap::raw::Decoder::new()
+                .decompress_vec(v)
+                .context("snappy decompress"),
+            Compression::Lz4 => {
+                let mut result = vec![];
+                lz4_flex::frame::FrameDecoder::new(v)
+                    .read_to_end(&mut result)
+                    .context("lz4 decompress")?;
+                Ok(result)
+            }
+            Compression::Zstd => Err(Error::UnknownCompression(4)),
+        }
+    }
+
+    pub fn compress(&self, v: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(v.to_vec()),
+            Compression::Gzip => {
+                let mut encoder =
+                    GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(v).context("gzip compress")?;
+                encoder.finish().context("gzip compress")
+            }
+            Compression::Snappy => snap::raw::Encoder::new()
+                .compress_vec(v)
+                .context("snappy compress"),
+            Compression::Lz4 => {
+                let mut encoder =
+                    lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(v).context("lz4 compress")?;
+                encoder.finish().context("lz4 compress")
+            }
+            Compression::Zstd => Err(Error::UnknownCompression(4)),
+        }
+    }
+}
+
+fn xerial_blocks(mut v: &[u8]) -> Result<Vec<u8>> {
+    let mut result = vec![];
+    while !v.is_empty() {
+        let (length, rest) = v.extract_u32()?;
+        let (block, rest) = rest.drop(length as usize)?;
+        result.extend(
+            snap::raw::Decoder::new()
+                .decompress_vec(block)
+                .context("snappy decompress")?,
+        );
+        v = rest;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let value = b"Hello Kafka! Hello Kafka! Hello Kafka!".to_vec();
+        for c in [Compression::Gzip, Compression::Snappy, Compression::Lz4] {
+            assert_eq!(c.decompress(&c.compress(&value)?)?, value);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::OnceLock;
+
+use crate::{
+    array_length, read, ApiKey, Meta, Result, TagBuffer, ToCompactString,
+    ToKafkaString, ToNullableString, TopicName, Version,
+};
+use bytes::BufMut;
+
+// server.properties given on the command line
+static BROKER_CONFIG: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Reads the static broker config, `key=value` lines with `#` comments.
+pub fn load_broker_config(path: &str) -> Result<()> {
+    let bytes = read(path)?;
+    let configs = String::from_utf8_lossy(&bytes)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect();
+    let _ = BROKER_CONFIG.set(configs);
+    Ok(())
+}
+
+pub fn broker_config() -> &'static HashMap<String, String> {
+    BROKER_CONFIG.get_or_init(HashMap::new)
+}
+
+/// Where a config value comes from, DescribeConfigs v1+.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigSource {
+    DynamicTopic,
+    StaticBroker,
+    Default,
+}
+
+impl Deref for ConfigSource {
+    type Target = i8;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            ConfigSource::DynamicTopic => &1,
+            ConfigSource::StaticBroker => &4,
+            ConfigSource::Default => &5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigType {
+    Unknown,
+    String,
+    Int,
+    Long,
+    List,
+}
+
+impl Deref for ConfigType {
+    type Target = i8;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            ConfigType::Unknown => &0,
+            ConfigType::String => &2,
+            ConfigType::Int => &3,
+            ConfigType::Long => &5,
+            ConfigType::List => &7,
+        }
+    }
+}
+
+/// A topic config the broker knows, with the broker settings supplying
+/// its default in order of precedence and the factor to this unit.
+#[derive(Debug)]
+pub struct ConfigDef {
+    pub name: &'static str,
+    pub config_type: ConfigType,
+    pub default: &'static str,
+    pub synonyms: &'static [(&'static str, i64)],
+    pub min: i64,
+    pub valid: &'static [&'static str],
+    pub documentation: &'static str,
+}
+
+pub const TOPIC_CONFIGS: &[ConfigDef] = &[
+    ConfigDef {
+        name: "cleanup.policy",
+        config_type: ConfigType::List,
+        default: "delete",
+        synonyms: &[("log.cleanup.policy", 1)],
+        min: 0,
+        valid: &["compact", "delete"],
+        documentation: "Delete old segments, compact by key, or both.",
+    },
+    ConfigDef {
+        name: "compression.type",
+        config_type: ConfigType::String,
+        default: "producer",
+        synonyms: &[("compression.type", 1)],
+        min: 0,
+        valid: &["uncompressed", "zstd", "lz4", "snappy", "gzip", "producer"],
+        documentation: "Codec of stored batches, producer keeps theirs.",
+    },
+    ConfigDef {
+        name: "delete.retention.ms",
+        config_type: ConfigType::Long,
+        default: "86400000",
+        synonyms: &[("log.cleaner.delete.retention.ms", 1)],
+        min: 0,
+        valid: &[],
+        documentation: "How long tombstones survive compaction.",
+    },
+    ConfigDef {
+        name: "max.message.bytes",
+        config_type: ConfigType::Int,
+        default: "1048588",
+        synonyms: &[("message.max.bytes", 1)],
+        min: 0,
+        valid: &[],
+        documentation: "Largest record batch the topic accepts.",
+    },
+    ConfigDef {
+        name: "retention.bytes",
+        config_type: ConfigType::Long,
+        default: "-1",
+        synonyms: &[("log.retention.bytes", 1)],
+        min: -1,
+        valid: &[],
+        documentation: "Size a partition may grow to before old segments \
+                        are deleted, -1 for no limit.",
+    },
+    ConfigDef {
+        name: "retention.ms",
+        config_type: ConfigType::Long,
+        default: "604800000",
+        synonyms: &[
+            ("log.retention.ms", 1),
+            ("log.retention.minutes", 60 * 1000),
+            ("log.retention.hours", 60 * 60 * 1000),
+        ],
+        min: -1,
+        valid: &[],
+        documentation: "How long segments are kept, -1 for ever.",
+    },
+    ConfigDef {
+        name: "segment.bytes",
+        config_type: ConfigType::Int,
+        default: "1073741824",
+        synonyms: &[("log.segment.bytes", 1)],
+        min: 14,
+        valid: &[],
+        documentation: "Size at which a new segment is rolled.",
+    },
+];
+
+impl ConfigDef {
+    pub fn find(name: &str) -> Option<&'static ConfigDef> {
+        TOPIC_CONFIGS.iter().find(|v| v.name == name)
+    }
+    /// Checks a value against the type, lower bound and valid values.
+    pub fn validate(&self, value: &str) -> std::result::Result<(), String> {
+        let invalid =
+            || format!("Invalid value {value} for configuration {}", self.name);
+        match self.config_type {
+            ConfigType::Int => value
+                .parse::<i32>()
+                .ok()
+                .filter(|v| *v as i64 >= self.min)
+                .map(|_| ())
+                .ok_or_else(invalid),
+            ConfigType::Long => value
+                .parse::<i64>()
+                .ok()
+                .filter(|v| *v >= self.min)
+                .map(|_| ())
+                .ok_or_else(invalid),
+            ConfigType::List => value
+                .split(',')
+                .map(str::trim)
+                .all(|v| self.valid.contains(&v))
+                .then_some(())
+                .ok_or_else(invalid),
+            _ if self.valid.is_empty() || self.valid.contains(&value) => Ok(()),
+            _ => Err(invalid()),
+        }
+    }
+    /// The first synonym set in server.properties, its value as written
+    /// and in the unit of this config.
+    fn static_value(&self) -> Option<(String, String, String)> {
+        self.synonyms.iter().find_map(|(name, factor)| {
+            let value = broker_config().get(*name)?;
+            let converted = match factor {
+                1 => value.clone(),
+                factor => (value.parse::<i64>().ok()? * factor).to_string(),
+            };
+            Some((name.to_string(), value.clone(), converted))
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigSynonym {
+    pub name: String,
+    pub value: Option<String>,
+    pub source: ConfigSource,
+}
+
+/// A config with its effective value and, as synonyms, every value it
+/// could take from highest to lowest precedence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigEntry {
+    pub name: String,
+    pub value: Option<String>,
+    pub source: ConfigSource,
+    pub config_type: ConfigType,
+    pub documentation: Option<String>,
+    pub synonyms: Vec<ConfigSynonym>,
+}
+
+impl ConfigEntry {
+    fn new(name: String, synonyms: Vec<ConfigSynonym>) -> Self {
+        let first = synonyms[0].clone();
+        Self {
+            name,
+            value: first.value,
+            source: first.source,
+            config_type: ConfigType::Unknown,
+            documentation: None,
+            synonyms,
+        }
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::DescribeConfigs.is_flexible(version);
+        let string = |v: String| match flexible {
+            true => v.to_compact_string(),
+            false => v.to_kafka_string(),
+        };
+        let mut bytes = string(self.name);
+        bytes.extend(self.value.to_nullable_string(flexible));
+        // read only
+        bytes.put_u8(0);
+        if version == Version::V0 {
+            bytes.put_u8((self.source == ConfigSource::Default) as u8);
+        } else {
+            bytes.put_i8(*self.source);
+        }
+        // sensitive
+        bytes.put_u8(0);
+        if version >= Version::V1 {
+            bytes.extend(array_length(flexible, self.synonyms.len()));
+            self.synonyms.into_iter().for_each(|s| {
+                bytes.extend(string(s.name));
+                bytes.extend(s.value.to_nullable_string(flexible));
+                bytes.put_i8(*s.source);
+                if flexible {
+                    bytes.put_u8(*TagBuffer::zero());
+                }
+            });
+        }
+        if version >= Version::V3 {
+            bytes.put_i8(*self.config_type);
+            bytes.extend(self.documentation.to_nullable_string(flexible));
+        }
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+/// Every topic config: the dynamic value from the metadata log, then
+/// server.properties, then the default.
+pub fn describe_topic_configs(
+    meta: &Meta,
+    topic_name: &TopicName,
+) -> Vec<ConfigEntry> {
+    let dynamic = meta.topic_configs(topic_name);
+    TOPIC_CONFIGS
+        .iter()
+        .map(|def| {
+            let static_value = def.static_value();
+            let value = dynamic
+                .get(def.name)
+                .or(static_value.as_ref().map(|v| &v.2))
+                .cloned()
+                .unwrap_or(def.default.to_string());
+            let mut synonyms = vec![];
+            if let Some(value) = dynamic.get(def.name) {
+                synonyms.push(ConfigSynonym {
+                    name: def.name.to_string(),
+                    value: Some(value.clone()),
+                    source: ConfigSource::DynamicTopic,
+                });
+            }
+            if let Some((name, value, _)) = static_value {
+                synonyms.push(ConfigSynonym {
+                    name,
+                    value: Some(value),
+                    source: ConfigSource::StaticBroker,
+                });
+            }
+            synonyms.push(ConfigSynonym {
+                name: def.synonyms[0].0.to_string(),
+                value: Some(def.default.to_string()),
+                source: ConfigSource::Default,
+            });
+            ConfigEntry {
+                value: Some(value),
+                config_type: def.config_type,
+                documentation: Some(def.documentation.to_string()),
+                ..ConfigEntry::new(def.name.to_string(), synonyms)
+            }
+        })
+        .collect()
+}
+
+/// The static broker settings, there are no dynamic broker configs.
+pub fn describe_broker_configs() -> Vec<ConfigEntry> {
+    let mut entries: Vec<ConfigEntry> = broker_config()
+        .iter()
+        .map(|(name, value)| {
+            ConfigEntry::new(
+                name.clone(),
+                vec![ConfigSynonym {
+                    name: name.clone(),
+                    value: Some(value.clone()),
+                    source: ConfigSource::StaticBroker,
+                }],
+            )
+        })
+        .collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
+
+/// Effective value of every topic config.
+pub fn topic_config_values(
+    meta: &Meta,
+    topic_name: &TopicName,
+) -> HashMap<String, String> {
+    describe_topic_configs(meta, topic_name)
+        .into_iter()
+        .filter_map(|e| e.value.map(|v| (e.name, v)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let def = |name| ConfigDef::find(name).unwrap();
+        assert!(def("retention.ms").validate("-1").is_ok());
+        assert!(def("retention.ms").validate("-2").is_err());
+        assert!(def("segment.bytes").validate("3000000000").is_err());
+        assert!(def("cleanup.policy").validate("compact,delete").is_ok());
+        assert!(def("cleanup.policy").validate("forever").is_err());
+        assert!(def("compression.type").validate("lz4").is_ok());
+        assert!(def("compression.type").validate("brotli").is_err());
+        assert!(ConfigDef::find("no.such.config").is_none());
+    }
+}
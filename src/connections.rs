@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Mutex, OnceLock};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::info;
+
+use crate::{
+    broker_config, fetch_sessions, ClientSoftware, Error, FetchContext,
+    Listener, Principal, Request, Result, SessionId,
+};
+
+/// Kafka's `connections.max.idle.ms` default.
+const MAX_IDLE_MS: u64 = 600_000;
+
+/// What the broker knows of a connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionState {
+    pub id: u64,
+    pub host: String,
+    pub listener: Listener,
+    pub client_id: Option<String>,
+    pub principal: Option<Principal>,
+    pub client_software: Option<ClientSoftware>,
+    /// Incremental fetch sessions the connection started, still cached.
+    pub fetch_sessions: Vec<SessionId>,
+    pub connected: SystemTime,
+    pub last_active: Instant,
+    /// Requests read and not yet answered, a parked Fetch among them.
+    pub in_flight: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    stream: TcpStream,
+    state: ConnectionState,
+    // shut down as idle, open until its handler sees the end of the stream
+    reaped: bool,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    next_id: u64,
+    closing: bool,
+    connections: HashMap<u64, Entry>,
+}
+
+/// The client connections the broker has open, so a shutdown can stop
+/// reading from all of them, idle ones are closed after
+/// `connections.max.idle.ms` and a host opens at most
+/// `max.connections.per.ip`.
+#[derive(Debug, Default)]
+pub struct ConnectionRegistry {
+    registry: Mutex<Registry>,
+}
+
+static CONNECTIONS: OnceLock<ConnectionRegistry> = OnceLock::new();
+
+pub fn connections() -> &'static ConnectionRegistry {
+    CONNECTIONS.get_or_init(ConnectionRegistry::default)
+}
+
+/// `max.connections.per.ip`, or for a host in
+/// `max.connections.per.ip.overrides`, e.g. `127.0.0.1:200,::1:200`, its
+/// own.
+fn max_connections(host: &str) -> u32 {
+    broker_config()
+        .get("max.connections.per.ip.overrides")
+        .into_iter()
+        .flat_map(|v| v.split(','))
+        .filter_map(|v| v.trim().rsplit_once(':'))
+        .find(|(h, _)| *h == host)
+        .and_then(|(_, max)| max.parse().ok())
+        .or_else(|| {
+            broker_config()
+                .get("max.connections.per.ip")
+                .and_then(|v| v.parse().ok())
+        })
+        .unwrap_or(i32::MAX as u32)
+}
+
+fn max_idle() -> Duration {
+    let ms = broker_config()
+        .get("connections.max.idle.ms")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(MAX_IDLE_MS);
+    Duration::from_millis(ms)
+}
+
+impl ConnectionRegistry {
+    /// Registers an accepted connection until the returned handle is
+    /// dropped. Refused once the broker is shutting down or `host` has
+    /// its most connections open.
+    pub fn register(
+        &self,
+        stream: &TcpStream,
+        host: &str,
+        listener: &Listener,
+    ) -> Result<ConnectionHandle<'_>> {
+        let mut registry = self.registry.lock().unwrap();
+        if registry.closing {
+            return Err(Error::ConnectionClosed("shutting down".to_string()));
+        }
+        let max = max_connections(host);
+        let open = registry
+            .connections
+            .values()
+            .filter(|e| e.state.host == host)
+            .count();
+        if open >= max as usize {
+            return Err(Error::TooManyConnections(host.to_string(), max));
+        }
+        let stream = stream
+            .try_clone()
+            .map_err(|e| Error::ConnectionClosed(e.to_string()))?;
+        let id = registry.next_id;
+        registry.next_id += 1;
+        let state = ConnectionState {
+            id,
+            host: host.to_string(),
+            listener: listener.clone(),
+            client_id: None,
+            principal: None,
+            client_software: None,
+            fetch_sessions: vec![],
+            connected: SystemTime::now(),
+            last_active: Instant::now(),
+            in_flight: 0,
+        };
+        registry.connections.insert(
+            id,
+            Entry {
+                stream,
+                state,
+                reaped: false,
+            },
+        );
+        Ok(ConnectionHandle {
+            registry: self,
+            id,
+        })
+    }
+    /// Every open connection, with the fetch sessions since closed or
+    /// evicted left out. No API reports them yet.
+    pub fn list(&self) -> Vec<ConnectionState> {
+        let registry = self.registry.lock().unwrap();
+        let mut states: Vec<_> =
+            registry.connections.values().map(|e| e.state.clone()).collect();
+        drop(registry);
+        states.iter_mut().for_each(|s| {
+            s.fetch_sessions.retain(|id| fetch_sessions().contains(*id))
+        });
+        states.sort_by_key(|s| s.id);
+        states
+    }
+    /// A response written, traffic after which the connection is not
+    /// idle until `connections.max.idle.ms` later.
+    pub fn answered(&self, id: u64) {
+        self.update(id, |state| {
+            state.in_flight = state.in_flight.saturating_sub(1)
+        });
+    }
+    fn update(&self, id: u64, f: impl FnOnce(&mut ConnectionState)) {
+        let mut registry = self.registry.lock().unwrap();
+        if let Some(entry) = registry.connections.get_mut(&id) {
+            entry.state.last_active = Instant::now();
+            f(&mut entry.state);
+        }
+    }
+    /// Closes the connections idle for longer than `max_idle` without a
+    /// request in flight, they are deregistered as their handlers see the
+    /// end of the stream. The next time one may be idle for long enough.
+    fn reap(&self, max_idle: Duration) -> Instant {
+        let mut registry = self.registry.lock().unwrap();
+        let now = Instant::now();
+        let mut next = now + max_idle;
+        for e in registry
+            .connections
+            .values_mut()
+            .filter(|e| !e.reaped && e.state.in_flight == 0)
+        {
+            let until = e.state.last_active + max_idle;
+            if until > now {
+                next = next.min(until);
+                continue;
+            }
+            info!(host = e.state.host, "closing idle connection");
+            let _ = e.stream.shutdown(Shutdown::Both);
+            e.reaped = true;
+        }
+        next
+    }
+    /// Stops reading from every connection. A request being handled is
+    /// still answered, then its connection sees the end of the stream.
+    pub fn close_all(&self) {
+        let mut registry = self.registry.lock().unwrap();
+        registry.closing = true;
+        registry.connections.values().for_each(|e| {
+            let _ = e.stream.shutdown(Shutdown::Read);
+        });
+    }
+    pub fn is_closing(&self) -> bool {
+        self.registry.lock().unwrap().closing
+    }
+}
+
+/// Closes the connections idle for `connections.max.idle.ms`.
+pub fn spawn_connection_reaper() -> JoinHandle<()> {
+    spawn(|| {
+        let max_idle = max_idle();
+        loop {
+            let next = connections().reap(max_idle);
+            sleep(next.saturating_duration_since(Instant::now()));
+        }
+    })
+}
+
+/// A registered connection, deregistered when dropped.
+#[derive(Debug)]
+pub struct ConnectionHandle<'a> {
+    registry: &'a ConnectionRegistry,
+    id: u64,
+}
+
+impl ConnectionHandle<'_> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+    /// A request read, in flight until [`ConnectionRegistry::answered`].
+    pub fn received(&self) {
+        self.registry.update(self.id, |state| state.in_flight += 1);
+    }
+    /// Records the client, principal and fetch session of a request, and
+    /// the software the connection reported so far.
+    pub fn request(
+        &self,
+        request: &Request,
+        client_software: Option<&ClientSoftware>,
+        fetch_context: Option<&FetchContext>,
+    ) {
+        self.registry.update(self.id, |state| {
+            state.client_id = Some(request.header.client_id().to_string());
+            state.principal = request.header.principal().cloned();
+            state.client_software = client_software.cloned();
+            let session_id = fetch_context.map(FetchContext::session_id);
+            if let Some(id) = session_id.filter(|id| **id != 0) {
+                if !state.fetch_sessions.contains(&id) {
+                    state.fetch_sessions.push(id);
+                }
+            }
+        });
+    }
+}
+
+impl Drop for ConnectionHandle<'_> {
+    fn drop(&mut self) {
+        let mut registry = self.registry.registry.lock().unwrap();
+        registry.connections.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn connect(listener: &TcpListener) -> (TcpStream, TcpStream) {
+        let client =
+            TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, listener.accept().unwrap().0)
+    }
+
+    #[test]
+    fn test_close_all() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let plaintext: Listener = "PLAINTEXT://127.0.0.1:0".parse().unwrap();
+        let (client, mut server) = connect(&listener);
+        let registry = ConnectionRegistry::default();
+        let handle =
+            registry.register(&server, "127.0.0.1", &plaintext).unwrap();
+        registry.close_all();
+        // a blocked read returns the end of the stream
+        assert_eq!(server.read(&mut [0; 4]).unwrap(), 0);
+        assert!(registry.register(&client, "127.0.0.1", &plaintext).is_err());
+        drop(handle);
+        assert!(registry.list().is_empty());
+    }
+
+    #[test]
+    fn test_reap_idle() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let plaintext: Listener = "PLAINTEXT://127.0.0.1:0".parse().unwrap();
+        let (_idle_client, mut idle) = connect(&listener);
+        let (_parked_client, mut parked) = connect(&listener);
+        let (_client, active) = connect(&listener);
+        let registry = ConnectionRegistry::default();
+        let idle_handle =
+            registry.register(&idle, "127.0.0.1", &plaintext).unwrap();
+        let parked_handle =
+            registry.register(&parked, "127.0.0.1", &plaintext).unwrap();
+        parked_handle.received();
+        sleep(Duration::from_millis(60));
+        let _active =
+            registry.register(&active, "127.0.0.1", &plaintext).unwrap();
+        registry.reap(Duration::from_millis(50));
+        assert_eq!(idle.read(&mut [0; 4]).unwrap(), 0);
+        // a request in flight keeps its connection open
+        parked.set_nonblocking(true).unwrap();
+        assert_eq!(
+            parked.read(&mut [0; 4]).unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+        // the idle one is listed until its handler sees the end of it
+        assert_eq!(registry.list().len(), 3);
+        drop(idle_handle);
+        let open = registry.list();
+        assert_eq!(open.len(), 2);
+        assert_eq!(open[0].id, parked_handle.id());
+        assert_eq!(open[0].in_flight, 1);
+        registry.answered(parked_handle.id());
+        assert_eq!(registry.list()[0].in_flight, 0);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    array_length, response_header, with_message_size,
+    AccessControlEntryRecordValue, AclBinding, AclOperation, ApiKey,
+    Authorizer, BytesOps, CorrelationId, Error, ErrorCode, FrameVersion,
+    HandlerContext, Meta, RecordValue, Request, RequestBody, Response,
+    ResponseBody, Result, TagBuffer, ThrottleTime, ToNullableString,
+    ValueVersion, Version,
+};
+use bytes::BufMut;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct AclCreationResult {
+    error_code: ErrorCode,
+    error_message: Option<String>,
+}
+
+impl AclCreationResult {
+    pub fn new() -> Self {
+        Self {
+            error_code: ErrorCode::NoError,
+            error_message: None,
+        }
+    }
+    pub fn error(error_code: ErrorCode, error_message: String) -> Self {
+        Self {
+            error_code,
+            error_message: Some(error_message),
+        }
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::CreateAcls.is_flexible(version);
+        let mut bytes = vec![];
+        bytes.put_i16(*self.error_code);
+        bytes.extend(self.error_message.to_nullable_string(flexible));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+impl Default for AclCreationResult {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestBody {
+    pub(crate) fn create_acls(body: &[u8], version: Version) -> Result<Self> {
+        let flexible = ApiKey::CreateAcls.is_flexible(version);
+        let (creations, _rest) = body.extract_array_with(flexible, |v| {
+            AclBinding::extract(v, version)
+        })?;
+        Ok(RequestBody::CreateAcls {
+            creations,
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_create_acls(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let authorizer = &cx.authorizer;
+        let allowed = cx.allowed(request);
+        let RequestBody::CreateAcls {
+            creations,
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        Ok(ResponseBody::CreateAcls {
+            results: match Self::acl_authorization(
+                authorizer,
+                &allowed,
+                AclOperation::ALTER,
+            ) {
+                Some((code, message)) => creations
+                    .iter()
+                    .map(|_| AclCreationResult::error(code, message.clone()))
+                    .collect(),
+                None => Self::create_acls(cx.metadata_log, creations),
+            },
+            throttle_time: ThrottleTime::zero(),
+        })
+    }
+
+    pub(crate) fn error_create_acls(
+        body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        let results = match body {
+            Some(RequestBody::CreateAcls {
+                creations,
+            }) => creations
+                .iter()
+                .map(|_| {
+                    AclCreationResult::error(
+                        error.error_code(),
+                        error.to_string(),
+                    )
+                })
+                .collect(),
+            _ => vec![],
+        };
+        ResponseBody::CreateAcls {
+            results,
+            throttle_time: ThrottleTime::zero(),
+        }
+    }
+
+    pub(crate) fn encode_create_acls(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::CreateAcls {
+            results,
+            throttle_time,
+        } = body
+        else {
+            unreachable!("not a CreateAcls");
+        };
+        let (mut bytes, flexible) =
+            response_header(ApiKey::CreateAcls, version, correlation_id);
+        bytes.put_u32(*throttle_time);
+        bytes.extend(array_length(flexible, results.len()));
+        results.into_iter().for_each(|r| bytes.extend(r.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+
+    /// Valid bindings are written as AccessControlEntryRecords in one
+    /// batch.
+    fn create_acls(
+        metadata_log: &str,
+        creations: &[AclBinding],
+    ) -> Vec<AclCreationResult> {
+        let results: Vec<_> =
+            creations.iter().map(AclBinding::validate).collect();
+        let values = creations
+            .iter()
+            .zip(&results)
+            .filter(|(_, result)| result.is_ok())
+            .map(|(binding, _)| {
+                RecordValue::AccessControlEntryRecord(
+                    AccessControlEntryRecordValue(
+                        FrameVersion::new(1),
+                        ValueVersion::new(0),
+                        Uuid::new_v4(),
+                        binding.clone(),
+                    ),
+                )
+            })
+            .collect::<Vec<_>>();
+        let written = match values.is_empty() {
+            true => Ok(()),
+            false => Meta::append(metadata_log, values)
+                .inspect(|_| Authorizer::invalidate()),
+        };
+        results
+            .into_iter()
+            .map(|result| match (result, &written) {
+                (Err(message), _) =>
+                    AclCreationResult::error(ErrorCode::InvalidRequest, message),
+                (Ok(()), Err(e)) => AclCreationResult::error(
+                    ErrorCode::UnknownServerError,
+                    e.to_string(),
+                ),
+                (Ok(()), Ok(())) => AclCreationResult::new(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestLogs;
+
+    #[test]
+    fn test_handle_create_acls() -> Result<()> {
+        let logs = TestLogs::new("create-acls");
+        // v0: User:alice may READ topic "orders"
+        let frame = "001e 0000 00000007 0001 74 00000001 \
+                     02 0006 6f7264657273 000a 557365723a616c696365 0001 2a \
+                     03 03";
+        let (_, bytes) = logs.handle(frame, Authorizer::default())?;
+        assert_eq!(
+            hex::encode(bytes),
+            [
+                "0000002b 00000007 00000000 00000001",
+                "0036 001b 4e6f20617574686f72697a65722069732063",
+                "6f6e66696775726564",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        // enabled, without a binding on the cluster
+        let authorizer = Authorizer::from_log(&logs.metadata_log);
+        let (_, bytes) = logs.handle(frame, authorizer)?;
+        assert_eq!(
+            hex::encode(bytes),
+            [
+                "0000002d 00000007 00000000 00000001",
+                "001f 001d 4e6f7420617574686f72697a6564206f6e2074686520636c75",
+                "73746572",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        assert!(Authorizer::from_log(&logs.metadata_log).acls().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,354 @@
+use std::fs::create_dir_all;
+
+use crate::{
+    array_length, response_header, with_message_size, AclOperation, ApiKey,
+    BytesOps, Context, CorrelationId, Error, ErrorCode, FrameVersion,
+    HandlerContext, ISRNode, Leader, LeaderEpoch, Log, MapTupleTwo, Meta,
+    NodeId, PartitionEpoch, PartitionIndex, PartitionRecordValue, RecordValue,
+    ReplicaNode, Request, RequestBody, Response, ResponseBody, Result,
+    TagBuffer, ThrottleTime, ToCompactString, ToKafkaString, ToNullableString,
+    TopicName, ValueVersion, Version,
+};
+use bytes::BufMut;
+
+#[derive(Debug, Clone)]
+pub struct CreatePartitionsTopic {
+    topic_name: TopicName,
+    count: u32,
+    assignments: Option<Vec<Vec<NodeId>>>,
+}
+
+impl CreatePartitionsTopic {
+    pub fn topic_name(&self) -> &TopicName {
+        &self.topic_name
+    }
+    /// The partition count wanted, not how many to add.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+    pub fn extract(value: &[u8], flexible: bool) -> Result<(Self, &[u8])> {
+        let (topic_name, rest) = match flexible {
+            true => value.extract_compact_str(),
+            false => value.extract_string(),
+        }
+        .map_tuple(TopicName::new)?;
+        let (count, rest) = rest.extract_u32()?;
+        let null = match flexible {
+            true => rest.first() == Some(&0),
+            false => rest.starts_with(&[0xff; 4]),
+        };
+        let (assignments, rest) = rest.extract_array_with(flexible, |v| {
+            let (broker_ids, rest) = v.extract_array_with(flexible, |v| {
+                v.extract_u32_into(NodeId::new)
+            })?;
+            match flexible {
+                true => rest.drop(1).map_tuple(|_| broker_ids),
+                false => Ok((broker_ids, rest)),
+            }
+        })?;
+        let rest = match flexible {
+            true => rest.drop(1).second()?,
+            false => rest,
+        };
+        Ok((
+            Self {
+                topic_name,
+                count,
+                assignments: (!null).then_some(assignments),
+            },
+            rest,
+        ))
+    }
+    /// PartitionRecords for the partitions past the current count. Without
+    /// assignments a new partition gets the replicas of the first one.
+    pub fn partition_records(
+        &self,
+        meta: &Meta,
+    ) -> std::result::Result<Vec<PartitionRecordValue>, (ErrorCode, String)>
+    {
+        let topic_id =
+            meta.find_topic_id(&self.topic_name).ok_or_else(|| {
+                (
+                    ErrorCode::UnknownTopicOrPartition,
+                    format!("Topic {} does not exist", *self.topic_name),
+                )
+            })?;
+        let mut partitions = meta.find_partitions(&topic_id);
+        partitions.sort_by_key(|p| *p.2);
+        let current = partitions.len() as u32;
+        if self.count <= current {
+            return Err((
+                ErrorCode::InvalidPartitions,
+                format!(
+                    "Topic currently has {current} partitions, which is \
+                     higher than or equal to the requested {}",
+                    self.count
+                ),
+            ));
+        }
+        let first = partitions.first().ok_or_else(|| {
+            (
+                ErrorCode::InvalidPartitions,
+                format!("Topic {} has no partitions", *self.topic_name),
+            )
+        })?;
+        let added = (self.count - current) as usize;
+        let assignments = match &self.assignments {
+            Some(v) if v.len() != added || v.iter().any(Vec::is_empty) =>
+                return Err((
+                    ErrorCode::InvalidReplicaAssignment,
+                    format!(
+                        "Increasing the partition count by {added} needs \
+                         {added} non empty assignments, got {}",
+                        v.len()
+                    ),
+                )),
+            Some(v) => v.clone(),
+            None if first.7.is_empty() =>
+                return Err((
+                    ErrorCode::InvalidReplicaAssignment,
+                    format!(
+                        "Partition 0 of topic {} has no replicas to assign \
+                         the new partitions",
+                        *self.topic_name
+                    ),
+                )),
+            None => vec![first.7.iter().map(|v| **v).collect(); added],
+        };
+        Ok(assignments
+            .into_iter()
+            .enumerate()
+            .map(|(i, replicas)| {
+                PartitionRecordValue(
+                    FrameVersion::new(1),
+                    ValueVersion::new(1),
+                    PartitionIndex::new(current + i as u32),
+                    topic_id,
+                    Leader::new(replicas[0]),
+                    LeaderEpoch::new(0),
+                    PartitionEpoch::new(0),
+                    replicas.iter().map(|v| ReplicaNode::new(*v)).collect(),
+                    replicas.iter().map(|v| ISRNode::new(*v)).collect(),
+                    vec![],
+                    vec![],
+                    first.11.clone(),
+                )
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CreatePartitionsTopicResult {
+    topic_name: TopicName,
+    error_code: ErrorCode,
+    error_message: Option<String>,
+}
+
+impl CreatePartitionsTopicResult {
+    pub fn new(topic_name: TopicName) -> Self {
+        Self {
+            topic_name,
+            error_code: ErrorCode::NoError,
+            error_message: None,
+        }
+    }
+    pub fn error(
+        topic_name: TopicName,
+        error_code: ErrorCode,
+        error_message: String,
+    ) -> Self {
+        Self {
+            error_code,
+            error_message: Some(error_message),
+            ..Self::new(topic_name)
+        }
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::CreatePartitions.is_flexible(version);
+        let mut bytes = match flexible {
+            true => self.topic_name.to_compact_string(),
+            false => self.topic_name.to_kafka_string(),
+        };
+        bytes.put_i16(*self.error_code);
+        bytes.extend(self.error_message.to_nullable_string(flexible));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+impl RequestBody {
+    pub(crate) fn create_partitions(
+        body: &[u8],
+        version: Version,
+    ) -> Result<Self> {
+        let flexible = ApiKey::CreatePartitions.is_flexible(version);
+        let (topics, rest) = body.extract_array_with(flexible, |v| {
+            CreatePartitionsTopic::extract(v, flexible)
+        })?;
+        let (timeout, rest) = rest.extract_u32()?;
+        let (validate_only, _rest) = rest.extract_u8().map_tuple(|v| v != 0)?;
+        Ok(RequestBody::CreatePartitions {
+            topics,
+            timeout,
+            validate_only,
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_create_partitions(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let topic_allowed = cx.topic_allowed(request);
+        let RequestBody::CreatePartitions {
+            topics,
+            validate_only,
+            ..
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        Ok(ResponseBody::CreatePartitions {
+            results: topics
+                .iter()
+                .map(|t| {
+                    let duplicate = topics
+                        .iter()
+                        .filter(|v| v.topic_name() == t.topic_name())
+                        .count()
+                        > 1;
+                    if !topic_allowed(AclOperation::ALTER, t.topic_name()) {
+                        return CreatePartitionsTopicResult::error(
+                            t.topic_name().clone(),
+                            ErrorCode::TopicAuthorizationFailed,
+                            "Not authorized".to_string(),
+                        );
+                    }
+                    Self::create_partitions(
+                        cx.metadata_log,
+                        cx.log_dir,
+                        t,
+                        duplicate,
+                        *validate_only,
+                    )
+                })
+                .collect(),
+            throttle_time: ThrottleTime::zero(),
+        })
+    }
+
+    pub(crate) fn error_create_partitions(
+        body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        let results = match body {
+            Some(RequestBody::CreatePartitions {
+                topics,
+                ..
+            }) => topics
+                .iter()
+                .map(|t| {
+                    CreatePartitionsTopicResult::error(
+                        t.topic_name().clone(),
+                        error.error_code(),
+                        error.to_string(),
+                    )
+                })
+                .collect(),
+            _ => vec![],
+        };
+        ResponseBody::CreatePartitions {
+            results,
+            throttle_time: ThrottleTime::zero(),
+        }
+    }
+
+    pub(crate) fn encode_create_partitions(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::CreatePartitions {
+            results,
+            throttle_time,
+        } = body
+        else {
+            unreachable!("not a CreatePartitions");
+        };
+        let (mut bytes, flexible) =
+            response_header(ApiKey::CreatePartitions, version, correlation_id);
+        bytes.put_u32(*throttle_time);
+        bytes.extend(array_length(flexible, results.len()));
+        results.into_iter().for_each(|r| bytes.extend(r.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+
+    /// Appends PartitionRecords for the new partitions to the metadata log
+    /// and creates their log directories.
+    fn create_partitions(
+        metadata_log: &str,
+        log_dir: &str,
+        topic: &CreatePartitionsTopic,
+        duplicate: bool,
+        validate_only: bool,
+    ) -> CreatePartitionsTopicResult {
+        let topic_name = topic.topic_name().clone();
+        if duplicate {
+            return CreatePartitionsTopicResult::error(
+                topic_name.clone(),
+                ErrorCode::InvalidRequest,
+                format!("Duplicate topic {} in request", *topic_name),
+            );
+        }
+        // the partition count is read and the new partitions appended under
+        // the metadata log's lock, so concurrent requests cannot both add
+        // the same partition
+        let added = Meta::append_with(metadata_log, |meta| {
+            match topic.partition_records(meta) {
+                Ok(_) if validate_only => (vec![], Ok(vec![])),
+                Ok(records) => {
+                    let dirs: Vec<_> = records
+                        .iter()
+                        .map(|r| Log::dir(log_dir, &topic_name, r.2))
+                        .collect();
+                    let values = records
+                        .into_iter()
+                        .map(RecordValue::PartitionRecord)
+                        .collect();
+                    (values, Ok(dirs))
+                }
+                Err(e) => (vec![], Err(e)),
+            }
+        });
+        let dirs = match added {
+            Ok(Ok(dirs)) => dirs,
+            Ok(Err((error_code, message))) =>
+                return CreatePartitionsTopicResult::error(
+                    topic_name, error_code, message,
+                ),
+            Err(e) =>
+                return CreatePartitionsTopicResult::error(
+                    topic_name,
+                    ErrorCode::UnknownServerError,
+                    e.to_string(),
+                ),
+        };
+        match dirs.iter().try_for_each(|dir| {
+            create_dir_all(dir).context("Creating partition directory")
+        }) {
+            Ok(()) => CreatePartitionsTopicResult::new(topic_name),
+            Err(e) => CreatePartitionsTopicResult::error(
+                topic_name,
+                ErrorCode::UnknownServerError,
+                e.to_string(),
+            ),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use crate::{
+    array_length, response_header, with_message_size, AclBinding,
+    AclBindingFilter, AclOperation, ApiKey, Authorizer, BytesOps,
+    CorrelationId, Error, ErrorCode, FrameVersion, HandlerContext, MapTupleTwo,
+    Meta, RecordValue, RemoveAccessControlEntryRecordValue, Request,
+    RequestBody, Response, ResponseBody, Result, TagBuffer, ThrottleTime,
+    ToNullableString, ValueVersion, Version,
+};
+use bytes::BufMut;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct DeleteAclsFilterResult {
+    error_code: ErrorCode,
+    error_message: Option<String>,
+    matching_acls: Vec<AclBinding>,
+}
+
+impl DeleteAclsFilterResult {
+    pub fn new(matching_acls: Vec<AclBinding>) -> Self {
+        Self {
+            error_code: ErrorCode::NoError,
+            error_message: None,
+            matching_acls,
+        }
+    }
+    pub fn error(error_code: ErrorCode, error_message: String) -> Self {
+        Self {
+            error_code,
+            error_message: Some(error_message),
+            matching_acls: vec![],
+        }
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::DeleteAcls.is_flexible(version);
+        let mut bytes = vec![];
+        bytes.put_i16(*self.error_code);
+        bytes.extend(self.error_message.to_nullable_string(flexible));
+        bytes.extend(array_length(flexible, self.matching_acls.len()));
+        self.matching_acls.into_iter().for_each(|acl| {
+            bytes.put_i16(*ErrorCode::NoError);
+            bytes.extend(None::<String>.to_nullable_string(flexible));
+            bytes.extend(acl.encode_resource(version));
+            bytes.extend(acl.encode_entry(version));
+            if flexible {
+                bytes.put_u8(*TagBuffer::zero());
+            }
+        });
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+impl RequestBody {
+    pub(crate) fn delete_acls(body: &[u8], version: Version) -> Result<Self> {
+        let flexible = ApiKey::DeleteAcls.is_flexible(version);
+        let (filters, _rest) = body.extract_array_with(flexible, |v| {
+            let (filter, rest) = AclBindingFilter::extract(v, version)?;
+            match flexible {
+                true => rest.drop(1).map_tuple(|_| filter),
+                false => Ok((filter, rest)),
+            }
+        })?;
+        Ok(RequestBody::DeleteAcls {
+            filters,
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_delete_acls(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let authorizer = &cx.authorizer;
+        let allowed = cx.allowed(request);
+        let RequestBody::DeleteAcls {
+            filters,
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        Ok(ResponseBody::DeleteAcls {
+            filter_results: match Self::acl_authorization(
+                authorizer,
+                &allowed,
+                AclOperation::ALTER,
+            ) {
+                Some((code, message)) => filters
+                    .iter()
+                    .map(|_| {
+                        DeleteAclsFilterResult::error(code, message.clone())
+                    })
+                    .collect(),
+                None => Self::delete_acls(cx.metadata_log, authorizer, filters),
+            },
+            throttle_time: ThrottleTime::zero(),
+        })
+    }
+
+    pub(crate) fn error_delete_acls(
+        body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        let filter_results = match body {
+            Some(RequestBody::DeleteAcls {
+                filters,
+            }) => filters
+                .iter()
+                .map(|_| {
+                    DeleteAclsFilterResult::error(
+                        error.error_code(),
+                        error.to_string(),
+                    )
+                })
+                .collect(),
+            _ => vec![],
+        };
+        ResponseBody::DeleteAcls {
+            filter_results,
+            throttle_time: ThrottleTime::zero(),
+        }
+    }
+
+    pub(crate) fn encode_delete_acls(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::DeleteAcls {
+            filter_results,
+            throttle_time,
+        } = body
+        else {
+            unreachable!("not a DeleteAcls");
+        };
+        let (mut bytes, flexible) =
+            response_header(ApiKey::DeleteAcls, version, correlation_id);
+        bytes.put_u32(*throttle_time);
+        bytes.extend(array_length(flexible, filter_results.len()));
+        filter_results
+            .into_iter()
+            .for_each(|r| bytes.extend(r.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+
+    /// Removes every binding matching any filter, each filter reports what
+    /// it matched.
+    fn delete_acls(
+        metadata_log: &str,
+        authorizer: &Authorizer,
+        filters: &[AclBindingFilter],
+    ) -> Vec<DeleteAclsFilterResult> {
+        let matched: Vec<Vec<&(Uuid, AclBinding)>> = filters
+            .iter()
+            .map(|filter| {
+                authorizer
+                    .acls()
+                    .iter()
+                    .filter(|(_, acl)| filter.matches(acl))
+                    .collect()
+            })
+            .collect();
+        let mut ids: Vec<Uuid> =
+            matched.iter().flatten().map(|(id, _)| *id).collect();
+        ids.sort();
+        ids.dedup();
+        let values = ids
+            .into_iter()
+            .map(|id| {
+                RecordValue::RemoveAccessControlEntryRecord(
+                    RemoveAccessControlEntryRecordValue(
+                        FrameVersion::new(1),
+                        ValueVersion::new(0),
+                        id,
+                    ),
+                )
+            })
+            .collect::<Vec<_>>();
+        let written = match values.is_empty() {
+            true => Ok(()),
+            false => Meta::append(metadata_log, values)
+                .inspect(|_| Authorizer::invalidate()),
+        };
+        matched
+            .into_iter()
+            .map(|acls| match &written {
+                Ok(()) => DeleteAclsFilterResult::new(
+                    acls.into_iter().map(|(_, acl)| acl.clone()).collect(),
+                ),
+                Err(e) => DeleteAclsFilterResult::error(
+                    ErrorCode::UnknownServerError,
+                    e.to_string(),
+                ),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{allow_cluster, TestLogs, CLUSTER_NAME};
+
+    #[test]
+    fn test_handle_delete_acls() -> Result<()> {
+        let logs = TestLogs::new("delete-acls");
+        allow_cluster(&logs.metadata_log)?;
+        // CreateAcls v1: User:alice may READ and WRITE topic "orders",
+        // User:bob may READ "payments"
+        let (_, bytes) = logs.handle(
+            "001e 0001 00000007 0001 74 00000003 \
+             02 0006 6f7264657273 03 000a 557365723a616c696365 0001 2a 03 03 \
+             02 0006 6f7264657273 03 000a 557365723a616c696365 0001 2a 04 03 \
+             02 0008 7061796d656e7473 03 0008 557365723a626f62 0001 2a 03 03",
+            Authorizer::from_log(&logs.metadata_log),
+        )?;
+        assert_eq!(
+            hex::encode(bytes),
+            "00000018 00000007 00000000 00000003 0000ffff 0000ffff 0000ffff"
+                .replace(" ", "")
+        );
+        // DeleteAcls v1 of what User:alice has on "orders", and of every
+        // group binding, of which there are none
+        let (_, bytes) = logs.handle(
+            "001f 0001 00000008 0001 74 00000002 \
+             02 0006 6f7264657273 03 000a 557365723a616c696365 ffff 01 01 \
+             03 ffff 01 ffff ffff 01 01",
+            Authorizer::from_log(&logs.metadata_log),
+        )?;
+        assert_eq!(
+            hex::encode(bytes),
+            [
+                "0000005a 00000008 00000000 00000002 0000 ffff 00000002",
+                "0000 ffff 02 0006 6f7264657273 03",
+                "000a 557365723a616c696365 0001 2a 03 03",
+                "0000 ffff 02 0006 6f7264657273 03",
+                "000a 557365723a616c696365 0001 2a 04 03",
+                "0000 ffff 00000000",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        // the cluster grant and bob's binding are left
+        let authorizer = Authorizer::from_log(&logs.metadata_log);
+        let left: Vec<_> = authorizer
+            .acls()
+            .iter()
+            .map(|(_, acl)| (acl.resource_name.as_str(), *acl.operation))
+            .collect();
+        assert_eq!(
+            left,
+            vec![
+                (CLUSTER_NAME, AclOperation::ALL),
+                ("payments", AclOperation::READ)
+            ]
+        );
+        Ok(())
+    }
+}
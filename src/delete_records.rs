@@ -0,0 +1,358 @@
+use crate::{
+    array_length, response_header, with_message_size, AclOperation, ApiKey,
+    BytesOps, CorrelationId, Error, ErrorCode, HandlerContext, Log,
+    MapTupleTwo, Meta, PartitionIndex, RecordOffset, Request, RequestBody,
+    Response, ResponseBody, Result, TagBuffer, ThrottleTime, ToCompactString,
+    ToKafkaString, TopicName, Version,
+};
+use bytes::BufMut;
+use tracing::error;
+
+#[derive(Debug, Clone)]
+pub struct DeleteRecordsTopic {
+    topic_name: TopicName,
+    partitions: Vec<DeleteRecordsPartition>,
+}
+
+impl DeleteRecordsTopic {
+    pub fn topic_name(&self) -> &TopicName {
+        &self.topic_name
+    }
+    pub fn partitions(&self) -> &Vec<DeleteRecordsPartition> {
+        &self.partitions
+    }
+    pub fn extract(value: &[u8], flexible: bool) -> Result<(Self, &[u8])> {
+        let (topic_name, rest) = match flexible {
+            true => value.extract_compact_str(),
+            false => value.extract_string(),
+        }
+        .map_tuple(TopicName::new)?;
+        let (partitions, rest) = rest.extract_array_with(flexible, |v| {
+            DeleteRecordsPartition::extract(v, flexible)
+        })?;
+        let rest = match flexible {
+            true => rest.drop(1).second()?,
+            false => rest,
+        };
+        Ok((
+            Self {
+                topic_name,
+                partitions,
+            },
+            rest,
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeleteRecordsPartition {
+    partition_index: PartitionIndex,
+    offset: i64,
+}
+
+impl DeleteRecordsPartition {
+    pub fn partition_index(&self) -> PartitionIndex {
+        self.partition_index
+    }
+    /// Deletes everything before this offset, -1 for the high watermark.
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+    fn extract(value: &[u8], flexible: bool) -> Result<(Self, &[u8])> {
+        let (partition_index, rest) =
+            value.extract_u32_into(PartitionIndex::new)?;
+        let (offset, rest) = rest.extract_u64().map_tuple(|v| v as i64)?;
+        let rest = match flexible {
+            true => rest.drop(1).second()?,
+            false => rest,
+        };
+        Ok((
+            Self {
+                partition_index,
+                offset,
+            },
+            rest,
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeleteRecordsResponse {
+    topic_name: TopicName,
+    partitions: Vec<DeleteRecordsPartitionResponse>,
+}
+
+impl DeleteRecordsResponse {
+    pub fn new(
+        topic_name: TopicName,
+        partitions: Vec<DeleteRecordsPartitionResponse>,
+    ) -> Self {
+        Self {
+            topic_name,
+            partitions,
+        }
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::DeleteRecords.is_flexible(version);
+        let mut bytes = match flexible {
+            true => self.topic_name.to_compact_string(),
+            false => self.topic_name.to_kafka_string(),
+        };
+        bytes.extend(array_length(flexible, self.partitions.len()));
+        self.partitions.into_iter().for_each(|p| {
+            bytes.put_u32(*p.partition_index);
+            bytes.put_u64(p.low_watermark.map(|v| *v).unwrap_or(u64::MAX));
+            bytes.put_i16(*p.error_code);
+            if flexible {
+                bytes.put_u8(*TagBuffer::zero());
+            }
+        });
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeleteRecordsPartitionResponse {
+    partition_index: PartitionIndex,
+    low_watermark: Option<RecordOffset>,
+    error_code: ErrorCode,
+}
+
+impl DeleteRecordsPartitionResponse {
+    pub fn new(
+        partition_index: PartitionIndex,
+        low_watermark: RecordOffset,
+    ) -> Self {
+        Self {
+            partition_index,
+            low_watermark: Some(low_watermark),
+            error_code: ErrorCode::NoError,
+        }
+    }
+    pub fn error(
+        partition_index: PartitionIndex,
+        error_code: ErrorCode,
+    ) -> Self {
+        Self {
+            partition_index,
+            low_watermark: None,
+            error_code,
+        }
+    }
+}
+
+impl RequestBody {
+    pub(crate) fn delete_records(
+        body: &[u8],
+        version: Version,
+    ) -> Result<Self> {
+        let flexible = ApiKey::DeleteRecords.is_flexible(version);
+        let (topics, rest) = body.extract_array_with(flexible, |v| {
+            DeleteRecordsTopic::extract(v, flexible)
+        })?;
+        let (timeout, _rest) = rest.extract_u32()?;
+        Ok(RequestBody::DeleteRecords {
+            topics,
+            timeout,
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_delete_records(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let topic_allowed = cx.topic_allowed(request);
+        let RequestBody::DeleteRecords {
+            topics,
+            ..
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        let meta = Meta::load(cx.metadata_log)?;
+        Ok(ResponseBody::DeleteRecords {
+            responses: topics
+                .iter()
+                .map(|t| {
+                    let authorized =
+                        topic_allowed(AclOperation::DELETE, t.topic_name());
+                    DeleteRecordsResponse::new(
+                        t.topic_name().clone(),
+                        t.partitions()
+                            .iter()
+                            .map(|p| match authorized {
+                                true => Self::delete_records(
+                                    cx.log_dir,
+                                    &meta,
+                                    t.topic_name(),
+                                    p,
+                                ),
+                                false => DeleteRecordsPartitionResponse::error(
+                                    p.partition_index(),
+                                    ErrorCode::TopicAuthorizationFailed,
+                                ),
+                            })
+                            .collect(),
+                    )
+                })
+                .collect(),
+            throttle_time: ThrottleTime::zero(),
+        })
+    }
+
+    pub(crate) fn error_delete_records(
+        body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        let responses = match body {
+            Some(RequestBody::DeleteRecords {
+                topics,
+                ..
+            }) => topics
+                .iter()
+                .map(|t| {
+                    DeleteRecordsResponse::new(
+                        t.topic_name().clone(),
+                        t.partitions()
+                            .iter()
+                            .map(|p| {
+                                DeleteRecordsPartitionResponse::error(
+                                    p.partition_index(),
+                                    error.error_code(),
+                                )
+                            })
+                            .collect(),
+                    )
+                })
+                .collect(),
+            _ => vec![],
+        };
+        ResponseBody::DeleteRecords {
+            responses,
+            throttle_time: ThrottleTime::zero(),
+        }
+    }
+
+    pub(crate) fn encode_delete_records(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::DeleteRecords {
+            responses,
+            throttle_time,
+        } = body
+        else {
+            unreachable!("not a DeleteRecords");
+        };
+        let (mut bytes, flexible) =
+            response_header(ApiKey::DeleteRecords, version, correlation_id);
+        bytes.put_u32(*throttle_time);
+        bytes.extend(array_length(flexible, responses.len()));
+        responses.into_iter().for_each(|r| bytes.extend(r.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+
+    /// Deleting up to -1 means up to the high watermark, beyond it is out
+    /// of range.
+    fn delete_records(
+        log_dir: &str,
+        meta: &Meta,
+        topic_name: &TopicName,
+        partition: &DeleteRecordsPartition,
+    ) -> DeleteRecordsPartitionResponse {
+        let index = partition.partition_index();
+        let end = Self::partition_log(meta, topic_name, index, || {
+            Log::end(log_dir, topic_name, index)
+        });
+        let high_watermark = match end {
+            Ok((_, next_offset)) => *next_offset,
+            Err(e) => return DeleteRecordsPartitionResponse::error(index, e),
+        };
+        let offset = match partition.offset() {
+            -1 => high_watermark,
+            v if v < 0 || v as u64 > high_watermark =>
+                return DeleteRecordsPartitionResponse::error(
+                    index,
+                    ErrorCode::OffsetOutOfRange,
+                ),
+            v => v as u64,
+        };
+        match Log::delete_records(
+            log_dir,
+            topic_name,
+            index,
+            RecordOffset::new(offset),
+        ) {
+            Ok(v) => DeleteRecordsPartitionResponse::new(
+                index,
+                RecordOffset::new(*v),
+            ),
+            Err(e) => {
+                error!("delete records of {:?}: {}", topic_name, e);
+                DeleteRecordsPartitionResponse::error(
+                    index,
+                    ErrorCode::UnknownServerError,
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Authorizer, TestLogs};
+
+    #[test]
+    fn test_handle_delete_records() -> Result<()> {
+        let logs = TestLogs::new("delete-records");
+        logs.messages(&[1000, 1010, 1020, 1030, 1040]);
+        // v0 up to offset 6 of partition 0 of "baz", past its high
+        // watermark of 5
+        let (_, bytes) = logs.handle(
+            "0015 0000 00000007 0001 74 00000001 0003 62617a \
+             00000001 00000000 0000000000000006 00001388",
+            Authorizer::default(),
+        )?;
+        assert_eq!(
+            hex::encode(bytes),
+            [
+                "00000023 00000007 00000000 00000001 0003 62617a 00000001",
+                "00000000 ffffffffffffffff 0001",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        // v2 up to offset 2, which becomes the log start offset
+        let (_, bytes) = logs.handle(
+            "0015 0002 00000008 0001 74 00 02 04 62617a \
+             02 00000000 0000000000000002 00 00 00001388 00",
+            Authorizer::default(),
+        )?;
+        assert_eq!(
+            hex::encode(bytes),
+            [
+                "00000020 00000008 00 00000000 02 04 62617a 02",
+                "00000000 0000000000000002 0000 00 00 00",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        let (log_start_offset, next_offset) = Log::end(
+            &logs.log_dir,
+            &TopicName::from("baz"),
+            PartitionIndex::new(0),
+        )?;
+        assert_eq!((*log_start_offset, *next_offset), (2, 5));
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{
+    array_length, response_header, with_message_size, AclBinding,
+    AclBindingFilter, AclOperation, ApiKey, CorrelationId, Error, ErrorCode,
+    HandlerContext, Request, RequestBody, Response, ResponseBody, Result,
+    TagBuffer, ThrottleTime, ToNullableString, Version,
+};
+use bytes::BufMut;
+
+/// The bindings of one resource pattern.
+#[derive(Debug, Clone)]
+pub struct DescribeAclsResource {
+    acls: Vec<AclBinding>,
+}
+
+impl DescribeAclsResource {
+    /// Groups bindings by resource type, name and pattern, in the order
+    /// they are first seen.
+    pub fn group(bindings: Vec<AclBinding>) -> Vec<Self> {
+        let mut resources: Vec<Self> = vec![];
+        for binding in bindings {
+            let same = |r: &&mut Self| {
+                let first = &r.acls[0];
+                first.resource_type == binding.resource_type
+                    && first.resource_name == binding.resource_name
+                    && first.pattern_type == binding.pattern_type
+            };
+            match resources.iter_mut().find(same) {
+                Some(resource) => resource.acls.push(binding),
+                None => resources.push(Self {
+                    acls: vec![binding],
+                }),
+            }
+        }
+        resources
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::DescribeAcls.is_flexible(version);
+        let mut bytes = self.acls[0].encode_resource(version);
+        bytes.extend(array_length(flexible, self.acls.len()));
+        self.acls.into_iter().for_each(|acl| {
+            bytes.extend(acl.encode_entry(version));
+            if flexible {
+                bytes.put_u8(*TagBuffer::zero());
+            }
+        });
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+impl RequestBody {
+    pub(crate) fn describe_acls(body: &[u8], version: Version) -> Result<Self> {
+        let (filter, _rest) = AclBindingFilter::extract(body, version)?;
+        Ok(RequestBody::DescribeAcls {
+            filter,
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_describe_acls(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let authorizer = &cx.authorizer;
+        let allowed = cx.allowed(request);
+        let RequestBody::DescribeAcls {
+            filter,
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        Ok(
+            match Self::acl_authorization(
+                authorizer,
+                &allowed,
+                AclOperation::DESCRIBE,
+            ) {
+                Some((error_code, message)) => ResponseBody::DescribeAcls {
+                    error_code,
+                    error_message: Some(message),
+                    resources: vec![],
+                    throttle_time: ThrottleTime::zero(),
+                },
+                None => ResponseBody::DescribeAcls {
+                    error_code: ErrorCode::NoError,
+                    error_message: None,
+                    resources: DescribeAclsResource::group(
+                        authorizer
+                            .acls()
+                            .iter()
+                            .filter(|(_, acl)| filter.matches(acl))
+                            .map(|(_, acl)| acl.clone())
+                            .collect(),
+                    ),
+                    throttle_time: ThrottleTime::zero(),
+                },
+            },
+        )
+    }
+
+    pub(crate) fn error_describe_acls(
+        _body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        ResponseBody::DescribeAcls {
+            error_code: error.error_code(),
+            error_message: Some(error.to_string()),
+            resources: vec![],
+            throttle_time: ThrottleTime::zero(),
+        }
+    }
+
+    pub(crate) fn encode_describe_acls(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::DescribeAcls {
+            error_code,
+            error_message,
+            resources,
+            throttle_time,
+        } = body
+        else {
+            unreachable!("not a DescribeAcls");
+        };
+        let (mut bytes, flexible) =
+            response_header(ApiKey::DescribeAcls, version, correlation_id);
+        bytes.put_u32(*throttle_time);
+        bytes.put_i16(*error_code);
+        bytes.extend(error_message.to_nullable_string(flexible));
+        bytes.extend(array_length(flexible, resources.len()));
+        resources.into_iter().for_each(|r| bytes.extend(r.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{allow_cluster, Authorizer, TestLogs};
+
+    #[test]
+    fn test_handle_describe_acls() -> Result<()> {
+        let logs = TestLogs::new("describe-acls");
+        // v1 of every binding
+        let frame = "001d 0001 00000007 0001 74 01 ffff 01 ffff ffff 01 01";
+        let described = |authorizer| match logs.handle(frame, authorizer) {
+            Ok((
+                ResponseBody::DescribeAcls {
+                    error_code,
+                    resources,
+                    ..
+                },
+                _,
+            )) => (error_code, resources.len()),
+            other => panic!("not a DescribeAcls: {other:?}"),
+        };
+        assert_eq!(
+            described(Authorizer::default()),
+            (ErrorCode::SecurityDisabled, 0)
+        );
+        let authorizer = || Authorizer::from_log(&logs.metadata_log);
+        assert_eq!(
+            described(authorizer()),
+            (ErrorCode::ClusterAuthorizationFailed, 0)
+        );
+        allow_cluster(&logs.metadata_log)?;
+        assert_eq!(described(authorizer()), (ErrorCode::NoError, 1));
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+use crate::{
+    array_length, describe_broker_configs, describe_topic_configs,
+    response_header, with_message_size, AclOperation, ApiKey, BytesOps,
+    ConfigEntry, ConfigResourceType, CorrelationId, Error, ErrorCode,
+    HandlerContext, MapTupleTwo, Meta, Request, RequestBody, Response,
+    ResponseBody, Result, TagBuffer, ThrottleTime, ToCompactString,
+    ToKafkaString, ToNullableString, TopicName, Version, BROKER_RESOURCE,
+    TOPIC_RESOURCE,
+};
+use bytes::BufMut;
+
+#[derive(Debug, Clone)]
+pub struct DescribeConfigsResource {
+    resource_type: ConfigResourceType,
+    resource_name: String,
+    configuration_keys: Vec<String>,
+}
+
+impl DescribeConfigsResource {
+    pub fn resource_type(&self) -> ConfigResourceType {
+        self.resource_type
+    }
+    pub fn resource_name(&self) -> &String {
+        &self.resource_name
+    }
+    /// Empty or null asks for every config.
+    pub fn configuration_keys(&self) -> &Vec<String> {
+        &self.configuration_keys
+    }
+    pub fn extract(value: &[u8], flexible: bool) -> Result<(Self, &[u8])> {
+        let (resource_type, rest) =
+            value.extract_u8_into(ConfigResourceType::new)?;
+        let (resource_name, rest) = rest.extract_flexible_string(flexible)?;
+        let (configuration_keys, rest) = rest
+            .extract_array_with(flexible, |v| {
+                v.extract_flexible_string(flexible)
+            })?;
+        let rest = rest.drop_tag_buffer(flexible)?;
+        Ok((
+            Self {
+                resource_type,
+                resource_name,
+                configuration_keys,
+            },
+            rest,
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DescribeConfigsResult {
+    error_code: ErrorCode,
+    error_message: Option<String>,
+    resource_type: ConfigResourceType,
+    resource_name: String,
+    configs: Vec<ConfigEntry>,
+}
+
+impl DescribeConfigsResult {
+    pub fn new(
+        resource: &DescribeConfigsResource,
+        configs: Vec<ConfigEntry>,
+    ) -> Self {
+        Self {
+            error_code: ErrorCode::NoError,
+            error_message: None,
+            resource_type: resource.resource_type,
+            resource_name: resource.resource_name.clone(),
+            configs,
+        }
+    }
+    pub fn error(
+        resource: &DescribeConfigsResource,
+        error_code: ErrorCode,
+        error_message: String,
+    ) -> Self {
+        Self {
+            error_code,
+            error_message: Some(error_message),
+            ..Self::new(resource, vec![])
+        }
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::DescribeConfigs.is_flexible(version);
+        let mut bytes = vec![];
+        bytes.put_i16(*self.error_code);
+        bytes.extend(self.error_message.to_nullable_string(flexible));
+        bytes.put_u8(*self.resource_type);
+        bytes.extend(match flexible {
+            true => self.resource_name.to_compact_string(),
+            false => self.resource_name.to_kafka_string(),
+        });
+        bytes.extend(array_length(flexible, self.configs.len()));
+        self.configs.into_iter().for_each(|c| bytes.extend(c.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+impl RequestBody {
+    pub(crate) fn describe_configs(
+        body: &[u8],
+        version: Version,
+    ) -> Result<Self> {
+        let flexible = ApiKey::DescribeConfigs.is_flexible(version);
+        let (resources, rest) = body.extract_array_with(flexible, |v| {
+            DescribeConfigsResource::extract(v, flexible)
+        })?;
+        let (include_synonyms, rest) = match version {
+            Version::V0 => (false, rest),
+            _ => rest.extract_u8().map_tuple(|v| v != 0)?,
+        };
+        let (include_documentation, _rest) = match version {
+            v if v >= Version::V3 => rest.extract_u8().map_tuple(|v| v != 0)?,
+            _ => (false, rest),
+        };
+        Ok(RequestBody::DescribeConfigs {
+            resources,
+            include_synonyms,
+            include_documentation,
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_describe_configs(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let allowed = cx.allowed(request);
+        let RequestBody::DescribeConfigs {
+            resources,
+            include_synonyms,
+            include_documentation,
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        let meta = Meta::load(cx.metadata_log)?;
+        Ok(ResponseBody::DescribeConfigs {
+            results: resources
+                .iter()
+                .map(|r| {
+                    match Self::config_authorization(
+                        &allowed,
+                        AclOperation::DESCRIBE_CONFIGS,
+                        *r.resource_type(),
+                        r.resource_name(),
+                    ) {
+                        Some(code) => DescribeConfigsResult::error(
+                            r,
+                            code,
+                            "Not authorized".to_string(),
+                        ),
+                        None => Self::describe_configs(
+                            &meta,
+                            r,
+                            *include_synonyms,
+                            *include_documentation,
+                        ),
+                    }
+                })
+                .collect(),
+            throttle_time: ThrottleTime::zero(),
+        })
+    }
+
+    pub(crate) fn error_describe_configs(
+        body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        let results = match body {
+            Some(RequestBody::DescribeConfigs {
+                resources,
+                ..
+            }) => resources
+                .iter()
+                .map(|r| {
+                    DescribeConfigsResult::error(
+                        r,
+                        error.error_code(),
+                        error.to_string(),
+                    )
+                })
+                .collect(),
+            _ => vec![],
+        };
+        ResponseBody::DescribeConfigs {
+            results,
+            throttle_time: ThrottleTime::zero(),
+        }
+    }
+
+    pub(crate) fn encode_describe_configs(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::DescribeConfigs {
+            results,
+            throttle_time,
+        } = body
+        else {
+            unreachable!("not a DescribeConfigs");
+        };
+        let (mut bytes, flexible) =
+            response_header(ApiKey::DescribeConfigs, version, correlation_id);
+        bytes.put_u32(*throttle_time);
+        bytes.extend(array_length(flexible, results.len()));
+        results.into_iter().for_each(|r| bytes.extend(r.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+
+    /// Topic configs resolve through the metadata log, server.properties
+    /// and the defaults, broker configs are the static ones.
+    fn describe_configs(
+        meta: &Meta,
+        resource: &DescribeConfigsResource,
+        include_synonyms: bool,
+        include_documentation: bool,
+    ) -> DescribeConfigsResult {
+        let topic_name = TopicName::new(resource.resource_name().clone());
+        let configs = match *resource.resource_type() {
+            TOPIC_RESOURCE if meta.find_topic_id(&topic_name).is_some() =>
+                describe_topic_configs(meta, &topic_name),
+            TOPIC_RESOURCE =>
+                return DescribeConfigsResult::error(
+                    resource,
+                    ErrorCode::UnknownTopicOrPartition,
+                    format!("Topic {} does not exist", *topic_name),
+                ),
+            BROKER_RESOURCE => describe_broker_configs(),
+            other =>
+                return DescribeConfigsResult::error(
+                    resource,
+                    ErrorCode::InvalidRequest,
+                    format!("Unsupported resource type {other}"),
+                ),
+        };
+        let keys = resource.configuration_keys();
+        let configs = configs
+            .into_iter()
+            .filter(|c| keys.is_empty() || keys.contains(&c.name))
+            .map(|c| ConfigEntry {
+                synonyms: match include_synonyms {
+                    true => c.synonyms,
+                    false => vec![],
+                },
+                documentation: c
+                    .documentation
+                    .filter(|_| include_documentation),
+                ..c
+            })
+            .collect();
+        DescribeConfigsResult::new(resource, configs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Authorizer, TestLogs};
+
+    #[test]
+    fn test_handle_describe_configs() -> Result<()> {
+        let logs = TestLogs::new("describe-configs");
+        // v0 for retention.ms of "baz" and every config of "nope", which
+        // does not exist
+        let (_, bytes) = logs.handle(
+            "0020 0000 00000007 0001 74 00000002 \
+             02 0003 62617a 00000001 000c 726574656e74696f6e2e6d73 \
+             02 0004 6e6f7065 ffffffff",
+            Authorizer::default(),
+        )?;
+        // v0 has is_default where later versions have config_source
+        assert_eq!(
+            hex::encode(bytes),
+            [
+                "0000005e 00000007 00000000 00000002",
+                "0000 ffff 02 0003 62617a 00000001",
+                "000c 726574656e74696f6e2e6d73",
+                "0009 363034383030303030 00 01 00",
+                "0003 0019 546f706963206e6f706520646f6573206e6f74206578697374",
+                "02 0004 6e6f7065 00000000",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        // the same as v4, without synonyms or documentation
+        let (_, bytes) = logs.handle(
+            "0020 0004 00000008 0001 74 00 03 \
+             02 04 62617a 02 0d 726574656e74696f6e2e6d73 00 \
+             02 05 6e6f7065 00 00 00 00 00",
+            Authorizer::default(),
+        )?;
+        // a default LONG config, with no synonyms and null documentation
+        assert_eq!(
+            hex::encode(bytes),
+            [
+                "00000057 00000008 00 00000000 03",
+                "0000 00 02 04 62617a 02",
+                "0d 726574656e74696f6e2e6d73 0a 363034383030303030",
+                "00 05 00 01 05 00 00 00",
+                "0003 1a 546f706963206e6f706520646f6573206e6f74206578697374",
+                "02 05 6e6f7065 01 00 00",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        Ok(())
+    }
+}
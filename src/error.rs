@@ -5,8 +5,8 @@ use std::sync::Arc;
 
 use thiserror::*;
 
-use crate::CorrelationId;
 use crate::Error::{ErrorWrapper, GeneralError};
+use crate::{CorrelationId, ErrorCode, RequestHeader};
 
 pub type Result<E> = std::result::Result<E, Error>;
 
@@ -16,8 +16,16 @@ pub enum Error {
     UnsupportedApiVersion(u16, Option<CorrelationId>),
     #[error("Unsupported Api Key {}", .0)]
     UnsupportedApiKey(u16, Option<CorrelationId>),
-    #[error("Unknown Topic or Partition {}", .0)]
-    UnknownTopicOrPartition(u16, Option<CorrelationId>),
+    #[error("Connection closed: {0}")]
+    ConnectionClosed(String),
+    #[error("Api Key {} needs SASL authentication first", .0)]
+    SaslAuthenticationRequired(u16),
+    #[error("Invalid receive (size = {0} larger than {1})")]
+    RequestTooLarge(u32, u32),
+    #[error("Too many connections from {0} (max = {1})")]
+    TooManyConnections(String, u32),
+    #[error("{}: {}", .0.name(), .1)]
+    Api(ErrorCode, String),
     #[error("Error Wrapper {}", .0)]
     ErrorWrapper(String, Arc<dyn std::error::Error + Send + Sync + 'static>),
     #[error("Failed to convert bytes to string: {0}")]
@@ -28,6 +36,14 @@ pub enum Error {
     TryFromInt(#[from] TryFromIntError),
     #[error("Unknown Batch Record Type {0}")]
     UnknownRecordType(u8),
+    #[error("Unknown Compression Codec {0}")]
+    UnknownCompression(u8),
+    #[error("Unsupported Magic Byte {0}")]
+    UnsupportedMagic(u8),
+    #[error("Corrupt Record at file offset {0}: {1}")]
+    CorruptRecord(u64, String),
+    #[error("Malformed {:?} request: {}", .0.api_key(), .1)]
+    MalformedRequest(Box<RequestHeader>, Arc<Error>),
     #[error("General Error {0}")]
     GeneralError(String),
 }
@@ -37,17 +53,60 @@ impl Error {
         match self {
             UnsupportedApiVersion(v, _) => UnsupportedApiVersion(*v, Some(id)),
             UnsupportedApiKey(v, _) => UnsupportedApiKey(*v, Some(id)),
-            UnknownTopicOrPartition(v, _) =>
-                UnknownTopicOrPartition(*v, Some(id)),
             _ => self.clone(),
         }
     }
     pub fn set_correlation_id(id: CorrelationId) -> impl FnOnce(Error) -> Self {
         move |e| e.with_correlation_id(id)
     }
+    /// The error code a client is answered with.
+    pub fn error_code(&self) -> ErrorCode {
+        use Error::*;
+        match self {
+            UnsupportedApiVersion(..) => ErrorCode::UnsupportedVersion,
+            UnsupportedApiKey(..) => ErrorCode::InvalidRequest,
+            SaslAuthenticationRequired(_) => ErrorCode::IllegalSaslState,
+            Api(error_code, _) => *error_code,
+            UnknownRecordType(_)
+            | UnknownCompression(_)
+            | UnsupportedMagic(_)
+            | CorruptRecord(..) => ErrorCode::CorruptMessage,
+            // records that do not parse are corrupt, a version the API is
+            // not served in is unsupported, anything else is an invalid
+            // request
+            MalformedRequest(_, e) => match e.error_code() {
+                code @ (ErrorCode::CorruptMessage
+                | ErrorCode::UnsupportedVersion) => code,
+                _ => ErrorCode::InvalidRequest,
+            },
+            Utf8ConversionError(_) | UuidError(_) | TryFromInt(_) =>
+                ErrorCode::InvalidRequest,
+            ConnectionClosed(_)
+            | RequestTooLarge(..)
+            | TooManyConnections(..)
+            | ErrorWrapper(..)
+            | GeneralError(_) => ErrorCode::UnknownServerError,
+        }
+    }
+    /// A Kafka error with the message Kafka gives it.
+    pub fn api(error_code: ErrorCode) -> Self {
+        Error::Api(error_code, error_code.message().to_string())
+    }
     pub fn general(v: &str) -> Self {
         GeneralError(v.to_string())
     }
+    /// A corrupt record found while parsing an entry, `at_file_offset`
+    /// fills in where the entry starts.
+    pub fn corrupt(reason: &str) -> Self {
+        Error::CorruptRecord(0, reason.to_string())
+    }
+    pub fn at_file_offset(position: u64) -> impl FnOnce(Error) -> Self {
+        move |e| match e {
+            Error::CorruptRecord(_, reason) =>
+                Error::CorruptRecord(position, reason),
+            e => Error::CorruptRecord(position, e.to_string()),
+        }
+    }
 }
 
 pub trait Context<T, E> {
@@ -1,37 +1,91 @@
+use std::time::Instant;
+
 use crate::{
-    Batch, BatchOffset, BytesOps, CurrentLeaderEpoch, ErrorCode, FetchOffset,
-    FirstOffset, HighWatermark, LastFetchEpoch, LastStableOffset,
-    LogStartOffset, MapTupleTwo, PartitionIndex, PartitionMaxBytes,
-    PreferredReadReplica, ProducerId, Result, TagBuffer, TopicId, TryExtract,
-    VarInt,
+    fetch_sessions, response_header, with_message_size, AclOperation,
+    AclResourceType, ApiKey, Authorizer, BytesOps, CorrelationId,
+    CurrentLeaderEpoch, Error, ErrorCode, FetchContext, FetchOffset,
+    FirstOffset, HandlerContext, HighWatermark, IsolationLevel, LastFetchEpoch,
+    LastStableOffset, Log, LogEntry, LogStartOffset, MagicByte, MapTupleTwo,
+    MaxBytes, MaxWait, Meta, MinBytes, PartitionIndex, PartitionMaxBytes,
+    PreferredReadReplica, ProducerId, RackId, RecordOffset, Request,
+    RequestBody, Response, ResponseBody, Result, SessionEpoch, SessionId,
+    TagBuffer, ThrottleTime, ToCompactString, ToKafkaString, TopicId,
+    TopicName, TryExtract, VarInt, Version, METADATA_LOG,
 };
 use bytes::BufMut;
 
 #[derive(Debug, Clone)]
 pub struct FetchTopic {
+    topic_name: Option<TopicName>,
     topic_id: TopicId,
     partitions: Vec<FetchPartition>,
 }
 
 impl FetchTopic {
+    pub fn new(
+        topic_name: Option<TopicName>,
+        topic_id: TopicId,
+        partitions: Vec<FetchPartition>,
+    ) -> Self {
+        Self {
+            topic_name,
+            topic_id,
+            partitions,
+        }
+    }
+    pub fn topic_name(&self) -> Option<&TopicName> {
+        self.topic_name.as_ref()
+    }
     pub fn topic_id(&self) -> TopicId {
-        self.topic_id.clone()
+        self.topic_id
     }
-}
-impl TryExtract for FetchTopic {
-    fn try_extract(value: &[u8]) -> Result<(Self, &[u8])> {
-        let (topic_id, rest) = value.drop(16).fmap_tuple(TopicId::mk)?;
-        let (partitions, rest) = rest.extract_array_into()?;
+    pub fn partitions(&self) -> &Vec<FetchPartition> {
+        &self.partitions
+    }
+    /// Topics are named before v13 and by id from then on.
+    pub fn extract(value: &[u8], version: Version) -> Result<(Self, &[u8])> {
+        let flexible = ApiKey::Fetch.is_flexible(version);
+        let (topic_name, topic_id, rest) = extract_topic(value, version)?;
+        let (partitions, rest) = rest.extract_array_with(flexible, |v| {
+            FetchPartition::extract(v, version)
+        })?;
         Ok((
             Self {
+                topic_name,
                 topic_id,
                 partitions,
             },
-            rest,
+            rest.drop_tag_buffer(flexible)?,
         ))
     }
 }
 
+/// In the latest layout.
+impl TryExtract for FetchTopic {
+    fn try_extract(value: &[u8]) -> Result<(Self, &[u8])> {
+        Self::extract(value, Version::V16)
+    }
+}
+
+fn extract_topic(
+    value: &[u8],
+    version: Version,
+) -> Result<(Option<TopicName>, TopicId, &[u8])> {
+    match version {
+        v if v >= Version::V13 => {
+            let (topic_id, rest) = value.drop(16).fmap_tuple(TopicId::mk)?;
+            Ok((None, topic_id, rest))
+        }
+        v => {
+            let (topic_name, rest) = match ApiKey::Fetch.is_flexible(v) {
+                true => value.extract_compact_str(),
+                false => value.extract_string(),
+            }?;
+            Ok((Some(TopicName::new(topic_name)), TopicId::zero(), rest))
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FetchPartition {
     partition_index: PartitionIndex,
@@ -42,17 +96,44 @@ pub struct FetchPartition {
     partition_max_bytes: PartitionMaxBytes,
 }
 
-impl TryExtract for FetchPartition {
-    fn try_extract(value: &[u8]) -> Result<(Self, &[u8])> {
+impl FetchPartition {
+    pub fn partition_index(&self) -> PartitionIndex {
+        self.partition_index
+    }
+    pub fn current_leader_epoch(&self) -> CurrentLeaderEpoch {
+        self.current_leader_epoch
+    }
+    pub fn fetch_offset(&self) -> FetchOffset {
+        self.fetch_offset
+    }
+    pub fn last_fetch_epoch(&self) -> LastFetchEpoch {
+        self.last_fetch_epoch
+    }
+    pub fn log_start_offset(&self) -> LogStartOffset {
+        self.log_start_offset
+    }
+    pub fn partition_max_bytes(&self) -> PartitionMaxBytes {
+        self.partition_max_bytes
+    }
+    pub fn extract(value: &[u8], version: Version) -> Result<(Self, &[u8])> {
         let (partition_index, rest) =
             value.extract_u32_into(PartitionIndex::new)?;
-        let (current_leader_epoch, rest) =
-            rest.extract_u32_into(CurrentLeaderEpoch::new)?;
+        let (current_leader_epoch, rest) = match version {
+            v if v >= Version::V9 =>
+                rest.extract_u32_into(CurrentLeaderEpoch::new)?,
+            _ => (CurrentLeaderEpoch::new(u32::MAX), rest),
+        };
         let (fetch_offset, rest) = rest.extract_u64_into(FetchOffset::new)?;
-        let (last_fetch_epoch, rest) =
-            rest.extract_u32_into(LastFetchEpoch::new)?;
-        let (log_start_offset, rest) =
-            rest.extract_u64_into(LogStartOffset::new)?;
+        let (last_fetch_epoch, rest) = match version {
+            v if v >= Version::V12 =>
+                rest.extract_u32_into(LastFetchEpoch::new)?,
+            _ => (LastFetchEpoch::new(u32::MAX), rest),
+        };
+        let (log_start_offset, rest) = match version {
+            v if v >= Version::V5 =>
+                rest.extract_u64_into(LogStartOffset::new)?,
+            _ => (LogStartOffset::new(u64::MAX), rest),
+        };
         let (partition_max_bytes, rest) =
             rest.extract_u32_into(PartitionMaxBytes::new)?;
         Ok((
@@ -64,63 +145,111 @@ impl TryExtract for FetchPartition {
                 log_start_offset,
                 partition_max_bytes,
             },
-            rest.drop(1).second()?,
+            rest.drop_tag_buffer(ApiKey::Fetch.is_flexible(version))?,
         ))
     }
 }
+
+/// Partitions an incremental fetch drops from its session, Fetch v7+.
 #[derive(Debug, Clone)]
-pub struct ForgottenTopicData(TopicId, Vec<PartitionIndex>);
+pub struct ForgottenTopicData {
+    topic_name: Option<TopicName>,
+    topic_id: TopicId,
+    partitions: Vec<PartitionIndex>,
+}
 
 impl ForgottenTopicData {
-    pub fn new(topic_id: TopicId, partitions: Vec<PartitionIndex>) -> Self {
-        Self(topic_id, partitions)
+    pub fn new(
+        topic_name: Option<TopicName>,
+        topic_id: TopicId,
+        partitions: Vec<PartitionIndex>,
+    ) -> Self {
+        Self {
+            topic_name,
+            topic_id,
+            partitions,
+        }
     }
-}
-
-impl TryExtract for ForgottenTopicData {
-    fn try_extract(value: &[u8]) -> Result<(Self, &[u8])> {
-        let (topic_id, rest) = value.drop(16).fmap_tuple(TopicId::mk)?;
-
-        let (partitions, rest) = rest.extract_array_into()?;
-        Ok((Self(topic_id, partitions), rest))
+    pub fn topic_name(&self) -> Option<&TopicName> {
+        self.topic_name.as_ref()
+    }
+    pub fn topic_id(&self) -> TopicId {
+        self.topic_id
+    }
+    pub fn partitions(&self) -> &Vec<PartitionIndex> {
+        &self.partitions
+    }
+    pub fn extract(value: &[u8], version: Version) -> Result<(Self, &[u8])> {
+        let flexible = ApiKey::Fetch.is_flexible(version);
+        let (topic_name, topic_id, rest) = extract_topic(value, version)?;
+        let (partitions, rest) =
+            rest.extract_array_with(flexible, PartitionIndex::try_extract)?;
+        Ok((
+            Self {
+                topic_name,
+                topic_id,
+                partitions,
+            },
+            rest.drop_tag_buffer(flexible)?,
+        ))
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct FetchResponse {
+    topic_name: TopicName,
     topic_id: TopicId,
     partitions: Vec<FetchPartitionResponse>,
 }
 
 impl FetchResponse {
     pub fn new(
+        topic_name: TopicName,
         topic_id: TopicId,
         partitions: Vec<FetchPartitionResponse>,
     ) -> Self {
         Self {
+            topic_name,
             topic_id,
             partitions,
         }
     }
-}
-impl From<FetchResponse> for Vec<u8> {
-    fn from(value: FetchResponse) -> Self {
+    pub fn topic_name(&self) -> &TopicName {
+        &self.topic_name
+    }
+    pub fn topic_id(&self) -> TopicId {
+        self.topic_id
+    }
+    pub fn partitions(&self) -> &Vec<FetchPartitionResponse> {
+        &self.partitions
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::Fetch.is_flexible(version);
         let mut bytes = vec![];
-        bytes.put_slice((*value.topic_id).as_bytes());
-        bytes.extend(VarInt::encode((value.partitions.len() + 1) as u64));
-        let partitions: Vec<u8> = value
-            .partitions
+        match version {
+            v if v >= Version::V13 => bytes.put_slice(self.topic_id.as_bytes()),
+            _ if flexible => bytes.extend(self.topic_name.to_compact_string()),
+            _ => bytes.extend(self.topic_name.to_kafka_string()),
+        }
+        bytes.extend(array_length(flexible, self.partitions.len()));
+        self.partitions
             .into_iter()
-            .flat_map::<Vec<u8>, _>(|e| e.into())
-            .collect();
-        bytes.put_slice(&partitions);
-        bytes.put_u8(*TagBuffer::zero());
-        bytes.put_u8(*TagBuffer::zero());
-
+            .for_each(|p| bytes.extend(p.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
         bytes
     }
 }
 
+pub(crate) fn array_length(compact: bool, length: usize) -> Vec<u8> {
+    if compact {
+        VarInt::encode((length + 1) as u64)
+    } else {
+        (length as u32).to_be_bytes().to_vec()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FetchPartitionResponse {
     partition_index: PartitionIndex,
@@ -130,66 +259,149 @@ pub struct FetchPartitionResponse {
     log_start_offset: LogStartOffset,
     aborted_transactions: Vec<AbortedTransaction>,
     preferred_read_replica: PreferredReadReplica,
-    batches: Vec<Batch>,
+    records: Vec<LogEntry>,
 }
 
-impl From<FetchPartitionResponse> for Vec<u8> {
-    fn from(value: FetchPartitionResponse) -> Self {
-        let mut bytes = vec![];
-        bytes.put_u32(*value.partition_index);
-        bytes.put_i16(*value.error_code);
-        bytes.put_u64(*value.high_watermark);
-        bytes.put_u64(*value.last_stable_offset);
-        bytes.put_u64(*value.log_start_offset);
-        bytes.extend(&VarInt::encode(
-            (value.aborted_transactions.len() + 1) as u64,
-        ));
-        let aborted: Vec<u8> = value
-            .aborted_transactions
-            .into_iter()
-            .flat_map::<Vec<u8>, _>(|e| e.into())
-            .collect();
-        bytes.extend(&aborted);
-        bytes.put_u32(*value.preferred_read_replica);
-        let batches: Vec<u8> = value
-            .batches
-            .into_iter()
-            .enumerate()
-            .map::<Vec<u8>, _>(|(i, b)| {
-                b.set_offset(BatchOffset::new(i as u64)).into()
-            })
-            .flatten()
-            .collect();
-        bytes.extend(VarInt::encode(batches.len() as u64));
-        bytes.extend(batches);
-        bytes.put_u8(*TagBuffer::zero());
-        bytes
-    }
-}
 impl FetchPartitionResponse {
-    pub fn new(partition_index: PartitionIndex, batches: Vec<Batch>) -> Self {
+    pub fn new(
+        partition_index: PartitionIndex,
+        records: Vec<LogEntry>,
+        log_start_offset: LogStartOffset,
+    ) -> Self {
+        let high_watermark = records
+            .last()
+            .map(|v| *v.last_offset() + 1)
+            .unwrap_or(0)
+            .max(*log_start_offset);
         Self {
             partition_index,
             error_code: ErrorCode::NoError,
-            high_watermark: HighWatermark::new(0),
-            last_stable_offset: LastStableOffset::new(0),
-            log_start_offset: LogStartOffset::new(0),
+            high_watermark: HighWatermark::new(high_watermark),
+            last_stable_offset: LastStableOffset::new(high_watermark),
+            log_start_offset,
             aborted_transactions: vec![],
-            preferred_read_replica: PreferredReadReplica::new(0),
-            batches,
+            preferred_read_replica: PreferredReadReplica::new(u32::MAX),
+            records,
         }
     }
+    /// The batches of a partition from the one holding `fetch_offset`,
+    /// down-converted for consumers that need legacy message sets. Batches
+    /// are added up to `max_bytes`, the first one even if larger so a
+    /// consumer always makes progress. An offset before the log start or
+    /// past the end is out of range.
+    pub fn read(
+        partition_index: PartitionIndex,
+        log: &Log,
+        fetch_offset: FetchOffset,
+        magic: Option<MagicByte>,
+        max_bytes: usize,
+    ) -> Self {
+        if *fetch_offset < *log.log_start_offset()
+            || *fetch_offset > *log.next_offset()
+        {
+            return Self {
+                high_watermark: HighWatermark::new(*log.next_offset()),
+                last_stable_offset: LastStableOffset::new(*log.next_offset()),
+                log_start_offset: log.log_start_offset(),
+                ..Self::error(partition_index, ErrorCode::OffsetOutOfRange)
+            };
+        }
+        let entries = match magic {
+            Some(magic) => log.down_convert(magic),
+            None => log.entries().clone(),
+        };
+        let mut records = vec![];
+        let mut size = 0;
+        for entry in entries {
+            if *entry.last_offset() < *fetch_offset {
+                continue;
+            }
+            if max_bytes == 0 || size > 0 && size + entry.size() > max_bytes {
+                break;
+            }
+            size += entry.size();
+            records.push(entry);
+        }
+        Self {
+            high_watermark: HighWatermark::new(*log.next_offset()),
+            last_stable_offset: LastStableOffset::new(*log.next_offset()),
+            ..Self::new(partition_index, records, log.log_start_offset())
+        }
+    }
+    /// A partition of a topic id the broker does not know.
     pub fn unknown(partition_index: PartitionIndex) -> Self {
         Self {
             partition_index,
-            error_code: ErrorCode::UnknownTopic,
+            error_code: ErrorCode::UnknownTopicId,
             high_watermark: HighWatermark::new(0),
             last_stable_offset: LastStableOffset::new(0),
             log_start_offset: LogStartOffset::new(0),
             aborted_transactions: vec![],
-            preferred_read_replica: PreferredReadReplica::new(0),
-            batches: vec![],
+            preferred_read_replica: PreferredReadReplica::new(u32::MAX),
+            records: vec![],
+        }
+    }
+    pub fn error(
+        partition_index: PartitionIndex,
+        error_code: ErrorCode,
+    ) -> Self {
+        Self {
+            error_code,
+            ..Self::unknown(partition_index)
+        }
+    }
+    pub fn partition_index(&self) -> PartitionIndex {
+        self.partition_index
+    }
+    pub fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+    pub fn high_watermark(&self) -> HighWatermark {
+        self.high_watermark
+    }
+    pub fn log_start_offset(&self) -> LogStartOffset {
+        self.log_start_offset
+    }
+    /// Size of the records on the wire, as they are on disk.
+    pub fn records_size(&self) -> usize {
+        self.records.iter().map(LogEntry::size).sum()
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::Fetch.is_flexible(version);
+        let mut bytes = vec![];
+        bytes.put_u32(*self.partition_index);
+        bytes.put_i16(*self.error_code);
+        bytes.put_u64(*self.high_watermark);
+        if version >= Version::V4 {
+            bytes.put_u64(*self.last_stable_offset);
+        }
+        if version >= Version::V5 {
+            bytes.put_u64(*self.log_start_offset);
+        }
+        if version >= Version::V4 {
+            bytes.extend(array_length(
+                flexible,
+                self.aborted_transactions.len(),
+            ));
+            self.aborted_transactions
+                .into_iter()
+                .for_each(|v| bytes.extend(Vec::<u8>::from(v)));
+        }
+        if version >= Version::V11 {
+            bytes.put_u32(*self.preferred_read_replica);
+        }
+        let records: Vec<u8> =
+            self.records.into_iter().flat_map(Vec::<u8>::from).collect();
+        if flexible {
+            bytes.extend(VarInt::encode((records.len() + 1) as u64));
+        } else {
+            bytes.put_u32(records.len() as u32);
         }
+        bytes.extend(records);
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
     }
 }
 
@@ -207,3 +419,364 @@ impl From<AbortedTransaction> for Vec<u8> {
         bytes
     }
 }
+
+impl RequestBody {
+    pub(crate) fn fetch(body: &[u8], version: Version) -> Result<Self> {
+        let flexible = ApiKey::Fetch.is_flexible(version);
+        // v15 moved the replica id into a tagged field
+        let rest = match version {
+            v if v < Version::V15 => body.drop(4).second()?,
+            _ => body,
+        };
+        let (max_wait, rest) = rest.extract_u32_into(MaxWait::new)?;
+        let (min_bytes, rest) = rest.extract_u32_into(MinBytes::new)?;
+        let (max_bytes, rest) = match version {
+            v if v >= Version::V3 => rest.extract_u32_into(MaxBytes::new)?,
+            _ => (MaxBytes::new(i32::MAX as u32), rest),
+        };
+        let (isolation_level, rest) = match version {
+            v if v >= Version::V4 =>
+                rest.extract_u8_into(IsolationLevel::new)?,
+            _ => (IsolationLevel::new(0), rest),
+        };
+        let (session_id, session_epoch, rest) = match version {
+            v if v >= Version::V7 => {
+                let (session_id, rest) =
+                    rest.extract_u32_into(SessionId::new)?;
+                let (session_epoch, rest) =
+                    rest.extract_u32_into(SessionEpoch::new)?;
+                (session_id, session_epoch, rest)
+            }
+            _ => (SessionId::new(0), SessionEpoch::FINAL, rest),
+        };
+        let (topics, rest) = rest.extract_array_with(flexible, |v| {
+            FetchTopic::extract(v, version)
+        })?;
+        let (forgotten_topics_data, rest) = match version {
+            v if v >= Version::V7 => rest
+                .extract_array_with(flexible, |v| {
+                    ForgottenTopicData::extract(v, version)
+                })?,
+            _ => (vec![], rest),
+        };
+        let (rack_id, _rest) = match version {
+            v if v >= Version::V11 && flexible =>
+                rest.extract_compact_str().map_tuple(RackId::new)?,
+            v if v >= Version::V11 =>
+                rest.extract_string().map_tuple(RackId::new)?,
+            _ => (RackId::new(String::new()), rest),
+        };
+        Ok(RequestBody::Fetch {
+            max_wait,
+            min_bytes,
+            max_bytes,
+            isolation_level,
+            session_id,
+            session_epoch,
+            topics,
+            forgotten_topics_data,
+            rack_id,
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_fetch(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let RequestBody::Fetch {
+            session_id,
+            session_epoch,
+            topics,
+            forgotten_topics_data,
+            ..
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        match fetch_sessions().context(
+            *session_id,
+            *session_epoch,
+            topics,
+            forgotten_topics_data,
+            Instant::now(),
+        ) {
+            Ok(context) =>
+                Self::fetch(request, context, &cx.topic_allowed(request)),
+            Err(error_code) => Ok(ResponseBody::Fetch {
+                throttle_time: ThrottleTime::zero(),
+                error_code,
+                session_id: SessionId::new(0),
+                responses: vec![],
+                context: None,
+                fetched: vec![],
+            }),
+        }
+    }
+
+    /// Before v7 only the partitions carry the error.
+    pub(crate) fn error_fetch(
+        body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        let responses = match body {
+            Some(RequestBody::Fetch {
+                topics,
+                ..
+            }) => topics
+                .iter()
+                .map(|t| {
+                    FetchResponse::new(
+                        t.topic_name().cloned().unwrap_or(TopicName::from("")),
+                        t.topic_id(),
+                        t.partitions()
+                            .iter()
+                            .map(|p| {
+                                FetchPartitionResponse::error(
+                                    p.partition_index(),
+                                    error.error_code(),
+                                )
+                            })
+                            .collect(),
+                    )
+                })
+                .collect(),
+            _ => vec![],
+        };
+        ResponseBody::Fetch {
+            throttle_time: ThrottleTime::zero(),
+            error_code: error.error_code(),
+            session_id: SessionId::new(0),
+            responses,
+            context: None,
+            fetched: vec![],
+        }
+    }
+
+    pub(crate) fn encode_fetch(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::Fetch {
+            throttle_time,
+            error_code,
+            session_id,
+            responses,
+            ..
+        } = body
+        else {
+            unreachable!("not a Fetch");
+        };
+        let (mut bytes, flexible) =
+            response_header(ApiKey::Fetch, version, correlation_id);
+        if version >= Version::V1 {
+            bytes.put_u32(*throttle_time);
+        }
+        if version >= Version::V7 {
+            bytes.put_i16(*error_code);
+            bytes.put_u32(*session_id);
+        }
+        bytes.extend(array_length(flexible, responses.len()));
+        responses.into_iter().for_each(|r| bytes.extend(r.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+
+    /// Fetches again for the purgatory, with the context the Fetch got
+    /// when it came in. Not metered, only the response sent is.
+    pub fn refetch(request: &Request, context: FetchContext) -> Result<Self> {
+        let authorizer = Authorizer::load(METADATA_LOG);
+        let topic_allowed = |operation, topic_name: &TopicName| {
+            authorizer.authorize(
+                &request.header,
+                operation,
+                AclResourceType::TOPIC,
+                topic_name,
+            )
+        };
+        Ok(Response::new(
+            request.header.correlation_id(),
+            request.header.api_version(),
+            Self::fetch(request, context, &topic_allowed)?,
+        ))
+    }
+
+    /// Reads the partitions of a fetch context, an incremental fetch
+    /// only answers for those with news.
+    fn fetch(
+        request: &Request,
+        context: FetchContext,
+        topic_allowed: &impl Fn(u8, &TopicName) -> bool,
+    ) -> Result<ResponseBody> {
+        let version = request.header.api_version();
+        let meta = Meta::load(METADATA_LOG)?;
+        // Consumers older than Fetch v4 only understand legacy message
+        // sets, v2 and v3 with timestamps.
+        let magic = match version {
+            Version::V0 | Version::V1 => Some(MagicByte::new(0)),
+            Version::V2 | Version::V3 => Some(MagicByte::new(1)),
+            _ => None,
+        };
+        let RequestBody::Fetch {
+            max_bytes,
+            ..
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        // what is left of the response's max_bytes for the partitions after
+        let mut remaining = **max_bytes as usize;
+        let responses: Vec<FetchResponse> = context
+            .topics()
+            .iter()
+            .map(|t| {
+                let topic_id = t
+                    .topic_name()
+                    .and_then(|n| meta.find_topic_id(n))
+                    .unwrap_or(t.topic_id());
+                let topic_name = t
+                    .topic_name()
+                    .cloned()
+                    .or_else(|| meta.find_topic_name(&topic_id))
+                    .unwrap_or_else(|| TopicName::from(""));
+                let authorized = topic_allowed(AclOperation::READ, &topic_name);
+                let partitions = t
+                    .partitions()
+                    .iter()
+                    .map(|p| {
+                        let index = p.partition_index();
+                        let offset = RecordOffset::new(*p.fetch_offset());
+                        let topic_log = meta
+                            .find_log(&topic_id, index, offset)
+                            .ok()
+                            .flatten();
+                        // topics are named before v13, an unknown id is
+                        // not an unknown partition of a known topic
+                        match (topic_log, t.topic_name()) {
+                            _ if !authorized => FetchPartitionResponse::error(
+                                index,
+                                ErrorCode::TopicAuthorizationFailed,
+                            ),
+                            (None, None)
+                                if meta
+                                    .find_topic_name(&topic_id)
+                                    .is_none() =>
+                                FetchPartitionResponse::unknown(index),
+                            (None, _) => FetchPartitionResponse::error(
+                                index,
+                                ErrorCode::UnknownTopicOrPartition,
+                            ),
+                            (Some(log), _) => {
+                                let response = FetchPartitionResponse::read(
+                                    index,
+                                    &log,
+                                    p.fetch_offset(),
+                                    magic,
+                                    remaining
+                                        .min(*p.partition_max_bytes() as usize),
+                                );
+                                remaining = remaining
+                                    .saturating_sub(response.records_size());
+                                response
+                            }
+                        }
+                    })
+                    .collect();
+                FetchResponse::new(topic_name, topic_id, partitions)
+            })
+            .collect();
+        let fetched = responses
+            .iter()
+            .flat_map(|t| {
+                t.partitions()
+                    .iter()
+                    .map(|p| (t.topic_name().clone(), p.partition_index()))
+            })
+            .collect();
+        Ok(ResponseBody::Fetch {
+            throttle_time: ThrottleTime::zero(),
+            error_code: ErrorCode::NoError,
+            session_id: context.session_id(),
+            responses: fetch_sessions().changed(&context, responses),
+            context: Some(context),
+            fetched,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Message, MessageAttributes, RecordOffset};
+
+    fn log(offsets: &[u64], log_start_offset: u64) -> Result<Log> {
+        let bytes = offsets
+            .iter()
+            .flat_map(|v| {
+                Vec::<u8>::from(Message::new(
+                    RecordOffset::new(*v),
+                    MagicByte::new(0),
+                    MessageAttributes::new(0),
+                    None,
+                    None,
+                    Some(b"value".to_vec()),
+                ))
+            })
+            .collect();
+        Ok(Log::new(
+            LogEntry::split(bytes)?,
+            LogStartOffset::new(log_start_offset),
+        ))
+    }
+
+    #[test]
+    fn test_read_out_of_range() -> Result<()> {
+        let log = log(&[2, 3, 4], 3)?;
+        let read = |offset| {
+            FetchPartitionResponse::read(
+                PartitionIndex::new(0),
+                &log,
+                FetchOffset::new(offset),
+                None,
+                usize::MAX,
+            )
+        };
+        for offset in [2, 6] {
+            let response = read(offset);
+            assert_eq!(response.error_code(), ErrorCode::OffsetOutOfRange);
+            assert_eq!(*response.log_start_offset(), 3);
+            assert_eq!(*response.high_watermark(), 5);
+        }
+        assert_eq!(read(3).error_code(), ErrorCode::NoError);
+        // at the end there is nothing to read yet
+        assert_eq!(read(5).error_code(), ErrorCode::NoError);
+        assert_eq!(read(5).records_size(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_max_bytes() -> Result<()> {
+        let log = log(&[0, 1, 2], 0)?;
+        let size = log.entries()[0].size();
+        let read = |max_bytes| {
+            FetchPartitionResponse::read(
+                PartitionIndex::new(0),
+                &log,
+                FetchOffset::new(1),
+                None,
+                max_bytes,
+            )
+            .records_size()
+        };
+        assert_eq!(read(2 * size + 1), 2 * size);
+        assert_eq!(read(2 * size - 1), size);
+        // the first batch even if larger, so the consumer moves on
+        assert_eq!(read(1), size);
+        assert_eq!(read(0), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::{
+    broker_config, ErrorCode, FetchPartition, FetchPartitionResponse,
+    FetchResponse, FetchTopic, ForgottenTopicData, HighWatermark,
+    LogStartOffset, PartitionIndex, SessionEpoch, SessionId, TopicId,
+    TopicName,
+};
+
+/// Kafka's `max.incremental.fetch.session.cache.slots` default.
+const CACHE_SLOTS: usize = 1000;
+/// Kafka's `min.incremental.fetch.session.eviction.ms` default.
+const EVICTION_MS: u64 = 120_000;
+
+fn config(name: &str, default: u64) -> u64 {
+    broker_config().get(name).and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// A partition of a session, with what the client last heard of it.
+#[derive(Debug, Clone)]
+struct CachedPartition {
+    topic_name: Option<TopicName>,
+    topic_id: TopicId,
+    partition: FetchPartition,
+    high_watermark: Option<HighWatermark>,
+    log_start_offset: Option<LogStartOffset>,
+}
+
+impl CachedPartition {
+    /// Topics are named before Fetch v13 and by id after.
+    fn is(
+        &self,
+        topic_name: Option<&TopicName>,
+        topic_id: TopicId,
+        index: PartitionIndex,
+    ) -> bool {
+        self.partition.partition_index() == index
+            && match (&self.topic_name, topic_name) {
+                (Some(name), Some(other)) => name == other,
+                _ => self.topic_id == topic_id,
+            }
+    }
+    /// Unchanged partitions are left out of incremental responses.
+    fn changed(&self, response: &FetchPartitionResponse) -> bool {
+        response.records_size() > 0
+            || response.error_code() != ErrorCode::NoError
+            || self.high_watermark != Some(response.high_watermark())
+            || self.log_start_offset != Some(response.log_start_offset())
+    }
+}
+
+#[derive(Debug)]
+struct FetchSession {
+    partitions: Vec<CachedPartition>,
+    // the epoch of the next incremental fetch
+    epoch: SessionEpoch,
+    last_used: Instant,
+}
+
+impl FetchSession {
+    fn update(
+        &mut self,
+        topics: &[FetchTopic],
+        forgotten: &[ForgottenTopicData],
+    ) {
+        topics.iter().for_each(|t| {
+            t.partitions().iter().for_each(|p| {
+                let index = p.partition_index();
+                match self
+                    .partitions
+                    .iter_mut()
+                    .find(|c| c.is(t.topic_name(), t.topic_id(), index))
+                {
+                    Some(cached) => cached.partition = p.clone(),
+                    None => self.partitions.push(CachedPartition {
+                        topic_name: t.topic_name().cloned(),
+                        topic_id: t.topic_id(),
+                        partition: p.clone(),
+                        high_watermark: None,
+                        log_start_offset: None,
+                    }),
+                }
+            })
+        });
+        forgotten.iter().for_each(|f| {
+            self.partitions.retain(|c| {
+                !f.partitions()
+                    .iter()
+                    .any(|p| c.is(f.topic_name(), f.topic_id(), *p))
+            })
+        });
+    }
+    /// Every partition of the session, grouped by topic again.
+    fn topics(&self) -> Vec<FetchTopic> {
+        let mut topics: Vec<FetchTopic> = vec![];
+        self.partitions.iter().for_each(|c| {
+            let position = topics.iter().position(|t| {
+                t.topic_name() == c.topic_name.as_ref()
+                    && t.topic_id() == c.topic_id
+            });
+            match position {
+                Some(i) => {
+                    let t = &topics[i];
+                    let mut partitions = t.partitions().clone();
+                    partitions.push(c.partition.clone());
+                    topics[i] = FetchTopic::new(
+                        t.topic_name().cloned(),
+                        t.topic_id(),
+                        partitions,
+                    );
+                }
+                None => topics.push(FetchTopic::new(
+                    c.topic_name.clone(),
+                    c.topic_id,
+                    vec![c.partition.clone()],
+                )),
+            }
+        });
+        topics
+    }
+}
+
+/// How a Fetch is served: the session it belongs to, if any, and the
+/// partitions to read, for an incremental fetch all of its session.
+#[derive(Debug, Clone)]
+pub struct FetchContext {
+    session_id: SessionId,
+    incremental: bool,
+    topics: Vec<FetchTopic>,
+}
+
+impl FetchContext {
+    fn sessionless(topics: &[FetchTopic]) -> Self {
+        Self {
+            session_id: SessionId::new(0),
+            incremental: false,
+            topics: topics.to_vec(),
+        }
+    }
+    /// Zero without a session.
+    pub fn session_id(&self) -> SessionId {
+        self.session_id
+    }
+    pub fn topics(&self) -> &Vec<FetchTopic> {
+        &self.topics
+    }
+}
+
+/// Incremental fetch sessions (KIP-227), so a consumer only lists the
+/// partitions that changed since its last fetch and hears only of those
+/// with news.
+#[derive(Debug, Default)]
+pub struct FetchSessionCache {
+    sessions: Mutex<HashMap<u32, FetchSession>>,
+}
+
+static FETCH_SESSIONS: OnceLock<FetchSessionCache> = OnceLock::new();
+
+pub fn fetch_sessions() -> &'static FetchSessionCache {
+    FETCH_SESSIONS.get_or_init(FetchSessionCache::default)
+}
+
+impl FetchSessionCache {
+    /// The context of a Fetch. The initial epoch starts a session, or a
+    /// sessionless fetch when the cache is full, the final epoch closes
+    /// one and other epochs fetch incrementally from the session, which
+    /// must expect that epoch.
+    pub fn context(
+        &self,
+        session_id: SessionId,
+        epoch: SessionEpoch,
+        topics: &[FetchTopic],
+        forgotten: &[ForgottenTopicData],
+        now: Instant,
+    ) -> std::result::Result<FetchContext, ErrorCode> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if epoch == SessionEpoch::INITIAL || epoch == SessionEpoch::FINAL {
+            sessions.remove(&*session_id);
+        }
+        if epoch == SessionEpoch::FINAL {
+            return Ok(FetchContext::sessionless(topics));
+        }
+        if epoch == SessionEpoch::INITIAL {
+            return match Self::evict(&mut sessions, now) {
+                true => {
+                    let id = Self::new_id(&sessions);
+                    let mut session = FetchSession {
+                        partitions: vec![],
+                        epoch: epoch.next(),
+                        last_used: now,
+                    };
+                    session.update(topics, forgotten);
+                    sessions.insert(id, session);
+                    Ok(FetchContext {
+                        session_id: SessionId::new(id),
+                        ..FetchContext::sessionless(topics)
+                    })
+                }
+                false => Ok(FetchContext::sessionless(topics)),
+            };
+        }
+        let session = sessions
+            .get_mut(&*session_id)
+            .ok_or(ErrorCode::FetchSessionIdNotFound)?;
+        if session.epoch != epoch {
+            return Err(ErrorCode::InvalidFetchSessionEpoch);
+        }
+        session.update(topics, forgotten);
+        session.epoch = epoch.next();
+        session.last_used = now;
+        Ok(FetchContext {
+            session_id,
+            incremental: true,
+            topics: session.topics(),
+        })
+    }
+    /// Sessions held, for the metrics.
+    pub fn session_count(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+    /// Whether a session is still cached.
+    pub fn contains(&self, session_id: SessionId) -> bool {
+        self.sessions.lock().unwrap().contains_key(&*session_id)
+    }
+    /// Leaves the partitions without news out of an incremental response.
+    pub fn changed(
+        &self,
+        context: &FetchContext,
+        responses: Vec<FetchResponse>,
+    ) -> Vec<FetchResponse> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = match sessions.get(&*context.session_id) {
+            Some(session) if context.incremental => session,
+            _ => return responses,
+        };
+        responses
+            .into_iter()
+            .map(|r| {
+                let partitions = r
+                    .partitions()
+                    .iter()
+                    .filter(|p| {
+                        session
+                            .partitions
+                            .iter()
+                            .find(|c| {
+                                c.is(
+                                    Some(r.topic_name()),
+                                    r.topic_id(),
+                                    p.partition_index(),
+                                )
+                            })
+                            .map_or(true, |c| c.changed(p))
+                    })
+                    .cloned()
+                    .collect();
+                FetchResponse::new(
+                    r.topic_name().clone(),
+                    r.topic_id(),
+                    partitions,
+                )
+            })
+            .filter(|r| !r.partitions().is_empty())
+            .collect()
+    }
+    /// Remembers what a sent response told the client of its partitions.
+    pub fn sent(&self, context: &FetchContext, responses: &[FetchResponse]) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let Some(session) = sessions.get_mut(&*context.session_id) else {
+            return;
+        };
+        responses.iter().for_each(|r| {
+            r.partitions().iter().for_each(|p| {
+                if let Some(cached) = session.partitions.iter_mut().find(|c| {
+                    c.is(
+                        Some(r.topic_name()),
+                        r.topic_id(),
+                        p.partition_index(),
+                    )
+                }) {
+                    cached.high_watermark = Some(p.high_watermark());
+                    cached.log_start_offset = Some(p.log_start_offset());
+                }
+            })
+        });
+    }
+    /// Makes room for a new session. A full cache evicts its least
+    /// recently used session once it has been idle long enough.
+    fn evict(sessions: &mut HashMap<u32, FetchSession>, now: Instant) -> bool {
+        let slots = config(
+            "max.incremental.fetch.session.cache.slots",
+            CACHE_SLOTS as u64,
+        ) as usize;
+        if sessions.len() < slots {
+            return true;
+        }
+        let eviction = Duration::from_millis(config(
+            "min.incremental.fetch.session.eviction.ms",
+            EVICTION_MS,
+        ));
+        let oldest = sessions
+            .iter()
+            .min_by_key(|(_, s)| s.last_used)
+            .filter(|(_, s)| now.duration_since(s.last_used) >= eviction)
+            .map(|(id, _)| *id);
+        match oldest {
+            Some(id) => {
+                sessions.remove(&id);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Random, so ids of a restarted broker are not taken for old ones.
+    fn new_id(sessions: &HashMap<u32, FetchSession>) -> u32 {
+        loop {
+            let id = Uuid::new_v4().as_u128() as u32 & i32::MAX as u32;
+            if id != 0 && !sessions.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Version;
+    use bytes::BufMut;
+
+    fn topic(id: u8, partitions: &[u32]) -> FetchTopic {
+        FetchTopic::new(
+            None,
+            TopicId::new(Uuid::from_bytes([id; 16])),
+            partitions
+                .iter()
+                .map(|p| {
+                    let mut bytes = vec![];
+                    bytes.put_u32(*p);
+                    bytes.put_i32(-1);
+                    bytes.put_u64(0);
+                    bytes.put_i32(-1);
+                    bytes.put_i64(-1);
+                    bytes.put_u32(1024);
+                    bytes.put_u8(0);
+                    FetchPartition::extract(&bytes, Version::V16).unwrap().0
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_fetch_session() {
+        let cache = FetchSessionCache::default();
+        let now = Instant::now();
+        let full = cache
+            .context(
+                SessionId::new(0),
+                SessionEpoch::INITIAL,
+                &[topic(1, &[0, 1])],
+                &[],
+                now,
+            )
+            .unwrap();
+        assert_ne!(*full.session_id(), 0);
+        // an incremental fetch adds a topic and forgets a partition
+        let incremental = cache
+            .context(
+                full.session_id(),
+                SessionEpoch::new(1),
+                &[topic(2, &[0])],
+                &[ForgottenTopicData::new(
+                    None,
+                    TopicId::new(Uuid::from_bytes([1; 16])),
+                    vec![PartitionIndex::new(1)],
+                )],
+                now,
+            )
+            .unwrap();
+        let partitions: usize =
+            incremental.topics().iter().map(|t| t.partitions().len()).sum();
+        assert_eq!(partitions, 2);
+        assert_eq!(
+            cache
+                .context(full.session_id(), SessionEpoch::new(1), &[], &[], now)
+                .unwrap_err(),
+            ErrorCode::InvalidFetchSessionEpoch
+        );
+        assert_eq!(
+            cache
+                .context(SessionId::new(7), SessionEpoch::new(1), &[], &[], now)
+                .unwrap_err(),
+            ErrorCode::FetchSessionIdNotFound
+        );
+        cache
+            .context(full.session_id(), SessionEpoch::FINAL, &[], &[], now)
+            .unwrap();
+        assert!(cache.sessions.lock().unwrap().is_empty());
+    }
+}
@@ -1,9 +1,35 @@
 use crate::{Context, Result};
-use std::fs::File;
-use std::io::Read;
+use std::fs::{create_dir_all, read_dir, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
 pub fn read(path: &str) -> Result<Vec<u8>> {
     let mut file = File::open(path).context("Open file {path}")?; // Open the file
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).context("Reading file")?; // Read file contents into buffer
     Ok(buffer)
 }
+pub fn append(path: &str, bytes: &[u8]) -> Result<()> {
+    if let Some(dir) = Path::new(path).parent() {
+        create_dir_all(dir).context("Creating log directory")?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context("Open file {path}")?
+        .write_all(bytes)
+        .context("Appending to file")
+}
+/// Entry names of a directory, none if it does not exist.
+pub fn read_dir_names(path: &str) -> Result<Vec<String>> {
+    if !Path::new(path).is_dir() {
+        return Ok(vec![]);
+    }
+    read_dir(path)
+        .context("Listing directory")?
+        .map(|v| {
+            v.context("Listing directory")
+                .map(|v| v.file_name().to_string_lossy().to_string())
+        })
+        .collect()
+}
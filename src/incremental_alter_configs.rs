@@ -0,0 +1,476 @@
+use crate::{
+    array_length, response_header, topic_config_values, with_message_size,
+    AclOperation, ApiKey, BytesOps, ConfigDef, ConfigRecordValue,
+    ConfigResourceType, ConfigType, CorrelationId, Error, ErrorCode,
+    FrameVersion, HandlerContext, MapTupleTwo, Meta, RecordValue, Request,
+    RequestBody, Response, ResponseBody, Result, TagBuffer, ThrottleTime,
+    ToCompactString, ToKafkaString, ToNullableString, TopicName, ValueVersion,
+    Version, BROKER_RESOURCE, TOPIC_RESOURCE,
+};
+use bytes::BufMut;
+use newtype_macro::newtype;
+use std::collections::HashMap;
+
+#[newtype]
+pub struct ConfigOperation(u8);
+
+impl ConfigOperation {
+    pub const SET: u8 = 0;
+    pub const DELETE: u8 = 1;
+    pub const APPEND: u8 = 2;
+    pub const SUBTRACT: u8 = 3;
+}
+
+#[derive(Debug, Clone)]
+pub struct AlterableConfig {
+    name: String,
+    config_operation: ConfigOperation,
+    value: Option<String>,
+}
+
+impl AlterableConfig {
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+    pub fn config_operation(&self) -> ConfigOperation {
+        self.config_operation
+    }
+    pub fn value(&self) -> Option<&String> {
+        self.value.as_ref()
+    }
+    fn extract(value: &[u8], flexible: bool) -> Result<(Self, &[u8])> {
+        let (name, rest) = match flexible {
+            true => value.extract_compact_str(),
+            false => value.extract_string(),
+        }?;
+        let (config_operation, rest) =
+            rest.extract_u8_into(ConfigOperation::new)?;
+        let (value, rest) = rest.extract_nullable_string(flexible)?;
+        let rest = match flexible {
+            true => rest.drop(1).second()?,
+            false => rest,
+        };
+        Ok((
+            Self {
+                name,
+                config_operation,
+                value,
+            },
+            rest,
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AlterConfigsResource {
+    resource_type: ConfigResourceType,
+    resource_name: String,
+    configs: Vec<AlterableConfig>,
+}
+
+impl AlterConfigsResource {
+    pub fn resource_type(&self) -> ConfigResourceType {
+        self.resource_type
+    }
+    pub fn resource_name(&self) -> &String {
+        &self.resource_name
+    }
+    pub fn configs(&self) -> &Vec<AlterableConfig> {
+        &self.configs
+    }
+    pub fn extract(value: &[u8], flexible: bool) -> Result<(Self, &[u8])> {
+        let (resource_type, rest) =
+            value.extract_u8_into(ConfigResourceType::new)?;
+        let (resource_name, rest) = match flexible {
+            true => rest.extract_compact_str(),
+            false => rest.extract_string(),
+        }?;
+        let (configs, rest) = rest.extract_array_with(flexible, |v| {
+            AlterableConfig::extract(v, flexible)
+        })?;
+        let rest = match flexible {
+            true => rest.drop(1).second()?,
+            false => rest,
+        };
+        Ok((
+            Self {
+                resource_type,
+                resource_name,
+                configs,
+            },
+            rest,
+        ))
+    }
+    /// Applies the operations to `current`, the effective values, and
+    /// returns the ConfigRecords to write. Nothing is written unless every
+    /// operation is valid.
+    pub fn config_records(
+        &self,
+        current: &HashMap<String, String>,
+    ) -> std::result::Result<Vec<ConfigRecordValue>, String> {
+        let mut changes: Vec<(String, Option<String>)> = vec![];
+        for config in &self.configs {
+            let def = ConfigDef::find(&config.name).ok_or_else(|| {
+                format!("Unknown topic config name: {}", config.name)
+            })?;
+            let list = |v: Option<&String>| -> Vec<String> {
+                v.map(|v| {
+                    v.split(',')
+                        .map(|v| v.trim().to_string())
+                        .filter(|v| !v.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+            };
+            let value = match *config.config_operation {
+                ConfigOperation::SET =>
+                    Some(config.value.clone().ok_or_else(|| {
+                        format!("Null value not supported for {}", def.name)
+                    })?),
+                ConfigOperation::DELETE => None,
+                op @ (ConfigOperation::APPEND | ConfigOperation::SUBTRACT) => {
+                    if def.config_type != ConfigType::List {
+                        return Err(format!(
+                            "Config {} is not a list, cannot append or \
+                             subtract",
+                            def.name
+                        ));
+                    }
+                    let mut values = list(current.get(def.name));
+                    let operand = list(config.value.as_ref());
+                    match op {
+                        ConfigOperation::APPEND =>
+                            operand.into_iter().for_each(|v| {
+                                if !values.contains(&v) {
+                                    values.push(v)
+                                }
+                            }),
+                        _ => values.retain(|v| !operand.contains(v)),
+                    }
+                    Some(values.join(","))
+                }
+                op => return Err(format!("Unknown config operation {op}")),
+            };
+            if let Some(value) = &value {
+                def.validate(value)?;
+            }
+            changes.push((def.name.to_string(), value));
+        }
+        Ok(changes
+            .into_iter()
+            .map(|(name, value)| {
+                ConfigRecordValue(
+                    FrameVersion::new(1),
+                    ValueVersion::new(0),
+                    self.resource_type,
+                    self.resource_name.clone(),
+                    name,
+                    value,
+                )
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AlterConfigsResourceResponse {
+    error_code: ErrorCode,
+    error_message: Option<String>,
+    resource_type: ConfigResourceType,
+    resource_name: String,
+}
+
+impl AlterConfigsResourceResponse {
+    pub fn new(resource: &AlterConfigsResource) -> Self {
+        Self {
+            error_code: ErrorCode::NoError,
+            error_message: None,
+            resource_type: resource.resource_type,
+            resource_name: resource.resource_name.clone(),
+        }
+    }
+    pub fn error(
+        resource: &AlterConfigsResource,
+        error_code: ErrorCode,
+        error_message: String,
+    ) -> Self {
+        Self {
+            error_code,
+            error_message: Some(error_message),
+            ..Self::new(resource)
+        }
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::IncrementalAlterConfigs.is_flexible(version);
+        let mut bytes = vec![];
+        bytes.put_i16(*self.error_code);
+        bytes.extend(self.error_message.to_nullable_string(flexible));
+        bytes.put_u8(*self.resource_type);
+        bytes.extend(match flexible {
+            true => self.resource_name.to_compact_string(),
+            false => self.resource_name.to_kafka_string(),
+        });
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+impl RequestBody {
+    pub(crate) fn incremental_alter_configs(
+        body: &[u8],
+        version: Version,
+    ) -> Result<Self> {
+        let flexible = ApiKey::IncrementalAlterConfigs.is_flexible(version);
+        let (resources, rest) = body.extract_array_with(flexible, |v| {
+            AlterConfigsResource::extract(v, flexible)
+        })?;
+        let (validate_only, _rest) = rest.extract_u8().map_tuple(|v| v != 0)?;
+        Ok(RequestBody::IncrementalAlterConfigs {
+            resources,
+            validate_only,
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_incremental_alter_configs(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let allowed = cx.allowed(request);
+        let RequestBody::IncrementalAlterConfigs {
+            resources,
+            validate_only,
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        let meta = Meta::load(cx.metadata_log)?;
+        Ok(ResponseBody::IncrementalAlterConfigs {
+            responses: resources
+                .iter()
+                .map(|r| {
+                    match Self::config_authorization(
+                        &allowed,
+                        AclOperation::ALTER_CONFIGS,
+                        *r.resource_type(),
+                        r.resource_name(),
+                    ) {
+                        Some(code) => AlterConfigsResourceResponse::error(
+                            r,
+                            code,
+                            "Not authorized".to_string(),
+                        ),
+                        None => Self::alter_configs(
+                            cx.metadata_log,
+                            &meta,
+                            r,
+                            *validate_only,
+                        ),
+                    }
+                })
+                .collect(),
+            throttle_time: ThrottleTime::zero(),
+        })
+    }
+
+    pub(crate) fn error_incremental_alter_configs(
+        body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        let responses = match body {
+            Some(RequestBody::IncrementalAlterConfigs {
+                resources,
+                ..
+            }) => resources
+                .iter()
+                .map(|r| {
+                    AlterConfigsResourceResponse::error(
+                        r,
+                        error.error_code(),
+                        error.to_string(),
+                    )
+                })
+                .collect(),
+            _ => vec![],
+        };
+        ResponseBody::IncrementalAlterConfigs {
+            responses,
+            throttle_time: ThrottleTime::zero(),
+        }
+    }
+
+    pub(crate) fn encode_incremental_alter_configs(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::IncrementalAlterConfigs {
+            responses,
+            throttle_time,
+        } = body
+        else {
+            unreachable!("not a IncrementalAlterConfigs");
+        };
+        let (mut bytes, flexible) = response_header(
+            ApiKey::IncrementalAlterConfigs,
+            version,
+            correlation_id,
+        );
+        bytes.put_u32(*throttle_time);
+        bytes.extend(array_length(flexible, responses.len()));
+        responses.into_iter().for_each(|r| bytes.extend(r.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+
+    /// Only topic configs are dynamic, they are written to the metadata log
+    /// as ConfigRecords unless `validate_only`.
+    fn alter_configs(
+        metadata_log: &str,
+        meta: &Meta,
+        resource: &AlterConfigsResource,
+        validate_only: bool,
+    ) -> AlterConfigsResourceResponse {
+        let topic_name = TopicName::new(resource.resource_name().clone());
+        let error = |error_code, message: String| {
+            AlterConfigsResourceResponse::error(resource, error_code, message)
+        };
+        match *resource.resource_type() {
+            TOPIC_RESOURCE if meta.find_topic_id(&topic_name).is_some() => {}
+            TOPIC_RESOURCE =>
+                return error(
+                    ErrorCode::UnknownTopicOrPartition,
+                    format!("Topic {} does not exist", *topic_name),
+                ),
+            BROKER_RESOURCE =>
+                return error(
+                    ErrorCode::InvalidRequest,
+                    "Broker configs are static, set them in \
+                     server.properties"
+                        .to_string(),
+                ),
+            other =>
+                return error(
+                    ErrorCode::InvalidRequest,
+                    format!("Unsupported resource type {other}"),
+                ),
+        }
+        let records = match resource
+            .config_records(&topic_config_values(meta, &topic_name))
+        {
+            Ok(records) => records,
+            Err(message) => return error(ErrorCode::InvalidConfig, message),
+        };
+        if validate_only || records.is_empty() {
+            return AlterConfigsResourceResponse::new(resource);
+        }
+        let values =
+            records.into_iter().map(RecordValue::ConfigRecord).collect();
+        match Meta::append(metadata_log, values) {
+            Ok(()) => AlterConfigsResourceResponse::new(resource),
+            Err(e) => error(ErrorCode::UnknownServerError, e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Authorizer, TestLogs};
+    use hex::decode;
+
+    #[test]
+    fn test_config_records() -> Result<()> {
+        // topic "foo": APPEND compact to cleanup.policy, SET retention.ms
+        let bytes = decode(
+            "02 04666f6f 03 0f636c65616e75702e706f6c696379 02 08636f6d70616374 00 \
+             0d726574656e74696f6e2e6d73 00 053130303000 00"
+                .replace(" ", ""),
+        )
+        .unwrap();
+        let (resource, rest) = AlterConfigsResource::extract(&bytes, true)?;
+        assert!(rest.is_empty());
+        let current = HashMap::from([(
+            "cleanup.policy".to_string(),
+            "delete".to_string(),
+        )]);
+        let records = resource.config_records(&current).unwrap();
+        let values: Vec<(String, Option<String>)> =
+            records.into_iter().map(|r| (r.4, r.5)).collect();
+        assert_eq!(
+            values,
+            vec![
+                (
+                    "cleanup.policy".to_string(),
+                    Some("delete,compact".to_string())
+                ),
+                ("retention.ms".to_string(), Some("1000".to_string())),
+            ]
+        );
+
+        let invalid = AlterConfigsResource {
+            configs: vec![AlterableConfig {
+                name: "retention.ms".to_string(),
+                config_operation: ConfigOperation::new(ConfigOperation::SET),
+                value: Some("soon".to_string()),
+            }],
+            ..resource
+        };
+        assert!(invalid.config_records(&current).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle_incremental_alter_configs() -> Result<()> {
+        let logs = TestLogs::new("incremental-alter-configs");
+        // v0 setting retention.ms=1000 for "baz" and retention.ms=1 for
+        // "nope", which does not exist
+        let (_, bytes) = logs.handle(
+            "002c 0000 00000007 0001 74 00000002 \
+             02 0003 62617a 00000001 000c 726574656e74696f6e2e6d73 00 \
+             0004 31303030 \
+             02 0004 6e6f7065 00000001 000c 726574656e74696f6e2e6d73 00 \
+             0001 31 00",
+            Authorizer::default(),
+        )?;
+        assert_eq!(
+            hex::encode(bytes),
+            [
+                "0000003a 00000007 00000000 00000002",
+                "0000 ffff 02 0003 62617a",
+                "0003 0019 546f706963206e6f706520646f6573206e6f74206578697374",
+                "02 0004 6e6f7065",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        // v1 setting retention.ms=soon, which is not a number
+        let (_, bytes) = logs.handle(
+            "002c 0001 00000008 0001 74 00 02 \
+             02 04 62617a 02 0d 726574656e74696f6e2e6d73 00 05 736f6f6e 00 \
+             00 00 00",
+            Authorizer::default(),
+        )?;
+        assert_eq!(
+            hex::encode(bytes),
+            [
+                "00000045 00000008 00 00000000 02",
+                "0028 32 496e76616c69642076616c756520736f6f6e20666f7220636f6e",
+                "66696775726174696f6e20726574656e74696f6e2e6d73",
+                "02 0462617a 00 00",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        // only the valid value was written
+        let meta = Meta::load(&logs.metadata_log)?;
+        let values = topic_config_values(&meta, &TopicName::from("baz"));
+        assert_eq!(values["retention.ms"], "1000");
+        Ok(())
+    }
+}
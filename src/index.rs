@@ -0,0 +1,232 @@
+use crate::{
+    BytesOps, Context, LogEntry, MapTupleTwo, RecordOffset, RecordTimestamp,
+    Result,
+};
+use bytes::BufMut;
+use newtype_macro::newtype;
+use std::fs::write;
+
+// Like Kafka, add an index entry once this many log bytes went by.
+const INDEX_INTERVAL_BYTES: usize = 4096;
+
+#[newtype]
+pub struct RelativeOffset(u32);
+
+#[newtype]
+pub struct FilePosition(u32);
+
+/// The sparse `.index` and `.timeindex` of a segment, offsets relative to
+/// the segment's base offset.
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+    offsets: Vec<(RelativeOffset, FilePosition)>,
+    timestamps: Vec<(RecordTimestamp, RelativeOffset)>,
+    // where the last entry was indexed and the largest timestamp so far,
+    // while building
+    last_indexed: Option<usize>,
+    max_timestamp: Option<(RecordTimestamp, RecordOffset)>,
+}
+
+/// `offset` relative to a segment's `base_offset`, an error when the
+/// segment cannot hold it.
+fn relative(base_offset: u64, offset: RecordOffset) -> Result<RelativeOffset> {
+    offset
+        .checked_sub(base_offset)
+        .and_then(|v| u32::try_from(v).ok())
+        .map(RelativeOffset::new)
+        .with_context(|| {
+            format!("offset {} out of segment {base_offset}", *offset)
+        })
+}
+
+impl Index {
+    pub fn build(base_offset: u64, entries: &[LogEntry]) -> Result<Self> {
+        let mut index = Index::default();
+        let mut position = 0;
+        for entry in entries {
+            index.add(base_offset, position, entry)?;
+            position += entry.size();
+        }
+        Ok(index)
+    }
+
+    /// Indexes the entry written at `position`, after those added before.
+    pub fn add(
+        &mut self,
+        base_offset: u64,
+        position: usize,
+        entry: &LogEntry,
+    ) -> Result<()> {
+        if let Some(timestamp) = entry.max_timestamp() {
+            if self.max_timestamp.map_or(true, |(t, _)| *timestamp > *t) {
+                self.max_timestamp = Some((timestamp, entry.last_offset()));
+            }
+        }
+        if self
+            .last_indexed
+            .map_or(true, |v| position - v >= INDEX_INTERVAL_BYTES)
+        {
+            let file_position = u32::try_from(position)
+                .ok()
+                .map(FilePosition::new)
+                .context("segment too large to index")?;
+            self.offsets.push((
+                relative(base_offset, entry.last_offset())?,
+                file_position,
+            ));
+            if let Some((timestamp, offset)) = self.max_timestamp {
+                self.timestamps
+                    .push((timestamp, relative(base_offset, offset)?));
+            }
+            self.last_indexed = Some(position);
+        }
+        Ok(())
+    }
+
+    /// Position to start reading from to find `offset`.
+    pub fn lookup(&self, base_offset: u64, offset: u64) -> FilePosition {
+        let relative = offset.saturating_sub(base_offset);
+        self.offsets
+            .iter()
+            .take_while(|(o, _)| (**o as u64) < relative)
+            .last()
+            .map(|(_, p)| *p)
+            .unwrap_or(FilePosition::new(0))
+    }
+
+    /// Position to start reading from to find the first record at or
+    /// after `timestamp`, all records before it are older.
+    pub fn lookup_timestamp(
+        &self,
+        base_offset: u64,
+        timestamp: u64,
+    ) -> FilePosition {
+        self.timestamps
+            .iter()
+            .take_while(|(t, _)| **t < timestamp)
+            .last()
+            .map_or(FilePosition::new(0), |(_, o)| {
+                self.lookup(base_offset, base_offset + **o as u64 + 1)
+            })
+    }
+
+    /// Writes `<segment>.index` and `<segment>.timeindex` next to the
+    /// `<segment>.log` at `log_path`.
+    pub fn write(&self, log_path: &str) -> Result<()> {
+        let stem = log_path.strip_suffix(".log").unwrap_or(log_path);
+        write(format!("{stem}.index"), self.offsets_bytes())
+            .context("Writing offset index")?;
+        write(format!("{stem}.timeindex"), self.timestamps_bytes())
+            .context("Writing time index")
+    }
+
+    pub fn read(bytes: &[u8], time_bytes: &[u8]) -> Result<Self> {
+        let offsets = bytes
+            .chunks(8)
+            .map(|v| {
+                let (offset, rest) = v.extract_u32_into(RelativeOffset::new)?;
+                rest.extract_u32_into(FilePosition::new)
+                    .first()
+                    .map(|p| (offset, p))
+            })
+            .collect::<Result<_>>()?;
+        let timestamps = time_bytes
+            .chunks(12)
+            .map(|v| {
+                let (timestamp, rest) =
+                    v.extract_u64_into(RecordTimestamp::new)?;
+                rest.extract_u32_into(RelativeOffset::new)
+                    .first()
+                    .map(|o| (timestamp, o))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            offsets,
+            timestamps,
+            ..Self::default()
+        })
+    }
+
+    fn offsets_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.offsets.iter().for_each(|(o, p)| {
+            bytes.put_u32(**o);
+            bytes.put_u32(**p);
+        });
+        bytes
+    }
+
+    fn timestamps_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.timestamps.iter().for_each(|(t, o)| {
+            bytes.put_u64(**t);
+            bytes.put_u32(**o);
+        });
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MagicByte, Message, MessageAttributes};
+
+    // v1 messages of about 2KB, so every other one is indexed
+    fn entries(base_offset: u64, count: u64) -> Vec<LogEntry> {
+        (0..count)
+            .map(|i| {
+                LogEntry::Message(Message::new(
+                    RecordOffset::new(base_offset + i),
+                    MagicByte::new(1),
+                    MessageAttributes::new(0),
+                    Some(RecordTimestamp::new(1000 + i)),
+                    None,
+                    Some(vec![0; 2048]),
+                ))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_build_and_lookup() -> Result<()> {
+        let entries = entries(100, 5);
+        let size = entries[0].size() as u32;
+        let index = Index::build(100, &entries)?;
+        assert_eq!(
+            index.offsets,
+            vec![
+                (RelativeOffset::new(0), FilePosition::new(0)),
+                (RelativeOffset::new(2), FilePosition::new(2 * size)),
+                (RelativeOffset::new(4), FilePosition::new(4 * size)),
+            ]
+        );
+        assert_eq!(
+            index.timestamps[1],
+            (RecordTimestamp::new(1002), RelativeOffset::new(2))
+        );
+        // the last indexed entry before the offset
+        assert_eq!(*index.lookup(100, 100), 0);
+        assert_eq!(*index.lookup(100, 103), 2 * size);
+        assert_eq!(*index.lookup(100, 105), 4 * size);
+        // the records before 1003 are indexed up to offset 102
+        assert_eq!(*index.lookup_timestamp(100, 1000), 0);
+        assert_eq!(*index.lookup_timestamp(100, 1003), 2 * size);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_round_trip() -> Result<()> {
+        let index = Index::build(100, &entries(100, 5))?;
+        let read =
+            Index::read(&index.offsets_bytes(), &index.timestamps_bytes())?;
+        assert_eq!(read.offsets, index.offsets);
+        assert_eq!(read.timestamps, index.timestamps);
+        Ok(())
+    }
+
+    #[test]
+    fn test_offset_out_of_segment() {
+        assert!(Index::build(200, &entries(100, 1)).is_err());
+        assert!(Index::build(0, &entries(1 << 32, 1)).is_err());
+    }
+}
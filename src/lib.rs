@@ -1,21 +1,79 @@
+mod acl;
+mod client_quotas;
+mod compression;
+mod config;
+mod connections;
+mod create_acls;
+mod create_partitions;
+mod delete_acls;
+mod delete_records;
+mod describe_acls;
+mod describe_configs;
 mod error;
+mod error_code;
 mod fetch;
+mod fetch_session;
 mod file;
+mod incremental_alter_configs;
+mod index;
+mod list_offsets;
+mod listener;
+mod log;
+mod memory_pool;
+mod message;
 mod meta;
+mod metrics;
 mod partition;
 mod pb;
+mod produce;
+mod purgatory;
+mod quota;
+mod recovery;
+mod registry;
 mod request;
+mod request_log;
 mod response;
+mod retention;
+mod sasl;
 mod topic;
 mod types;
 
+pub use acl::*;
+pub use client_quotas::*;
+pub use compression::*;
+pub use config::*;
+pub use connections::*;
+pub use create_acls::*;
+pub use create_partitions::*;
+pub use delete_acls::*;
+pub use delete_records::*;
+pub use describe_acls::*;
+pub use describe_configs::*;
 pub use error::*;
+pub use error_code::*;
 pub use fetch::*;
+pub use fetch_session::*;
 pub use file::*;
+pub use incremental_alter_configs::*;
+pub use index::*;
+pub use list_offsets::*;
+pub use listener::*;
+pub use log::*;
+pub use memory_pool::*;
+pub use message::*;
 pub use meta::*;
+pub use metrics::*;
 pub use partition::*;
 pub use pb::*;
+pub use produce::*;
+pub use purgatory::*;
+pub use quota::*;
+pub use recovery::*;
+pub use registry::*;
 pub use request::*;
+pub use request_log::*;
 pub use response::*;
+pub use retention::*;
+pub use sasl::*;
 pub use topic::*;
 pub use types::*;
@@ -0,0 +1,373 @@
+use crate::{
+    array_length, response_header, with_message_size, AclOperation, ApiKey,
+    BytesOps, CorrelationId, CurrentLeaderEpoch, Error, ErrorCode,
+    HandlerContext, IsolationLevel, Log, MapTupleTwo, Meta, PartitionIndex,
+    RecordOffset, Request, RequestBody, Response, ResponseBody, Result,
+    TagBuffer, ThrottleTime, ToCompactString, ToKafkaString, TopicName,
+    Version,
+};
+use bytes::BufMut;
+use newtype_macro::newtype;
+
+/// Timestamp to look up, or one of the special values below.
+#[newtype]
+pub struct TargetTimestamp(i64);
+
+impl TargetTimestamp {
+    pub const LATEST: i64 = -1;
+    pub const EARLIEST: i64 = -2;
+    pub const MAX_TIMESTAMP: i64 = -3;
+}
+
+#[derive(Debug, Clone)]
+pub struct ListOffsetsTopic {
+    topic_name: TopicName,
+    partitions: Vec<ListOffsetsPartition>,
+}
+
+impl ListOffsetsTopic {
+    pub fn topic_name(&self) -> &TopicName {
+        &self.topic_name
+    }
+    pub fn partitions(&self) -> &Vec<ListOffsetsPartition> {
+        &self.partitions
+    }
+    pub fn extract(value: &[u8], version: Version) -> Result<(Self, &[u8])> {
+        let flexible = ApiKey::ListOffsets.is_flexible(version);
+        let (topic_name, rest) = match flexible {
+            true => value.extract_compact_str(),
+            false => value.extract_string(),
+        }
+        .map_tuple(TopicName::new)?;
+        let (partitions, rest) = rest.extract_array_with(flexible, |v| {
+            ListOffsetsPartition::extract(v, version)
+        })?;
+        let rest = match flexible {
+            true => rest.drop(1).second()?,
+            false => rest,
+        };
+        Ok((
+            Self {
+                topic_name,
+                partitions,
+            },
+            rest,
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ListOffsetsPartition {
+    partition_index: PartitionIndex,
+    current_leader_epoch: CurrentLeaderEpoch,
+    timestamp: TargetTimestamp,
+}
+
+impl ListOffsetsPartition {
+    pub fn partition_index(&self) -> PartitionIndex {
+        self.partition_index
+    }
+    pub fn current_leader_epoch(&self) -> CurrentLeaderEpoch {
+        self.current_leader_epoch
+    }
+    pub fn timestamp(&self) -> TargetTimestamp {
+        self.timestamp
+    }
+    fn extract(value: &[u8], version: Version) -> Result<(Self, &[u8])> {
+        let (partition_index, rest) =
+            value.extract_u32_into(PartitionIndex::new)?;
+        let (current_leader_epoch, rest) = match version {
+            v if v >= Version::V4 =>
+                rest.extract_u32_into(CurrentLeaderEpoch::new)?,
+            _ => (CurrentLeaderEpoch::new(u32::MAX), rest),
+        };
+        let (timestamp, rest) =
+            rest.extract_u64().map_tuple(|v| TargetTimestamp::new(v as i64))?;
+        let rest = match ApiKey::ListOffsets.is_flexible(version) {
+            true => rest.drop(1).second()?,
+            false => rest,
+        };
+        Ok((
+            Self {
+                partition_index,
+                current_leader_epoch,
+                timestamp,
+            },
+            rest,
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ListOffsetsResponse {
+    topic_name: TopicName,
+    partitions: Vec<ListOffsetsPartitionResponse>,
+}
+
+impl ListOffsetsResponse {
+    pub fn new(
+        topic_name: TopicName,
+        partitions: Vec<ListOffsetsPartitionResponse>,
+    ) -> Self {
+        Self {
+            topic_name,
+            partitions,
+        }
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::ListOffsets.is_flexible(version);
+        let mut bytes = match flexible {
+            true => self.topic_name.to_compact_string(),
+            false => self.topic_name.to_kafka_string(),
+        };
+        bytes.extend(array_length(flexible, self.partitions.len()));
+        self.partitions
+            .into_iter()
+            .for_each(|p| bytes.extend(p.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ListOffsetsPartitionResponse {
+    partition_index: PartitionIndex,
+    error_code: ErrorCode,
+    timestamp: i64,
+    offset: i64,
+}
+
+impl ListOffsetsPartitionResponse {
+    /// The offset found for the target timestamp, with the timestamp of
+    /// its record.
+    pub fn new(
+        partition_index: PartitionIndex,
+        found: Option<(i64, RecordOffset)>,
+    ) -> Self {
+        let (timestamp, offset) =
+            found.map(|(t, o)| (t, *o as i64)).unwrap_or((-1, -1));
+        Self {
+            partition_index,
+            error_code: ErrorCode::NoError,
+            timestamp,
+            offset,
+        }
+    }
+    pub fn error(
+        partition_index: PartitionIndex,
+        error_code: ErrorCode,
+    ) -> Self {
+        Self {
+            partition_index,
+            error_code,
+            timestamp: -1,
+            offset: -1,
+        }
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.put_u32(*self.partition_index);
+        bytes.put_i16(*self.error_code);
+        bytes.put_i64(self.timestamp);
+        bytes.put_i64(self.offset);
+        if version >= Version::V4 {
+            bytes.put_i32(-1);
+        }
+        if ApiKey::ListOffsets.is_flexible(version) {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+impl RequestBody {
+    /// v0 answers with an offsets array and is not supported.
+    pub(crate) fn list_offsets(body: &[u8], version: Version) -> Result<Self> {
+        let flexible = ApiKey::ListOffsets.is_flexible(version);
+        let (_replica_id, rest) = body.extract_u32()?;
+        let (isolation_level, rest) = match version {
+            v if v >= Version::V2 =>
+                rest.extract_u8_into(IsolationLevel::new)?,
+            _ => (IsolationLevel::new(0), rest),
+        };
+        let (topics, _rest) = rest.extract_array_with(flexible, |v| {
+            ListOffsetsTopic::extract(v, version)
+        })?;
+        Ok(RequestBody::ListOffsets {
+            isolation_level,
+            topics,
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_list_offsets(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let topic_allowed = cx.topic_allowed(request);
+        let RequestBody::ListOffsets {
+            topics,
+            ..
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        let meta = Meta::load(cx.metadata_log)?;
+        Ok(ResponseBody::ListOffsets {
+            responses: topics
+                .iter()
+                .map(|t| {
+                    let authorized =
+                        topic_allowed(AclOperation::DESCRIBE, t.topic_name());
+                    ListOffsetsResponse::new(
+                        t.topic_name().clone(),
+                        t.partitions()
+                            .iter()
+                            .map(|p| {
+                                let index = p.partition_index();
+                                let found = match authorized {
+                                    true => Self::partition_log(
+                                        &meta,
+                                        t.topic_name(),
+                                        index,
+                                        || {
+                                            Log::offset_for(
+                                                cx.log_dir,
+                                                t.topic_name(),
+                                                index,
+                                                p.timestamp(),
+                                            )
+                                        },
+                                    ),
+                                    false => Err(
+                                        ErrorCode::TopicAuthorizationFailed,
+                                    ),
+                                };
+                                match found {
+                                    Ok(found) =>
+                                        ListOffsetsPartitionResponse::new(
+                                            index, found,
+                                        ),
+                                    Err(e) =>
+                                        ListOffsetsPartitionResponse::error(
+                                            index, e,
+                                        ),
+                                }
+                            })
+                            .collect(),
+                    )
+                })
+                .collect(),
+            throttle_time: ThrottleTime::zero(),
+        })
+    }
+
+    pub(crate) fn error_list_offsets(
+        body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        let responses = match body {
+            Some(RequestBody::ListOffsets {
+                topics,
+                ..
+            }) => topics
+                .iter()
+                .map(|t| {
+                    ListOffsetsResponse::new(
+                        t.topic_name().clone(),
+                        t.partitions()
+                            .iter()
+                            .map(|p| {
+                                ListOffsetsPartitionResponse::error(
+                                    p.partition_index(),
+                                    error.error_code(),
+                                )
+                            })
+                            .collect(),
+                    )
+                })
+                .collect(),
+            _ => vec![],
+        };
+        ResponseBody::ListOffsets {
+            responses,
+            throttle_time: ThrottleTime::zero(),
+        }
+    }
+
+    pub(crate) fn encode_list_offsets(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::ListOffsets {
+            responses,
+            throttle_time,
+        } = body
+        else {
+            unreachable!("not a ListOffsets");
+        };
+        let (mut bytes, flexible) =
+            response_header(ApiKey::ListOffsets, version, correlation_id);
+        if version >= Version::V2 {
+            bytes.put_u32(*throttle_time);
+        }
+        bytes.extend(array_length(flexible, responses.len()));
+        responses.into_iter().for_each(|r| bytes.extend(r.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Authorizer, TestLogs};
+
+    #[test]
+    fn test_handle_list_offsets() -> Result<()> {
+        let logs = TestLogs::new("list-offsets");
+        logs.messages(&[1000, 1010, 1020, 1030, 1040]);
+        // v1 for partition 0 of "baz" at the latest (-1), the earliest (-2)
+        // and the first record at 1025 or later
+        let (_, bytes) = logs.handle(
+            "0002 0001 00000007 0001 74 ffffffff 00000001 0003 62617a \
+             00000003 00000000 ffffffffffffffff 00000000 fffffffffffffffe \
+             00000000 0000000000000401",
+            Authorizer::default(),
+        )?;
+        assert_eq!(
+            hex::encode(bytes),
+            [
+                "00000053 00000007 00000001 0003 62617a 00000003",
+                "00000000 0000 ffffffffffffffff 0000000000000005",
+                "00000000 0000 ffffffffffffffff 0000000000000000",
+                "00000000 0000 0000000000000406 0000000000000003",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        // v7 for the same timestamp, with a throttle time and leader epoch
+        let (_, bytes) = logs.handle(
+            "0002 0007 00000008 0001 74 00 ffffffff 00 02 04 62617a \
+             02 00000000 ffffffff 0000000000000401 00 00 00",
+            Authorizer::default(),
+        )?;
+        assert_eq!(
+            hex::encode(bytes),
+            [
+                "0000002c 00000008 00 00000000 02 04 62617a 02",
+                "00000000 0000 0000000000000406 0000000000000003 ffffffff",
+                "00 00 00",
+            ]
+            .join("")
+            .replace(" ", "")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig, ServerConnection};
+use x509_parser::objects::{oid2abbrev, oid_registry};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::{broker_config, Context, Error, Principal, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SecurityProtocol {
+    Plaintext,
+    Ssl,
+}
+
+impl FromStr for SecurityProtocol {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "PLAINTEXT" => Ok(SecurityProtocol::Plaintext),
+            "SSL" => Ok(SecurityProtocol::Ssl),
+            v => Err(Error::general(&format!("Unknown security protocol {v}"))),
+        }
+    }
+}
+
+/// One entry of `listeners`, `SSL://:9093` listens on every interface.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listener {
+    pub security_protocol: SecurityProtocol,
+    pub address: String,
+}
+
+impl FromStr for Listener {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, address) = s
+            .trim()
+            .split_once("://")
+            .with_context(|| format!("Invalid listener {s}"))?;
+        let address = match address.strip_prefix(':') {
+            Some(port) => format!("0.0.0.0:{port}"),
+            None => address.to_string(),
+        };
+        Ok(Self {
+            security_protocol: name.parse()?,
+            address,
+        })
+    }
+}
+
+/// The `listeners` of server.properties, plaintext on 9092 by default.
+pub fn listeners() -> Result<Vec<Listener>> {
+    broker_config()
+        .get("listeners")
+        .map(String::as_str)
+        .unwrap_or("PLAINTEXT://127.0.0.1:9092")
+        .split(',')
+        .map(str::parse)
+        .collect()
+}
+
+fn pem_file(key: &str) -> Result<BufReader<File>> {
+    let path = broker_config()
+        .get(key)
+        .with_context(|| format!("{key} is required for an SSL listener"))?;
+    File::open(path)
+        .with_context(|| format!("open {key} {path}"))
+        .map(BufReader::new)
+}
+
+fn certificates(key: &str) -> Result<Vec<CertificateDer<'static>>> {
+    rustls_pemfile::certs(&mut pem_file(key)?)
+        .collect::<std::result::Result<_, _>>()
+        .with_context(|| format!("read certificates of {key}"))
+}
+
+/// The SSL listener setup: `ssl.keystore.location` is a PEM file with the
+/// private key and certificate chain, `ssl.client.auth` is `required`,
+/// `requested` or `none` and client certificates are checked against the
+/// CA certificates of `ssl.truststore.location`.
+pub fn tls_config() -> Result<Arc<ServerConfig>> {
+    let certs = certificates("ssl.keystore.location")?;
+    let key: PrivateKeyDer =
+        rustls_pemfile::private_key(&mut pem_file("ssl.keystore.location")?)
+            .context("read ssl.keystore.location private key")?
+            .context("ssl.keystore.location has no private key")?;
+    let builder = ServerConfig::builder();
+    let builder = match broker_config()
+        .get("ssl.client.auth")
+        .map(String::as_str)
+    {
+        None | Some("none") => builder.with_no_client_auth(),
+        Some(auth) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates("ssl.truststore.location")? {
+                roots.add(cert).context("add ssl.truststore.location CA")?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+            let verifier = match auth {
+                "requested" => verifier.allow_unauthenticated(),
+                _ => verifier,
+            };
+            builder.with_client_cert_verifier(
+                verifier.build().context("client certificate verifier")?,
+            )
+        }
+    };
+    builder
+        .with_single_cert(certs, key)
+        .context("ssl.keystore.location certificate")
+        .map(Arc::new)
+}
+
+/// `User:` and the subject of the certificate, most specific attribute
+/// first as in RFC 2253, e.g. `User:CN=alice,O=example`.
+pub fn certificate_principal(der: &[u8]) -> Result<Principal> {
+    let (_, cert) = X509Certificate::from_der(der)
+        .map_err(|e| Error::general(&format!("client certificate: {e}")))?;
+    let subject = cert
+        .subject()
+        .iter_rdn()
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .flat_map(|rdn| rdn.iter())
+        .map(|attr| {
+            let name = oid2abbrev(attr.attr_type(), oid_registry())
+                .map(str::to_string)
+                .unwrap_or_else(|_| attr.attr_type().to_id_string());
+            let value = attr.as_str().unwrap_or_default();
+            format!("{name}={value}")
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    Ok(Principal::new(format!("User:{subject}")))
+}
+
+/// A client connection one thread reads requests from while another
+/// writes the responses.
+pub trait Connection: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+impl Connection for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+}
+
+/// A TLS session shared by the clones of a connection. Readers wait for
+/// TLS records without holding the session, so writes are not held up.
+pub struct TlsStream {
+    connection: Arc<Mutex<ServerConnection>>,
+    tcp: TcpStream,
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut records = [0u8; 16 * 1024];
+        loop {
+            match self.connection.lock().unwrap().reader().read(buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            let n = self.tcp.read(&mut records)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            let mut connection = self.connection.lock().unwrap();
+            let mut rest = &records[..n];
+            while !rest.is_empty() {
+                connection.read_tls(&mut rest)?;
+                connection
+                    .process_new_packets()
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            }
+            while connection.wants_write() {
+                connection.write_tls(&mut self.tcp)?;
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut connection = self.connection.lock().unwrap();
+        let n = connection.writer().write(buf)?;
+        while connection.wants_write() {
+            connection.write_tls(&mut self.tcp)?;
+        }
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.tcp.flush()
+    }
+}
+
+impl Connection for TlsStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            connection: self.connection.clone(),
+            tcp: self.tcp.try_clone()?,
+        })
+    }
+}
+
+/// Runs the handshake, the principal comes from the client certificate
+/// if one was sent.
+pub fn accept_tls(
+    config: Arc<ServerConfig>,
+    mut stream: TcpStream,
+) -> Result<(TlsStream, Principal)> {
+    let mut connection =
+        ServerConnection::new(config).context("new TLS connection")?;
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream).context("TLS handshake")?;
+    }
+    let principal = match connection.peer_certificates() {
+        Some([cert, ..]) => certificate_principal(cert)?,
+        _ => Principal::anonymous(),
+    };
+    let stream = TlsStream {
+        connection: Arc::new(Mutex::new(connection)),
+        tcp: stream,
+    };
+    Ok((stream, principal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{
+        BasicConstraints, CertificateParams, DistinguishedName, DnType, IsCa,
+        KeyPair,
+    };
+    use rustls::pki_types::{PrivatePkcs8KeyDer, ServerName};
+    use rustls::{ClientConfig, ClientConnection, StreamOwned};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_client_certificate() -> Result<()> {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params.distinguished_name.push(DnType::CommonName, "test ca");
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let signed = |names: Vec<String>, cn: &str| {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(names).unwrap();
+            params.distinguished_name = DistinguishedName::new();
+            params.distinguished_name.push(DnType::OrganizationName, "example");
+            params.distinguished_name.push(DnType::CommonName, cn);
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            let key = PrivatePkcs8KeyDer::from(key.serialize_der());
+            (cert.der().clone(), PrivateKeyDer::from(key))
+        };
+        let (server_cert, server_key) =
+            signed(vec!["localhost".to_string()], "localhost");
+        let (client_cert, client_key) = signed(vec![], "alice");
+        assert_eq!(
+            certificate_principal(&client_cert)?,
+            Principal::new("User:CN=alice,O=example".to_string())
+        );
+
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der().clone()).unwrap();
+        let roots = Arc::new(roots);
+        let verifier =
+            WebPkiClientVerifier::builder(roots.clone()).build().unwrap();
+        let server = Arc::new(
+            ServerConfig::builder()
+                .with_client_cert_verifier(verifier)
+                .with_single_cert(vec![server_cert], server_key)
+                .unwrap(),
+        );
+        let client = Arc::new(
+            ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_client_auth_cert(vec![client_cert], client_key)
+                .unwrap(),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let tcp = TcpStream::connect(address).unwrap();
+            let name = ServerName::try_from("localhost").unwrap();
+            let connection = ClientConnection::new(client, name).unwrap();
+            let mut tls = StreamOwned::new(connection, tcp);
+            tls.write_all(b"ping").unwrap();
+            let mut pong = [0u8; 4];
+            tls.read_exact(&mut pong).unwrap();
+            pong
+        });
+        let (tcp, _) = listener.accept().unwrap();
+        let (mut tls, principal) = accept_tls(server, tcp)?;
+        assert_eq!(principal, Principal::new("User:CN=alice,O=example".into()));
+        let mut ping = [0u8; 4];
+        tls.read_exact(&mut ping).unwrap();
+        assert_eq!(&ping, b"ping");
+        tls.write_all(b"pong").unwrap();
+        assert_eq!(&handle.join().unwrap(), b"pong");
+        Ok(())
+    }
+}
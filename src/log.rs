@@ -0,0 +1,1083 @@
+use std::collections::HashMap;
+use std::fs::{metadata, read_dir, remove_file, rename, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::UNIX_EPOCH;
+
+use crate::{
+    append, read, Batch, BatchOffset, Context, FilePosition, Index, LogConfig,
+    LogEntry, LogStartOffset, MagicByte, PartitionIndex, Record, RecordOffset,
+    RecordTimestamp, Result, TargetTimestamp, TopicName,
+};
+
+pub const LOG_DIR: &str = "/tmp/kraft-combined-logs";
+pub const METADATA_LOG: &str =
+    "/tmp/kraft-combined-logs/__cluster_metadata-0/00000000000000000000.log";
+
+const LOG_START_OFFSET_CHECKPOINT: &str = "log-start-offset-checkpoint";
+
+/// Where a partition log starts and ends, kept between appends so they need
+/// not read the log: the next offset and the active segment with its size
+/// and index.
+#[derive(Debug)]
+struct LogEnd {
+    log_start_offset: LogStartOffset,
+    next_offset: RecordOffset,
+    active: Option<(Segment, usize, Index)>,
+}
+
+type PartitionLock = Arc<Mutex<Option<LogEnd>>>;
+
+// Appends, retention, compaction and DeleteRecords rewrite the segments of a
+// partition, reads must not see them half way. Each partition directory has
+// its own lock guarding its cached end, dropped when segments are rewritten.
+static PARTITIONS: OnceLock<Mutex<HashMap<PathBuf, PartitionLock>>> =
+    OnceLock::new();
+
+// The checkpoint is shared by every partition.
+static CHECKPOINT_LOCK: Mutex<()> = Mutex::new(());
+
+fn with_partition<T>(
+    dir: &Path,
+    f: impl FnOnce(&mut Option<LogEnd>) -> Result<T>,
+) -> Result<T> {
+    let lock = PARTITIONS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(dir.to_path_buf())
+        .or_default()
+        .clone();
+    let mut end = lock.lock().unwrap_or_else(|e| {
+        // a panic may have left the cached end behind the files
+        let mut end = e.into_inner();
+        *end = None;
+        end
+    });
+    f(&mut end)
+}
+
+impl LogEnd {
+    fn load(dir: &Path, log_start_offset: LogStartOffset) -> Result<Self> {
+        let segments = Segment::list(dir)?;
+        let mut next_offset = None;
+        for segment in segments.iter().rev() {
+            next_offset = segment.read()?.last().map(|v| *v.last_offset() + 1);
+            if next_offset.is_some() {
+                break;
+            }
+        }
+        let active = match segments.last() {
+            None => None,
+            Some(segment) => {
+                let entries = segment.read()?;
+                let size = entries.iter().map(LogEntry::size).sum();
+                let index = Index::build(*segment.base_offset, &entries)?;
+                Some((segment.clone(), size, index))
+            }
+        };
+        Ok(Self {
+            log_start_offset,
+            next_offset: RecordOffset::new(
+                next_offset.unwrap_or(0).max(*log_start_offset),
+            ),
+            active,
+        })
+    }
+}
+
+/// One `<base offset>.log` file of a partition with its indexes.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    base_offset: RecordOffset,
+    path: PathBuf,
+}
+
+impl Segment {
+    fn new(dir: &Path, base_offset: RecordOffset) -> Self {
+        Self {
+            base_offset,
+            path: dir.join(format!("{:020}.log", *base_offset)),
+        }
+    }
+    /// The segments of a partition directory ordered by base offset.
+    pub fn list(dir: &Path) -> Result<Vec<Segment>> {
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut result = vec![];
+        for file in read_dir(dir).context("Listing partition")? {
+            let path = file.context("Listing partition")?.path();
+            let base_offset = path
+                .file_stem()
+                .and_then(|v| v.to_str())
+                .and_then(|v| v.parse::<u64>().ok());
+            match (path.extension(), base_offset) {
+                (Some(ext), Some(base_offset)) if ext == "log" =>
+                    result.push(Segment {
+                        base_offset: RecordOffset::new(base_offset),
+                        path,
+                    }),
+                _ => {}
+            }
+        }
+        result.sort_by_key(|v| *v.base_offset);
+        Ok(result)
+    }
+    pub fn base_offset(&self) -> RecordOffset {
+        self.base_offset
+    }
+    pub fn path(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+    pub fn size(&self) -> u64 {
+        metadata(&self.path).map(|v| v.len()).unwrap_or(0)
+    }
+    /// The largest record timestamp, or when the file was last written for
+    /// segments of v0 messages, which have none.
+    fn newest_timestamp(&self) -> Result<u64> {
+        let newest = self
+            .read()?
+            .iter()
+            .filter_map(LogEntry::max_timestamp)
+            .map(|v| *v)
+            .max();
+        match newest {
+            Some(v) => Ok(v),
+            None => metadata(&self.path)
+                .and_then(|v| v.modified())
+                .context("Reading segment mtime")
+                .map(|v| {
+                    v.duration_since(UNIX_EPOCH)
+                        .map_or(0, |v| v.as_millis() as u64)
+                }),
+        }
+    }
+    pub fn read(&self) -> Result<Vec<LogEntry>> {
+        read(&self.path()).and_then(LogEntry::split)
+    }
+    /// The entries from `position` on.
+    fn read_from(&self, position: FilePosition) -> Result<Vec<LogEntry>> {
+        let mut bytes = vec![];
+        File::open(&self.path)
+            .and_then(|mut f| {
+                f.seek(SeekFrom::Start(*position as u64))?;
+                f.read_to_end(&mut bytes)
+            })
+            .context("Reading segment")?;
+        LogEntry::split(bytes)
+    }
+    /// The indexes written next to the segment, or an empty one pointing at
+    /// its start when they cannot be read.
+    fn index(&self) -> Index {
+        let stem = self.path.with_extension("");
+        let read = |ext| std::fs::read(stem.with_extension(ext));
+        match (read("index"), read("timeindex")) {
+            (Ok(offsets), Ok(timestamps)) =>
+                Index::read(&offsets, &timestamps).unwrap_or_default(),
+            _ => Index::default(),
+        }
+    }
+    pub fn write_index(&self, entries: &[LogEntry]) -> Result<()> {
+        Index::build(*self.base_offset, entries)?.write(&self.path())
+    }
+    /// Swaps the content for `entries` through a synced `.cleaned` file,
+    /// so a crash leaves either the old or the new segment. Recovery
+    /// removes a `.cleaned` file left behind and rebuilds the indexes.
+    fn replace(&self, entries: Vec<LogEntry>) -> Result<()> {
+        let cleaned = self.path.with_extension("log.cleaned");
+        let bytes: Vec<u8> =
+            entries.into_iter().flat_map(Vec::<u8>::from).collect();
+        File::create(&cleaned)
+            .and_then(|mut f| {
+                f.write_all(&bytes)?;
+                f.sync_all()
+            })
+            .context("Writing cleaned segment")?;
+        rename(&cleaned, &self.path).context("Swapping cleaned segment")?;
+        if let Some(dir) = self.path.parent() {
+            File::open(dir)
+                .and_then(|f| f.sync_all())
+                .context("Syncing partition directory")?;
+        }
+        self.write_index(&self.read()?)
+    }
+    fn delete(&self) -> Result<()> {
+        let stem = self.path.with_extension("");
+        ["index", "timeindex"].iter().for_each(|ext| {
+            let _ = remove_file(stem.with_extension(ext));
+        });
+        remove_file(&self.path).context("Deleting segment")
+    }
+}
+
+/// The readable part of a partition log: entries from the log start offset
+/// on, over all segments.
+#[derive(Debug, Clone)]
+pub struct Log {
+    entries: Vec<LogEntry>,
+    log_start_offset: LogStartOffset,
+    next_offset: RecordOffset,
+}
+
+impl Log {
+    pub fn new(
+        entries: Vec<LogEntry>,
+        log_start_offset: LogStartOffset,
+    ) -> Self {
+        let next = entries.last().map(|v| *v.last_offset() + 1);
+        Self {
+            entries,
+            log_start_offset,
+            next_offset: RecordOffset::new(
+                next.unwrap_or(0).max(*log_start_offset),
+            ),
+        }
+    }
+    pub fn entries(&self) -> &Vec<LogEntry> {
+        &self.entries
+    }
+    pub fn log_start_offset(&self) -> LogStartOffset {
+        self.log_start_offset
+    }
+    pub fn down_convert(&self, magic: MagicByte) -> Vec<LogEntry> {
+        self.entries.iter().flat_map(|v| v.down_convert(magic)).collect()
+    }
+    /// The offset the next appended record gets.
+    pub fn next_offset(&self) -> RecordOffset {
+        self.next_offset
+    }
+    /// Offsets and timestamps of the records a consumer can read.
+    pub fn records(&self) -> Vec<(RecordOffset, Option<RecordTimestamp>)> {
+        self.entries
+            .iter()
+            .flat_map(LogEntry::records)
+            .filter(|(o, _)| **o >= *self.log_start_offset)
+            .collect()
+    }
+    pub fn dir(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+    ) -> PathBuf {
+        Path::new(log_dir).join(format!(
+            "{}-{}",
+            topic_name.value(),
+            *partition
+        ))
+    }
+    pub fn load_log(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+    ) -> Result<Log> {
+        with_partition(&Self::dir(log_dir, topic_name, partition), |_| {
+            Self::load(log_dir, topic_name, partition)
+        })
+    }
+    fn load(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+    ) -> Result<Log> {
+        let segments =
+            Segment::list(&Self::dir(log_dir, topic_name, partition))?;
+        let log_start_offset =
+            Self::start_offset(log_dir, topic_name, partition, &segments)?;
+        let mut entries = vec![];
+        for segment in segments {
+            entries.extend(
+                segment
+                    .read()?
+                    .into_iter()
+                    .filter(|v| *v.last_offset() >= *log_start_offset),
+            );
+        }
+        Ok(Log::new(entries, log_start_offset))
+    }
+    /// The checkpointed log start offset, or the first segment's base
+    /// offset if it is past it.
+    fn start_offset(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+        segments: &[Segment],
+    ) -> Result<LogStartOffset> {
+        let checkpoint = read_checkpoint(log_dir)?
+            .get(&(topic_name.to_string(), *partition))
+            .copied()
+            .unwrap_or(0);
+        let first = segments.first().map(|v| *v.base_offset).unwrap_or(0);
+        Ok(LogStartOffset::new(checkpoint.max(first)))
+    }
+    /// The cached end of a partition taken out of the cache, or loaded from
+    /// its files, to be put back once used.
+    fn take_end(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+        cached: &mut Option<LogEnd>,
+    ) -> Result<LogEnd> {
+        match cached.take() {
+            Some(end) => Ok(end),
+            None => {
+                let dir = Self::dir(log_dir, topic_name, partition);
+                let segments = Segment::list(&dir)?;
+                let log_start_offset = Self::start_offset(
+                    log_dir, topic_name, partition, &segments,
+                )?;
+                LogEnd::load(&dir, log_start_offset)
+            }
+        }
+    }
+    /// The log start and next offsets of a partition, without reading it.
+    pub fn end(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+    ) -> Result<(LogStartOffset, RecordOffset)> {
+        let dir = Self::dir(log_dir, topic_name, partition);
+        with_partition(&dir, |cached| {
+            let end = Self::take_end(log_dir, topic_name, partition, cached)?;
+            let offsets = (end.log_start_offset, end.next_offset);
+            *cached = Some(end);
+            Ok(offsets)
+        })
+    }
+    /// The part of a partition log from the entry holding `offset`, or the
+    /// log start offset if later, on. The offset index tells where to start
+    /// reading in its segment, earlier segments are not read.
+    pub fn read_from(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+        offset: RecordOffset,
+    ) -> Result<Log> {
+        let dir = Self::dir(log_dir, topic_name, partition);
+        with_partition(&dir, |cached| {
+            let end = Self::take_end(log_dir, topic_name, partition, cached)?;
+            let (log_start_offset, next_offset) =
+                (end.log_start_offset, end.next_offset);
+            let offset = (*offset).max(*log_start_offset);
+            let active = end.active.as_ref().map(|(segment, _, index)| {
+                let base_offset = *segment.base_offset;
+                (base_offset, index.lookup(base_offset, offset))
+            });
+            *cached = Some(end);
+
+            let segments = Segment::list(&dir)?;
+            let first = segments
+                .iter()
+                .rposition(|v| *v.base_offset <= offset)
+                .unwrap_or(0);
+            let mut entries = vec![];
+            for (i, segment) in segments.iter().enumerate().skip(first) {
+                let base_offset = *segment.base_offset;
+                let position = match active {
+                    _ if i > first => FilePosition::new(0),
+                    Some((active, position)) if active == base_offset =>
+                        position,
+                    _ => segment.index().lookup(base_offset, offset),
+                };
+                entries.extend(
+                    segment
+                        .read_from(position)?
+                        .into_iter()
+                        .filter(|v| *v.last_offset() >= offset),
+                );
+            }
+            Ok(Log {
+                entries,
+                log_start_offset,
+                next_offset,
+            })
+        })
+    }
+    /// The offset ListOffsets finds for `timestamp` with the timestamp of
+    /// its record: earliest is the log start offset, latest the next offset
+    /// and otherwise the first record at or after it, found through the
+    /// time indexes.
+    pub fn offset_for(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+        timestamp: TargetTimestamp,
+    ) -> Result<Option<(i64, RecordOffset)>> {
+        let target = match *timestamp {
+            TargetTimestamp::EARLIEST => {
+                let (log_start_offset, _) =
+                    Self::end(log_dir, topic_name, partition)?;
+                return Ok(Some((-1, RecordOffset::new(*log_start_offset))));
+            }
+            TargetTimestamp::LATEST => {
+                let (_, next_offset) =
+                    Self::end(log_dir, topic_name, partition)?;
+                return Ok(Some((-1, next_offset)));
+            }
+            TargetTimestamp::MAX_TIMESTAMP => {
+                let log = Self::load_log(log_dir, topic_name, partition)?;
+                return Ok(log
+                    .records()
+                    .into_iter()
+                    .filter_map(|(o, t)| t.map(|t| (*t as i64, o)))
+                    .max_by_key(|(t, _)| *t));
+            }
+            target => target.max(0) as u64,
+        };
+        let dir = Self::dir(log_dir, topic_name, partition);
+        with_partition(&dir, |_| {
+            let segments = Segment::list(&dir)?;
+            let log_start_offset =
+                Self::start_offset(log_dir, topic_name, partition, &segments)?;
+            for segment in &segments {
+                let position = segment
+                    .index()
+                    .lookup_timestamp(*segment.base_offset, target);
+                let found = segment
+                    .read_from(position)?
+                    .iter()
+                    .flat_map(LogEntry::records)
+                    .filter(|(o, _)| **o >= *log_start_offset)
+                    .find_map(|(o, t)| {
+                        t.filter(|t| **t >= target).map(|t| (*t as i64, o))
+                    });
+                if found.is_some() {
+                    return Ok(found);
+                }
+            }
+            Ok(None)
+        })
+    }
+    /// Checks the batches of a Produce request and appends them after the
+    /// last offset of the partition, returning the base offset assigned and
+    /// the log start offset. A new segment is rolled once the active one
+    /// reaches `segment.bytes`.
+    pub fn append(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+        config: &LogConfig,
+        records: &[u8],
+    ) -> Result<(RecordOffset, LogStartOffset)> {
+        let dir = Self::dir(log_dir, topic_name, partition);
+        with_partition(&dir, |cached| {
+            let end = Self::take_end(log_dir, topic_name, partition, cached)?;
+            // left uncached on an error, to be loaded again from the files
+            let (base_offset, end) =
+                Self::append_to(&dir, end, config, records)?;
+            let log_start_offset = end.log_start_offset;
+            *cached = Some(end);
+            Ok((base_offset, log_start_offset))
+        })
+    }
+    fn append_to(
+        dir: &Path,
+        end: LogEnd,
+        config: &LogConfig,
+        records: &[u8],
+    ) -> Result<(RecordOffset, LogEnd)> {
+        let base_offset = end.next_offset;
+        let (batches, next) = Batch::split_by_batch(records.to_vec())?
+            .into_iter()
+            .fold((vec![], *base_offset), |(mut batches, offset), batch| {
+                let batch = batch.set_offset(BatchOffset::new(offset));
+                let next = *batch.last_offset() + 1;
+                batches.push(LogEntry::Batch(batch));
+                (batches, next)
+            });
+        let (active, mut size, mut index) = match end.active {
+            Some((segment, size, index))
+                if (size as u64) < config.segment_bytes =>
+                (segment, size, index),
+            _ => (Segment::new(dir, base_offset), 0, Index::default()),
+        };
+        let mut bytes = vec![];
+        for batch in batches {
+            index.add(*active.base_offset, size, &batch)?;
+            size += batch.size();
+            bytes.extend(Vec::<u8>::from(batch));
+        }
+        append(&active.path(), &bytes)?;
+        index.write(&active.path())?;
+        Ok((
+            base_offset,
+            LogEnd {
+                log_start_offset: end.log_start_offset,
+                next_offset: RecordOffset::new(next),
+                active: Some((active, size, index)),
+            },
+        ))
+    }
+    /// DeleteRecords: moves the log start offset up to `offset` and drops
+    /// the segments wholly below it.
+    pub fn delete_records(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+        offset: RecordOffset,
+    ) -> Result<LogStartOffset> {
+        let dir = Self::dir(log_dir, topic_name, partition);
+        with_partition(&dir, |cached| {
+            *cached = None;
+            Self::delete_records_locked(log_dir, topic_name, partition, offset)
+        })
+    }
+    fn delete_records_locked(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+        offset: RecordOffset,
+    ) -> Result<LogStartOffset> {
+        let log = Self::load(log_dir, topic_name, partition)?;
+        let log_start_offset =
+            LogStartOffset::new((*offset).max(*log.log_start_offset));
+        let segments =
+            Segment::list(&Self::dir(log_dir, topic_name, partition))?;
+        for pair in segments.windows(2) {
+            if *pair[1].base_offset <= *log_start_offset {
+                pair[0].delete()?;
+            }
+        }
+        update_checkpoint(log_dir, topic_name, partition, log_start_offset)?;
+        Ok(log_start_offset)
+    }
+    /// Deletes the oldest segments past `retention.ms` or beyond
+    /// `retention.bytes`, never the active one, and moves the log start
+    /// offset to the first segment left. Returns how many were deleted.
+    pub fn retain(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+        config: &LogConfig,
+        now: u64,
+    ) -> Result<usize> {
+        let dir = Self::dir(log_dir, topic_name, partition);
+        with_partition(&dir, |cached| {
+            *cached = None;
+            Self::retain_locked(log_dir, topic_name, partition, config, now)
+        })
+    }
+    fn retain_locked(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+        config: &LogConfig,
+        now: u64,
+    ) -> Result<usize> {
+        let mut segments =
+            Segment::list(&Self::dir(log_dir, topic_name, partition))?;
+        let active = match segments.pop() {
+            None => return Ok(0),
+            Some(v) => v,
+        };
+        let mut size: u64 =
+            segments.iter().map(Segment::size).sum::<u64>() + active.size();
+        let mut deleted = 0;
+        for segment in &segments {
+            let expired = config.retention_ms >= 0
+                && now.saturating_sub(segment.newest_timestamp()?)
+                    > config.retention_ms as u64;
+            let oversized = config.retention_bytes >= 0
+                && size - segment.size() >= config.retention_bytes as u64;
+            if !expired && !oversized {
+                break;
+            }
+            size -= segment.size();
+            segment.delete()?;
+            deleted += 1;
+        }
+        if deleted > 0 {
+            let first = segments.get(deleted).unwrap_or(&active).base_offset;
+            let log = Self::load(log_dir, topic_name, partition)?;
+            let log_start_offset =
+                LogStartOffset::new((*first).max(*log.log_start_offset));
+            update_checkpoint(
+                log_dir,
+                topic_name,
+                partition,
+                log_start_offset,
+            )?;
+        }
+        Ok(deleted)
+    }
+    /// Keeps only the latest record of every key in the segments before the
+    /// active one and drops tombstones older than `delete.retention.ms`.
+    /// Offsets are left as they are, a consumer just sees gaps. Records
+    /// without a key and legacy messages are kept. Returns how many records
+    /// were removed.
+    pub fn compact(
+        log_dir: &str,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+        config: &LogConfig,
+        now: u64,
+    ) -> Result<usize> {
+        let dir = Self::dir(log_dir, topic_name, partition);
+        with_partition(&dir, |cached| {
+            *cached = None;
+            Self::compact_segments(&dir, config, now)
+        })
+    }
+    fn compact_segments(
+        dir: &Path,
+        config: &LogConfig,
+        now: u64,
+    ) -> Result<usize> {
+        let mut segments = Segment::list(dir)?;
+        if segments.pop().is_none() {
+            return Ok(0);
+        }
+        let cleanable = segments
+            .into_iter()
+            .map(|s| s.read().map(|entries| (s, entries)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut latest: HashMap<Vec<u8>, RecordOffset> = HashMap::new();
+        for (_, entries) in &cleanable {
+            for entry in entries {
+                if let LogEntry::Batch(batch) = entry {
+                    for record in batch.batch_records() {
+                        if let Some(key) = record.key() {
+                            latest.insert(
+                                key.to_vec(),
+                                batch.record_offset(record),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        let delete_retention_ms = config.delete_retention_ms.max(0) as u64;
+        let mut removed = 0;
+        for (segment, entries) in cleanable {
+            let mut dirty = false;
+            let cleaned: Vec<LogEntry> = entries
+                .into_iter()
+                .filter_map(|entry| match entry {
+                    LogEntry::Batch(batch) => {
+                        let keep = |r: &Record| match r.key() {
+                            None => true,
+                            Some(key) => {
+                                let offset = batch.record_offset(r);
+                                let timestamp = *batch.record_timestamp(r);
+                                latest.get(&**key) == Some(&offset)
+                                    && (r.value().is_some()
+                                        || now.saturating_sub(timestamp)
+                                            <= delete_retention_ms)
+                            }
+                        };
+                        let filtered = batch.filter_records(keep);
+                        let count = batch.batch_records().len();
+                        removed += count - filtered.batch_records().len();
+                        match filtered.batch_records().len() {
+                            n if n == count => Some(LogEntry::Batch(batch)),
+                            0 => {
+                                dirty = true;
+                                None
+                            }
+                            _ => {
+                                dirty = true;
+                                Some(LogEntry::Batch(filtered))
+                            }
+                        }
+                    }
+                    message => Some(message),
+                })
+                .collect();
+            if dirty {
+                segment.replace(cleaned)?;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// `log-start-offset-checkpoint` in Kafka's format: a version line, a
+/// count line, then one `topic partition offset` line per partition.
+fn read_checkpoint(log_dir: &str) -> Result<HashMap<(String, u32), u64>> {
+    let path = Path::new(log_dir).join(LOG_START_OFFSET_CHECKPOINT);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let bytes = read(&path.to_string_lossy())?;
+    Ok(String::from_utf8_lossy(&bytes)
+        .lines()
+        .skip(2)
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let topic = fields.next()?.to_string();
+            let partition = fields.next()?.parse().ok()?;
+            let offset = fields.next()?.parse().ok()?;
+            Some(((topic, partition), offset))
+        })
+        .collect())
+}
+
+fn update_checkpoint(
+    log_dir: &str,
+    topic_name: &TopicName,
+    partition: PartitionIndex,
+    log_start_offset: LogStartOffset,
+) -> Result<()> {
+    let _lock = CHECKPOINT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut checkpoint = read_checkpoint(log_dir)?;
+    checkpoint.insert((topic_name.to_string(), *partition), *log_start_offset);
+    let mut lines: Vec<String> =
+        checkpoint.iter().map(|((t, p), o)| format!("{t} {p} {o}")).collect();
+    lines.sort();
+    let content = format!("0\n{}\n{}\n", lines.len(), lines.join("\n"));
+    // through a synced temporary file, so a crash leaves the old or the new
+    // checkpoint and never a torn one
+    let path = Path::new(log_dir).join(LOG_START_OFFSET_CHECKPOINT);
+    let tmp = path.with_extension("tmp");
+    File::create(&tmp)
+        .and_then(|mut f| {
+            f.write_all(content.as_bytes())?;
+            f.sync_all()
+        })
+        .context("Writing log start offset checkpoint")?;
+    rename(&tmp, &path).context("Replacing log start offset checkpoint")?;
+    File::open(log_dir)
+        .and_then(|f| f.sync_all())
+        .context("Syncing log directory")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CleanupPolicy, Message, MessageAttributes, SignedVarInt};
+    use bytes::BufMut;
+    use std::fs::{create_dir_all, write};
+    use std::time::SystemTime;
+
+    fn message(offset: u64, timestamp: u64) -> Vec<u8> {
+        Message::new(
+            RecordOffset::new(offset),
+            MagicByte::new(1),
+            MessageAttributes::new(0),
+            Some(RecordTimestamp::new(timestamp)),
+            None,
+            Some(b"value".to_vec()),
+        )
+        .into()
+    }
+
+    #[test]
+    fn test_read_through_indexes() -> Result<()> {
+        let log_dir = std::env::temp_dir()
+            .join(format!("log-index-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let topic_name = TopicName::from("foo");
+        let partition = PartitionIndex::new(0);
+        let dir = Log::dir(&log_dir, &topic_name, partition);
+        create_dir_all(&dir).context("test dir")?;
+        // messages of 2KB written at 1000, 1010, ..., so an index entry
+        // every other one, in two segments of five
+        for base in [0, 5] {
+            let segment = Segment::new(&dir, RecordOffset::new(base));
+            let entries = (base..base + 5)
+                .map(|offset| {
+                    LogEntry::Message(Message::new(
+                        RecordOffset::new(offset),
+                        MagicByte::new(1),
+                        MessageAttributes::new(0),
+                        Some(RecordTimestamp::new(1000 + offset * 10)),
+                        None,
+                        Some(vec![0; 2048]),
+                    ))
+                })
+                .collect::<Vec<_>>();
+            write(
+                &segment.path,
+                entries
+                    .iter()
+                    .cloned()
+                    .flat_map(Vec::<u8>::from)
+                    .collect::<Vec<_>>(),
+            )
+            .context("test segment")?;
+            segment.write_index(&entries)?;
+        }
+        update_checkpoint(
+            &log_dir,
+            &topic_name,
+            partition,
+            LogStartOffset::new(1),
+        )?;
+
+        let read_from = |offset| -> Result<Vec<u64>> {
+            let log = Log::read_from(
+                &log_dir,
+                &topic_name,
+                partition,
+                RecordOffset::new(offset),
+            )?;
+            assert_eq!(*log.log_start_offset(), 1);
+            assert_eq!(*log.next_offset(), 10);
+            Ok(log.records().iter().map(|(o, _)| **o).collect())
+        };
+        assert_eq!(read_from(0)?, (1..10).collect::<Vec<_>>());
+        assert_eq!(read_from(3)?, (3..10).collect::<Vec<_>>());
+        assert_eq!(read_from(8)?, vec![8, 9]);
+        assert_eq!(read_from(12)?, vec![]);
+
+        let offset_for = |timestamp| {
+            Log::offset_for(
+                &log_dir,
+                &topic_name,
+                partition,
+                TargetTimestamp::new(timestamp),
+            )
+            .map(|v| v.map(|(t, o)| (t, *o)))
+        };
+        assert_eq!(offset_for(TargetTimestamp::EARLIEST)?, Some((-1, 1)));
+        assert_eq!(offset_for(TargetTimestamp::LATEST)?, Some((-1, 10)));
+        assert_eq!(
+            offset_for(TargetTimestamp::MAX_TIMESTAMP)?,
+            Some((1090, 9))
+        );
+        // records before the log start offset are not found
+        assert_eq!(offset_for(0)?, Some((1010, 1)));
+        assert_eq!(offset_for(1035)?, Some((1040, 4)));
+        assert_eq!(offset_for(1060)?, Some((1060, 6)));
+        assert_eq!(offset_for(1100)?, None);
+
+        std::fs::remove_dir_all(log_dir).context("cleanup")
+    }
+
+    #[test]
+    fn test_retention_without_timestamps() -> Result<()> {
+        let log_dir = std::env::temp_dir()
+            .join(format!("log-v0-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let topic_name = TopicName::from("foo");
+        let partition = PartitionIndex::new(0);
+        let dir = Log::dir(&log_dir, &topic_name, partition);
+        create_dir_all(&dir).context("test dir")?;
+        for base in [0, 1] {
+            let segment = Segment::new(&dir, RecordOffset::new(base));
+            let message = Message::new(
+                RecordOffset::new(base),
+                MagicByte::new(0),
+                MessageAttributes::new(0),
+                None,
+                None,
+                Some(b"value".to_vec()),
+            );
+            write(&segment.path, Vec::<u8>::from(message))
+                .context("test segment")?;
+        }
+        let config = LogConfig {
+            retention_ms: 60_000,
+            ..LogConfig::default()
+        };
+        // aged from the file just written, not from the epoch
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |v| v.as_millis() as u64);
+        let retain =
+            |now| Log::retain(&log_dir, &topic_name, partition, &config, now);
+        assert_eq!(retain(now)?, 0);
+        assert_eq!(retain(now + 120_000)?, 1);
+
+        std::fs::remove_dir_all(log_dir).context("cleanup")
+    }
+
+    #[test]
+    fn test_retention_and_delete_records() -> Result<()> {
+        let log_dir = std::env::temp_dir()
+            .join(format!("log-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let topic_name = TopicName::from("foo");
+        let partition = PartitionIndex::new(0);
+        let dir = Log::dir(&log_dir, &topic_name, partition);
+        create_dir_all(&dir).context("test dir")?;
+        // three segments of two messages each, written at 1000, 2000, 3000
+        for base in [0, 2, 4] {
+            let segment = Segment::new(&dir, RecordOffset::new(base));
+            let ts = (base + 2) * 500;
+            let bytes = [message(base, ts), message(base + 1, ts)].concat();
+            write(&segment.path, bytes).context("test segment")?;
+        }
+
+        let config = LogConfig {
+            retention_ms: 1500,
+            ..LogConfig::default()
+        };
+        assert_eq!(
+            Log::retain(&log_dir, &topic_name, partition, &config, 3400)?,
+            1
+        );
+        let log = Log::load_log(&log_dir, &topic_name, partition)?;
+        assert_eq!(*log.log_start_offset(), 2);
+
+        let log_start_offset = Log::delete_records(
+            &log_dir,
+            &topic_name,
+            partition,
+            RecordOffset::new(5),
+        )?;
+        assert_eq!(*log_start_offset, 5);
+        assert_eq!(Segment::list(&dir)?.len(), 1);
+        let log = Log::load_log(&log_dir, &topic_name, partition)?;
+        let offsets: Vec<u64> =
+            log.records().iter().map(|(o, _)| **o).collect();
+        assert_eq!(offsets, vec![5]);
+        assert_eq!(*log.next_offset(), 6);
+
+        std::fs::remove_dir_all(log_dir).context("cleanup")
+    }
+
+    // A v2 batch of one record at timestamp 1000, `None` value is a
+    // tombstone.
+    fn keyed(offset: u64, key: &[u8], value: Option<&[u8]>) -> Vec<u8> {
+        let mut record = vec![0, 0, 0];
+        record.extend(SignedVarInt::encode(key.len() as i64));
+        record.extend(key);
+        match value {
+            Some(v) => {
+                record.extend(SignedVarInt::encode(v.len() as i64));
+                record.extend(v);
+            }
+            None => record.extend(SignedVarInt::encode(-1)),
+        }
+        record.push(0);
+        let mut body = vec![];
+        body.put_u16(0);
+        body.put_u32(0);
+        body.put_u64(1000);
+        body.put_u64(1000);
+        body.put_u64(u64::MAX);
+        body.put_u16(u16::MAX);
+        body.put_u32(u32::MAX);
+        body.put_u32(1);
+        body.extend(SignedVarInt::encode(record.len() as i64));
+        body.extend(record);
+        let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI).checksum(&body);
+        let mut batch = vec![];
+        batch.put_u32(0);
+        batch.put_u8(2);
+        batch.put_u32(crc);
+        batch.extend(body);
+        let mut bytes = offset.to_be_bytes().to_vec();
+        bytes.put_u32(batch.len() as u32);
+        bytes.extend(batch);
+        bytes
+    }
+
+    #[test]
+    fn test_compact() -> Result<()> {
+        let log_dir = std::env::temp_dir()
+            .join(format!("compact-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let topic_name = TopicName::from("foo");
+        let partition = PartitionIndex::new(0);
+        let dir = Log::dir(&log_dir, &topic_name, partition);
+        create_dir_all(&dir).context("test dir")?;
+        let segments = [
+            (0, vec![keyed(0, b"a", Some(b"1")), keyed(1, b"b", Some(b"1"))]),
+            (2, vec![keyed(2, b"a", Some(b"2")), keyed(3, b"b", None)]),
+            (4, vec![keyed(4, b"a", Some(b"3"))]),
+        ];
+        for (base, batches) in segments {
+            let segment = Segment::new(&dir, RecordOffset::new(base));
+            write(&segment.path, batches.concat()).context("test segment")?;
+        }
+        let offsets = || -> Result<Vec<u64>> {
+            let log = Log::load_log(&log_dir, &topic_name, partition)?;
+            Ok(log.records().iter().map(|(o, _)| **o).collect())
+        };
+        let config = LogConfig {
+            cleanup_policy: CleanupPolicy::Compact,
+            delete_retention_ms: 500,
+            ..LogConfig::default()
+        };
+
+        // the active segment is left alone, the tombstone is still fresh
+        assert_eq!(
+            Log::compact(&log_dir, &topic_name, partition, &config, 1200)?,
+            2
+        );
+        assert_eq!(offsets()?, vec![2, 3, 4]);
+
+        assert_eq!(
+            Log::compact(&log_dir, &topic_name, partition, &config, 2000)?,
+            1
+        );
+        assert_eq!(offsets()?, vec![2, 4]);
+        let log = Log::load_log(&log_dir, &topic_name, partition)?;
+        assert_eq!(*log.next_offset(), 5);
+
+        std::fs::remove_dir_all(log_dir).context("cleanup")
+    }
+
+    #[test]
+    fn test_append() -> Result<()> {
+        let log_dir = std::env::temp_dir()
+            .join(format!("append-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let topic_name = TopicName::from("foo");
+        let partition = PartitionIndex::new(0);
+        let dir = Log::dir(&log_dir, &topic_name, partition);
+        create_dir_all(&dir).context("test dir")?;
+        let config = LogConfig {
+            segment_bytes: 100,
+            ..LogConfig::default()
+        };
+        let produce = |batches: Vec<Vec<u8>>| {
+            Log::append(
+                &log_dir,
+                &topic_name,
+                partition,
+                &config,
+                &batches.concat(),
+            )
+            .map(|(offset, log_start_offset)| (*offset, *log_start_offset))
+        };
+
+        // a batch is about 70 bytes, a segment is rolled after two
+        assert_eq!(produce(vec![keyed(9, b"a", Some(b"1"))])?, (0, 0));
+        assert_eq!(
+            produce(vec![keyed(0, b"b", Some(b"1")), keyed(0, b"c", None)])?,
+            (1, 0)
+        );
+        assert_eq!(produce(vec![keyed(0, b"a", Some(b"2"))])?, (3, 0));
+        let segments = Segment::list(&dir)?;
+        let bases: Vec<u64> = segments.iter().map(|s| *s.base_offset).collect();
+        assert_eq!(bases, vec![0, 3]);
+        // the indexes kept up to date are those built from the segments
+        for segment in &segments {
+            let stem = segment.path.with_extension("");
+            let files = || {
+                ["index", "timeindex"]
+                    .map(|ext| std::fs::read(stem.with_extension(ext)).unwrap())
+            };
+            let appended = files();
+            segment.write_index(&segment.read()?)?;
+            assert_eq!(appended, files());
+        }
+
+        // DeleteRecords drops the first segment, appends carry on after it
+        Log::delete_records(
+            &log_dir,
+            &topic_name,
+            partition,
+            RecordOffset::new(4),
+        )?;
+        assert_eq!(produce(vec![keyed(0, b"b", Some(b"2"))])?, (4, 4));
+        let log = Log::load_log(&log_dir, &topic_name, partition)?;
+        let offsets: Vec<u64> =
+            log.records().iter().map(|(o, _)| **o).collect();
+        assert_eq!(offsets, vec![4]);
+        assert_eq!(*log.next_offset(), 5);
+
+        std::fs::remove_dir_all(log_dir).context("cleanup")
+    }
+}
@@ -1,57 +1,297 @@
-use std::io::Write;
-use std::net::{TcpListener, TcpStream};
+use std::io::{IsTerminal, Read};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use bytes::BufMut;
 use codecrafters_kafka::{
-    Context, CorrelationId, Error, ErrorCode, MessageSize, Request, Response,
-    Result,
+    accept_tls, api_handler, broker_config, close_logs, connections,
+    fetch_purgatory, listeners, load_broker_config, log_request, metrics,
+    recover, spawn_cleaner, spawn_connection_reaper, spawn_fetch_purgatory,
+    spawn_metrics, tls_config, ApiKey, ClientSoftware, Connection,
+    ConnectionHandle, Context, CorrelationId, Error, ErrorCode, Listener,
+    MessageSize, Request, Response, Result, SaslState, SecurityProtocol,
+    LOG_DIR, METADATA_LOG,
 };
+use rustls::ServerConfig;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use tracing::{debug, info, warn};
+use tracing_subscriber::EnvFilter;
 
-fn error_response(correlation_id: &CorrelationId) -> Vec<u8> {
+/// An ApiVersions request of a version the broker does not serve is
+/// answered in the v0 layout, which a client can read whatever version it
+/// sent, with the ApiVersions versions to retry with.
+fn unsupported_api_versions(correlation_id: &CorrelationId) -> Vec<u8> {
     let mut error: Vec<u8> = Vec::new();
-    error.put_u32(*MessageSize::new(10));
     error.put_u32(**correlation_id);
     error.put_i16(*ErrorCode::UnsupportedVersion);
-    error
-}
-
-fn process_stream(stream: &mut TcpStream) -> Result<Vec<u8>> {
-    println!("accepted new connection");
-    let req: Result<Request> = stream.try_into();
-    let res: Vec<u8> = req
-        .and_then(|r| Response::response(&r))
-        .map(|v| v.into())
-        .unwrap_or_else(|e| match e {
-            Error::UnsupportedApiVersion(_, Some(id)) => error_response(&id),
-            Error::UnsupportedApiKey(_, Some(id)) => error_response(&id),
-            Error::ErrorWrapper(txt, err) => Vec::new(),
-            e => Vec::new(),
-        });
-    Ok(res)
+    error.put_u32(1);
+    if let Ok(handler) = api_handler(ApiKey::ApiVersions) {
+        error.put_u16(*handler.api_key);
+        error.put_u16(*handler.min);
+        error.put_u16(*handler.max);
+    }
+    let mut bytes = Vec::new();
+    bytes.put_u32(*MessageSize::new(error.len() as u32));
+    bytes.extend(error);
+    bytes
 }
 
-fn main() -> Result<()> {
-    // You can use print statements as follows for debugging, they'll be visible when running tests.
-    println!("Logs from your program will appear here!");
+/// Unauthenticated requests other than ApiVersions and SASL, read
+/// failures, requests over `socket.request.max.bytes`, requests of an
+/// unknown API and requests whose header does not parse close the
+/// connection, other failures are answered with an error response in the
+/// layout of the requested API. The response goes to `reply`, later
+/// for a parked Fetch, and the client is throttled for the returned time.
+fn process_stream<S: Read>(
+    stream: &mut S,
+    sasl: &mut SaslState,
+    client_software: &mut Option<ClientSoftware>,
+    host: &str,
+    connection: &ConnectionHandle,
+    reply: &Sender<Vec<u8>>,
+) -> Result<Duration> {
+    let req: Result<Request> =
+        Request::read(stream).map(|r| r.with_client_host(host.to_string()));
+    let req = match req.and_then(|r| sasl.authorize(r)) {
+        Err(e @ Error::SaslAuthenticationRequired(_)) => return Err(e),
+        Err(e @ Error::ConnectionClosed(_)) => return Err(e),
+        Err(e @ Error::RequestTooLarge(..)) => {
+            warn!(host, "closing connection: {}", e);
+            return Err(e);
+        }
+        req => req,
+    };
+    // in flight until the writer has sent the response
+    connection.received();
+    let response = req.and_then(|r| {
+        let started = Instant::now();
+        let response = Response::response(&r, sasl, client_software)?;
+        connection.request(
+            &r,
+            client_software.as_ref(),
+            response.fetch_context(),
+        );
+        let header = r.header.clone();
+        Ok(fetch_purgatory()
+            .try_complete(r, response, reply, started)
+            .map(|v| (header, v)))
+    });
+    let (res, throttle) = match response {
+        // parked, the purgatory replies
+        Ok(None) => return Ok(Duration::ZERO),
+        Ok(Some((header, v))) => {
+            log_request(&header, v.error_code());
+            let throttle = Duration::from_millis(*v.throttle_time() as u64);
+            (v.into(), throttle)
+        }
+        Err(Error::UnsupportedApiVersion(_, Some(id))) =>
+            (unsupported_api_versions(&id), Duration::ZERO),
+        Err(ref e @ Error::MalformedRequest(ref header, _)) => {
+            debug!(host, "{}", e);
+            log_request(header, e.error_code());
+            (Response::failed(header, None, e)?.into(), Duration::ZERO)
+        }
+        // a request without a header to answer to
+        Err(e) => return Err(e),
+    };
+    // only fails once the writer is gone with the connection
+    let _ = reply.send(res);
+    Ok(throttle)
+}
 
-    // Uncomment this block to pass the first stage
-    //
-    let listener = TcpListener::bind("127.0.0.1:9092")
+/// Responses are written in request order by a thread of their own, so a
+/// parked Fetch holds back the responses after it but not the reading of
+/// further requests. Reads and writes keep the connection from idling.
+fn handle<S: Connection>(
+    mut stream: S,
+    mut sasl: SaslState,
+    host: String,
+    connection: &ConnectionHandle,
+) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => return warn!(host, "{}", e),
+    };
+    let (replies, queue) = mpsc::channel::<Receiver<Vec<u8>>>();
+    let id = connection.id();
+    let writing = thread::spawn(move || {
+        for reply in queue {
+            match reply.recv() {
+                Ok(resp) if writer.write_all(&resp).is_ok() =>
+                    connections().answered(id),
+                _ => break,
+            }
+        }
+    });
+    debug!(host, "accepted connection");
+    metrics().connection_opened();
+    // as named in ApiVersions, for metrics
+    let mut client_software = None;
+    loop {
+        let (reply, response) = mpsc::channel();
+        match process_stream(
+            &mut stream,
+            &mut sasl,
+            &mut client_software,
+            &host,
+            connection,
+            &reply,
+        ) {
+            Ok(throttle) if replies.send(response).is_ok() =>
+            // muted while throttled, as Kafka stops reading from the
+            // channel
+                thread::sleep(throttle),
+            _ => break,
+        }
+    }
+    drop(replies);
+    let _ = writing.join();
+    metrics().connection_closed();
+}
+
+/// The client address ACL hosts are matched against.
+fn peer_host(stream: &TcpStream) -> String {
+    stream.peer_addr().map(|a| a.ip().to_string()).unwrap_or_default()
+}
+
+/// Binds a listener, SSL ones with their certificate loaded.
+fn bind(
+    listener: &Listener,
+) -> Result<(TcpListener, Option<Arc<ServerConfig>>)> {
+    let tls = match listener.security_protocol {
+        SecurityProtocol::Ssl => Some(tls_config()?),
+        SecurityProtocol::Plaintext => None,
+    };
+    let tcp = TcpListener::bind(&listener.address)
         .with_context(|| "Unable to create tcp listener")?;
+    Ok((tcp, tls))
+}
+
+/// SSL connections are authenticated by their client certificate and skip
+/// SASL. Once the broker is shutting down the next accept, if only the one
+/// waking it, ends the loop and the connections still open are waited for.
+fn serve(listener: Listener, tcp: TcpListener, tls: Option<Arc<ServerConfig>>) {
     let mut handlers = vec![];
-    for stream in listener.incoming() {
-        let handler = thread::spawn(move || match stream {
-            Ok(mut stream) =>
-                while let Ok(resp) = process_stream(&mut stream) {
-                    stream.write(resp.as_ref()).context("").unwrap();
+    for stream in tcp.incoming() {
+        if connections().is_closing() {
+            break;
+        }
+        let tls = tls.clone();
+        let listener = listener.clone();
+        let handler = thread::spawn(move || {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => return warn!("accept failed: {}", e),
+            };
+            let host = peer_host(&stream);
+            let registered =
+                match connections().register(&stream, &host, &listener) {
+                    Ok(registered) => registered,
+                    Err(e) => return warn!(host, "connection refused: {}", e),
+                };
+            match tls {
+                None => handle(stream, SaslState::default(), host, &registered),
+                Some(config) => match accept_tls(config, stream) {
+                    Ok((stream, principal)) => handle(
+                        stream,
+                        SaslState::authenticated(principal),
+                        host,
+                        &registered,
+                    ),
+                    Err(e) => warn!(host, "TLS handshake failed: {}", e),
                 },
-            Err(e) => {
-                println!("error: {}", e);
             }
         });
         handlers.push(handler);
     }
     handlers.into_iter().for_each(|i| i.join().unwrap());
-    Ok(())
+}
+
+/// How long a shutdown waits for connections to finish their requests,
+/// `shutdown.timeout.ms`.
+fn shutdown_timeout() -> Duration {
+    let ms = broker_config()
+        .get("shutdown.timeout.ms")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30_000);
+    Duration::from_millis(ms)
+}
+
+/// Connects to a listener so its blocked accept returns.
+fn wake(address: SocketAddr) {
+    let address = match address.ip() {
+        ip if ip.is_unspecified() && ip.is_ipv4() =>
+            SocketAddr::from((Ipv4Addr::LOCALHOST, address.port())),
+        ip if ip.is_unspecified() =>
+            SocketAddr::from((Ipv6Addr::LOCALHOST, address.port())),
+        _ => address,
+    };
+    let _ = TcpStream::connect_timeout(&address, Duration::from_secs(1));
+}
+
+fn main() -> Result<()> {
+    // RUST_LOG filters, e.g. `RUST_LOG=debug,kafka.request.logger=off`
+    tracing_subscriber::fmt()
+        .with_ansi(std::io::stdout().is_terminal())
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+    if let Some(path) = std::env::args().nth(1) {
+        load_broker_config(&path)?;
+    }
+    recover(LOG_DIR)?;
+    let cleaner =
+        spawn_cleaner(LOG_DIR, METADATA_LOG, Duration::from_secs(300));
+    spawn_fetch_purgatory();
+    spawn_connection_reaper();
+    // Prometheus scrapes `GET /metrics` here, e.g. `localhost:9404`
+    if let Some(address) = broker_config().get("metrics.listener") {
+        let metrics = TcpListener::bind(address)
+            .with_context(|| "Unable to create metrics listener")?;
+        spawn_metrics(metrics);
+    }
+
+    let mut signals = Signals::new([SIGTERM, SIGINT])
+        .with_context(|| "Unable to register signal handlers")?;
+    let bound = listeners()?
+        .into_iter()
+        .map(|l| bind(&l).map(|(tcp, tls)| (l, tcp, tls)))
+        .collect::<Result<Vec<_>>>()?;
+    let addresses = bound
+        .iter()
+        .filter_map(|(_, tcp, _)| tcp.local_addr().ok())
+        .collect::<Vec<_>>();
+    let listeners = bound
+        .into_iter()
+        .map(|(l, tcp, tls)| thread::spawn(move || serve(l, tcp, tls)))
+        .collect::<Vec<_>>();
+    let (stopped, drained) = mpsc::channel();
+    thread::spawn(move || {
+        listeners.into_iter().for_each(|l| l.join().unwrap());
+        let _ = stopped.send(());
+    });
+
+    if let Some(signal) = signals.forever().next() {
+        info!(signal, "shutting down");
+    }
+    // in-flight requests are answered, parked fetches with what they have
+    connections().close_all();
+    fetch_purgatory().expire_all();
+    addresses.into_iter().for_each(wake);
+    let timeout = shutdown_timeout();
+    let drained = drained.recv_timeout(timeout).is_ok();
+    close_logs(LOG_DIR, cleaner, drained)?;
+    if drained {
+        info!("shut down cleanly");
+    } else {
+        // a write may still be under way, recovery checks the logs instead
+        warn!(?timeout, "connections still open, shutting down uncleanly");
+    }
+    std::process::exit(0)
 }
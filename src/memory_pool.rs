@@ -0,0 +1,129 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex, OnceLock};
+
+use crate::broker_config;
+
+/// Kafka's `socket.request.max.bytes` default.
+const REQUEST_MAX_BYTES: u32 = 104_857_600;
+
+/// The largest request a client may send, larger ones close the
+/// connection before their buffer is allocated.
+pub fn request_max_bytes() -> u32 {
+    broker_config()
+        .get("socket.request.max.bytes")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(REQUEST_MAX_BYTES)
+}
+
+/// Bounds the memory taken by request buffers across connections, with
+/// `queued.max.request.bytes`, -1 by default for no bound. A read waits
+/// for its buffer while the pool is full, so a busy broker stops reading
+/// instead of running out of memory.
+#[derive(Debug, Default)]
+pub struct MemoryPool {
+    capacity: Option<usize>,
+    used: Mutex<usize>,
+    released: Condvar,
+}
+
+static MEMORY_POOL: OnceLock<MemoryPool> = OnceLock::new();
+
+pub fn memory_pool() -> &'static MemoryPool {
+    MEMORY_POOL.get_or_init(|| {
+        let capacity = broker_config()
+            .get("queued.max.request.bytes")
+            .and_then(|v| v.parse::<i64>().ok())
+            .and_then(|v| usize::try_from(v).ok())
+            .filter(|v| *v > 0);
+        MemoryPool::new(capacity)
+    })
+}
+
+impl MemoryPool {
+    pub fn new(capacity: Option<usize>) -> Self {
+        Self {
+            capacity,
+            ..Self::default()
+        }
+    }
+    /// A zeroed buffer of `size` bytes, returned to the pool once dropped.
+    /// A buffer larger than the pool waits until the pool is empty rather
+    /// than for ever.
+    pub fn allocate(&self, size: usize) -> PooledBuffer<'_> {
+        let mut used = self.used.lock().unwrap();
+        if let Some(capacity) = self.capacity {
+            while *used > 0 && *used + size > capacity {
+                used = self.released.wait(used).unwrap();
+            }
+        }
+        *used += size;
+        PooledBuffer {
+            pool: self,
+            bytes: vec![0; size],
+        }
+    }
+    /// Bytes handed out and not yet returned.
+    pub fn used(&self) -> usize {
+        *self.used.lock().unwrap()
+    }
+    fn release(&self, size: usize) {
+        *self.used.lock().unwrap() -= size;
+        self.released.notify_all();
+    }
+}
+
+/// A request buffer taken from a `MemoryPool`.
+#[derive(Debug)]
+pub struct PooledBuffer<'a> {
+    pool: &'a MemoryPool,
+    bytes: Vec<u8>,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.bytes
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.bytes
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        self.pool.release(self.bytes.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_memory_pool() {
+        let pool: &'static MemoryPool =
+            Box::leak(Box::new(MemoryPool::new(Some(10))));
+        let first = pool.allocate(6);
+        assert_eq!(pool.used(), 6);
+        // a second buffer does not fit until the first is returned
+        let (sent, allocated) = mpsc::channel();
+        let waiting = thread::spawn(move || {
+            let buffer = pool.allocate(6);
+            sent.send(buffer.len()).unwrap();
+        });
+        assert!(allocated.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(first);
+        assert_eq!(allocated.recv().unwrap(), 6);
+        waiting.join().unwrap();
+        // larger than the pool, but the pool is empty
+        assert_eq!(pool.allocate(20).len(), 20);
+        assert_eq!(pool.used(), 0);
+    }
+}
@@ -0,0 +1,470 @@
+use crate::{
+    Batch, BatchOffset, BytesOps, Compression, Error, MagicByte, MapTupleTwo,
+    RecordKey, RecordOffset, RecordTimestamp, Result, ToNullableBytes, CRC,
+};
+use bytes::BufMut;
+use newtype_macro::newtype;
+
+// Legacy messages are checksummed with the IEEE polynomial, v2 batches use
+// Castagnoli.
+const CRC_32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+#[newtype]
+pub struct MessageAttributes(u8);
+
+/// A v0 or v1 message of the legacy MessageSet format.
+///
+/// A compressed wrapper keeps its compressed value so it re-encodes to the
+/// same bytes, and exposes the decompressed messages through `messages`.
+#[derive(Debug, Clone)]
+pub struct Message {
+    offset: RecordOffset,
+    crc: CRC,
+    magic_byte: MagicByte,
+    attributes: MessageAttributes,
+    timestamp: Option<RecordTimestamp>,
+    key: Option<RecordKey>,
+    value: Option<Vec<u8>>,
+    inner: Vec<Message>,
+}
+
+impl Message {
+    pub fn new(
+        offset: RecordOffset,
+        magic_byte: MagicByte,
+        attributes: MessageAttributes,
+        timestamp: Option<RecordTimestamp>,
+        key: Option<RecordKey>,
+        value: Option<Vec<u8>>,
+    ) -> Self {
+        let message = Self {
+            offset,
+            crc: CRC::new(0),
+            magic_byte,
+            attributes,
+            timestamp,
+            key,
+            value,
+            inner: vec![],
+        };
+        Self {
+            crc: CRC::new(CRC_32.checksum(&message.crc_bytes())),
+            ..message
+        }
+    }
+
+    /// Splits a legacy message set, e.g. the value of a compressed wrapper.
+    pub fn split(v: &[u8]) -> Result<Vec<Message>> {
+        split_entries(v, |offset, _, message| {
+            Message::mk(RecordOffset::new(offset), message)
+        })
+    }
+
+    /// A wrapper's inner messages are read without decompressing them in
+    /// turn, a compressed inner message is corrupt as it is for Kafka.
+    pub(crate) fn mk(offset: RecordOffset, v: &[u8]) -> Result<Self> {
+        let message = Self::mk_flat(offset, v)?;
+        let inner = match (message.compression()?, &message.value) {
+            (Compression::None, _) | (_, None) => vec![],
+            (compression, Some(value)) => {
+                let inner = split_entries(
+                    &compression.decompress(value)?,
+                    |offset, _, inner| {
+                        Message::mk_flat(RecordOffset::new(offset), inner)
+                    },
+                )?;
+                if inner
+                    .iter()
+                    .any(|m| !matches!(m.compression(), Ok(Compression::None)))
+                {
+                    return Err(Error::corrupt(
+                        "compressed message inside a compressed wrapper",
+                    ));
+                }
+                message.absolute(inner)
+            }
+        };
+        Ok(Self {
+            inner,
+            ..message
+        })
+    }
+
+    fn mk_flat(offset: RecordOffset, v: &[u8]) -> Result<Self> {
+        let (crc, rest) = v.extract_u32_into(CRC::new)?;
+        if CRC_32.checksum(rest) != *crc {
+            return Err(Error::corrupt("message crc mismatch"));
+        }
+        let (magic_byte, rest) = rest.extract_u8_into(MagicByte::new)?;
+        let (attributes, rest) =
+            rest.extract_u8_into(MessageAttributes::new)?;
+        let (timestamp, rest) = match *magic_byte {
+            0 => Ok((None, rest)),
+            1 => rest.extract_u64_into(|v| Some(RecordTimestamp::new(v))),
+            v => Err(Error::UnsupportedMagic(v)),
+        }?;
+        let (key, rest) = rest
+            .extract_nullable_bytes()
+            .map_tuple(|v| v.map(RecordKey::new))?;
+        let (value, _rest) = rest
+            .extract_nullable_bytes()
+            .map_tuple(|v| v.map(<[u8]>::to_vec))?;
+        Ok(Self {
+            offset,
+            crc,
+            magic_byte,
+            attributes,
+            timestamp,
+            key,
+            value,
+            inner: vec![],
+        })
+    }
+
+    // Since v1 the inner offsets of a wrapper are relative and the wrapper
+    // carries the offset of the last inner message.
+    fn absolute(&self, inner: Vec<Message>) -> Vec<Message> {
+        match (*self.magic_byte, inner.last().map(|v| *v.offset)) {
+            (1, Some(last)) => inner
+                .into_iter()
+                .map(|v| Self {
+                    offset: RecordOffset::new(
+                        self.offset.wrapping_sub(last).wrapping_add(*v.offset),
+                    ),
+                    ..v
+                })
+                .collect(),
+            _ => inner,
+        }
+    }
+
+    fn crc_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.put_u8(*self.magic_byte);
+        bytes.put_u8(*self.attributes);
+        if let Some(timestamp) = self.timestamp {
+            bytes.put_u64(*timestamp);
+        }
+        bytes.extend(self.key.as_ref().map(|v| v.to_vec()).to_nullable_bytes());
+        bytes.extend(self.value.to_nullable_bytes());
+        bytes
+    }
+
+    pub fn compression(&self) -> Result<Compression> {
+        Compression::from_attributes(*self.attributes as u16)
+    }
+    pub fn offset(&self) -> RecordOffset {
+        self.offset
+    }
+    pub fn crc(&self) -> CRC {
+        self.crc
+    }
+    pub fn magic_byte(&self) -> MagicByte {
+        self.magic_byte
+    }
+    pub fn timestamp(&self) -> Option<RecordTimestamp> {
+        self.timestamp
+    }
+    pub fn key(&self) -> Option<&RecordKey> {
+        self.key.as_ref()
+    }
+    pub fn value(&self) -> Option<&[u8]> {
+        self.value.as_deref()
+    }
+
+    /// The messages a consumer sees: the inner messages of a compressed
+    /// wrapper, otherwise the message itself.
+    pub fn messages(&self) -> Vec<Message> {
+        if self.inner.is_empty() {
+            vec![self.clone()]
+        } else {
+            self.inner.clone()
+        }
+    }
+
+    fn down_convert(&self, magic: MagicByte) -> Vec<Message> {
+        if *self.magic_byte <= *magic {
+            vec![self.clone()]
+        } else {
+            self.messages()
+                .into_iter()
+                .map(|m| {
+                    Message::new(
+                        m.offset,
+                        magic,
+                        MessageAttributes::new(0),
+                        None,
+                        m.key,
+                        m.value,
+                    )
+                })
+                .collect()
+        }
+    }
+}
+
+impl From<Message> for Vec<u8> {
+    fn from(value: Message) -> Self {
+        let body = value.crc_bytes();
+        let mut bytes = value.offset.to_be_bytes().to_vec();
+        bytes.put_u32(body.len() as u32 + 4);
+        bytes.put_u32(*value.crc);
+        bytes.extend(body);
+        bytes
+    }
+}
+
+/// Walks the `offset, length, entry` framing shared by log segments and
+/// Produce payloads. A length running past the end of `v` or an entry that
+/// fails to parse is reported as `CorruptRecord` at the entry's position.
+pub(crate) fn split_entries<T>(
+    v: &[u8],
+    mut f: impl FnMut(u64, u32, &[u8]) -> Result<T>,
+) -> Result<Vec<T>> {
+    let mut result = vec![];
+    let mut rest = v;
+    while !rest.is_empty() {
+        let position = (v.len() - rest.len()) as u64;
+        let (value, next) = split_entry(rest, &mut f)
+            .map_err(Error::at_file_offset(position))?;
+        result.push(value);
+        rest = next;
+    }
+    Ok(result)
+}
+
+fn split_entry<'a, T>(
+    v: &'a [u8],
+    f: &mut impl FnMut(u64, u32, &[u8]) -> Result<T>,
+) -> Result<(T, &'a [u8])> {
+    if v.len() < 12 {
+        return Err(Error::corrupt("truncated entry header"));
+    }
+    let (offset, rest) = v.extract_u64()?;
+    let (length, rest) = rest.extract_u32()?;
+    if length as usize > rest.len() {
+        return Err(Error::corrupt(&format!(
+            "length {} exceeds the {} remaining bytes",
+            length,
+            rest.len()
+        )));
+    }
+    let (entry, rest) = rest.drop(length as usize)?;
+    f(offset, length, entry).map(|v| (v, rest))
+}
+
+/// An entry of a partition log: a v2 record batch or a legacy message.
+#[derive(Debug, Clone)]
+pub enum LogEntry {
+    Batch(Batch),
+    Message(Message),
+}
+
+impl LogEntry {
+    /// Splits a log segment. The magic byte sits 16 bytes into every entry
+    /// in all three formats, which tells how to read the rest.
+    pub fn split(v: Vec<u8>) -> Result<Vec<LogEntry>> {
+        split_entries(&v, |offset, length, entry| match entry.get(4) {
+            Some(0 | 1) => Message::mk(RecordOffset::new(offset), entry)
+                .map(LogEntry::Message),
+            Some(2) =>
+                Batch::mk_entry(offset, length, entry).map(LogEntry::Batch),
+            Some(v) => Err(Error::corrupt(&format!("unknown magic byte {v}"))),
+            None => Err(Error::corrupt("entry too short for a magic byte")),
+        })
+    }
+
+    pub fn fold<'a, Z>(
+        &'a self,
+        batch: impl FnOnce(&'a Batch) -> Z,
+        message: impl FnOnce(&'a Message) -> Z,
+    ) -> Z {
+        match self {
+            LogEntry::Batch(v) => batch(v),
+            LogEntry::Message(v) => message(v),
+        }
+    }
+
+    pub fn base_offset(&self) -> BatchOffset {
+        self.fold(
+            |b| b.base_offset(),
+            |m| {
+                let first = m.messages().first().map(|v| *v.offset);
+                BatchOffset::new(first.unwrap_or(*m.offset))
+            },
+        )
+    }
+
+    pub fn last_offset(&self) -> RecordOffset {
+        self.fold(|b| b.last_offset(), |m| m.offset)
+    }
+
+    /// v0 messages carry no timestamp.
+    pub fn max_timestamp(&self) -> Option<RecordTimestamp> {
+        self.fold(
+            |b| Some(b.max_timestamp()),
+            |m| {
+                m.messages()
+                    .iter()
+                    .filter_map(|v| v.timestamp)
+                    .max_by_key(|v| **v)
+            },
+        )
+    }
+
+    /// Offset and timestamp of every record, inner messages for a wrapper.
+    pub fn records(&self) -> Vec<(RecordOffset, Option<RecordTimestamp>)> {
+        self.fold(
+            |b| {
+                b.batch_records()
+                    .iter()
+                    .map(|r| (b.record_offset(r), Some(b.record_timestamp(r))))
+                    .collect()
+            },
+            |m| m.messages().iter().map(|v| (v.offset, v.timestamp)).collect(),
+        )
+    }
+
+    /// Bytes the entry takes in a segment, offset and length included.
+    pub fn size(&self) -> usize {
+        self.fold(
+            |b| 12 + b.batch_length() as usize,
+            |m| 16 + m.crc_bytes().len(),
+        )
+    }
+
+    /// Rewrites the entry in the given legacy format for consumers that
+    /// fetch with a version that predates it. v2 batches lose their headers
+    /// and producer state, newer legacy messages lose their timestamp.
+    pub fn down_convert(&self, magic: MagicByte) -> Vec<LogEntry> {
+        let messages = match self {
+            LogEntry::Batch(b) => b
+                .batch_records()
+                .iter()
+                .map(|r| {
+                    let timestamp = match *magic {
+                        0 => None,
+                        _ => Some(b.record_timestamp(r)),
+                    };
+                    let attributes = match (*magic, b.is_log_append_time()) {
+                        (1, true) => 0x08,
+                        _ => 0,
+                    };
+                    Message::new(
+                        b.record_offset(r),
+                        magic,
+                        MessageAttributes::new(attributes),
+                        timestamp,
+                        r.key().cloned(),
+                        r.value().cloned().map(Vec::from),
+                    )
+                })
+                .collect(),
+            LogEntry::Message(m) => m.down_convert(magic),
+        };
+        messages.into_iter().map(LogEntry::Message).collect()
+    }
+}
+
+impl From<LogEntry> for Vec<u8> {
+    fn from(value: LogEntry) -> Self {
+        match value {
+            LogEntry::Batch(v) => v.into(),
+            LogEntry::Message(v) => v.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex::decode;
+
+    #[test]
+    fn test_legacy_round_trip() -> Result<()> {
+        // v0 message at offset 5 followed by a v1 message at offset 6,
+        // key "k", value "v1" / null key, value "v2"
+        let v0 = Message::new(
+            RecordOffset::new(5),
+            MagicByte::new(0),
+            MessageAttributes::new(0),
+            None,
+            Some(RecordKey::new(b"k")),
+            Some(b"v1".to_vec()),
+        );
+        let v1 = Message::new(
+            RecordOffset::new(6),
+            MagicByte::new(1),
+            MessageAttributes::new(0),
+            Some(RecordTimestamp::new(0x0191e05b6d8b)),
+            None,
+            Some(b"v2".to_vec()),
+        );
+        let mut bytes: Vec<u8> = v0.into();
+        bytes.extend(Vec::<u8>::from(v1));
+        let entries = LogEntry::split(bytes.clone())?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(*entries[1].last_offset(), 6);
+        let encoded: Vec<u8> =
+            entries.into_iter().flat_map(Vec::<u8>::from).collect();
+        assert_eq!(encoded, bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_wrapper() -> Result<()> {
+        // v1 wrapper at offset 12 holding two gzip compressed messages with
+        // relative offsets 0 and 1
+        let inner: Vec<u8> = [0, 1]
+            .into_iter()
+            .flat_map(|i| {
+                Vec::<u8>::from(Message::new(
+                    RecordOffset::new(i),
+                    MagicByte::new(1),
+                    MessageAttributes::new(0),
+                    Some(RecordTimestamp::new(10)),
+                    None,
+                    Some(vec![b'a' + i as u8]),
+                ))
+            })
+            .collect();
+        let wrapper = Message::new(
+            RecordOffset::new(12),
+            MagicByte::new(1),
+            MessageAttributes::new(1),
+            Some(RecordTimestamp::new(10)),
+            None,
+            Some(Compression::Gzip.compress(&inner)?),
+        );
+        let bytes: Vec<u8> = wrapper.into();
+        let entries = LogEntry::split(bytes.clone())?;
+        let LogEntry::Message(message) = &entries[0] else {
+            panic!("expected a legacy message")
+        };
+        let offsets: Vec<u64> =
+            message.messages().iter().map(|m| *m.offset()).collect();
+        assert_eq!(offsets, vec![11, 12]);
+        assert_eq!(*entries[0].base_offset(), 11);
+        assert_eq!(Vec::<u8>::from(entries[0].clone()), bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn test_down_convert() -> Result<()> {
+        let bytes_str = "00 00 00 00  00 00 00 00  00 00 00 44  00 00 00 00  02 ab fd 04  91 00 00 00  00 00 00 00  00 01 91 e0  5b 6d 8b 00  00 01 91 e0  5b 6d 8b 00  00 00 00 00  00 00 00 00  00 00 00 00  00 00 00 00  01 24 00 00  00 01 18 48  65 6c 6c 6f  20 4b 61 66  6b 61 21 00";
+        let entries =
+            LogEntry::split(decode(bytes_str.replace(" ", "")).unwrap())?;
+        let converted = entries[0].down_convert(MagicByte::new(1));
+        let LogEntry::Message(message) = &converted[0] else {
+            panic!("expected a legacy message")
+        };
+        assert_eq!(*message.magic_byte(), 1);
+        assert_eq!(message.value(), Some(b"Hello Kafka!".as_slice()));
+        assert_eq!(message.timestamp().map(|v| *v), Some(0x0191e05b6d8b));
+
+        let bytes: Vec<u8> = converted[0].clone().into();
+        let parsed = Message::split(&bytes)?;
+        assert_eq!(*parsed[0].crc(), *message.crc());
+        Ok(())
+    }
+}
@@ -1,24 +1,38 @@
+use std::fs::metadata;
 use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::{
-    read, AddingReplica, BytesOps, Directory, ISRNode, Leader, LeaderEpoch,
-    MapTupleTwo, NodeId, PartitionEpoch, PartitionIndex, RemovingReplica,
-    ReplicaNode, Result, SignedVarInt, TagBuffer, ToArray, ToCompactString,
-    TopicId, TopicName,
+    append, read, split_entries, AclBinding, AclOperation, AclPermissionType,
+    AclResourceType, AddingReplica, BytesOps, ClientQuotaEntity, ClientQuotas,
+    Compression, Context, Directory, Error, ISRNode, Leader, LeaderEpoch, Log,
+    MapTupleTwo, NodeId, PartitionEpoch, PartitionIndex, PatternType,
+    RemovingReplica, ReplicaNode, Result, SignedVarInt, TagBuffer, ToArray,
+    ToCompactString, ToVarBytes, TopicId, TopicName, TryExtract, VarInt,
+    Version, LOG_DIR,
 };
 use bytes::BufMut;
 use newtype_macro::newtype;
-use pretty_hex::*;
+use std::collections::{BTreeMap, HashMap};
+use std::str::from_utf8;
+use tracing::trace;
 use uuid::Uuid;
 
 #[newtype]
 pub struct RecordAttributes(u8);
 
 #[newtype]
-pub struct TimestampDelta(u8);
+pub struct TimestampDelta(i64);
 
 #[newtype]
-pub struct OffsetDelta(u8);
+pub struct OffsetDelta(i32);
+
+#[newtype]
+pub struct RecordOffset(u64);
+
+#[newtype]
+pub struct RecordTimestamp(u64);
 
 #[newtype]
 pub struct FrameVersion(u8);
@@ -27,7 +41,9 @@ pub struct FrameVersion(u8);
 pub struct ValueVersion(u8);
 
 #[newtype]
-pub struct Header(u64);
+pub struct HeaderKey(String);
+#[newtype]
+pub struct ConfigResourceType(u8);
 #[newtype]
 pub struct BatchOffset(u64);
 #[newtype]
@@ -37,7 +53,7 @@ struct BatchLength(u32);
 struct PartitionLeaderEpic(u32);
 
 #[newtype]
-struct MagicByte(u8);
+pub struct MagicByte(u8);
 
 #[newtype]
 pub struct CRC(u32);
@@ -67,34 +83,58 @@ struct BaseSequence(u32);
 //
 #[derive(Debug, Clone)]
 pub struct Meta(Vec<Batch>);
-#[derive(Debug, Clone)]
-pub struct Log(Vec<Batch>);
-impl Log {
-    pub fn new(v: Vec<Batch>) -> Self {
-        Self(v)
+
+// ConfigResource.Type of a topic and of a broker
+pub const TOPIC_RESOURCE: u8 = 2;
+pub const BROKER_RESOURCE: u8 = 4;
+
+// writers of the metadata log, each batch goes after the last offset
+static META_LOCK: Mutex<()> = Mutex::new(());
+
+/// What a request needs of the metadata log, such as its ACLs or quotas,
+/// derived once and again only when the log has grown or the records were
+/// written here. The log is only appended to, so its size moves with its
+/// end offset.
+#[derive(Debug)]
+pub struct MetaCache<T> {
+    derive: fn(&Meta) -> T,
+    // the log the value was derived from and its size then
+    cached: Mutex<Option<(String, u64, Arc<T>)>>,
+}
+
+impl<T: Default> MetaCache<T> {
+    pub const fn new(derive: fn(&Meta) -> T) -> Self {
+        Self {
+            derive,
+            cached: Mutex::new(None),
+        }
     }
-    pub fn batches(&self) -> &Vec<Batch> {
-        &self.0
+    /// The value of the log at `path`, the default if it cannot be read.
+    pub fn get(&self, path: &str) -> Arc<T> {
+        let Ok(size) = metadata(path).map(|v| v.len()) else {
+            return Arc::default();
+        };
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        match &*cached {
+            Some((from, at, value)) if from == path && *at == size =>
+                value.clone(),
+            _ => {
+                let value = Arc::new(
+                    Meta::load(path)
+                        .map(|m| (self.derive)(&m))
+                        .unwrap_or_default(),
+                );
+                *cached = Some((path.to_string(), size, value.clone()));
+                value
+            }
+        }
     }
-    pub fn load_log(topic_name: &TopicName) -> Result<Log> {
-        //let partition_metadata = format!("/tmp/kraft-combined-logs/{}-0/partition.metadata", **topic_name);
-        let log = format!(
-            "/tmp/kraft-combined-logs/{}-0/00000000000000000000.log",
-            topic_name.value()
-        );
-
-        //let l1 = read(&partition_metadata)?;
-        //println!("partition metadata {:?}", pretty_hex(&l1));
-        read(&log)
-            .map(|v| {
-                println!("LOG: {:?}", pretty_hex(&v));
-                println!("LOG simple: {:?}", simple_hex(&v));
-                v
-            })
-            .and_then(Batch::split_by_batch)
-            .map(Log::new)
+    /// Derives the value again on the next `get`.
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap_or_else(|e| e.into_inner()) = None;
     }
 }
+
 impl Meta {
     pub fn new(v: Vec<Batch>) -> Self {
         Self(v)
@@ -102,23 +142,66 @@ impl Meta {
     pub fn load(path: &str) -> Result<Self> {
         read(path).and_then(Batch::split_by_batch).map(Self::new)
     }
+    /// Appends `values` as one batch after the last offset of the log.
+    pub fn append(path: &str, values: Vec<RecordValue>) -> Result<()> {
+        Self::append_with(path, |_| (values, ()))
+    }
+    /// Appends the values `f` derives from the log as it is, with no other
+    /// writer in between, and returns what `f` returns beside them. No
+    /// values append nothing.
+    pub fn append_with<T>(
+        path: &str,
+        f: impl FnOnce(&Meta) -> (Vec<RecordValue>, T),
+    ) -> Result<T> {
+        let _lock = META_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let meta = Self::load(path)?;
+        let (values, result) = f(&meta);
+        if values.is_empty() {
+            return Ok(result);
+        }
+        let next = meta.0.last().map(|v| *v.last_offset() + 1).unwrap_or(0);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|v| v.as_millis() as u64)
+            .unwrap_or(0);
+        let records = values
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| {
+                Record::new(OffsetDelta::new(i as i32), None, Some(v))
+            })
+            .collect();
+        let batch = Batch::new(
+            BatchOffset::new(next),
+            RecordTimestamp::new(now),
+            records,
+        );
+        append(path, &Vec::<u8>::from(batch))?;
+        Ok(result)
+    }
 
-    pub fn find_log(&self, topic_id: &TopicId) -> Result<Option<Log>> {
+    /// The log of a topic's partition from `offset` on, None when the
+    /// topic has no such partition.
+    pub fn find_log(
+        &self,
+        topic_id: &TopicId,
+        partition: PartitionIndex,
+        offset: RecordOffset,
+    ) -> Result<Option<Log>> {
         match self.find_topic_name(topic_id) {
+            Some(topic_name) if !self.has_partition(&topic_name, partition) =>
+                Ok(None),
             None => Ok(None),
-            Some(topic_name) => Log::load_log(&topic_name).map(Option::from),
+            Some(topic_name) =>
+                Log::read_from(LOG_DIR, &topic_name, partition, offset)
+                    .map(Option::from),
         }
     }
     pub fn find_batch(&self, topic_id: TopicId) -> Option<Batch> {
         self.0
             .iter()
-            .find(|&v| {
-                v.records
-                    .iter()
-                    .find(|&r| r.topic_id() == Some(topic_id.clone()))
-                    .is_some()
-            })
-            .map(|v| v.clone())
+            .find(|&v| v.records.iter().any(|r| r.topic_id() == Some(topic_id)))
+            .cloned()
     }
 
     pub fn find_topic_name(&self, topic_id: &TopicId) -> Option<TopicName> {
@@ -126,7 +209,7 @@ impl Meta {
             .iter()
             .flat_map(|b| b.records.iter()) // Flatten inner structure
             .find_map(|r| match r.topic_id() {
-                Some(t) if t == topic_id.clone() => r.value.name(),
+                Some(t) if t == *topic_id => r.name(),
                 _ => None,
             })
     }
@@ -138,23 +221,110 @@ impl Meta {
             .iter()
             .flat_map(|b| b.records.iter())
             .filter_map(|r| match r.topic_id() {
-                Some(t) if t == topic_id.clone() => r.value.partition_record(),
+                Some(t) if t == *topic_id => r.partition(),
                 _ => None,
             })
             .collect()
     }
+    pub fn has_partition(
+        &self,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+    ) -> bool {
+        self.find_topic_id(topic_name).is_some_and(|id| {
+            self.find_partitions(&id).iter().any(|p| p.2 == partition)
+        })
+    }
     pub fn find_topic_id(&self, topic_name: &TopicName) -> Option<TopicId> {
         self.0
             .iter()
             .flat_map(|b| b.records.iter()) // Flatten inner structure
-            .find_map(|r| match r.value.name() {
-                Some(n) if n == topic_name.clone() => r.topic_id(),
+            .find_map(|r| match r.name() {
+                Some(n) if n == *topic_name => r.topic_id(),
                 _ => None,
             })
     }
-    fn records(&self) -> Vec<&Record> {
+    pub fn records(&self) -> Vec<&Record> {
         self.0.iter().flat_map(|v| v.records.iter()).collect()
     }
+    pub fn scram_credentials(&self) -> Vec<&UserScramCredentialRecordValue> {
+        self.records()
+            .into_iter()
+            .filter_map(|r| {
+                r.value.as_ref().and_then(|v| v.user_scram_credential_record())
+            })
+            .collect()
+    }
+    /// The ACLs in force, in the order they were created.
+    pub fn acls(&self) -> Vec<(Uuid, AclBinding)> {
+        self.records().into_iter().filter_map(Record::value).fold(
+            vec![],
+            |mut acls, v| {
+                if let Some(v) = v.access_control_entry_record() {
+                    acls.push((v.2, v.3.clone()));
+                }
+                if let Some(v) = v.remove_access_control_entry_record() {
+                    acls.retain(|(id, _)| *id != v.2);
+                }
+                acls
+            },
+        )
+    }
+    /// Client quotas by entity, a removed quota drops out.
+    pub fn client_quotas(&self) -> ClientQuotas {
+        self.records()
+            .into_iter()
+            .filter_map(|r| {
+                r.value.as_ref().and_then(|v| v.client_quota_record())
+            })
+            .fold(ClientQuotas::new(), |mut acc, q| {
+                let values = acc.entry(q.2.clone()).or_default();
+                match q.5 {
+                    true => values.remove(&q.3),
+                    false => values.insert(q.3.clone(), q.4),
+                };
+                if values.is_empty() {
+                    acc.remove(&q.2);
+                }
+                acc
+            })
+    }
+    /// The finalized features with their levels and the offset of the
+    /// last FeatureLevelRecord, -1 without any.
+    pub fn finalized_features(&self) -> (BTreeMap<String, i16>, i64) {
+        self.0
+            .iter()
+            .flat_map(|b| b.batch_records().iter().map(move |r| (b, r)))
+            .filter_map(|(b, r)| {
+                r.value()
+                    .and_then(RecordValue::feature_level_record)
+                    .map(|f| (b.record_offset(r), f))
+            })
+            .fold((BTreeMap::new(), -1), |(mut acc, _), (offset, f)| {
+                match f.3 {
+                    0 => acc.remove(&f.2),
+                    level => acc.insert(f.2.clone(), level),
+                };
+                (acc, *offset as i64)
+            })
+    }
+    /// Dynamic configs of a topic, later records override earlier ones.
+    pub fn topic_configs(
+        &self,
+        topic_name: &TopicName,
+    ) -> HashMap<String, String> {
+        self.records()
+            .into_iter()
+            .filter_map(|r| r.value.as_ref().and_then(|v| v.config_record()))
+            .filter(|c| *c.2 == TOPIC_RESOURCE && c.3 == **topic_name)
+            .fold(HashMap::new(), |mut acc, c| {
+                match &c.5 {
+                    Some(v) => acc.insert(c.4.clone(), v.clone()),
+                    None => acc.remove(&c.4),
+                };
+                acc
+            })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -172,46 +342,120 @@ pub struct Batch {
     producer_epoch: Option<ProducerEpoch>,
     base_sequence: Option<BaseSequence>,
     records: Vec<Record>,
+    // the records as stored when the batch is compressed
+    compressed: Option<Vec<u8>>,
 }
+// partition leader epoch up to and including the record count
+const BATCH_HEADER_LENGTH: usize = 49;
 const CRC_32_C: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
 
 impl Batch {
-    fn split_by_batch(v: Vec<u8>) -> Result<Vec<Batch>> {
-        fn do_split(v: &[u8], mut result: Vec<Batch>) -> Result<Vec<Batch>> {
-            if v.is_empty() {
-                Ok(result)
-            } else {
-                let (batch_offset, rest) =
-                    v.extract_u64_into(BatchOffset::new)?;
-                let (batch_length, rest) =
-                    rest.extract_u32_into(BatchLength::new)?;
-                let (batch, rest) =
-                    rest.drop(batch_length.deref().clone() as usize)?;
-                result.push(Batch::mk(batch_offset, batch_length, batch)?);
-                do_split(rest, result)
-            }
+    /// An uncompressed batch without producer state, all records at
+    /// `timestamp`.
+    pub fn new(
+        batch_offset: BatchOffset,
+        timestamp: RecordTimestamp,
+        records: Vec<Record>,
+    ) -> Self {
+        Self {
+            batch_offset,
+            batch_length: BatchLength::new(0),
+            partition_leader_epic: PartitionLeaderEpic::new(0),
+            magic_byte: MagicByte::new(2),
+            crc: None,
+            attributes: Attributes::new(0),
+            last_offset_delta: LastOffsetDelta::new(
+                records.len().saturating_sub(1) as u32,
+            ),
+            base_timestamp: BaseTimestamp::new(*timestamp),
+            max_timestamp: MaxTimestamp::new(*timestamp),
+            producer_id: None,
+            producer_epoch: None,
+            base_sequence: None,
+            records,
+            compressed: None,
         }
-        do_split(&v, vec![])
     }
+    pub(crate) fn split_by_batch(v: Vec<u8>) -> Result<Vec<Batch>> {
+        split_entries(&v, Batch::mk_entry)
+    }
+    /// Drops records, the result is stored uncompressed.
     pub fn filter_records(&self, mut f: impl FnMut(&Record) -> bool) -> Batch {
         let rec = self.records.clone().into_iter().filter(|v| f(v)).collect();
         Batch {
             records: rec,
             crc: None,
+            attributes: Attributes::new(*self.attributes & !0x07),
+            compressed: None,
             ..(*self).clone()
         }
     }
+    pub(crate) fn mk_entry(offset: u64, length: u32, v: &[u8]) -> Result<Self> {
+        Self::mk(BatchOffset::new(offset), BatchLength::new(length), v)
+    }
     pub fn set_offset(&self, v: BatchOffset) -> Self {
         Self {
             batch_offset: v,
             ..self.clone()
         }
     }
+    /// Checks what can be checked before parsing: the header fits, the magic
+    /// byte and the CRC32C over attributes to the end of the batch.
+    fn verify(v: &[u8]) -> Result<()> {
+        if v.len() < BATCH_HEADER_LENGTH {
+            return Err(Error::corrupt(&format!(
+                "batch length {} is shorter than the header",
+                v.len()
+            )));
+        }
+        let (_, rest) = v.extract_u32()?;
+        let (magic_byte, rest) = rest.extract_u8()?;
+        if magic_byte != 2 {
+            return Err(Error::corrupt(&format!(
+                "unexpected magic byte {magic_byte} in a record batch"
+            )));
+        }
+        let (crc, rest) = rest.extract_u32()?;
+        match CRC_32_C.checksum(rest) {
+            computed if computed == crc => Ok(()),
+            computed => Err(Error::corrupt(&format!(
+                "crc {crc:#010x} does not match computed {computed:#010x}"
+            ))),
+        }
+    }
+    /// Offset deltas must increase and fit in `last_offset_delta`, the count
+    /// can be lower than the offset span once the log has been compacted.
+    fn verify_records(&self, record_count: u32) -> Result<()> {
+        let last_offset_delta = *self.last_offset_delta as i64;
+        let deltas: Vec<i64> =
+            self.records.iter().map(|r| *r.offset_delta as i64).collect();
+        if self.records.len() != record_count as usize {
+            Err(Error::corrupt(&format!(
+                "batch declares {} records but holds {}",
+                record_count,
+                self.records.len()
+            )))
+        } else if record_count as i64 > last_offset_delta + 1 {
+            Err(Error::corrupt(&format!(
+                "{record_count} records do not fit last offset delta {last_offset_delta}"
+            )))
+        } else if deltas.first().is_some_and(|v| *v < 0)
+            || deltas.windows(2).any(|v| v[0] >= v[1])
+            || deltas.last().is_some_and(|v| *v > last_offset_delta)
+        {
+            Err(Error::corrupt(&format!(
+                "offset deltas {deltas:?} are not increasing up to {last_offset_delta}"
+            )))
+        } else {
+            Ok(())
+        }
+    }
     fn mk(
         batch_offset: BatchOffset,
         batch_length: BatchLength,
         v: &[u8],
     ) -> Result<Self> {
+        Self::verify(v)?;
         let (partition_leader_epic, rest) =
             v.extract_u32_into(PartitionLeaderEpic::new)?;
         let (magic_byte, rest) = rest.extract_u8_into(MagicByte::new)?;
@@ -228,24 +472,30 @@ impl Batch {
             rest.extract_u16_as_option_into(ProducerEpoch::new)?;
         let (base_sequence, rest) =
             rest.extract_u32_as_option_into(BaseSequence::new)?;
-        let (_record_length, rest) = rest.extract_u32()?;
+        let (record_count, rest) = rest.extract_u32()?;
         fn split_records(
-            v: &[u8],
+            mut v: &[u8],
             mut result: Vec<Record>,
         ) -> Result<Vec<Record>> {
-            if v.is_empty() {
-                Ok(result)
-            } else {
-                let (length, rest) = SignedVarInt::decode(&v)?;
-                println!("record len: {:?}", length.value());
-                let (record, rest) = rest.drop(length.value() as usize)?;
-                let record = Record::mk(record)?;
-                result.push(record);
-                split_records(rest, result)
+            while !v.is_empty() {
+                let (length, rest) = SignedVarInt::decode(v)?;
+                trace!("record len: {:?}", length.value());
+                let (record, rest) =
+                    rest.drop(usize::try_from(length.value())?)?;
+                result.push(Record::mk(record)?);
+                v = rest;
             }
+            Ok(result)
         }
-        let records = split_records(rest, vec![])?;
-        Ok(Self {
+        let (records, compressed) =
+            match Compression::from_attributes(*attributes)? {
+                Compression::None => (split_records(rest, vec![])?, None),
+                compression => (
+                    split_records(&compression.decompress(rest)?, vec![])?,
+                    Some(rest.to_vec()),
+                ),
+            };
+        let batch = Self {
             batch_offset,
             batch_length,
             partition_leader_epic,
@@ -259,10 +509,50 @@ impl Batch {
             producer_epoch,
             base_sequence,
             records,
-        })
+            compressed,
+        };
+        batch.verify_records(record_count)?;
+        Ok(batch)
     }
     pub fn records(&self) -> Vec<RecordValue> {
-        self.records.iter().map(|v| v.value.clone()).collect()
+        self.records.iter().flat_map(|v| v.value.clone()).collect()
+    }
+    pub fn batch_records(&self) -> &Vec<Record> {
+        &self.records
+    }
+    pub fn batch_length(&self) -> u32 {
+        *self.batch_length
+    }
+    pub fn base_offset(&self) -> BatchOffset {
+        self.batch_offset
+    }
+    pub fn last_offset(&self) -> RecordOffset {
+        RecordOffset::new(
+            self.batch_offset.wrapping_add(*self.last_offset_delta as u64),
+        )
+    }
+    pub fn record_offset(&self, record: &Record) -> RecordOffset {
+        RecordOffset::new(
+            self.batch_offset.wrapping_add_signed(*record.offset_delta as i64),
+        )
+    }
+    pub fn max_timestamp(&self) -> RecordTimestamp {
+        RecordTimestamp::new(*self.max_timestamp)
+    }
+    pub fn is_log_append_time(&self) -> bool {
+        *self.attributes & 0x08 != 0
+    }
+    /// With LogAppendTime (attribute bit 3) the broker stamps every record
+    /// with the batch max timestamp, otherwise it is base + delta.
+    pub fn record_timestamp(&self, record: &Record) -> RecordTimestamp {
+        if self.is_log_append_time() {
+            RecordTimestamp::new(*self.max_timestamp)
+        } else {
+            RecordTimestamp::new(
+                self.base_timestamp
+                    .wrapping_add_signed(*record.timestamp_delta),
+            )
+        }
     }
 }
 
@@ -288,16 +578,19 @@ impl From<Batch> for Vec<u8> {
         });
 
         bytes.put_u32(value.records.len() as u32);
-        let mut records: Vec<u8> = value
-            .records
-            .into_iter()
-            .flat_map::<Vec<u8>, _>(|e| {
-                let b: Vec<u8> = e.into();
-                let mut len = SignedVarInt::encode(b.len() as i64);
-                len.extend(&b); //
-                len
-            })
-            .collect();
+        let mut records: Vec<u8> = match value.compressed {
+            Some(compressed) => compressed,
+            None => value
+                .records
+                .into_iter()
+                .flat_map::<Vec<u8>, _>(|e| {
+                    let b: Vec<u8> = e.into();
+                    let mut len = SignedVarInt::encode(b.len() as i64);
+                    len.extend(&b); //
+                    len
+                })
+                .collect(),
+        };
         bytes.append(&mut records);
 
         let mut batch_with_crc =
@@ -324,46 +617,33 @@ pub struct Record {
     timestamp_delta: TimestampDelta,
     offset_delta: OffsetDelta,
     key: Option<RecordKey>,
-    value: RecordValue,
+    value: Option<RecordValue>,
     headers: Vec<Header>,
 }
 impl From<Record> for Vec<u8> {
     fn from(value: Record) -> Self {
         let mut bytes = vec![];
         bytes.put_u8(*value.attributes);
-        bytes.put_u8(*value.timestamp_delta);
-        bytes.put_u8(*value.offset_delta);
-        match value.key {
-            None => bytes.put_u8(0x01),
-            Some(RecordKey(v)) => {
-                bytes.extend(SignedVarInt::encode(v.len() as i64));
-                bytes.extend(v);
-            }
-        }
-        let v: Vec<u8> = value.value.into();
-        bytes.extend(SignedVarInt::encode(v.len() as i64));
-        bytes.extend(v);
+        bytes.extend(SignedVarInt::encode(*value.timestamp_delta));
+        bytes.extend(SignedVarInt::encode(*value.offset_delta as i64));
+        bytes.extend(value.key.map(|v| v.0).to_var_bytes());
+        bytes.extend(value.value.map(Vec::<u8>::from).to_var_bytes());
         bytes.extend(SignedVarInt::encode(value.headers.len() as i64));
+        value
+            .headers
+            .into_iter()
+            .for_each(|h| bytes.extend(Vec::<u8>::from(h)));
         bytes
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct RecordKey(Vec<u8>);
 
 impl RecordKey {
-    fn new(v: &[u8]) -> Self {
+    pub fn new(v: &[u8]) -> Self {
         RecordKey(v.to_vec())
     }
-    fn mk(v: &[u8]) -> Result<(Option<RecordKey>, &[u8])> {
-        let (key_length, rest) = v.extract_signed_var_int()?;
-        if key_length.value() < 0 {
-            Ok((None, rest))
-        } else {
-            v.drop(key_length.value() as usize)
-                .map_tuple(|v| Some(Self::new(v)))
-        }
-    }
 }
 
 impl Deref for RecordKey {
@@ -373,6 +653,48 @@ impl Deref for RecordKey {
         &self.0
     }
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    key: HeaderKey,
+    value: Option<Vec<u8>>,
+}
+
+impl Header {
+    pub fn new(key: HeaderKey, value: Option<Vec<u8>>) -> Self {
+        Self {
+            key,
+            value,
+        }
+    }
+    pub fn key(&self) -> &HeaderKey {
+        &self.key
+    }
+    pub fn value(&self) -> Option<&[u8]> {
+        self.value.as_deref()
+    }
+}
+
+impl TryExtract for Header {
+    fn try_extract(v: &[u8]) -> Result<(Self, &[u8])> {
+        let (key, rest) = v.extract_var_bytes()?;
+        let key = key
+            .context("header key must not be null")
+            .and_then(|k| Ok(from_utf8(k)?.to_string()))
+            .map(HeaderKey::new)?;
+        let (value, rest) = rest.extract_var_bytes()?;
+        Ok((Self::new(key, value.map(|v| v.to_vec())), rest))
+    }
+}
+
+impl From<Header> for Vec<u8> {
+    fn from(value: Header) -> Self {
+        let mut bytes = Some(value.key.as_bytes().to_vec()).to_var_bytes();
+        bytes.extend(value.value.to_var_bytes());
+        bytes
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TopicRecordValue(FrameVersion, ValueVersion, TopicName, TopicId);
 impl From<TopicRecordValue> for Vec<u8> {
@@ -481,11 +803,189 @@ impl From<PartitionRecordValue> for Vec<u8> {
         bytes
     }
 }
+/// A dynamic config entry, a null value deletes the key.
 #[derive(Debug, Clone)]
-pub struct FeatureLevelRecordValue(Vec<u8>);
+pub struct ConfigRecordValue(
+    pub FrameVersion,
+    pub ValueVersion,
+    pub ConfigResourceType,
+    pub String,
+    pub String,
+    pub Option<String>,
+);
+impl From<ConfigRecordValue> for Vec<u8> {
+    fn from(value: ConfigRecordValue) -> Self {
+        let ConfigRecordValue(
+            frame_version,
+            value_version,
+            resource_type,
+            resource_name,
+            name,
+            config_value,
+        ) = value;
+        let mut bytes = vec![];
+        bytes.put_u8(*frame_version);
+        bytes.put_u8(0x04);
+        bytes.put_u8(*value_version);
+        bytes.put_u8(*resource_type);
+        bytes.extend(resource_name.to_compact_string());
+        bytes.extend(name.to_compact_string());
+        match config_value {
+            None => bytes.put_u8(0),
+            Some(v) => bytes.extend(v.to_compact_string()),
+        }
+        bytes.put_u8(*TagBuffer::zero());
+        bytes
+    }
+}
+/// SCRAM credential of a user, the mechanism is 1 for SCRAM-SHA-256 and 2
+/// for SCRAM-SHA-512.
+#[derive(Debug, Clone)]
+pub struct UserScramCredentialRecordValue(
+    pub FrameVersion,
+    pub ValueVersion,
+    pub String,
+    pub u8,
+    pub Vec<u8>,
+    pub Vec<u8>,
+    pub Vec<u8>,
+    pub u32,
+);
+impl From<UserScramCredentialRecordValue> for Vec<u8> {
+    fn from(value: UserScramCredentialRecordValue) -> Self {
+        let UserScramCredentialRecordValue(
+            frame_version,
+            value_version,
+            name,
+            mechanism,
+            salt,
+            stored_key,
+            server_key,
+            iterations,
+        ) = value;
+        let mut bytes = vec![];
+        bytes.put_u8(*frame_version);
+        bytes.put_u8(0x0b);
+        bytes.put_u8(*value_version);
+        bytes.extend(name.to_compact_string());
+        bytes.put_u8(mechanism);
+        [salt, stored_key, server_key].into_iter().for_each(|v| {
+            bytes.extend(VarInt::encode((v.len() + 1) as u64));
+            bytes.extend(v);
+        });
+        bytes.put_u32(iterations);
+        bytes.put_u8(*TagBuffer::zero());
+        bytes
+    }
+}
+/// An ACL binding and the id it is removed by.
+#[derive(Debug, Clone)]
+pub struct AccessControlEntryRecordValue(
+    pub FrameVersion,
+    pub ValueVersion,
+    pub Uuid,
+    pub AclBinding,
+);
+impl From<AccessControlEntryRecordValue> for Vec<u8> {
+    fn from(value: AccessControlEntryRecordValue) -> Self {
+        let AccessControlEntryRecordValue(
+            frame_version,
+            value_version,
+            id,
+            binding,
+        ) = value;
+        let mut bytes = vec![];
+        bytes.put_u8(*frame_version);
+        bytes.put_u8(0x06);
+        bytes.put_u8(*value_version);
+        bytes.put_slice(id.as_bytes());
+        bytes.put_u8(*binding.resource_type);
+        bytes.extend(binding.resource_name.to_compact_string());
+        bytes.put_u8(*binding.pattern_type);
+        bytes.extend(binding.principal.to_compact_string());
+        bytes.extend(binding.host.to_compact_string());
+        bytes.put_u8(*binding.operation);
+        bytes.put_u8(*binding.permission_type);
+        bytes.put_u8(*TagBuffer::zero());
+        bytes
+    }
+}
+#[derive(Debug, Clone)]
+pub struct RemoveAccessControlEntryRecordValue(
+    pub FrameVersion,
+    pub ValueVersion,
+    pub Uuid,
+);
+impl From<RemoveAccessControlEntryRecordValue> for Vec<u8> {
+    fn from(value: RemoveAccessControlEntryRecordValue) -> Self {
+        let RemoveAccessControlEntryRecordValue(
+            frame_version,
+            value_version,
+            id,
+        ) = value;
+        let mut bytes = vec![];
+        bytes.put_u8(*frame_version);
+        bytes.put_u8(0x07);
+        bytes.put_u8(*value_version);
+        bytes.put_slice(id.as_bytes());
+        bytes.put_u8(*TagBuffer::zero());
+        bytes
+    }
+}
+/// Sets or, with remove, drops one quota of an entity.
+#[derive(Debug, Clone)]
+pub struct ClientQuotaRecordValue(
+    pub FrameVersion,
+    pub ValueVersion,
+    pub ClientQuotaEntity,
+    pub String,
+    pub f64,
+    pub bool,
+);
+impl From<ClientQuotaRecordValue> for Vec<u8> {
+    fn from(value: ClientQuotaRecordValue) -> Self {
+        let ClientQuotaRecordValue(
+            frame_version,
+            value_version,
+            entity,
+            key,
+            quota,
+            remove,
+        ) = value;
+        let mut bytes = vec![];
+        bytes.put_u8(*frame_version);
+        bytes.put_u8(0x0e);
+        bytes.put_u8(*value_version);
+        // the entity array is laid out as in flexible client quota requests
+        bytes.extend(entity.encode(Version::V1));
+        bytes.extend(key.to_compact_string());
+        bytes.put_f64(quota);
+        bytes.put_u8(u8::from(remove));
+        bytes.put_u8(*TagBuffer::zero());
+        bytes
+    }
+}
+/// Finalizes a feature, such as `metadata.version`, at a level. Level
+/// zero disables it.
+#[derive(Debug, Clone)]
+pub struct FeatureLevelRecordValue(
+    pub FrameVersion,
+    pub ValueVersion,
+    pub String,
+    pub i16,
+);
 impl From<FeatureLevelRecordValue> for Vec<u8> {
     fn from(value: FeatureLevelRecordValue) -> Self {
-        value.0
+        let FeatureLevelRecordValue(frame_version, value_version, name, level) =
+            value;
+        let mut bytes = vec![];
+        bytes.put_u8(*frame_version);
+        bytes.put_u8(0x0c);
+        bytes.put_u8(*value_version);
+        bytes.extend(name.to_compact_string());
+        bytes.put_i16(level);
+        bytes.put_u8(*TagBuffer::zero());
+        bytes
     }
 }
 #[derive(Debug, Clone)]
@@ -500,44 +1000,208 @@ pub enum RecordValue {
     FeatureLevelRecord(FeatureLevelRecordValue),
     TopicRecord(TopicRecordValue),
     PartitionRecord(PartitionRecordValue),
+    ConfigRecord(ConfigRecordValue),
+    UserScramCredentialRecord(UserScramCredentialRecordValue),
+    AccessControlEntryRecord(AccessControlEntryRecordValue),
+    RemoveAccessControlEntryRecord(RemoveAccessControlEntryRecordValue),
+    ClientQuotaRecord(ClientQuotaRecordValue),
     Raw(RawValue),
 }
 
 impl RecordValue {
+    #[allow(clippy::too_many_arguments)]
     pub fn fold<'a, Z>(
         &'a self,
         flrv: impl FnOnce(&'a FeatureLevelRecordValue) -> Z,
         trv: impl FnOnce(&'a TopicRecordValue) -> Z,
         prv: impl FnOnce(&'a PartitionRecordValue) -> Z,
+        crv: impl FnOnce(&'a ConfigRecordValue) -> Z,
+        ucrv: impl FnOnce(&'a UserScramCredentialRecordValue) -> Z,
+        acerv: impl FnOnce(&'a AccessControlEntryRecordValue) -> Z,
+        racerv: impl FnOnce(&'a RemoveAccessControlEntryRecordValue) -> Z,
+        cqrv: impl FnOnce(&'a ClientQuotaRecordValue) -> Z,
         rv: impl FnOnce(&'a RawValue) -> Z,
     ) -> Z {
         match self {
             RecordValue::FeatureLevelRecord(v) => flrv(v),
             RecordValue::TopicRecord(v) => trv(v),
             RecordValue::PartitionRecord(v) => prv(v),
+            RecordValue::ConfigRecord(v) => crv(v),
+            RecordValue::UserScramCredentialRecord(v) => ucrv(v),
+            RecordValue::AccessControlEntryRecord(v) => acerv(v),
+            RecordValue::RemoveAccessControlEntryRecord(v) => racerv(v),
+            RecordValue::ClientQuotaRecord(v) => cqrv(v),
             RecordValue::Raw(v) => rv(v),
         }
     }
     pub fn feature_level_record(&self) -> Option<&FeatureLevelRecordValue> {
-        self.fold(|v| Some(v), |_| None, |_| None, |_| None)
+        self.fold(
+            Some,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+        )
     }
     pub fn topic_record(&self) -> Option<&TopicRecordValue> {
-        self.fold(|_| None, |v| Some(v), |_| None, |_| None)
+        self.fold(
+            |_| None,
+            Some,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+        )
     }
     pub fn partition_record(&self) -> Option<&PartitionRecordValue> {
-        self.fold(|_| None, |_| None, |v| Some(v), |_| None)
+        self.fold(
+            |_| None,
+            |_| None,
+            Some,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+        )
+    }
+    pub fn config_record(&self) -> Option<&ConfigRecordValue> {
+        self.fold(
+            |_| None,
+            |_| None,
+            |_| None,
+            Some,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+        )
+    }
+    pub fn user_scram_credential_record(
+        &self,
+    ) -> Option<&UserScramCredentialRecordValue> {
+        self.fold(
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            Some,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+        )
     }
     pub fn raw(&self) -> Option<&RawValue> {
-        self.fold(|_| None, |_| None, |_| None, |v| Some(v))
+        self.fold(
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            Some,
+        )
     }
     pub fn topic_id(&self) -> Option<TopicId> {
-        self.fold(|_| None, |v| Some(v.3.clone()), |v| Some(v.3), |_| None)
+        self.fold(
+            |_| None,
+            |v| Some(v.3),
+            |v| Some(v.3),
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+        )
     }
     pub fn name(&self) -> Option<TopicName> {
-        self.fold(|_| None, |v| Some(v.2.clone()), |_| None, |_| None)
+        self.fold(
+            |_| None,
+            |v| Some(v.2.clone()),
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+        )
     }
-    fn mk_feature_level_record(v: &[u8]) -> RecordValue {
-        RecordValue::FeatureLevelRecord(FeatureLevelRecordValue(v.to_vec()))
+    pub fn access_control_entry_record(
+        &self,
+    ) -> Option<&AccessControlEntryRecordValue> {
+        self.fold(
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            Some,
+            |_| None,
+            |_| None,
+            |_| None,
+        )
+    }
+    pub fn remove_access_control_entry_record(
+        &self,
+    ) -> Option<&RemoveAccessControlEntryRecordValue> {
+        self.fold(
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            Some,
+            |_| None,
+            |_| None,
+        )
+    }
+    pub fn client_quota_record(&self) -> Option<&ClientQuotaRecordValue> {
+        self.fold(
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            |_| None,
+            Some,
+            |_| None,
+        )
+    }
+    /// Metadata records are recognised by their type byte. Anything that
+    /// does not re-encode to the same bytes is kept raw, so user payloads
+    /// round-trip untouched.
+    fn mk(v: &[u8]) -> RecordValue {
+        let value = match v.get(1) {
+            Some(0x0c) => Record::feature_level_record(v),
+            Some(0x02) => Record::topic_record(v),
+            Some(0x03) => Record::partition_record(v),
+            Some(0x04) => Record::config_record(v),
+            Some(0x0b) => Record::user_scram_credential_record(v),
+            Some(0x06) => Record::access_control_entry_record(v),
+            Some(0x07) => Record::remove_access_control_entry_record(v),
+            Some(0x0e) => Record::client_quota_record(v),
+            _ => Record::raw_value(v),
+        };
+        match value {
+            Ok(value) if Vec::<u8>::from(value.clone()) == v => value,
+            _ => Self::mk_raw(v),
+        }
     }
     fn mk_topic_record(
         frame_version: FrameVersion,
@@ -552,6 +1216,7 @@ impl RecordValue {
             topic_id,
         ))
     }
+    #[allow(clippy::too_many_arguments)]
     fn mk_partition_record(
         frame_version: FrameVersion,
         value_version: ValueVersion,
@@ -588,34 +1253,79 @@ impl RecordValue {
 impl Record {
     fn mk(v: &[u8]) -> Result<Record> {
         let (attributes, rest) = v.extract_u8_into(RecordAttributes::new)?;
-        let (timestamp_delta, rest) =
-            rest.extract_u8_into(TimestampDelta::new)?;
-        let (offset_delta, rest) = rest.extract_u8_into(OffsetDelta::new)?;
-        let (key, rest) = RecordKey::mk(rest)?;
-        let (record_length, rest) = rest.extract_signed_var_int()?;
-        let record_type = rest[1];
-        let record_value = match record_type {
-            0x0c => Self::feature_level_record(&rest),
-            0x02 => Self::topic_record(&rest),
-            0x03 => Self::partition_record(&rest),
-            _ => Self::raw_value(
-                &rest.drop(record_length.value() as usize).first()?,
-            ),
-        }?;
+        let (timestamp_delta, rest) = rest
+            .extract_signed_var_int()
+            .map_tuple(|v| TimestampDelta::new(v.value()))?;
+        let (offset_delta, rest) = rest
+            .extract_signed_var_int()
+            .fmap_tuple(|v| Ok(OffsetDelta::new(i32::try_from(v.value())?)))?;
+        let (key, rest) =
+            rest.extract_var_bytes().map_tuple(|v| v.map(RecordKey::new))?;
+        let (value, rest) =
+            rest.extract_var_bytes().map_tuple(|v| v.map(RecordValue::mk))?;
+        let (headers, rest) = rest.extract_var_array_into::<Header>()?;
+        if !rest.is_empty() {
+            return Err(Error::general("Record has trailing bytes"));
+        }
         Ok(Self {
             attributes,
             timestamp_delta,
             offset_delta,
             key,
-            value: record_value,
-            headers: vec![],
+            value,
+            headers,
         })
     }
+    pub fn new(
+        offset_delta: OffsetDelta,
+        key: Option<RecordKey>,
+        value: Option<RecordValue>,
+    ) -> Self {
+        Self {
+            attributes: RecordAttributes::new(0),
+            timestamp_delta: TimestampDelta::new(0),
+            offset_delta,
+            key,
+            value,
+            headers: vec![],
+        }
+    }
+    pub fn key(&self) -> Option<&RecordKey> {
+        self.key.as_ref()
+    }
+    pub fn value(&self) -> Option<&RecordValue> {
+        self.value.as_ref()
+    }
+    pub fn headers(&self) -> &Vec<Header> {
+        &self.headers
+    }
+    pub fn timestamp_delta(&self) -> TimestampDelta {
+        self.timestamp_delta
+    }
+    pub fn offset_delta(&self) -> OffsetDelta {
+        self.offset_delta
+    }
+    pub fn name(&self) -> Option<TopicName> {
+        self.value.as_ref().and_then(|v| v.name())
+    }
+    pub fn partition(&self) -> Option<&PartitionRecordValue> {
+        self.value.as_ref().and_then(|v| v.partition_record())
+    }
     fn raw_value(v: &[u8]) -> Result<RecordValue> {
         Ok(RecordValue::mk_raw(v))
     }
     fn feature_level_record(v: &[u8]) -> Result<RecordValue> {
-        Ok(RecordValue::mk_feature_level_record(v))
+        let (frame_version, rest) = v.extract_u8_into(FrameVersion::new)?;
+        let (_type, rest) = rest.extract_u8()?;
+        let (version, rest) = rest.extract_u8_into(ValueVersion::new)?;
+        let (name, rest) = rest.extract_compact_str()?;
+        let level = rest.extract_u16().map_tuple(|v| v as i16).first()?;
+        Ok(RecordValue::FeatureLevelRecord(FeatureLevelRecordValue(
+            frame_version,
+            version,
+            name,
+            level,
+        )))
     }
     pub fn topic_record(v: &[u8]) -> Result<RecordValue> {
         let (frame_version, rest) = v.extract_u8_into(FrameVersion::new)?;
@@ -631,6 +1341,104 @@ impl Record {
             topic_id,
         ))
     }
+    fn config_record(v: &[u8]) -> Result<RecordValue> {
+        let (frame_version, rest) = v.extract_u8_into(FrameVersion::new)?;
+        let (_type, rest) = rest.extract_u8()?;
+        let (version, rest) = rest.extract_u8_into(ValueVersion::new)?;
+        let (resource_type, rest) =
+            rest.extract_u8_into(ConfigResourceType::new)?;
+        let (resource_name, rest) = rest.extract_compact_str()?;
+        let (name, rest) = rest.extract_compact_str()?;
+        let (value, _rest) = rest.extract_nullable_string(true)?;
+        Ok(RecordValue::ConfigRecord(ConfigRecordValue(
+            frame_version,
+            version,
+            resource_type,
+            resource_name,
+            name,
+            value,
+        )))
+    }
+    fn user_scram_credential_record(v: &[u8]) -> Result<RecordValue> {
+        let (frame_version, rest) = v.extract_u8_into(FrameVersion::new)?;
+        let (_type, rest) = rest.extract_u8()?;
+        let (version, rest) = rest.extract_u8_into(ValueVersion::new)?;
+        let (name, rest) = rest.extract_compact_str()?;
+        let (mechanism, rest) = rest.extract_u8()?;
+        let (salt, rest) = rest.extract_compact_nullable_bytes()?;
+        let (stored_key, rest) = rest.extract_compact_nullable_bytes()?;
+        let (server_key, rest) = rest.extract_compact_nullable_bytes()?;
+        let (iterations, _rest) = rest.extract_u32()?;
+        Ok(RecordValue::UserScramCredentialRecord(
+            UserScramCredentialRecordValue(
+                frame_version,
+                version,
+                name,
+                mechanism,
+                salt.unwrap_or_default().to_vec(),
+                stored_key.unwrap_or_default().to_vec(),
+                server_key.unwrap_or_default().to_vec(),
+                iterations,
+            ),
+        ))
+    }
+    fn access_control_entry_record(v: &[u8]) -> Result<RecordValue> {
+        let (frame_version, rest) = v.extract_u8_into(FrameVersion::new)?;
+        let (_type, rest) = rest.extract_u8()?;
+        let (version, rest) = rest.extract_u8_into(ValueVersion::new)?;
+        let (id, rest) = rest.extract_uuid()?;
+        let (resource_type, rest) =
+            rest.extract_u8_into(AclResourceType::new)?;
+        let (resource_name, rest) = rest.extract_compact_str()?;
+        let (pattern_type, rest) = rest.extract_u8_into(PatternType::new)?;
+        let (principal, rest) = rest.extract_compact_str()?;
+        let (host, rest) = rest.extract_compact_str()?;
+        let (operation, rest) = rest.extract_u8_into(AclOperation::new)?;
+        let (permission_type, _rest) =
+            rest.extract_u8_into(AclPermissionType::new)?;
+        Ok(RecordValue::AccessControlEntryRecord(
+            AccessControlEntryRecordValue(
+                frame_version,
+                version,
+                id,
+                AclBinding {
+                    resource_type,
+                    resource_name,
+                    pattern_type,
+                    principal,
+                    host,
+                    operation,
+                    permission_type,
+                },
+            ),
+        ))
+    }
+    fn remove_access_control_entry_record(v: &[u8]) -> Result<RecordValue> {
+        let (frame_version, rest) = v.extract_u8_into(FrameVersion::new)?;
+        let (_type, rest) = rest.extract_u8()?;
+        let (version, rest) = rest.extract_u8_into(ValueVersion::new)?;
+        let id = rest.extract_uuid().first()?;
+        Ok(RecordValue::RemoveAccessControlEntryRecord(
+            RemoveAccessControlEntryRecordValue(frame_version, version, id),
+        ))
+    }
+    fn client_quota_record(v: &[u8]) -> Result<RecordValue> {
+        let (frame_version, rest) = v.extract_u8_into(FrameVersion::new)?;
+        let (_type, rest) = rest.extract_u8()?;
+        let (version, rest) = rest.extract_u8_into(ValueVersion::new)?;
+        let (entity, rest) = ClientQuotaEntity::extract(rest, Version::V1)?;
+        let (key, rest) = rest.extract_compact_str()?;
+        let (quota, rest) = rest.extract_u64().map_tuple(f64::from_bits)?;
+        let (remove, _rest) = rest.extract_u8().map_tuple(|v| v != 0)?;
+        Ok(RecordValue::ClientQuotaRecord(ClientQuotaRecordValue(
+            frame_version,
+            version,
+            entity,
+            key,
+            quota,
+            remove,
+        )))
+    }
     fn array_node_id<T>(
         v: &[u8],
         f: impl FnMut(NodeId) -> T,
@@ -650,11 +1458,11 @@ impl Record {
         let (removing, rest) = Self::array_node_id(rest, RemovingReplica::new)?;
         let (adding, rest) = Self::array_node_id(rest, AddingReplica::new)?;
         let (leader, rest) =
-            rest.extract_u32_into(NodeId::new).map_tuple(|v| Leader::new(v))?;
+            rest.extract_u32_into(NodeId::new).map_tuple(Leader::new)?;
         let (leader_epoch, rest) = rest.extract_u32_into(LeaderEpoch::new)?;
         let (partition_epoch, rest) =
             rest.extract_u32_into(PartitionEpoch::new)?;
-        let (directories, rest) = rest.extract_array_into::<Uuid>()?;
+        let (directories, _rest) = rest.extract_array_into::<Uuid>()?;
         Ok(RecordValue::mk_partition_record(
             frame_version,
             version,
@@ -672,13 +1480,13 @@ impl Record {
     }
 
     pub fn is_partition_record(&self) -> bool {
-        self.value.partition_record().is_some()
+        self.partition().is_some()
     }
     pub fn is_topic_record(&self) -> bool {
-        self.value.topic_record().is_some()
+        self.value.as_ref().and_then(|v| v.topic_record()).is_some()
     }
     pub fn topic_id(&self) -> Option<TopicId> {
-        self.value.topic_id()
+        self.value.as_ref().and_then(|v| v.topic_id())
     }
 }
 
@@ -687,15 +1495,39 @@ impl From<RecordValue> for Vec<u8> {
         fn from1<T: Into<Vec<u8>> + Clone>(v: &T) -> Vec<u8> {
             T::into(v.clone())
         }
-        value.fold(from1, from1, from1, from1)
+        value
+            .fold(from1, from1, from1, from1, from1, from1, from1, from1, from1)
     }
 }
 
+/// The feature level record and the records creating topic "baz" with
+/// its one partition, a metadata log for tests.
+#[cfg(test)]
+pub(crate) const BAZ_METADATA_LOG: &str =
+    "00 00 00 00  00 00 00 01  00 00 00 4f  00 00 00 01 \
+    02 b0 69 45  7c 00 00 00  00 00 00 00  00 01 91 e0 \
+    5a f8 18 00  00 01 91 e0  5a f8 18 ff  ff ff ff ff \
+    ff ff ff ff  ff ff ff ff  ff 00 00 00  01 3a 00 00 \
+    00 01 2e 01  0c 00 11 6d  65 74 61 64  61 74 61 2e \
+    76 65 72 73  69 6f 6e 00  14 00 00 00  00 00 00 00 \
+    00 00 02 00  00 00 9a 00  00 00 01 02  fb c9 6e 51 \
+    00 00 00 00  00 01 00 00  01 91 e0 5b  2d 15 00 00 \
+    01 91 e0 5b  2d 15 ff ff  ff ff ff ff  ff ff ff ff \
+    ff ff ff ff  00 00 00 02  3c 00 00 00  01 30 01 02 \
+    00 04 62 61  7a 00 00 00  00 00 00 40  00 80 00 00 \
+    00 00 00 00  11 00 00 90  01 00 00 02  01 82 01 01 \
+    03 01 00 00  00 00 00 00  00 00 00 00  40 00 80 00 \
+    00 00 00 00  00 11 02 00  00 00 01 02  00 00 00 01 \
+    01 01 00 00  00 01 00 00  00 00 00 00  00 00 02 10 \
+    00 00 00 00  00 40 00 80  00 00 00 00  00 00 01 00 \
+    00";
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Context, VarInt};
+    use crate::{Context, CreatePartitionsTopic, ErrorCode, VarInt};
     use hex::decode;
+    use pretty_hex::*;
 
     #[test]
     fn test_load() -> Result<()> {
@@ -711,9 +1543,100 @@ mod tests {
         Ok(())
     }
     #[test]
+    fn test_find_partitions() -> Result<()> {
+        let bytes = decode(BAZ_METADATA_LOG.replace(" ", "")).unwrap();
+        let meta = Batch::split_by_batch(bytes).map(Meta::new)?;
+        let topic_id =
+            meta.find_topic_id(&TopicName::from("baz")).context("topic")?;
+        let partitions = meta.find_partitions(&topic_id);
+        assert_eq!(partitions.len(), 1);
+        Ok(())
+    }
+    // CreatePartitions for baz, v0 with assignments or null
+    fn create_partitions(
+        meta: &Meta,
+        count: u32,
+        assignments: Option<Vec<u32>>,
+    ) -> std::result::Result<Vec<PartitionRecordValue>, ErrorCode> {
+        let mut bytes = vec![0, 3];
+        bytes.extend(b"baz");
+        bytes.put_u32(count);
+        match assignments {
+            None => bytes.put_i32(-1),
+            Some(v) => {
+                bytes.put_u32(v.len() as u32);
+                v.iter().for_each(|v| {
+                    bytes.put_u32(1);
+                    bytes.put_u32(*v);
+                });
+            }
+        }
+        let (topic, _) = CreatePartitionsTopic::extract(&bytes, false).unwrap();
+        topic.partition_records(meta).map_err(|(e, _)| e)
+    }
+
+    #[test]
+    fn test_create_partitions() -> Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("meta-{}.log", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        std::fs::write(
+            &path,
+            decode(BAZ_METADATA_LOG.replace(" ", "")).unwrap(),
+        )
+        .context("test log")?;
+        let baz = TopicName::from("baz");
+        let meta = Meta::load(&path)?;
+        let topic_id = meta.find_topic_id(&baz).context("topic")?;
+
+        for count in [0, 1] {
+            assert_eq!(
+                create_partitions(&meta, count, None).unwrap_err(),
+                ErrorCode::InvalidPartitions
+            );
+        }
+        assert_eq!(
+            create_partitions(&meta, 4, Some(vec![1])).unwrap_err(),
+            ErrorCode::InvalidReplicaAssignment
+        );
+
+        Meta::append_with(&path, |meta| {
+            let records = create_partitions(meta, 3, None).unwrap();
+            let values =
+                records.into_iter().map(RecordValue::PartitionRecord).collect();
+            (values, ())
+        })?;
+        let meta = Meta::load(&path)?;
+        let partitions = meta.find_partitions(&topic_id);
+        let indexes: Vec<u32> = partitions.iter().map(|p| *p.2).collect();
+        assert_eq!(indexes, vec![0, 1, 2]);
+        // new partitions get the replicas of the first
+        assert_eq!(partitions[2].7, partitions[0].7);
+        // and are fetched from like it, a partition past them is unknown
+        let offset = RecordOffset::new(0);
+        assert!(meta
+            .find_log(&topic_id, PartitionIndex::new(2), offset)?
+            .is_some());
+        assert!(meta
+            .find_log(&topic_id, PartitionIndex::new(3), offset)?
+            .is_none());
+
+        std::fs::remove_file(path).context("cleanup")
+    }
+    #[test]
+    fn test_finalized_features() -> Result<()> {
+        let bytes = decode(BAZ_METADATA_LOG.replace(" ", "")).unwrap();
+        let meta = Batch::split_by_batch(bytes).map(Meta::new)?;
+        let (features, epoch) = meta.finalized_features();
+        assert_eq!(features.get("metadata.version"), Some(&20));
+        assert_eq!(epoch, 1);
+        Ok(())
+    }
+    #[test]
     fn test_batch() {
         let bytes_str = "00 00 00 00  00 00 00 00  00 00 00 44  00 00 00 00  02 ab fd 04  91 00 00 00  00 00 00 00  00 01 91 e0  5b 6d 8b 00  00 01 91 e0  5b 6d 8b 00  00 00 00 00  00 00 00 00  00 00 00 00  00 00 00 00  01 24 00 00  00 01 18 48  65 6c 6c 6f  20 4b 61 66  6b 61 21 00  00 00 00 00  00 00 00 01  00 00 00 52  00 00 00 00  02 8b aa 87  2a 00 00 00  00 00 00 00  00 01 91 e0  5b 6d 8b 00  00 01 91 e0  5b 6d 8b 00  00 00 00 00  00 00 00 00  00 00 00 00  00 00 00 00  01 40 00 00  00 01 34 48  65 6c 6c 6f  20 52 65 76  65 72 73 65  20 45 6e 67  69 6e 65 65  72 69 6e 67  21 00";
-        let byte_vec = decode(bytes_str.clone().replace(" ", "")).unwrap();
+        let byte_vec = decode(bytes_str.replace(" ", "")).unwrap();
         let batches = Batch::split_by_batch(byte_vec.clone()).unwrap();
         let bytes2: Vec<u8> = batches
             .clone()
@@ -729,6 +1652,198 @@ mod tests {
         assert_eq!(byte_vec.clone(), bytes2.clone());
     }
 
+    // Wraps hex encoded records into a v2 batch with a valid crc.
+    fn mk_batch(
+        base_offset: u64,
+        last_offset_delta: u32,
+        records: &[&str],
+    ) -> Vec<u8> {
+        let mut body = vec![];
+        body.put_u16(0);
+        body.put_u32(last_offset_delta);
+        body.put_u64(0x0191e05b6d8b);
+        body.put_u64(0x0191e05b6d8b);
+        body.put_u64(0xffffffffffffffff);
+        body.put_u16(0xffff);
+        body.put_u32(0xffffffff);
+        body.put_u32(records.len() as u32);
+        records.iter().for_each(|r| {
+            let r = decode(r.replace(" ", "")).unwrap();
+            body.extend(SignedVarInt::encode(r.len() as i64));
+            body.extend(r);
+        });
+        let mut batch = vec![];
+        batch.put_u32(0);
+        batch.put_u8(2);
+        batch.put_u32(CRC_32_C.checksum(&body));
+        batch.extend(body);
+        let mut bytes = base_offset.to_be_bytes().to_vec();
+        bytes.put_u32(batch.len() as u32);
+        bytes.extend(batch);
+        bytes
+    }
+
+    #[test]
+    fn test_record_key_headers_and_deltas() {
+        // ts delta 300, offset delta 70, key "k1", value "hello",
+        // headers h=v and n=null
+        let record = "00 d8 04 8c 01 04 6b 31 0a 68 65 6c 6c 6f \
+            04 02 68 02 76 02 6e 01";
+        let bytes = mk_batch(100, 70, &[record]);
+        let batches = Batch::split_by_batch(bytes.clone()).unwrap();
+        let batch = &batches[0];
+        let record = &batch.batch_records()[0];
+
+        assert_eq!(*record.timestamp_delta(), 300);
+        assert_eq!(*record.offset_delta(), 70);
+        assert_eq!(record.key().map(|k| k.to_vec()), Some(b"k1".to_vec()));
+        assert_eq!(
+            record.headers(),
+            &vec![
+                Header::new(
+                    HeaderKey::new("h".to_string()),
+                    Some(b"v".to_vec())
+                ),
+                Header::new(HeaderKey::new("n".to_string()), None),
+            ]
+        );
+        assert_eq!(*batch.record_offset(record), 170);
+        assert_eq!(*batch.record_timestamp(record), 0x0191e05b6d8b + 300);
+        assert_eq!(*batch.last_offset(), 170);
+
+        let encoded: Vec<u8> = batch.clone().into();
+        assert_eq!(encoded, bytes);
+    }
+
+    #[test]
+    fn test_corrupt_batch() {
+        let record = "00 00 00 00 0a 68 65 6c 6c 6f 00";
+        let first = mk_batch(0, 0, &[record]);
+        let second = mk_batch(1, 0, &[record]);
+        let position = first.len() as u64;
+
+        // flip a bit of the value in the second batch
+        let mut bytes = [first.clone(), second.clone()].concat();
+        let last = bytes.len() - 2;
+        bytes[last] ^= 0x01;
+        assert!(matches!(
+            Batch::split_by_batch(bytes),
+            Err(Error::CorruptRecord(p, _)) if p == position
+        ));
+
+        // a torn write leaves a length past the end of the file
+        let torn = [first.clone(), second[..20].to_vec()].concat();
+        assert!(matches!(
+            Batch::split_by_batch(torn),
+            Err(Error::CorruptRecord(p, _)) if p == position
+        ));
+
+        // two records cannot fit in last offset delta 0
+        let crowded = mk_batch(0, 0, &[record, record]);
+        assert!(matches!(
+            Batch::split_by_batch(crowded),
+            Err(Error::CorruptRecord(0, _))
+        ));
+    }
+
+    #[test]
+    fn test_meta_cache() -> Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("meta-cache-{}.log", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        std::fs::write(
+            &path,
+            decode(BAZ_METADATA_LOG.replace(" ", "")).unwrap(),
+        )
+        .context("test log")?;
+        static BATCHES: MetaCache<usize> = MetaCache::new(|m| m.0.len());
+        assert_eq!(*BATCHES.get(&path), 2);
+        // read again once the log grew
+        let feature = RecordValue::FeatureLevelRecord(FeatureLevelRecordValue(
+            FrameVersion::new(1),
+            ValueVersion::new(0),
+            "metadata.version".to_string(),
+            20,
+        ));
+        Meta::append(&path, vec![feature])?;
+        assert_eq!(*BATCHES.get(&path), 3);
+        assert_eq!(*BATCHES.get("missing"), 0);
+        std::fs::remove_file(path).context("cleanup")
+    }
+
+    #[test]
+    fn test_config_record() {
+        // a ConfigRecord v0 as a KRaft controller writes it, setting
+        // retention.ms=1000 for topic "foo"
+        let value = decode(
+            "01 04 00 02 04 666f6f 0d 726574656e74696f6e2e6d73 05 31303030 00"
+                .replace(" ", ""),
+        )
+        .unwrap();
+        let record = RecordValue::mk(&value);
+        let Some(ConfigRecordValue(
+            _,
+            _,
+            resource_type,
+            resource_name,
+            name,
+            config_value,
+        )) = record.config_record()
+        else {
+            panic!("not a ConfigRecord");
+        };
+        assert_eq!(**resource_type, 2);
+        assert_eq!(resource_name, "foo");
+        assert_eq!(name, "retention.ms");
+        assert_eq!(config_value.as_deref(), Some("1000"));
+        assert_eq!(Vec::<u8>::from(record), value);
+    }
+
+    #[test]
+    fn test_access_control_entry_records() {
+        // AccessControlEntryRecord and RemoveAccessControlEntryRecord v0
+        // as a KRaft controller writes them, allowing User:alice ALL on
+        // the literal topic "foo" from any host
+        let id = "0102030405060708090a0b0c0d0e0f10";
+        let entry = decode(
+            format!(
+                "01 06 00 {id} 02 04 666f6f 03 0b 557365723a616c696365 02 2a \
+                 02 03 00"
+            )
+            .replace(" ", ""),
+        )
+        .unwrap();
+        let record = RecordValue::mk(&entry);
+        let Some(AccessControlEntryRecordValue(_, _, entry_id, binding)) =
+            record.access_control_entry_record().cloned()
+        else {
+            panic!("not an AccessControlEntryRecord");
+        };
+        assert_eq!(entry_id.simple().to_string(), id);
+        assert_eq!(binding.resource_name, "foo");
+        assert_eq!(binding.principal, "User:alice");
+        assert_eq!(binding.host, "*");
+        assert_eq!((*binding.operation, *binding.permission_type), (2, 3));
+        assert_eq!(Vec::<u8>::from(record), entry);
+        let remove =
+            decode(format!("01 07 00 {id} 00").replace(" ", "")).unwrap();
+        let record = RecordValue::mk(&remove);
+        let Some(RemoveAccessControlEntryRecordValue(_, _, removed)) =
+            record.remove_access_control_entry_record()
+        else {
+            panic!("not a RemoveAccessControlEntryRecord");
+        };
+        assert_eq!(*removed, entry_id);
+        assert_eq!(Vec::<u8>::from(record), remove);
+        // a DelegationTokenRecord is not taken for an ACL
+        let mut token = entry.clone();
+        token[1] = 0x17;
+        assert!(RecordValue::mk(&token)
+            .access_control_entry_record()
+            .is_none());
+    }
+
     #[test]
     fn something() -> Result<()> {
         let topic_name = TopicName::new("saz".to_string());
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::{
+    fetch_sessions, read_dir_names, ErrorCode, Log, PartitionIndex,
+    RequestHeader, Segment, TopicName, LOG_DIR,
+};
+
+/// Upper bounds of the request latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 13] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+    10.0,
+];
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    // not cumulative, summed up when rendered
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|b| value <= *b) {
+            self.buckets[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    // by api key name and version
+    requests: BTreeMap<(String, u16), Histogram>,
+    errors: BTreeMap<&'static str, u64>,
+    bytes_in: BTreeMap<String, u64>,
+    bytes_out: BTreeMap<String, u64>,
+}
+
+/// What the broker counts for `/metrics`, in the Prometheus text format.
+/// Log end offsets and sizes are read from the log directory when scraped.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+    connections: AtomicI64,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::default)
+}
+
+impl Metrics {
+    /// A request answered with `error_code`, its latency since it was read.
+    pub fn request(&self, header: &RequestHeader, error_code: ErrorCode) {
+        let mut counters = self.counters.lock().unwrap();
+        counters
+            .requests
+            .entry((format!("{:?}", header.api_key()), *header.api_version()))
+            .or_default()
+            .observe(header.received().elapsed().as_secs_f64());
+        if error_code != ErrorCode::NoError {
+            *counters.errors.entry(error_code.name()).or_default() += 1;
+        }
+    }
+    /// Record bytes appended to a topic.
+    pub fn bytes_in(&self, topic_name: &TopicName, bytes: usize) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters.bytes_in.entry(topic_name.to_string()).or_default() +=
+            bytes as u64;
+    }
+    /// Record bytes fetched from a topic.
+    pub fn bytes_out(&self, topic_name: &TopicName, bytes: usize) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters.bytes_out.entry(topic_name.to_string()).or_default() +=
+            bytes as u64;
+    }
+    pub fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn connection_closed(&self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+    /// Every metric in the Prometheus text exposition format.
+    pub fn render(&self, log_dir: &str) -> String {
+        let mut out = String::new();
+        let counters = self.counters.lock().unwrap();
+        header(
+            &mut out,
+            "kafka_requests_total",
+            "counter",
+            "Requests answered.",
+        );
+        for ((api_key, version), h) in &counters.requests {
+            let labels =
+                format!("api_key=\"{api_key}\",api_version=\"{version}\"");
+            let _ =
+                writeln!(out, "kafka_requests_total{{{labels}}} {}", h.count);
+        }
+        header(
+            &mut out,
+            "kafka_request_latency_seconds",
+            "histogram",
+            "Time from reading a request to answering it.",
+        );
+        for ((api_key, version), h) in &counters.requests {
+            let labels =
+                format!("api_key=\"{api_key}\",api_version=\"{version}\"");
+            let mut cumulative = 0;
+            for (bound, n) in LATENCY_BUCKETS.iter().zip(h.buckets) {
+                cumulative += n;
+                let _ = writeln!(
+                    out,
+                    "kafka_request_latency_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "kafka_request_latency_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                h.count
+            );
+            let _ = writeln!(
+                out,
+                "kafka_request_latency_seconds_sum{{{labels}}} {}",
+                h.sum
+            );
+            let _ = writeln!(
+                out,
+                "kafka_request_latency_seconds_count{{{labels}}} {}",
+                h.count
+            );
+        }
+        header(
+            &mut out,
+            "kafka_errors_total",
+            "counter",
+            "Requests answered with a top-level error, by error code.",
+        );
+        for (error_code, n) in &counters.errors {
+            let _ = writeln!(
+                out,
+                "kafka_errors_total{{error_code=\"{error_code}\"}} {n}"
+            );
+        }
+        for (name, help, values) in [
+            (
+                "kafka_topic_bytes_in_total",
+                "Record bytes produced to a topic.",
+                &counters.bytes_in,
+            ),
+            (
+                "kafka_topic_bytes_out_total",
+                "Record bytes fetched from a topic.",
+                &counters.bytes_out,
+            ),
+        ] {
+            header(&mut out, name, "counter", help);
+            for (topic, n) in values {
+                let _ =
+                    writeln!(out, "{name}{{topic=\"{}\"}} {n}", escape(topic));
+            }
+        }
+        drop(counters);
+        header(
+            &mut out,
+            "kafka_connections_active",
+            "gauge",
+            "Open client connections.",
+        );
+        let _ = writeln!(
+            out,
+            "kafka_connections_active {}",
+            self.connections.load(Ordering::Relaxed)
+        );
+        header(
+            &mut out,
+            "kafka_fetch_session_cache_size",
+            "gauge",
+            "Incremental fetch sessions cached.",
+        );
+        let _ = writeln!(
+            out,
+            "kafka_fetch_session_cache_size {}",
+            fetch_sessions().session_count()
+        );
+        let partitions = partitions(log_dir);
+        header(
+            &mut out,
+            "kafka_log_end_offset",
+            "gauge",
+            "Offset the next record appended to a partition gets.",
+        );
+        for (topic_name, partition) in &partitions {
+            if let Ok((_, next_offset)) =
+                Log::end(log_dir, topic_name, *partition)
+            {
+                let _ = writeln!(
+                    out,
+                    "kafka_log_end_offset{{{}}} {}",
+                    partition_labels(topic_name, *partition),
+                    *next_offset
+                );
+            }
+        }
+        header(
+            &mut out,
+            "kafka_log_size_bytes",
+            "gauge",
+            "Bytes of a partition's segments.",
+        );
+        for (topic_name, partition) in &partitions {
+            let dir = Log::dir(log_dir, topic_name, *partition);
+            if let Ok(segments) = Segment::list(&dir) {
+                let _ = writeln!(
+                    out,
+                    "kafka_log_size_bytes{{{}}} {}",
+                    partition_labels(topic_name, *partition),
+                    segments.iter().map(Segment::size).sum::<u64>()
+                );
+            }
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn partition_labels(
+    topic_name: &TopicName,
+    partition: PartitionIndex,
+) -> String {
+    format!("topic=\"{}\",partition=\"{}\"", escape(topic_name), *partition)
+}
+
+/// The `<topic>-<partition>` directories of the log directory.
+fn partitions(log_dir: &str) -> Vec<(TopicName, PartitionIndex)> {
+    read_dir_names(log_dir)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|v| v.rsplit_once('-'))
+        .filter_map(|(topic, partition)| {
+            let partition = partition.parse().ok()?;
+            Some((TopicName::from(topic), PartitionIndex::new(partition)))
+        })
+        .collect()
+}
+
+/// Serves `GET /metrics` on the `metrics.listener` address, one scrape at
+/// a time.
+pub fn spawn_metrics(listener: TcpListener) -> JoinHandle<()> {
+    spawn(move || {
+        for stream in listener.incoming() {
+            if let Err(e) = stream.and_then(scrape) {
+                warn!("metrics scrape failed: {}", e);
+            }
+        }
+    })
+}
+
+fn scrape(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let (status, body) = match request_line.split_whitespace().nth(1) {
+        Some("/metrics") => ("200 OK", metrics().render(LOG_DIR)),
+        _ => ("404 Not Found", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ApiKey, Request};
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        // ApiVersions v0 from client "t"
+        let frame = [0, 0, 0, 11, 0, 18, 0, 0, 0, 0, 0, 1, 0, 1, b't'];
+        let request = Request::read(&mut frame.as_slice()).unwrap();
+        assert!(matches!(request.header.api_key(), ApiKey::ApiVersions));
+        metrics.request(&request.header, ErrorCode::NoError);
+        metrics.request(&request.header, ErrorCode::UnsupportedVersion);
+        metrics.bytes_in(&TopicName::from("foo"), 70);
+        metrics.connection_opened();
+        let text = metrics.render("/nonexistent");
+        assert!(text.contains(
+            "kafka_requests_total{api_key=\"ApiVersions\",api_version=\"0\"} 2"
+        ));
+        assert!(text.contains(
+            "kafka_request_latency_seconds_bucket{api_key=\"ApiVersions\",api_version=\"0\",le=\"+Inf\"} 2"
+        ));
+        assert!(text.contains(
+            "kafka_errors_total{error_code=\"UNSUPPORTED_VERSION\"} 1"
+        ));
+        assert!(text.contains("kafka_topic_bytes_in_total{topic=\"foo\"} 70"));
+        assert!(text.contains("kafka_connections_active 1"));
+    }
+}
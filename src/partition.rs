@@ -1,9 +1,6 @@
-use crate::{
-    BytesOps, ErrorCode, Result, TagBuffer, ToArray, TryExtract, VarInt,
-};
+use crate::{BytesOps, ErrorCode, Result, TagBuffer, TryExtract, VarInt};
 use bytes::BufMut;
 use newtype_macro::newtype;
-use std::ops::Deref;
 use uuid::Uuid;
 
 #[newtype]
@@ -70,7 +67,6 @@ pub struct RackId(String);
 
 #[derive(Debug, Clone)]
 pub struct Partition {
-    error_code: ErrorCode,
     partition_index: PartitionIndex,
     leader_id: Leader,
     leader_epoch: LeaderEpoch,
@@ -79,9 +75,9 @@ pub struct Partition {
     eligible_leader_replicas: Vec<EligibleLeaderReplicas>,
     last_known_elrs: Vec<LastKnownELR>,
     offline_replicas: Vec<OfflineReplica>,
-    tag_buffer: TagBuffer,
 }
 impl Partition {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         partition_index: PartitionIndex,
         leader_id: Leader,
@@ -93,7 +89,6 @@ impl Partition {
         offline_replicas: Vec<OfflineReplica>,
     ) -> Self {
         Self {
-            error_code: ErrorCode::NoError,
             partition_index,
             leader_id,
             leader_epoch,
@@ -102,7 +97,6 @@ impl Partition {
             eligible_leader_replicas,
             last_known_elrs,
             offline_replicas,
-            tag_buffer: TagBuffer::zero(),
         }
     }
 }
@@ -116,33 +110,24 @@ impl From<Partition> for Vec<u8> {
         bytes.put_u32(*partition.leader_epoch);
         bytes
             .extend(VarInt::encode((partition.replica_nodes.len() + 1) as u64));
-        partition
-            .replica_nodes
-            .iter()
-            .for_each(|v| bytes.put_u32(**(v.clone())));
+        partition.replica_nodes.iter().for_each(|v| bytes.put_u32(***v));
         bytes.extend(VarInt::encode((partition.isr_nodes.len() + 1) as u64));
-        partition.isr_nodes.iter().for_each(|v| bytes.put_u32(**(v.clone())));
+        partition.isr_nodes.iter().for_each(|v| bytes.put_u32(***v));
         bytes.extend(VarInt::encode(
             (partition.eligible_leader_replicas.len() + 1) as u64,
         ));
         partition
             .eligible_leader_replicas
             .iter()
-            .for_each(|v| bytes.put_u32(**(v.clone())));
+            .for_each(|v| bytes.put_u32(***v));
         bytes.extend(VarInt::encode(
             (partition.last_known_elrs.len() + 1) as u64,
         ));
-        partition
-            .last_known_elrs
-            .iter()
-            .for_each(|v| bytes.put_u32(**(v.clone())));
+        partition.last_known_elrs.iter().for_each(|v| bytes.put_u32(***v));
         bytes.extend(VarInt::encode(
             (partition.offline_replicas.len() + 1) as u64,
         ));
-        partition
-            .offline_replicas
-            .iter()
-            .for_each(|v| bytes.put_u32(**(v.clone())));
+        partition.offline_replicas.iter().for_each(|v| bytes.put_u32(***v));
         bytes.put_u8(*TagBuffer::zero());
         bytes
     }
@@ -1,8 +1,9 @@
-use crate::{Context, Error, MapTupleTwo, NodeId, Result, TopicName};
+use crate::{Context, Error, MapTupleTwo, Result, TopicName};
 use bytes::{Buf, BufMut};
 use pretty_hex::simple_hex;
 use std::ops::Deref;
 use std::str::from_utf8;
+use tracing::trace;
 use uuid::Uuid;
 
 #[derive(Debug, Clone)]
@@ -25,6 +26,9 @@ impl SignedVarInt {
     pub fn value(&self) -> i64 {
         self.value
     }
+    pub fn n_bytes(&self) -> usize {
+        self.n_bytes
+    }
 
     fn zig_zag_decode(n: u64) -> i64 {
         ((n >> 1) as i64) ^ -((n & 1) as i64)
@@ -51,22 +55,14 @@ impl VarInt {
     pub fn decode(bytes: &[u8]) -> Result<(Self, &[u8])> {
         let mut value: u64 = 0;
         let mut shift = 0;
-        let mut n_bytes = 0;
 
-        for &byte in bytes {
+        for (i, &byte) in bytes.iter().enumerate() {
             let part = (byte & 0x7F) as u64; // Extract lower 7 bits
             value |= part << shift; // Shift and accumulate
-            n_bytes += 1;
 
             if (byte & 0x80) == 0 {
                 // If MSB is 0, this is the last byte
-                return Ok((
-                    Self {
-                        value,
-                        n_bytes,
-                    },
-                    &bytes[n_bytes..],
-                ));
+                return Ok((Self::new(value, i + 1), &bytes[i + 1..]));
             }
 
             shift += 7;
@@ -166,6 +162,14 @@ pub trait BytesOps {
         f: impl FnMut(u32) -> T,
     ) -> Result<(Vec<T>, &[u8])>;
     fn extract_array_into<T: TryExtract>(&self) -> Result<(Vec<T>, &[u8])>;
+    /// Reads a COMPACT_ARRAY or, when `compact` is false, an ARRAY with an
+    /// int32 length, using `f` for the elements.
+    fn extract_array_with<'a, T>(
+        &'a self,
+        compact: bool,
+        f: impl FnMut(&'a [u8]) -> Result<(T, &'a [u8])>,
+    ) -> Result<(Vec<T>, &'a [u8])>;
+    fn extract_var_array_into<T: TryExtract>(&self) -> Result<(Vec<T>, &[u8])>;
     fn drop(&self, num: usize) -> Result<(&[u8], &[u8])>;
     fn extract_compact_str(&self) -> Result<(String, &[u8])>;
 
@@ -182,9 +186,45 @@ pub trait BytesOps {
     }
 
     fn extract_signed_var_int(&self) -> Result<(SignedVarInt, &[u8])>;
+    fn extract_var_bytes(&self) -> Result<(Option<&[u8]>, &[u8])>;
+    fn extract_nullable_bytes(&self) -> Result<(Option<&[u8]>, &[u8])> {
+        let (length, rest) = self.extract_u32()?;
+        match length {
+            0xffffffff => Ok((None, rest)),
+            _ => rest.drop(length as usize).map_tuple(Some),
+        }
+    }
+    fn extract_string(&self) -> Result<(String, &[u8])> {
+        let (length, rest) = self.extract_u16()?;
+        rest.extract_str(length as usize).map_tuple(str::to_string)
+    }
+    /// A compact string in flexible versions, otherwise a u16 length one.
+    fn extract_flexible_string(
+        &self,
+        flexible: bool,
+    ) -> Result<(String, &[u8])> {
+        match flexible {
+            true => self.extract_compact_str(),
+            false => self.extract_string(),
+        }
+    }
+    /// Skips the tag buffer that ends a structure in flexible versions.
+    fn drop_tag_buffer(&self, flexible: bool) -> Result<&[u8]>;
+    /// Compact length is n+1 with 0 for null, otherwise -1 is null.
+    fn extract_nullable_string(
+        &self,
+        compact: bool,
+    ) -> Result<(Option<String>, &[u8])>;
+    fn extract_compact_nullable_bytes(&self) -> Result<(Option<&[u8]>, &[u8])>;
 }
 
 impl BytesOps for [u8] {
+    fn drop_tag_buffer(&self, flexible: bool) -> Result<&[u8]> {
+        match flexible {
+            true => self.drop(1).second(),
+            false => Ok(self),
+        }
+    }
     fn extract_u32(&self) -> Result<(u32, &[u8])> {
         self.drop(4).map_tuple(move |mut l| l.get_u32())
     }
@@ -198,39 +238,60 @@ impl BytesOps for [u8] {
     }
 
     fn extract_u16(&self) -> Result<(u16, &[u8])> {
-        self.drop(2).map_tuple(|l| l.clone().get_u16())
+        self.drop(2).map_tuple(|mut l| l.get_u16())
     }
 
     fn extract_array<T: Clone>(
         &self,
         mut f: impl FnMut(u32) -> T,
     ) -> Result<(Vec<T>, &[u8])> {
-        let (len, rest) = VarInt::decode(self).map_tuple(|v| v.value() - 1)?;
-        rest.drop(len * 4).map_tuple(|replicas| {
+        let (len, rest) =
+            VarInt::decode(self).map_tuple(|v| v.value().saturating_sub(1))?;
+        let size = len.checked_mul(4).context("array length")?;
+        rest.drop(size).map_tuple(|replicas| {
             let r: Vec<T> =
-                replicas.chunks(32).map(|mut rep| f(rep.get_u32())).collect();
+                replicas.chunks(4).map(|mut rep| f(rep.get_u32())).collect();
             r
         })
     }
 
     fn extract_array_into<T: TryExtract>(&self) -> Result<(Vec<T>, &[u8])> {
-        fn do_split<T: TryExtract>(
-            v: &[u8],
-            len: usize,
-            mut result: Vec<T>,
-        ) -> Result<(Vec<T>, &[u8])> {
-            if len == 0 {
-                Ok((result, v))
-            } else {
-                let (value, rest) = T::try_extract(v)?;
-                result.push(value);
-                do_split(rest, len - 1, result)
-            }
+        trace!("extract_array: {:?}", simple_hex(&self));
+        let (len, rest) =
+            VarInt::decode(self).map_tuple(|v| v.value().saturating_sub(1))?;
+        trace!("len: {:?}", len);
+        extract_n(rest, len, vec![])
+    }
+
+    fn extract_array_with<'a, T>(
+        &'a self,
+        compact: bool,
+        mut f: impl FnMut(&'a [u8]) -> Result<(T, &'a [u8])>,
+    ) -> Result<(Vec<T>, &'a [u8])> {
+        let (len, mut rest) = if compact {
+            VarInt::decode(self).map_tuple(|v| v.value().saturating_sub(1))?
+        } else {
+            self.extract_u32().map_tuple(|v| {
+                if v == 0xffffffff {
+                    0
+                } else {
+                    v as usize
+                }
+            })?
+        };
+        let mut result = vec![];
+        for _ in 0..len {
+            let (value, next) = f(rest)?;
+            result.push(value);
+            rest = next;
         }
-        println!("extract_array: {:?}", simple_hex(&self));
-        let (len, rest) = VarInt::decode(&self).map_tuple(|v| v.value() - 1)?;
-        println!("len: {:?}", len);
-        do_split(&rest, len, vec![])
+        Ok((result, rest))
+    }
+
+    fn extract_var_array_into<T: TryExtract>(&self) -> Result<(Vec<T>, &[u8])> {
+        let (len, rest) = self.extract_signed_var_int()?;
+        let len = usize::try_from(len.value())?;
+        extract_n(rest, len, vec![])
     }
 
     fn drop(&self, num: usize) -> Result<(&[u8], &[u8])> {
@@ -238,7 +299,7 @@ impl BytesOps for [u8] {
     }
 
     fn extract_compact_str(&self) -> Result<(String, &[u8])> {
-        let (length, rest) = VarInt::decode(&self)?;
+        let (length, rest) = VarInt::decode(self)?;
         if length.value == 0 {
             Ok(("".to_string(), rest))
         } else {
@@ -255,6 +316,53 @@ impl BytesOps for [u8] {
     fn extract_signed_var_int(&self) -> Result<(SignedVarInt, &[u8])> {
         SignedVarInt::decode(self)
     }
+
+    fn extract_var_bytes(&self) -> Result<(Option<&[u8]>, &[u8])> {
+        let (length, rest) = self.extract_signed_var_int()?;
+        if length.value() < 0 {
+            Ok((None, rest))
+        } else {
+            rest.drop(usize::try_from(length.value())?).map_tuple(Some)
+        }
+    }
+    fn extract_nullable_string(
+        &self,
+        compact: bool,
+    ) -> Result<(Option<String>, &[u8])> {
+        let (length, rest) = match compact {
+            true =>
+                VarInt::decode(self).map_tuple(|v| v.value().checked_sub(1))?,
+            false => self
+                .extract_u16()
+                .map_tuple(|v| (v != 0xffff).then_some(v as usize))?,
+        };
+        match length {
+            None => Ok((None, rest)),
+            Some(length) =>
+                rest.extract_str(length).map_tuple(|v| Some(v.to_string())),
+        }
+    }
+    fn extract_compact_nullable_bytes(&self) -> Result<(Option<&[u8]>, &[u8])> {
+        let (length, rest) = VarInt::decode(self)?;
+        match length.value().checked_sub(1) {
+            None => Ok((None, rest)),
+            Some(length) => rest.drop(length).map_tuple(Some),
+        }
+    }
+}
+
+// A loop rather than recursion, the length comes off the wire.
+fn extract_n<T: TryExtract>(
+    mut v: &[u8],
+    len: usize,
+    mut result: Vec<T>,
+) -> Result<(Vec<T>, &[u8])> {
+    for _ in 0..len {
+        let (value, rest) = T::try_extract(v)?;
+        result.push(value);
+        v = rest;
+    }
+    Ok((result, v))
 }
 pub trait TryExtract {
     fn try_extract(v: &[u8]) -> Result<(Self, &[u8])>
@@ -269,7 +377,7 @@ impl ToCompactString for String {
     fn to_compact_string(&self) -> Vec<u8> {
         let mut bytes = vec![];
         bytes.extend(VarInt::encode((&self.len() + 1) as u64));
-        bytes.put_slice(&self.as_bytes());
+        bytes.put_slice(self.as_bytes());
         bytes
     }
 }
@@ -288,6 +396,73 @@ impl TryExtract for Uuid {
     }
 }
 
+pub trait ToKafkaString {
+    fn to_kafka_string(&self) -> Vec<u8>;
+}
+
+impl ToKafkaString for String {
+    fn to_kafka_string(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.put_u16(self.len() as u16);
+        bytes.put_slice(self.as_bytes());
+        bytes
+    }
+}
+impl ToKafkaString for TopicName {
+    fn to_kafka_string(&self) -> Vec<u8> {
+        self.deref().to_kafka_string()
+    }
+}
+
+pub trait ToNullableBytes {
+    fn to_nullable_bytes(&self) -> Vec<u8>;
+}
+
+impl ToNullableBytes for Option<Vec<u8>> {
+    fn to_nullable_bytes(&self) -> Vec<u8> {
+        match self {
+            None => 0xffffffffu32.to_be_bytes().to_vec(),
+            Some(v) => {
+                let mut bytes = (v.len() as u32).to_be_bytes().to_vec();
+                bytes.put_slice(v);
+                bytes
+            }
+        }
+    }
+}
+
+pub trait ToNullableString {
+    fn to_nullable_string(&self, compact: bool) -> Vec<u8>;
+}
+
+impl ToNullableString for Option<String> {
+    fn to_nullable_string(&self, compact: bool) -> Vec<u8> {
+        match (self, compact) {
+            (None, true) => vec![0],
+            (None, false) => 0xffffu16.to_be_bytes().to_vec(),
+            (Some(v), true) => v.to_compact_string(),
+            (Some(v), false) => v.to_kafka_string(),
+        }
+    }
+}
+
+pub trait ToVarBytes {
+    fn to_var_bytes(&self) -> Vec<u8>;
+}
+
+impl ToVarBytes for Option<Vec<u8>> {
+    fn to_var_bytes(&self) -> Vec<u8> {
+        match self {
+            None => SignedVarInt::encode(-1),
+            Some(v) => {
+                let mut bytes = SignedVarInt::encode(v.len() as i64);
+                bytes.put_slice(v);
+                bytes
+            }
+        }
+    }
+}
+
 pub trait ToArray {
     fn to_pb_array(&self) -> Result<Vec<u8>>;
 }
@@ -313,17 +488,16 @@ impl ToArray for Vec<Uuid> {
 
 #[cfg(test)]
 mod test {
-    use crate::{BytesOps, FetchTopic, MapTupleTwo, Result};
+    use crate::FetchTopic;
     use hex::decode;
 
     #[test]
     fn test_encode() {
         let num: u64 = 0b1011_0010_1110_1101_0001;
-        let num1: u64 = 0b1011_0010_1110_1101_0001;
 
-        let first_bits = (num);
-        let first_bits2 = (num >> 7);
-        let first_bits3 = (num >> 14);
+        let first_bits = num;
+        let first_bits2 = num >> 7;
+        let first_bits3 = num >> 14;
         let last_7_bits = first_bits & 0b111_1111;
         let last_7_bits2 = first_bits2 & 0b111_1111;
         let last_7_bits3 = first_bits3 & 0b111_1111;
@@ -336,7 +510,7 @@ mod test {
     #[test]
     fn test_extract_array_into() {
         use super::*;
-        let data: String = vec![
+        let data: String = [
             "02 00 00 00  00 00 00 00  00 00 00 00  00 00 00 53",
             "21 02 00 00  00 00 ff ff  ff ff 00 00  00 00 00 00",
             "00 00 ff ff  ff ff ff ff  ff ff ff ff  ff ff 00 10",
@@ -0,0 +1,401 @@
+use crate::{
+    array_length, fetch_purgatory, metrics, response_header, with_message_size,
+    AclOperation, ApiKey, BytesOps, CorrelationId, Error, ErrorCode,
+    HandlerContext, Log, LogConfig, LogStartOffset, MapTupleTwo, Meta,
+    PartitionIndex, RecordOffset, Request, RequestBody, Response, ResponseBody,
+    Result, TagBuffer, ThrottleTime, ToCompactString, ToKafkaString, TopicName,
+    Version,
+};
+use bytes::BufMut;
+use newtype_macro::newtype;
+use tracing::{error, warn};
+
+#[newtype]
+pub struct Acks(i16);
+
+#[newtype]
+pub struct ProduceTimeout(u32);
+
+#[newtype]
+pub struct LogAppendTime(u64);
+
+#[derive(Debug, Clone)]
+pub struct ProduceTopic {
+    topic_name: TopicName,
+    partitions: Vec<ProducePartition>,
+}
+
+impl ProduceTopic {
+    pub fn topic_name(&self) -> &TopicName {
+        &self.topic_name
+    }
+    pub fn partitions(&self) -> &Vec<ProducePartition> {
+        &self.partitions
+    }
+    pub fn extract(value: &[u8], flexible: bool) -> Result<(Self, &[u8])> {
+        let (topic_name, rest) = match flexible {
+            true => value.extract_compact_str(),
+            false => value.extract_string(),
+        }
+        .map_tuple(TopicName::new)?;
+        let (partitions, rest) = rest.extract_array_with(flexible, |v| {
+            ProducePartition::extract(v, flexible)
+        })?;
+        let rest = match flexible {
+            true => rest.drop(1).second()?,
+            false => rest,
+        };
+        Ok((
+            Self {
+                topic_name,
+                partitions,
+            },
+            rest,
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProducePartition {
+    partition_index: PartitionIndex,
+    records: Option<Vec<u8>>,
+}
+
+impl ProducePartition {
+    pub fn partition_index(&self) -> PartitionIndex {
+        self.partition_index
+    }
+    pub fn records(&self) -> Option<&[u8]> {
+        self.records.as_deref()
+    }
+    fn extract(value: &[u8], flexible: bool) -> Result<(Self, &[u8])> {
+        let (partition_index, rest) =
+            value.extract_u32_into(PartitionIndex::new)?;
+        let (records, rest) = match flexible {
+            true => rest.extract_compact_nullable_bytes(),
+            false => rest.extract_nullable_bytes(),
+        }
+        .map_tuple(|v| v.map(<[u8]>::to_vec))?;
+        let rest = match flexible {
+            true => rest.drop(1).second()?,
+            false => rest,
+        };
+        Ok((
+            Self {
+                partition_index,
+                records,
+            },
+            rest,
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProduceResponse {
+    topic_name: TopicName,
+    partitions: Vec<ProducePartitionResponse>,
+}
+
+impl ProduceResponse {
+    pub fn new(
+        topic_name: TopicName,
+        partitions: Vec<ProducePartitionResponse>,
+    ) -> Self {
+        Self {
+            topic_name,
+            partitions,
+        }
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::Produce.is_flexible(version);
+        let mut bytes = match flexible {
+            true => self.topic_name.to_compact_string(),
+            false => self.topic_name.to_kafka_string(),
+        };
+        bytes.extend(array_length(flexible, self.partitions.len()));
+        self.partitions
+            .into_iter()
+            .for_each(|p| bytes.extend(p.encode(version)));
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProducePartitionResponse {
+    partition_index: PartitionIndex,
+    error_code: ErrorCode,
+    base_offset: RecordOffset,
+    log_append_time: LogAppendTime,
+    log_start_offset: LogStartOffset,
+}
+
+impl ProducePartitionResponse {
+    pub fn new(
+        partition_index: PartitionIndex,
+        base_offset: RecordOffset,
+        log_start_offset: LogStartOffset,
+    ) -> Self {
+        Self {
+            partition_index,
+            error_code: ErrorCode::NoError,
+            base_offset,
+            log_append_time: LogAppendTime::new(u64::MAX),
+            log_start_offset,
+        }
+    }
+    pub fn error(
+        partition_index: PartitionIndex,
+        error_code: ErrorCode,
+    ) -> Self {
+        Self {
+            error_code,
+            base_offset: RecordOffset::new(u64::MAX),
+            ..Self::new(
+                partition_index,
+                RecordOffset::new(0),
+                LogStartOffset::new(u64::MAX),
+            )
+        }
+    }
+    pub fn error_code(&self) -> ErrorCode {
+        self.error_code
+    }
+    pub fn base_offset(&self) -> RecordOffset {
+        self.base_offset
+    }
+    pub fn encode(self, version: Version) -> Vec<u8> {
+        let flexible = ApiKey::Produce.is_flexible(version);
+        let mut bytes = vec![];
+        bytes.put_u32(*self.partition_index);
+        bytes.put_i16(*self.error_code);
+        bytes.put_u64(*self.base_offset);
+        bytes.put_u64(*self.log_append_time);
+        if version >= Version::V5 {
+            bytes.put_u64(*self.log_start_offset);
+        }
+        if version >= Version::V8 {
+            // no record errors and a null error message
+            bytes.extend(array_length(flexible, 0));
+            match flexible {
+                true => bytes.put_u8(0),
+                false => bytes.put_i16(-1),
+            }
+        }
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        bytes
+    }
+}
+
+impl RequestBody {
+    /// Produce v3 is the first to carry v2 record batches.
+    pub(crate) fn produce(body: &[u8], version: Version) -> Result<Self> {
+        let flexible = ApiKey::Produce.is_flexible(version);
+        let (transactional_id, rest) =
+            body.extract_nullable_string(flexible)?;
+        let (acks, rest) =
+            rest.extract_u16().map_tuple(|v| Acks::new(v as i16))?;
+        let (timeout, rest) = rest.extract_u32_into(ProduceTimeout::new)?;
+        let (topics, _rest) = rest.extract_array_with(flexible, |v| {
+            ProduceTopic::extract(v, flexible)
+        })?;
+        Ok(RequestBody::Produce {
+            transactional_id,
+            acks,
+            timeout,
+            topics,
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_produce(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let topic_allowed = cx.topic_allowed(request);
+        let RequestBody::Produce {
+            acks,
+            topics,
+            ..
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        let meta = Meta::load(cx.metadata_log)?;
+        Ok(ResponseBody::Produce {
+            acks: *acks,
+            responses: topics
+                .iter()
+                .map(|t| {
+                    let config = LogConfig::for_topic(&meta, t.topic_name());
+                    let authorized =
+                        topic_allowed(AclOperation::WRITE, t.topic_name());
+                    ProduceResponse::new(
+                        t.topic_name().clone(),
+                        t.partitions()
+                            .iter()
+                            .map(|p| match authorized {
+                                true => {
+                                    let response = Self::produce(
+                                        cx.log_dir, &meta, &config, t, p,
+                                    );
+                                    fetch_purgatory().wake(
+                                        t.topic_name(),
+                                        p.partition_index(),
+                                    );
+                                    response
+                                }
+                                false => ProducePartitionResponse::error(
+                                    p.partition_index(),
+                                    ErrorCode::TopicAuthorizationFailed,
+                                ),
+                            })
+                            .collect(),
+                    )
+                })
+                .collect(),
+            throttle_time: ThrottleTime::zero(),
+        })
+    }
+
+    pub(crate) fn error_produce(
+        body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        let (acks, responses) = match body {
+            Some(RequestBody::Produce {
+                acks,
+                topics,
+                ..
+            }) => (
+                *acks,
+                topics
+                    .iter()
+                    .map(|t| {
+                        ProduceResponse::new(
+                            t.topic_name().clone(),
+                            t.partitions()
+                                .iter()
+                                .map(|p| {
+                                    ProducePartitionResponse::error(
+                                        p.partition_index(),
+                                        error.error_code(),
+                                    )
+                                })
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+            ),
+            // without acks, answered as if the producer waits
+            _ => (Acks::new(1), vec![]),
+        };
+        ResponseBody::Produce {
+            acks,
+            responses,
+            throttle_time: ThrottleTime::zero(),
+        }
+    }
+
+    pub(crate) fn encode_produce(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::Produce {
+            acks,
+            responses,
+            throttle_time,
+        } = body
+        else {
+            unreachable!("not a Produce");
+        };
+        // acks=0 producers do not wait for a response
+        if *acks == 0 {
+            return vec![];
+        }
+        let (mut bytes, flexible) =
+            response_header(ApiKey::Produce, version, correlation_id);
+        bytes.extend(array_length(flexible, responses.len()));
+        responses.into_iter().for_each(|r| bytes.extend(r.encode(version)));
+        // v0 is only answered with an unsupported version
+        if version >= Version::V1 {
+            bytes.put_u32(*throttle_time);
+        }
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+
+    /// Appends to the partition log, batches failing validation are
+    /// rejected with CORRUPT_MESSAGE and nothing is written.
+    fn produce(
+        log_dir: &str,
+        meta: &Meta,
+        config: &LogConfig,
+        topic: &ProduceTopic,
+        partition: &ProducePartition,
+    ) -> ProducePartitionResponse {
+        let topic_name = topic.topic_name();
+        let index = partition.partition_index();
+        match (meta.has_partition(topic_name, index), partition.records()) {
+            (false, _) => ProducePartitionResponse::error(
+                index,
+                ErrorCode::UnknownTopicOrPartition,
+            ),
+            (true, None) => ProducePartitionResponse::error(
+                index,
+                ErrorCode::CorruptMessage,
+            ),
+            (true, Some(records)) =>
+                match Log::append(log_dir, topic_name, index, config, records) {
+                    Ok((base_offset, log_start_offset)) => {
+                        metrics().bytes_in(topic_name, records.len());
+                        ProducePartitionResponse::new(
+                            index,
+                            base_offset,
+                            log_start_offset,
+                        )
+                    }
+                    Err(e @ Error::CorruptRecord(..)) => {
+                        warn!("produce to {:?}: {}", topic_name, e);
+                        ProducePartitionResponse::error(
+                            index,
+                            ErrorCode::CorruptMessage,
+                        )
+                    }
+                    Err(e) => {
+                        error!("produce to {:?}: {}", topic_name, e);
+                        ProducePartitionResponse::error(
+                            index,
+                            ErrorCode::UnknownServerError,
+                        )
+                    }
+                },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex::decode;
+
+    #[test]
+    fn test_extract_flexible_topic() -> Result<()> {
+        // topic "foo", partition 1 with 3 bytes of records, then tag buffers
+        let bytes = decode("04666f6f0200000001046162630000").unwrap();
+        let (topic, rest) = ProduceTopic::extract(&bytes, true)?;
+        assert_eq!(topic.topic_name().value(), "foo");
+        assert_eq!(*topic.partitions()[0].partition_index(), 1);
+        assert_eq!(topic.partitions()[0].records(), Some(b"abc".as_slice()));
+        assert!(rest.is_empty());
+        Ok(())
+    }
+}
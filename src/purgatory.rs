@@ -0,0 +1,435 @@
+use std::mem::take;
+use std::sync::mpsc::Sender;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use crate::{
+    connections, log_request, FetchContext, Log, PartitionIndex, RecordOffset,
+    Request, RequestBody, Response, Result, TopicName, LOG_DIR,
+};
+
+/// A Fetch waiting for `min_bytes` of records and where its response goes.
+struct DelayedFetch {
+    request: Request,
+    context: FetchContext,
+    min_bytes: usize,
+    deadline: Instant,
+    partitions: Vec<(TopicName, PartitionIndex)>,
+    // where the logs of the partitions ended when last fetched
+    log_ends: Vec<Option<RecordOffset>>,
+    woken: bool,
+    reply: Sender<Vec<u8>>,
+}
+
+impl DelayedFetch {
+    /// Fetches again once one of its logs grew, the fetch stays parked
+    /// while it is short of `min_bytes` and has time left. The response is
+    /// metered when it is sent.
+    fn complete(
+        self,
+        purgatory: &FetchPurgatory,
+        now: Instant,
+    ) -> Option<Self> {
+        let log_ends = purgatory.log_ends(&self.partitions);
+        if self.deadline > now && log_ends == self.log_ends {
+            return Some(Self {
+                woken: false,
+                ..self
+            });
+        }
+        let started = Instant::now();
+        let response = (purgatory.refetch)(&self.request, self.context.clone());
+        match response {
+            Ok(response)
+                if self.deadline > now
+                    && response
+                        .fetched()
+                        .is_some_and(|(bytes, _)| bytes < self.min_bytes) =>
+                Some(Self {
+                    log_ends,
+                    woken: false,
+                    ..self
+                }),
+            Ok(response) => {
+                let response = response.throttled(&self.request, started);
+                response.sent();
+                log_request(&self.request.header, response.error_code());
+                // the client may have gone in the meantime
+                let _ = self.reply.send(response.into());
+                None
+            }
+            Err(e) => {
+                warn!("delayed fetch failed: {e}");
+                let response = Response::failed(
+                    &self.request.header,
+                    Some(&self.request.body),
+                    &e,
+                );
+                log_request(&self.request.header, e.error_code());
+                let _ = self.reply.send(response.map_or(vec![], Vec::from));
+                None
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    fetches: Vec<DelayedFetch>,
+    // counts wakes, so one during a completion is not lost
+    wakes: u64,
+}
+
+/// Fetches that cannot meet `min_bytes` yet. They are answered by the
+/// purgatory thread once a Produce to one of their partitions brings
+/// enough records or `max_wait` runs out, the connection goes on reading
+/// requests meanwhile.
+pub struct FetchPurgatory {
+    pending: Mutex<Pending>,
+    changed: Condvar,
+    refetch: fn(&Request, FetchContext) -> Result<Response>,
+    log_end: fn(&TopicName, PartitionIndex) -> Option<RecordOffset>,
+}
+
+impl Default for FetchPurgatory {
+    fn default() -> Self {
+        Self {
+            pending: Mutex::default(),
+            changed: Condvar::new(),
+            refetch: Response::refetch,
+            log_end: |topic_name, partition| {
+                Log::end(LOG_DIR, topic_name, partition).ok().map(|v| v.1)
+            },
+        }
+    }
+}
+
+static FETCH_PURGATORY: OnceLock<FetchPurgatory> = OnceLock::new();
+
+pub fn fetch_purgatory() -> &'static FetchPurgatory {
+    FETCH_PURGATORY.get_or_init(FetchPurgatory::default)
+}
+
+pub fn spawn_fetch_purgatory() -> JoinHandle<()> {
+    spawn(|| fetch_purgatory().run())
+}
+
+impl FetchPurgatory {
+    /// The response to send now, metered as handled since `started`, or
+    /// None when the fetch is parked and its response is sent to `reply`
+    /// later.
+    pub fn try_complete(
+        &self,
+        request: Request,
+        response: Response,
+        reply: &Sender<Vec<u8>>,
+        started: Instant,
+    ) -> Option<Response> {
+        let RequestBody::Fetch {
+            max_wait,
+            min_bytes,
+            ..
+        } = &request.body
+        else {
+            return Some(response);
+        };
+        match (response.fetched(), response.fetch_context()) {
+            (Some((bytes, partitions)), Some(context))
+                if bytes < **min_bytes as usize
+                    && **max_wait > 0
+                    && !connections().is_closing() =>
+            {
+                let fetch = DelayedFetch {
+                    context: context.clone(),
+                    min_bytes: **min_bytes as usize,
+                    deadline: Instant::now()
+                        + Duration::from_millis(**max_wait as u64),
+                    log_ends: self.log_ends(&partitions),
+                    partitions,
+                    woken: false,
+                    reply: reply.clone(),
+                    request,
+                };
+                self.pending.lock().unwrap().fetches.push(fetch);
+                self.changed.notify_all();
+                None
+            }
+            _ => {
+                let response = response.throttled(&request, started);
+                response.sent();
+                Some(response)
+            }
+        }
+    }
+    fn log_ends(
+        &self,
+        partitions: &[(TopicName, PartitionIndex)],
+    ) -> Vec<Option<RecordOffset>> {
+        partitions.iter().map(|(t, p)| (self.log_end)(t, *p)).collect()
+    }
+    /// Records were appended to a partition.
+    pub fn wake(&self, topic_name: &TopicName, partition: PartitionIndex) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.wakes += 1;
+        pending
+            .fetches
+            .iter_mut()
+            .filter(|f| {
+                f.partitions
+                    .iter()
+                    .any(|(t, p)| t == topic_name && *p == partition)
+            })
+            .for_each(|f| f.woken = true);
+        self.changed.notify_all();
+    }
+    /// Answers every parked fetch now with what it has, on shutdown.
+    pub fn expire_all(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        let now = Instant::now();
+        pending.fetches.iter_mut().for_each(|f| {
+            f.deadline = now;
+            f.woken = true;
+        });
+        self.changed.notify_all();
+    }
+    fn run(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let (due, waiting): (Vec<_>, Vec<_>) = take(&mut pending.fetches)
+                .into_iter()
+                .partition(|f| f.woken || f.deadline <= now);
+            pending.fetches = waiting;
+            let wakes = pending.wakes;
+            drop(pending);
+            let parked: Vec<_> =
+                due.into_iter().filter_map(|f| f.complete(self, now)).collect();
+            pending = self.pending.lock().unwrap();
+            let missed = pending.wakes != wakes;
+            pending.fetches.extend(parked.into_iter().map(|f| DelayedFetch {
+                woken: missed,
+                ..f
+            }));
+            if pending.fetches.iter().any(|f| f.woken) {
+                continue;
+            }
+            let timeout = pending
+                .fetches
+                .iter()
+                .map(|f| f.deadline.saturating_duration_since(Instant::now()))
+                .min()
+                .unwrap_or(Duration::from_secs(60));
+            pending = self.changed.wait_timeout(pending, timeout).unwrap().0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fetch_sessions, ErrorCode, FetchPartitionResponse, FetchResponse,
+        LogEntry, LogStartOffset, MagicByte, Message, MessageAttributes,
+        ResponseBody, SessionEpoch, SessionId, ThrottleTime, TopicId,
+    };
+    use bytes::BufMut;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use std::sync::mpsc::{channel, Receiver};
+
+    // records each test topic's partition 0 has, and how often the
+    // purgatory read it
+    static AVAILABLE: Mutex<Option<HashMap<String, u64>>> = Mutex::new(None);
+    static REFETCHES: Mutex<Option<HashMap<String, usize>>> = Mutex::new(None);
+
+    fn available(topic: &str) -> u64 {
+        let available = AVAILABLE.lock().unwrap();
+        available.as_ref().and_then(|v| v.get(topic)).copied().unwrap_or(0)
+    }
+
+    fn produce(topic: &str) {
+        let mut available = AVAILABLE.lock().unwrap();
+        *available
+            .get_or_insert_with(HashMap::new)
+            .entry(topic.into())
+            .or_default() += 1;
+    }
+
+    fn refetches(topic: &str) -> usize {
+        let refetches = REFETCHES.lock().unwrap();
+        refetches.as_ref().and_then(|v| v.get(topic)).copied().unwrap_or(0)
+    }
+
+    fn refetch(request: &Request, context: FetchContext) -> Result<Response> {
+        let topic_name = context.topics()[0].topic_name().cloned().unwrap();
+        *REFETCHES
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .entry(topic_name.to_string())
+            .or_default() += 1;
+        let records = (0..available(&topic_name))
+            .map(|offset| {
+                LogEntry::Message(Message::new(
+                    RecordOffset::new(offset),
+                    MagicByte::new(0),
+                    MessageAttributes::new(0),
+                    None,
+                    None,
+                    Some(b"value".to_vec()),
+                ))
+            })
+            .collect();
+        let partition = PartitionIndex::new(0);
+        let responses = vec![FetchResponse::new(
+            topic_name.clone(),
+            TopicId::new(uuid::Uuid::nil()),
+            vec![FetchPartitionResponse::new(
+                partition,
+                records,
+                LogStartOffset::new(0),
+            )],
+        )];
+        Ok(Response::new(
+            request.header.correlation_id(),
+            request.header.api_version(),
+            ResponseBody::Fetch {
+                throttle_time: ThrottleTime::zero(),
+                error_code: ErrorCode::NoError,
+                session_id: SessionId::new(0),
+                responses,
+                context: Some(context),
+                fetched: vec![(topic_name, partition)],
+            },
+        ))
+    }
+
+    fn purgatory() -> &'static FetchPurgatory {
+        let purgatory: &'static FetchPurgatory =
+            Box::leak(Box::new(FetchPurgatory {
+                refetch,
+                log_end: |topic_name, _| {
+                    Some(RecordOffset::new(available(topic_name)))
+                },
+                ..FetchPurgatory::default()
+            }));
+        spawn(|| purgatory.run());
+        purgatory
+    }
+
+    // Fetch v12 of partition 0 of `topic` from offset 0, for 1 byte
+    fn fetch(topic: &str, max_wait: u32) -> Request {
+        let mut body = vec![];
+        body.put_i32(-1);
+        body.put_u32(max_wait);
+        body.put_u32(1);
+        body.put_u32(1 << 20);
+        body.put_u8(0);
+        body.put_u32(0);
+        body.put_i32(-1);
+        body.put_u8(2);
+        body.put_u8(topic.len() as u8 + 1);
+        body.extend(topic.as_bytes());
+        body.put_u8(2);
+        body.put_u32(0);
+        body.put_i32(-1);
+        body.put_u64(0);
+        body.put_i32(-1);
+        body.put_i64(-1);
+        body.put_u32(1 << 20);
+        body.extend([0, 0, 1, 1, 0]);
+        let mut frame = vec![];
+        frame.put_u16(1);
+        frame.put_u16(12);
+        frame.put_u32(7);
+        frame.put_u16(1);
+        frame.extend(b"t\0");
+        frame.extend(body);
+        let mut bytes = (frame.len() as u32).to_be_bytes().to_vec();
+        bytes.extend(frame);
+        Request::read(&mut Cursor::new(bytes)).unwrap()
+    }
+
+    // parks a fetch of `topic`, which has no records yet
+    fn park(
+        purgatory: &FetchPurgatory,
+        topic: &str,
+        max_wait: u32,
+    ) -> Receiver<Vec<u8>> {
+        let request = fetch(topic, max_wait);
+        let RequestBody::Fetch {
+            topics,
+            ..
+        } = &request.body
+        else {
+            panic!("not a Fetch");
+        };
+        let context = fetch_sessions()
+            .context(
+                SessionId::new(0),
+                SessionEpoch::FINAL,
+                topics,
+                &[],
+                Instant::now(),
+            )
+            .unwrap();
+        let response = refetch(&request, context).unwrap();
+        let (reply, receiver) = channel();
+        let started = Instant::now();
+        assert!(purgatory
+            .try_complete(request, response, &reply, started)
+            .is_none());
+        receiver
+    }
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_complete_once_min_bytes_arrive() {
+        let purgatory = purgatory();
+        let receiver = park(purgatory, "early", 60_000);
+        produce("early");
+        purgatory.wake(&TopicName::from("early"), PartitionIndex::new(0));
+        assert!(receiver.recv_timeout(WAIT).is_ok());
+        assert_eq!(refetches("early"), 2);
+    }
+
+    #[test]
+    fn test_empty_response_at_max_wait() {
+        let purgatory = purgatory();
+        let parked = Instant::now();
+        let receiver = park(purgatory, "late", 100);
+        assert!(receiver.recv_timeout(WAIT).is_ok());
+        assert!(parked.elapsed() >= Duration::from_millis(100));
+        assert_eq!(available("late"), 0);
+    }
+
+    #[test]
+    fn test_expire_all() {
+        let purgatory = purgatory();
+        let receiver = park(purgatory, "expire", 60_000);
+        purgatory.expire_all();
+        assert!(receiver.recv_timeout(WAIT).is_ok());
+    }
+
+    #[test]
+    fn test_wake_rechecks_affected_partitions() {
+        let purgatory = purgatory();
+        let woken = park(purgatory, "woken", 60_000);
+        let other = park(purgatory, "other", 60_000);
+        // a wake without new records does not fetch again
+        purgatory.wake(&TopicName::from("woken"), PartitionIndex::new(0));
+        assert!(woken.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(refetches("woken"), 1);
+
+        produce("woken");
+        produce("other");
+        purgatory.wake(&TopicName::from("woken"), PartitionIndex::new(0));
+        assert!(woken.recv_timeout(WAIT).is_ok());
+        assert!(other.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(refetches("other"), 1);
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::{
+    ClientQuotaEntity, Meta, MetaCache, Principal, RequestHeader, CLIENT_ID,
+    USER,
+};
+
+/// Kafka's `quota.window.num` and `quota.window.size.seconds` defaults.
+const SAMPLES: u32 = 11;
+const SAMPLE_WINDOW: Duration = Duration::from_secs(1);
+
+pub type ClientQuotas = BTreeMap<ClientQuotaEntity, BTreeMap<String, f64>>;
+
+/// Usage per second in a sliding window of samples.
+#[derive(Debug, Default)]
+struct Rate {
+    samples: VecDeque<(Instant, f64)>,
+}
+
+impl Rate {
+    fn record(&mut self, now: Instant, value: f64) {
+        self.samples.retain(|(start, _)| {
+            now.duration_since(*start) < SAMPLE_WINDOW * SAMPLES
+        });
+        match self.samples.back_mut() {
+            Some((start, total))
+                if now.duration_since(*start) < SAMPLE_WINDOW =>
+                *total += value,
+            _ => self.samples.push_back((now, value)),
+        }
+    }
+    /// The rate and the window it is measured over. Until the samples
+    /// cover it the window counts as all but the current sample, so a
+    /// first burst is not measured over a fraction of a second.
+    fn measure(&self, now: Instant) -> (f64, Duration) {
+        let total: f64 = self.samples.iter().map(|(_, v)| v).sum();
+        let window = self
+            .samples
+            .front()
+            .map_or(Duration::ZERO, |(start, _)| now.duration_since(*start))
+            .max(SAMPLE_WINDOW * (SAMPLES - 1));
+        (total / window.as_secs_f64(), window)
+    }
+}
+
+/// The quota of `key` that applies to a user and client id, most specific
+/// first as in Kafka: user and client id, user, default user, client id.
+pub fn resolve_quota(
+    quotas: &ClientQuotas,
+    user: &str,
+    client_id: &str,
+    key: &str,
+) -> Option<(ClientQuotaEntity, f64)> {
+    let entity = |entries: &[(&str, Option<&str>)]| {
+        ClientQuotaEntity::new(
+            entries
+                .iter()
+                .map(|(t, name)| (t.to_string(), name.map(str::to_string)))
+                .collect(),
+        )
+    };
+    let (user, client_id) = (Some(user), Some(client_id));
+    [
+        entity(&[(USER, user), (CLIENT_ID, client_id)]),
+        entity(&[(USER, user), (CLIENT_ID, None)]),
+        entity(&[(USER, user)]),
+        entity(&[(USER, None), (CLIENT_ID, client_id)]),
+        entity(&[(USER, None), (CLIENT_ID, None)]),
+        entity(&[(USER, None)]),
+        entity(&[(CLIENT_ID, client_id)]),
+        entity(&[(CLIENT_ID, None)]),
+    ]
+    .into_iter()
+    .find_map(|e| {
+        let quota = quotas.get(&e).and_then(|values| values.get(key)).copied();
+        quota.map(|quota| (e, quota))
+    })
+}
+
+/// A quota key and the entity types metered with the actual user or
+/// client id.
+type Sensor = (String, Vec<(String, String)>);
+
+/// Client usage against the quotas, shared by every connection.
+#[derive(Debug, Default)]
+pub struct QuotaManager {
+    rates: Mutex<HashMap<Sensor, Rate>>,
+}
+
+static QUOTA_MANAGER: OnceLock<QuotaManager> = OnceLock::new();
+
+static QUOTAS: MetaCache<ClientQuotas> = MetaCache::new(Meta::client_quotas);
+
+pub fn quota_manager() -> &'static QuotaManager {
+    QUOTA_MANAGER.get_or_init(QuotaManager::default)
+}
+
+impl QuotaManager {
+    /// Quotas of the metadata log, none if it cannot be read. They are
+    /// read again once the log has grown since.
+    pub fn quotas(path: &str) -> Arc<ClientQuotas> {
+        QUOTAS.get(path)
+    }
+    /// After AlterClientQuotas, the next lookup reads the quotas.
+    pub fn invalidate() {
+        QUOTAS.invalidate();
+    }
+    /// Records `value` of the quota `key` and returns how long the client
+    /// is throttled, `(O - T) / T * W` for an observed rate O over window W
+    /// against quota T. Clients sharing a quota entity share its usage,
+    /// with a default entity each user or client id is metered on its own.
+    pub fn record(
+        &self,
+        quotas: &ClientQuotas,
+        header: &RequestHeader,
+        key: &str,
+        value: f64,
+        now: Instant,
+    ) -> Duration {
+        let anonymous = Principal::anonymous();
+        let principal = header.principal().unwrap_or(&anonymous);
+        let user = principal.strip_prefix("User:").unwrap_or(principal);
+        let client_id = header.client_id().value();
+        let Some((entity, quota)) =
+            resolve_quota(quotas, user, &client_id, key)
+        else {
+            return Duration::ZERO;
+        };
+        let metered = entity
+            .entries()
+            .iter()
+            .map(|(t, _)| match t.as_str() {
+                USER => (t.clone(), user.to_string()),
+                _ => (t.clone(), client_id.to_string()),
+            })
+            .collect();
+        let mut rates = self.rates.lock().unwrap();
+        let rate = rates.entry((key.to_string(), metered)).or_default();
+        rate.record(now, value);
+        let (observed, window) = rate.measure(now);
+        match observed > quota {
+            true => window
+                .mul_f64((observed - quota) / quota)
+                .min(SAMPLE_WINDOW * SAMPLES),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CONSUMER_BYTE_RATE, PRODUCER_BYTE_RATE};
+
+    #[test]
+    fn test_resolve_quota() {
+        let entity = |entries: &[(&str, Option<&str>)]| {
+            ClientQuotaEntity::new(
+                entries
+                    .iter()
+                    .map(|(t, n)| (t.to_string(), n.map(str::to_string)))
+                    .collect(),
+            )
+        };
+        let quotas: ClientQuotas = [
+            (entity(&[(USER, None)]), 100.0),
+            (entity(&[(USER, Some("alice"))]), 200.0),
+            (entity(&[(CLIENT_ID, Some("app"))]), 300.0),
+        ]
+        .into_iter()
+        .map(|(e, v)| (e, BTreeMap::from([(PRODUCER_BYTE_RATE.into(), v)])))
+        .collect();
+        let quota = |user, client_id| {
+            resolve_quota(&quotas, user, client_id, PRODUCER_BYTE_RATE)
+                .map(|(_, v)| v)
+        };
+        assert_eq!(quota("alice", "app"), Some(200.0));
+        assert_eq!(quota("bob", "app"), Some(100.0));
+        assert_eq!(
+            resolve_quota(&quotas, "bob", "app", CONSUMER_BYTE_RATE),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rate() {
+        let start = Instant::now();
+        let mut rate = Rate::default();
+        rate.record(start, 1000.0);
+        // A first burst is measured over ten seconds
+        assert_eq!(rate.measure(start), (100.0, Duration::from_secs(10)));
+        rate.record(start + Duration::from_secs(12), 500.0);
+        let (observed, _) = rate.measure(start + Duration::from_secs(12));
+        assert_eq!(observed, 50.0);
+    }
+}
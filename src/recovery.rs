@@ -0,0 +1,192 @@
+use crate::{
+    read, read_dir_names, Cleaner, Context, Error, LogEntry, Result, Segment,
+};
+use std::fs::{remove_file, write, File, OpenOptions};
+use std::io::ErrorKind;
+use std::path::Path;
+use tracing::warn;
+
+const CLEAN_SHUTDOWN: &str = ".kafka_cleanshutdown";
+
+/// Startup pass over every segment in `log_dir`. After an unclean shutdown
+/// each segment is cut back to its last valid entry, by length and CRC, and
+/// its indexes are rebuilt. A clean shutdown marker skips all of it.
+pub fn recover(log_dir: &str) -> Result<()> {
+    remove_cleaned(log_dir)?;
+    let marker = Path::new(log_dir).join(CLEAN_SHUTDOWN);
+    if marker.exists() {
+        return remove_file(marker).context("Removing clean shutdown marker");
+    }
+    segments(log_dir)?.iter().try_for_each(recover_segment)
+}
+
+/// Records that the logs were closed cleanly, for the next `recover`.
+pub fn mark_clean_shutdown(log_dir: &str) -> Result<()> {
+    write(Path::new(log_dir).join(CLEAN_SHUTDOWN), [])
+        .context("Writing clean shutdown marker")
+}
+
+/// Flushes every partition's segments and indexes, and the directories
+/// naming them, to disk.
+pub fn sync_logs(log_dir: &str) -> Result<()> {
+    for partition in read_dir_names(log_dir)? {
+        let dir = Path::new(log_dir).join(partition);
+        if !dir.is_dir() {
+            continue;
+        }
+        for file in read_dir_names(&dir.to_string_lossy())? {
+            sync(&dir.join(file)).context("Syncing log file")?;
+        }
+        sync(&dir).context("Syncing log directory")?;
+    }
+    sync(Path::new(log_dir)).context("Syncing log directory")
+}
+
+/// Stops the cleaner, so no segment is swapped or deleted under the sync,
+/// then flushes the logs. Only once every connection has drained are they
+/// marked clean, a write may still be under way otherwise.
+pub fn close_logs(
+    log_dir: &str,
+    cleaner: Cleaner,
+    drained: bool,
+) -> Result<()> {
+    cleaner.stop();
+    sync_logs(log_dir)?;
+    match drained {
+        true => mark_clean_shutdown(log_dir),
+        false => Ok(()),
+    }
+}
+
+fn sync(path: &Path) -> std::io::Result<()> {
+    match File::open(path).and_then(|f| f.sync_all()) {
+        // deleted since it was listed, by DeleteRecords
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Compacted segments a crash left before they were swapped in, the
+/// segments they were to replace are still whole.
+fn remove_cleaned(log_dir: &str) -> Result<()> {
+    for partition in read_dir_names(log_dir)? {
+        let dir = Path::new(log_dir).join(partition);
+        if !dir.is_dir() {
+            continue;
+        }
+        for file in read_dir_names(&dir.to_string_lossy())? {
+            if file.ends_with(".log.cleaned") {
+                remove_file(dir.join(file))
+                    .context("Removing cleaned segment")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn segments(log_dir: &str) -> Result<Vec<Segment>> {
+    let mut result = vec![];
+    for partition in read_dir_names(log_dir)? {
+        result.extend(Segment::list(&Path::new(log_dir).join(partition))?);
+    }
+    Ok(result)
+}
+
+fn recover_segment(segment: &Segment) -> Result<()> {
+    let path = segment.path();
+    let mut bytes = read(&path)?;
+    let entries = match LogEntry::split(bytes.clone()) {
+        Ok(entries) => entries,
+        Err(Error::CorruptRecord(position, reason)) => {
+            warn!("truncating {path} at {position}: {reason}");
+            OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .and_then(|f| f.set_len(position))
+                .context("Truncating segment")?;
+            bytes.truncate(position as usize);
+            LogEntry::split(bytes)?
+        }
+        Err(e) => return Err(e),
+    };
+    segment.write_index(&entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MagicByte, Message, MessageAttributes, RecordOffset};
+    use std::fs::{create_dir_all, metadata};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_truncate_torn_write() -> Result<()> {
+        let dir = std::env::temp_dir()
+            .join(format!("recovery-{}", std::process::id()));
+        let partition = dir.join("foo-0");
+        create_dir_all(&partition).context("test dir")?;
+        let message = |offset| {
+            Vec::<u8>::from(Message::new(
+                RecordOffset::new(offset),
+                MagicByte::new(0),
+                MessageAttributes::new(0),
+                None,
+                None,
+                Some(b"value".to_vec()),
+            ))
+        };
+        let valid = [message(0), message(1)].concat();
+        let torn = [valid.clone(), message(2)[..10].to_vec()].concat();
+        let segment = partition.join("00000000000000000000.log");
+        write(&segment, torn).context("test segment")?;
+
+        // a compaction cut off before its swap
+        let cleaned = partition.join("00000000000000000000.log.cleaned");
+        write(&cleaned, &valid[..5]).context("test cleaned segment")?;
+
+        let log_dir = dir.to_string_lossy().to_string();
+        recover(&log_dir)?;
+        assert_eq!(read(&segment.to_string_lossy())?, valid);
+        assert!(!cleaned.exists());
+        assert!(partition.join("00000000000000000000.index").exists());
+
+        // after a clean shutdown nothing is touched
+        write(&segment, [valid.clone(), vec![0; 3]].concat()).context("")?;
+        mark_clean_shutdown(&log_dir)?;
+        recover(&log_dir)?;
+        assert_eq!(
+            metadata(&segment).context("")?.len() as usize,
+            valid.len() + 3
+        );
+        assert!(!dir.join(CLEAN_SHUTDOWN).exists());
+
+        std::fs::remove_dir_all(dir).context("cleanup")
+    }
+
+    #[test]
+    fn test_close_logs() -> Result<()> {
+        let dir = std::env::temp_dir()
+            .join(format!("close-logs-{}", std::process::id()));
+        create_dir_all(dir.join("foo-0")).context("test dir")?;
+        write(dir.join("foo-0").join("00000000000000000000.log"), [])
+            .context("test segment")?;
+        let log_dir: &'static str = dir.to_string_lossy().to_string().leak();
+        let cleaner = || {
+            crate::spawn_cleaner(log_dir, "missing", Duration::from_secs(3600))
+        };
+
+        // the cleaner is stopped without waiting out its interval, and
+        // the logs are not marked clean before the connections drained
+        let started = Instant::now();
+        close_logs(log_dir, cleaner(), false)?;
+        assert!(started.elapsed() < Duration::from_secs(60));
+        assert!(!dir.join(CLEAN_SHUTDOWN).exists());
+        close_logs(log_dir, cleaner(), true)?;
+        assert!(dir.join(CLEAN_SHUTDOWN).exists());
+
+        // a file gone since it was listed does not fail the sync
+        assert!(sync(&dir.join("foo-0").join("gone.log")).is_ok());
+
+        std::fs::remove_dir_all(dir).context("cleanup")
+    }
+}
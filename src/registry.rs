@@ -0,0 +1,484 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    array_length, with_message_size, AclResourceType, Api, ApiKey, Authorizer,
+    BytesOps, ClientSoftware, CorrelationId, Error, ErrorCode, Meta, Request,
+    RequestBody, Response, ResponseBody, Result, SaslState, TagBuffer,
+    ThrottleTime, ToCompactString, TopicName, VarInt, Version,
+};
+use bytes::BufMut;
+
+/// What a handler has besides its request.
+pub struct HandlerContext<'a> {
+    pub authorizer: Authorizer,
+    pub sasl: &'a mut SaslState,
+    pub client_software: &'a mut Option<ClientSoftware>,
+    // the logs the handlers read and write, LOG_DIR and METADATA_LOG but
+    // in tests
+    pub log_dir: &'a str,
+    pub metadata_log: &'a str,
+}
+
+impl HandlerContext<'_> {
+    pub fn allowed<'a>(
+        &'a self,
+        request: &'a Request,
+    ) -> impl Fn(u8, u8, &str) -> bool + 'a {
+        |operation, resource_type, resource_name| {
+            self.authorizer.authorize(
+                &request.header,
+                operation,
+                resource_type,
+                resource_name,
+            )
+        }
+    }
+    pub fn topic_allowed<'a>(
+        &'a self,
+        request: &'a Request,
+    ) -> impl Fn(u8, &TopicName) -> bool + 'a {
+        let allowed = self.allowed(request);
+        move |operation, topic_name| {
+            allowed(operation, AclResourceType::TOPIC, topic_name)
+        }
+    }
+}
+
+pub type Parse = fn(&[u8], Version) -> Result<RequestBody>;
+pub type Handle = fn(&Request, &mut HandlerContext) -> Result<ResponseBody>;
+/// Answers a request that failed, or whose body did not parse, with the
+/// error in every place the response has one.
+pub type ErrorBody = fn(Option<&RequestBody>, &Error) -> ResponseBody;
+/// Encodes a response body of the API, with its header and size.
+pub type Encode = fn(ResponseBody, Version, CorrelationId) -> Vec<u8>;
+
+/// An API the broker serves, the versions it supports, how its requests
+/// are parsed and handled and how its responses are encoded.
+pub struct ApiHandler {
+    pub api_key: ApiKey,
+    pub min: Version,
+    pub max: Version,
+    pub parse: Parse,
+    pub handle: Handle,
+    pub error: ErrorBody,
+    pub encode: Encode,
+}
+
+/// Every API the broker serves, ApiVersions advertises them in this order.
+pub const API_HANDLERS: &[ApiHandler] = &[
+    ApiHandler {
+        api_key: ApiKey::Produce,
+        min: Version::V3,
+        max: Version::V11,
+        parse: RequestBody::produce,
+        handle: Response::handle_produce,
+        error: Response::error_produce,
+        encode: Response::encode_produce,
+    },
+    ApiHandler {
+        api_key: ApiKey::ListOffsets,
+        min: Version::V1,
+        max: Version::V7,
+        parse: RequestBody::list_offsets,
+        handle: Response::handle_list_offsets,
+        error: Response::error_list_offsets,
+        encode: Response::encode_list_offsets,
+    },
+    ApiHandler {
+        api_key: ApiKey::DeleteRecords,
+        min: Version::V0,
+        max: Version::V2,
+        parse: RequestBody::delete_records,
+        handle: Response::handle_delete_records,
+        error: Response::error_delete_records,
+        encode: Response::encode_delete_records,
+    },
+    ApiHandler {
+        api_key: ApiKey::DescribeConfigs,
+        min: Version::V0,
+        max: Version::V4,
+        parse: RequestBody::describe_configs,
+        handle: Response::handle_describe_configs,
+        error: Response::error_describe_configs,
+        encode: Response::encode_describe_configs,
+    },
+    ApiHandler {
+        api_key: ApiKey::IncrementalAlterConfigs,
+        min: Version::V0,
+        max: Version::V1,
+        parse: RequestBody::incremental_alter_configs,
+        handle: Response::handle_incremental_alter_configs,
+        error: Response::error_incremental_alter_configs,
+        encode: Response::encode_incremental_alter_configs,
+    },
+    ApiHandler {
+        api_key: ApiKey::CreatePartitions,
+        min: Version::V0,
+        max: Version::V3,
+        parse: RequestBody::create_partitions,
+        handle: Response::handle_create_partitions,
+        error: Response::error_create_partitions,
+        encode: Response::encode_create_partitions,
+    },
+    ApiHandler {
+        api_key: ApiKey::SaslHandshake,
+        min: Version::V1,
+        max: Version::V1,
+        parse: RequestBody::sasl_handshake,
+        handle: Response::handle_sasl_handshake,
+        error: Response::error_sasl_handshake,
+        encode: Response::encode_sasl_handshake,
+    },
+    ApiHandler {
+        api_key: ApiKey::SaslAuthenticate,
+        min: Version::V0,
+        max: Version::V2,
+        parse: RequestBody::sasl_authenticate,
+        handle: Response::handle_sasl_authenticate,
+        error: Response::error_sasl_authenticate,
+        encode: Response::encode_sasl_authenticate,
+    },
+    ApiHandler {
+        api_key: ApiKey::DescribeAcls,
+        min: Version::V0,
+        max: Version::V3,
+        parse: RequestBody::describe_acls,
+        handle: Response::handle_describe_acls,
+        error: Response::error_describe_acls,
+        encode: Response::encode_describe_acls,
+    },
+    ApiHandler {
+        api_key: ApiKey::CreateAcls,
+        min: Version::V0,
+        max: Version::V3,
+        parse: RequestBody::create_acls,
+        handle: Response::handle_create_acls,
+        error: Response::error_create_acls,
+        encode: Response::encode_create_acls,
+    },
+    ApiHandler {
+        api_key: ApiKey::DeleteAcls,
+        min: Version::V0,
+        max: Version::V3,
+        parse: RequestBody::delete_acls,
+        handle: Response::handle_delete_acls,
+        error: Response::error_delete_acls,
+        encode: Response::encode_delete_acls,
+    },
+    ApiHandler {
+        api_key: ApiKey::DescribeClientQuotas,
+        min: Version::V0,
+        max: Version::V1,
+        parse: RequestBody::describe_client_quotas,
+        handle: Response::handle_describe_client_quotas,
+        error: Response::error_describe_client_quotas,
+        encode: Response::encode_describe_client_quotas,
+    },
+    ApiHandler {
+        api_key: ApiKey::AlterClientQuotas,
+        min: Version::V0,
+        max: Version::V1,
+        parse: RequestBody::alter_client_quotas,
+        handle: Response::handle_alter_client_quotas,
+        error: Response::error_alter_client_quotas,
+        encode: Response::encode_alter_client_quotas,
+    },
+    ApiHandler {
+        api_key: ApiKey::ApiVersions,
+        min: Version::V0,
+        max: Version::V4,
+        parse: RequestBody::api_versions,
+        handle: Response::handle_api_versions,
+        error: Response::error_api_versions,
+        encode: Response::encode_api_versions,
+    },
+    ApiHandler {
+        api_key: ApiKey::DescribeTopicPartitions,
+        min: Version::V0,
+        max: Version::V0,
+        parse: RequestBody::describe_topic_partitions,
+        handle: Response::handle_describe_topic_partitions,
+        error: Response::error_describe_topic_partitions,
+        encode: Response::encode_describe_topic_partitions,
+    },
+    ApiHandler {
+        api_key: ApiKey::Fetch,
+        min: Version::V0,
+        max: Version::V16,
+        parse: RequestBody::fetch,
+        handle: Response::handle_fetch,
+        error: Response::error_fetch,
+        encode: Response::encode_fetch,
+    },
+];
+
+pub fn api_handler(api_key: ApiKey) -> Result<&'static ApiHandler> {
+    API_HANDLERS
+        .iter()
+        .find(|h| *h.api_key == *api_key)
+        .ok_or(Error::UnsupportedApiKey(*api_key, None))
+}
+
+/// The features the broker supports with their minimum and maximum
+/// levels, `metadata.version` 20 being 3.8-IV0.
+pub const SUPPORTED_FEATURES: &[(&str, i16, i16)] =
+    &[("metadata.version", 1, 20)];
+
+/// The ApiVersions entries of the registry.
+pub fn api_versions() -> Vec<Api> {
+    API_HANDLERS
+        .iter()
+        .map(|h| Api::new(h.api_key, h.min, h.max, TagBuffer::new(0)))
+        .collect()
+}
+
+/// The tagged fields of ApiVersions v3+: SupportedFeatures (0),
+/// FinalizedFeaturesEpoch (1) and FinalizedFeatures (2), each only when
+/// set.
+fn feature_fields(
+    finalized_features: BTreeMap<String, i16>,
+    finalized_features_epoch: i64,
+) -> Vec<u8> {
+    let mut fields: Vec<(u64, Vec<u8>)> = vec![];
+    let mut supported = array_length(true, SUPPORTED_FEATURES.len());
+    SUPPORTED_FEATURES.iter().for_each(|(name, min, max)| {
+        supported.extend(name.to_string().to_compact_string());
+        supported.put_i16(*min);
+        supported.put_i16(*max);
+        supported.put_u8(*TagBuffer::zero());
+    });
+    fields.push((0, supported));
+    if finalized_features_epoch >= 0 {
+        fields.push((1, finalized_features_epoch.to_be_bytes().to_vec()));
+    }
+    if !finalized_features.is_empty() {
+        let mut finalized = array_length(true, finalized_features.len());
+        finalized_features.iter().for_each(|(name, level)| {
+            finalized.extend(name.to_compact_string());
+            // max_version_level, then min_version_level
+            finalized.put_i16(*level);
+            finalized.put_i16(*level);
+            finalized.put_u8(*TagBuffer::zero());
+        });
+        fields.push((2, finalized));
+    }
+    let mut bytes = VarInt::encode(fields.len() as u64);
+    fields.into_iter().for_each(|(tag, data)| {
+        bytes.extend(VarInt::encode(tag));
+        bytes.extend(VarInt::encode(data.len() as u64));
+        bytes.extend(data);
+    });
+    bytes
+}
+
+impl RequestBody {
+    /// Clients name their software from v3.
+    pub(crate) fn api_versions(body: &[u8], version: Version) -> Result<Self> {
+        let client_software = match version >= Version::V3 {
+            true => {
+                let (name, rest) = body.extract_compact_str()?;
+                let (version, _rest) = rest.extract_compact_str()?;
+                Some(ClientSoftware {
+                    name,
+                    version,
+                })
+            }
+            false => None,
+        };
+        Ok(RequestBody::ApiVersions {
+            client_software,
+        })
+    }
+}
+
+impl Response {
+    /// A valid client software name and version is kept for the
+    /// connection.
+    pub(crate) fn handle_api_versions(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let RequestBody::ApiVersions {
+            client_software,
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        let error_code = match client_software {
+            Some(v) if !v.is_valid() => ErrorCode::InvalidRequest,
+            Some(v) => {
+                *cx.client_software = Some(v.clone());
+                ErrorCode::NoError
+            }
+            None => ErrorCode::NoError,
+        };
+        let (finalized_features, finalized_features_epoch) =
+            Meta::load(cx.metadata_log)
+                .map(|m| m.finalized_features())
+                .unwrap_or((BTreeMap::new(), -1));
+        Ok(ResponseBody::ApiVersions {
+            error_code,
+            api_versions: api_versions(),
+            throttle_time: ThrottleTime::zero(),
+            finalized_features,
+            finalized_features_epoch,
+        })
+    }
+
+    pub(crate) fn error_api_versions(
+        _body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        ResponseBody::ApiVersions {
+            error_code: error.error_code(),
+            api_versions: api_versions(),
+            throttle_time: ThrottleTime::zero(),
+            finalized_features: BTreeMap::new(),
+            finalized_features_epoch: -1,
+        }
+    }
+
+    pub(crate) fn encode_api_versions(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::ApiVersions {
+            error_code,
+            api_versions,
+            throttle_time,
+            finalized_features,
+            finalized_features_epoch,
+        } = body
+        else {
+            unreachable!("not a ApiVersions");
+        };
+        let flexible = ApiKey::ApiVersions.is_flexible(version);
+        let mut bytes: Vec<u8> = Vec::new();
+        // the response header stays v0, for clients to read whatever
+        // version they sent
+        bytes.put_u32(*correlation_id);
+        bytes.put_i16(*error_code);
+        bytes.extend(array_length(flexible, api_versions.len()));
+        api_versions.into_iter().for_each(|v| {
+            bytes.put_u16(*v.api_key());
+            bytes.put_u16(*v.min());
+            bytes.put_u16(*v.max());
+            if flexible {
+                bytes.put_u8(*v.tagged_fields());
+            }
+        });
+        if version >= Version::V1 {
+            bytes.put_u32(*throttle_time);
+        }
+        if flexible {
+            bytes.extend(feature_fields(
+                finalized_features,
+                finalized_features_epoch,
+            ));
+        }
+        with_message_size(&bytes)
+    }
+}
+
+/// A log directory for handler tests, with topic "baz" in its metadata
+/// log, removed when dropped.
+#[cfg(test)]
+pub(crate) struct TestLogs {
+    pub log_dir: String,
+    pub metadata_log: String,
+}
+
+#[cfg(test)]
+impl TestLogs {
+    pub fn new(name: &str) -> Self {
+        let log_dir = std::env::temp_dir()
+            .join(format!("{name}-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let metadata_log =
+            format!("{log_dir}/__cluster_metadata-0/00000000000000000000.log");
+        let _ = std::fs::remove_dir_all(&log_dir);
+        std::fs::create_dir_all(format!("{log_dir}/__cluster_metadata-0"))
+            .unwrap();
+        let baz = hex::decode(crate::BAZ_METADATA_LOG.replace(" ", ""));
+        std::fs::write(&metadata_log, baz.unwrap()).unwrap();
+        Self {
+            log_dir,
+            metadata_log,
+        }
+    }
+    /// Reads a request frame, its header and body in hex, answers it with
+    /// the handler registered for its API and encodes the response.
+    pub fn handle(
+        &self,
+        frame: &str,
+        authorizer: Authorizer,
+    ) -> Result<(ResponseBody, Vec<u8>)> {
+        let frame = hex::decode(frame.replace(" ", "")).unwrap();
+        let mut bytes = (frame.len() as u32).to_be_bytes().to_vec();
+        bytes.extend(frame);
+        let request = Request::read(&mut &bytes[..])?;
+        let handler = api_handler(request.header.api_key())?;
+        let mut cx = HandlerContext {
+            authorizer,
+            sasl: &mut SaslState::default(),
+            client_software: &mut None,
+            log_dir: &self.log_dir,
+            metadata_log: &self.metadata_log,
+        };
+        let body = (handler.handle)(&request, &mut cx)?;
+        let response = Response::new(
+            request.header.correlation_id(),
+            request.header.api_version(),
+            body.clone(),
+        );
+        Ok((body, response.into()))
+    }
+    /// Writes a message for each of `timestamps` to partition 0 of "baz",
+    /// from offset 0.
+    pub fn messages(&self, timestamps: &[u64]) {
+        let dir = crate::Log::dir(
+            &self.log_dir,
+            &TopicName::from("baz"),
+            crate::PartitionIndex::new(0),
+        );
+        std::fs::create_dir_all(&dir).unwrap();
+        let messages = timestamps.iter().enumerate().flat_map(|(i, t)| {
+            Vec::<u8>::from(crate::Message::new(
+                crate::RecordOffset::new(i as u64),
+                crate::MagicByte::new(1),
+                crate::MessageAttributes::new(0),
+                Some(crate::RecordTimestamp::new(*t)),
+                None,
+                Some(b"value".to_vec()),
+            ))
+        });
+        let segment = dir.join("00000000000000000000.log");
+        std::fs::write(segment, messages.collect::<Vec<_>>()).unwrap();
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestLogs {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.log_dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_handler() {
+        let fetch = api_handler(ApiKey::Fetch).unwrap();
+        assert_eq!((fetch.min, fetch.max), (Version::V0, Version::V16));
+        // a request outside the registered versions is not parsed
+        assert!(matches!(
+            RequestBody::mk(ApiKey::SaslHandshake, Version::V0, &[]),
+            Err(Error::UnsupportedApiVersion(0, None))
+        ));
+        assert_eq!(api_versions().len(), API_HANDLERS.len());
+    }
+}
@@ -1,23 +1,57 @@
 use std::convert::{TryFrom, TryInto};
 use std::io::Read;
 use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Instant;
 
-use pretty_hex::{simple_hex, PrettyHex};
+use pretty_hex::simple_hex;
+use tracing::trace;
 
 use crate::error::Error;
 use crate::{
-    ApiKey, BytesOps, ClientId, Context, CorrelationId, Cursor, FetchTopic,
-    ForgottenTopicData, IsolationLevel, MapTupleTwo, MaxBytes, MaxWait,
-    MessageSize, MinBytes, RackId, Result, SessionEpoch, SessionId, TopicName,
-    Version,
+    api_handler, memory_pool, request_max_bytes, Acks, AclBinding,
+    AclBindingFilter, AlterConfigsResource, ApiKey, BytesOps, ClientId,
+    ClientQuotaAlteration, ClientQuotaFilter, Context, CorrelationId,
+    CreatePartitionsTopic, Cursor, DeleteRecordsTopic, DescribeConfigsResource,
+    FetchTopic, ForgottenTopicData, IsolationLevel, ListOffsetsTopic,
+    MapTupleTwo, MaxBytes, MaxWait, MessageSize, MinBytes, Principal,
+    ProduceTimeout, ProduceTopic, RackId, Result, SessionEpoch, SessionId,
+    TopicName, Version,
 };
 
+/// The software a client reports in ApiVersions v3+, kept for the
+/// connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientSoftware {
+    pub name: String,
+    pub version: String,
+}
+
+impl ClientSoftware {
+    /// Kafka's pattern for both, `[a-zA-Z0-9](?:[a-zA-Z0-9\-.]*[a-zA-Z0-9])?`.
+    pub fn is_valid(&self) -> bool {
+        fn valid(v: &str) -> bool {
+            let ends =
+                |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+            ends(v.chars().next())
+                && ends(v.chars().last())
+                && v.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        }
+        valid(&self.name) && valid(&self.version)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RequestHeader {
     api_key: ApiKey,
     api_version: Version,
     correlation_id: CorrelationId,
     client_id: ClientId,
+    message_size: MessageSize,
+    principal: Option<Principal>,
+    client_host: Option<String>,
+    received: Instant,
 }
 impl RequestHeader {
     fn new(
@@ -25,12 +59,17 @@ impl RequestHeader {
         api_version: Version,
         correlation_id: CorrelationId,
         client_id: ClientId,
+        message_size: MessageSize,
     ) -> Self {
         Self {
             api_key,
             api_version,
             correlation_id,
             client_id,
+            message_size,
+            principal: None,
+            client_host: None,
+            received: Instant::now(),
         }
     }
     pub fn api_key(&self) -> ApiKey {
@@ -42,10 +81,81 @@ impl RequestHeader {
     pub fn correlation_id(&self) -> CorrelationId {
         self.correlation_id
     }
+    pub fn client_id(&self) -> &ClientId {
+        &self.client_id
+    }
+    /// Size of the request on the wire, without the size field.
+    pub fn message_size(&self) -> MessageSize {
+        self.message_size
+    }
+    /// Set once the connection is authenticated, or anonymous without SASL.
+    pub fn principal(&self) -> Option<&Principal> {
+        self.principal.as_ref()
+    }
+    /// Address the connection comes from, for host ACLs.
+    pub fn client_host(&self) -> Option<&String> {
+        self.client_host.as_ref()
+    }
+    /// When the request was read off the connection, for its latency.
+    pub fn received(&self) -> Instant {
+        self.received
+    }
 }
 #[derive(Debug, Clone)]
 pub enum RequestBody {
-    ApiVersions,
+    Produce {
+        transactional_id: Option<String>,
+        acks: Acks,
+        timeout: ProduceTimeout,
+        topics: Vec<ProduceTopic>,
+    },
+    ListOffsets {
+        isolation_level: IsolationLevel,
+        topics: Vec<ListOffsetsTopic>,
+    },
+    DeleteRecords {
+        topics: Vec<DeleteRecordsTopic>,
+        timeout: u32,
+    },
+    DescribeConfigs {
+        resources: Vec<DescribeConfigsResource>,
+        include_synonyms: bool,
+        include_documentation: bool,
+    },
+    IncrementalAlterConfigs {
+        resources: Vec<AlterConfigsResource>,
+        validate_only: bool,
+    },
+    CreatePartitions {
+        topics: Vec<CreatePartitionsTopic>,
+        timeout: u32,
+        validate_only: bool,
+    },
+    SaslHandshake {
+        mechanism: String,
+    },
+    DescribeAcls {
+        filter: AclBindingFilter,
+    },
+    CreateAcls {
+        creations: Vec<AclBinding>,
+    },
+    DeleteAcls {
+        filters: Vec<AclBindingFilter>,
+    },
+    DescribeClientQuotas {
+        filter: ClientQuotaFilter,
+    },
+    AlterClientQuotas {
+        entries: Vec<ClientQuotaAlteration>,
+        validate_only: bool,
+    },
+    SaslAuthenticate {
+        auth_bytes: Vec<u8>,
+    },
+    ApiVersions {
+        client_software: Option<ClientSoftware>,
+    },
     DescribeTopicPartitions {
         topics: Vec<TopicName>,
         limit: ResponsePartitionLimit,
@@ -64,73 +174,14 @@ pub enum RequestBody {
     },
 }
 impl RequestBody {
-    pub fn mk(api_key: ApiKey, body: &[u8]) -> Result<Self> {
-        match api_key {
-            ApiKey::ApiVersions => Ok(RequestBody::ApiVersions),
-            ApiKey::DescribeTopicPartitions =>
-                Self::describe_topic_partitions(body),
-            ApiKey::Fetch => Self::fetch(body),
-        }
-    }
-    fn describe_topic_partitions(body: &[u8]) -> Result<Self> {
-        fn topic_name(
-            bytes: &[u8],
-            mut result: Vec<TopicName>,
-            len: u8,
-        ) -> Result<Vec<TopicName>> {
-            if result.len() as u8 == len {
-                Ok(result)
-            } else {
-                let length = bytes[0] as usize;
-                let name = String::from_utf8(bytes[1..length].to_vec())
-                    .context("create a topic name")
-                    .map(TopicName::new)?;
-                result.push(name);
-                topic_name(&bytes[length + 1..], result, len)
-            }
+    /// Parses a request with the parser registered for its API, for the
+    /// versions the broker supports.
+    pub fn mk(api_key: ApiKey, version: Version, body: &[u8]) -> Result<Self> {
+        let handler = api_handler(api_key)?;
+        if version < handler.min || version > handler.max {
+            return Err(Error::UnsupportedApiVersion(*version, None));
         }
-
-        let array_length: u8 = body[0] - 1;
-        let topics: Vec<TopicName> =
-            topic_name(&body[1..], vec![], array_length)?;
-        let topics_size =
-            topics.iter().fold(0, |acc, v| acc + v.value().len()) + 1;
-        let limit =
-            ResponsePartitionLimit::mk(&body[topics_size..topics_size + 4])?;
-        let cursor = Cursor::mk(body[topics_size + 4]);
-
-        Ok(RequestBody::DescribeTopicPartitions {
-            topics,
-            limit,
-            cursor,
-        })
-    }
-    fn fetch(body: &[u8]) -> Result<Self> {
-        let (max_wait, rest) = body.extract_u32_into(MaxWait::new)?;
-        let (min_bytes, rest) = rest.extract_u32_into(MinBytes::new)?;
-        let (max_bytes, rest) = rest.extract_u32_into(MaxBytes::new)?;
-        let (isolation_level, rest) =
-            rest.extract_u8_into(IsolationLevel::new)?;
-        let (session_id, rest) = rest.extract_u32_into(SessionId::new)?;
-        let (session_epoch, rest) = rest.extract_u32_into(SessionEpoch::new)?;
-        let (topics, rest) = rest.extract_array_into()?;
-        let (forgotten_topics_data, rest) =
-            rest.drop(1).second().and_then(|v| v.extract_array_into())?;
-
-        let (rack_id, _rest) =
-            rest.extract_compact_str().map_tuple(RackId::new)?;
-
-        Ok(RequestBody::Fetch {
-            max_wait,
-            min_bytes,
-            max_bytes,
-            isolation_level,
-            session_id,
-            session_epoch,
-            topics,
-            forgotten_topics_data,
-            rack_id,
-        })
+        (handler.parse)(body, version)
     }
 }
 #[derive(Debug, Clone)]
@@ -139,6 +190,9 @@ impl ResponsePartitionLimit {
     pub fn new(v: i32) -> Self {
         Self(v)
     }
+    pub fn value(&self) -> i32 {
+        self.0
+    }
     pub fn mk(bytes: &[u8]) -> Result<ResponsePartitionLimit> {
         bytes
             .try_into()
@@ -159,49 +213,84 @@ impl Request {
             body,
         }
     }
+    pub fn with_principal(mut self, principal: Principal) -> Self {
+        self.header.principal = Some(principal);
+        self
+    }
+    pub fn with_client_host(mut self, host: String) -> Self {
+        self.header.client_host = Some(host);
+        self
+    }
 }
 impl TryFrom<&mut TcpStream> for Request {
     type Error = Error;
 
     fn try_from(stream: &mut TcpStream) -> Result<Self> {
+        Request::read(stream)
+    }
+}
+impl Request {
+    /// Reads one request off a plaintext or TLS stream.
+    pub fn read<S: Read>(stream: &mut S) -> Result<Self> {
         let message_size = message_size(stream)?;
-        println!("message size {:?}", message_size);
-        let mut request: Vec<u8> = vec![0; *message_size as usize];
-        stream.read_exact(&mut request).context("not able to read stream")?;
-        println!("{:?}", request);
-        let (correlation_id, _) =
-            request[4..8].as_ref().extract_u32_into(CorrelationId::new)?;
-        println!("correlation_id {:?}", correlation_id);
-        let (api_key, rest) = request
-            .as_slice()
-            .extract_u16()
-            .fmap_tuple(TryFrom::try_from)
-            .map_err(Error::set_correlation_id(correlation_id))?;
-        println!("api_key {:?}", api_key);
-        let (api_version, rest) = rest
-            .extract_u16()
-            .fmap_tuple(TryFrom::try_from)
-            .map_err(Error::set_correlation_id(correlation_id))?;
-        println!("api_version {:?}", api_version);
-        let (client_id_length, rest) =
-            rest.drop(4).second().and_then(|v| v.extract_u16())?; //drop correlation_id
+        let max_bytes = request_max_bytes();
+        if *message_size > max_bytes {
+            return Err(Error::RequestTooLarge(*message_size, max_bytes));
+        }
+        // waits while other connections' requests fill the pool
+        let mut request = memory_pool().allocate(*message_size as usize);
+        stream
+            .read_exact(&mut request)
+            .map_err(|e| Error::ConnectionClosed(e.to_string()))?;
+        let (api_key, rest) = request.extract_u16()?;
+        let (api_version, rest) = rest.extract_u16()?;
+        let (correlation_id, rest) =
+            rest.extract_u32_into(CorrelationId::new)?;
+        // an unknown API has no layout to answer in and closes the
+        // connection, ApiVersions is answered in its v0 layout whatever
+        // the version
+        let api_key = ApiKey::try_from(api_key)?;
+        let api_versions = matches!(api_key, ApiKey::ApiVersions);
+        let api_version =
+            Version::try_from(api_version).map_err(|e| match api_versions {
+                true => e.with_correlation_id(correlation_id),
+                false => e,
+            })?;
+        // a null client id is read as an empty one
         let (client_id, rest) = rest
-            .extract_str(client_id_length as usize)
-            .map_tuple(|s| ClientId::new(s.to_string()))?;
-        println!("client_id {:?}", client_id);
-        let header =
-            RequestHeader::new(api_key, api_version, correlation_id, client_id);
-        println!("header {:?}", header);
-        let body = RequestBody::mk(header.api_key, rest.drop(1).second()?)?;
+            .extract_nullable_string(false)
+            .map_tuple(|s| ClientId::new(s.unwrap_or_default()))?;
+        let header = RequestHeader::new(
+            api_key,
+            api_version,
+            correlation_id,
+            client_id,
+            message_size,
+        );
+        trace!(?header, payload = %simple_hex(&&request[..]), "read request");
+        // the header is known from here on, so a body that does not parse
+        // or a version the API is not served in is answered with an error
+        // response in the API's own layout
+        let body = match api_key.is_flexible(api_version) {
+            true => rest.drop(1).second(),
+            false => Ok(rest),
+        }
+        .and_then(|rest| RequestBody::mk(api_key, api_version, rest))
+        .map_err(|e| match e {
+            e @ Error::UnsupportedApiVersion(..) if api_versions =>
+                e.with_correlation_id(correlation_id),
+            e @ Error::UnsupportedApiKey(..) => e,
+            e => Error::MalformedRequest(Box::new(header.clone()), Arc::new(e)),
+        })?;
         Ok(Request::new(header, body))
     }
 }
 
-fn message_size(stream: &mut TcpStream) -> Result<MessageSize> {
+fn message_size<S: Read>(stream: &mut S) -> Result<MessageSize> {
     let mut result = [0u8; 4];
     stream
         .read_exact(&mut result)
-        .with_context(|| "MessageSize is not valid")?;
+        .map_err(|e| Error::ConnectionClosed(e.to_string()))?;
     MessageSize::try_from_bytes(result)
 }
 
@@ -212,7 +301,7 @@ mod test {
     #[test]
     fn test_fetch() {
         use super::*;
-        let data: String = vec![
+        let data: String = [
             "00 00 01 f4  00 00 00 01  03 20 00 00  00 00 00 00",
             "00 00 00 00  00 02 00 00  00 00 00 00  00 00 00 00",
             "00 00 00 00  43 35 02 00  00 00 00 ff  ff ff ff 00",
@@ -222,8 +311,138 @@ mod test {
         .join("")
         .replace(" ", "");
         let bytes = decode(data).expect("");
-        let req = RequestBody::fetch(&bytes);
+        let req = RequestBody::fetch(&bytes, Version::V16);
 
         println!("req {:?}", req)
     }
+    #[test]
+    fn test_fetch_topic_names() {
+        use super::*;
+        // Fetch v12, flexible with topic names: replica -1, max wait 500,
+        // min bytes 1, max bytes 1024, isolation 0, session 0 epoch -1,
+        // topic "t" partition 0 from offset 5, forgetting "u" partition 1
+        // and rack "r"
+        let data: String = [
+            "ff ff ff ff  00 00 01 f4  00 00 00 01  00 00 04 00",
+            "00 00 00 00  00 ff ff ff  ff 02 02 74  02 00 00 00",
+            "00 ff ff ff  ff 00 00 00  00 00 00 00  05 ff ff ff",
+            "ff ff ff ff  ff ff ff ff  ff 00 00 04  00 00 00 02",
+            "02 75 02 00  00 00 01 00  02 72 00",
+        ]
+        .join("")
+        .replace(" ", "");
+        let bytes = decode(data).expect("");
+        let Ok(RequestBody::Fetch {
+            session_epoch,
+            topics,
+            forgotten_topics_data,
+            rack_id,
+            ..
+        }) = RequestBody::fetch(&bytes, Version::V12)
+        else {
+            panic!("not a fetch");
+        };
+        assert_eq!(session_epoch, SessionEpoch::FINAL);
+        assert_eq!(topics[0].topic_name(), Some(&TopicName::from("t")));
+        assert_eq!(*topics[0].partitions()[0].fetch_offset(), 5);
+        assert_eq!(
+            forgotten_topics_data[0].topic_name(),
+            Some(&TopicName::from("u"))
+        );
+        assert_eq!(*rack_id, "r");
+    }
+    #[test]
+    fn test_api_versions_client_software() {
+        use super::*;
+        // ApiVersions v4 from "kafka-java" "3.8.0"
+        let bytes = decode(
+            "0b6b61666b612d6a6176610633 2e 38 2e 30 00".replace(" ", ""),
+        )
+        .expect("");
+        let Ok(RequestBody::ApiVersions {
+            client_software: Some(client_software),
+        }) = RequestBody::api_versions(&bytes, Version::V4)
+        else {
+            panic!("not an ApiVersions");
+        };
+        assert_eq!(client_software.name, "kafka-java");
+        assert!(client_software.is_valid());
+        let invalid = ClientSoftware {
+            version: "3.8.0-".to_string(),
+            ..client_software
+        };
+        assert!(!invalid.is_valid());
+    }
+    #[test]
+    fn test_malformed_request() {
+        use super::*;
+        // ListOffsets v1 from client "t", cut off in its topics array
+        let frame = decode(
+            "0000000d 0002 0001 00000007 0001 74 ffffffff 00".replace(" ", ""),
+        )
+        .expect("");
+        let Err(e @ Error::MalformedRequest(..)) =
+            Request::read(&mut &frame[..])
+        else {
+            panic!("not malformed");
+        };
+        assert_eq!(e.error_code(), crate::ErrorCode::InvalidRequest);
+    }
+    #[test]
+    fn test_unsupported_requests() {
+        use super::*;
+        let read = |frame: &str| {
+            let frame = decode(frame.replace(" ", "")).expect("");
+            Request::read(&mut &frame[..])
+        };
+        // Produce v2 is answered as a Produce, ApiVersions v5 in the v0
+        // layout and an unknown API key not at all
+        let Err(e @ Error::MalformedRequest(..)) =
+            read("0000000c 0000 0002 00000007 0001 74 00")
+        else {
+            panic!("not answered as a Produce");
+        };
+        assert_eq!(e.error_code(), crate::ErrorCode::UnsupportedVersion);
+        assert!(matches!(
+            read("0000000c 0012 0005 00000007 0001 74 00"),
+            Err(Error::UnsupportedApiVersion(5, Some(_)))
+        ));
+        assert!(matches!(
+            read("0000000c 00ff 0000 00000007 0001 74 00"),
+            Err(Error::UnsupportedApiKey(255, None))
+        ));
+    }
+    #[test]
+    fn test_truncated_and_corrupt_frames() {
+        use super::*;
+        // DescribeTopicPartitions v0 for "baz" and ApiVersions v4, every
+        // prefix and every byte set to 0xff parses or fails without a panic
+        let frames = [
+            "004b 0000 00000001 0001 74 00 02 0462617a 00 0000000a ff 00",
+            "0012 0004 00000002 0001 74 00 02 63 02 31 00",
+        ];
+        for frame in frames {
+            let request = decode(frame.replace(" ", "")).expect("");
+            let read = |body: &[u8]| {
+                let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+                frame.extend_from_slice(body);
+                Request::read(&mut frame.as_slice())
+            };
+            assert!(read(&request).is_ok());
+            // the trailing tagged fields are not read
+            for cut in 0..request.len() - 1 {
+                assert!(read(&request[..cut]).is_err());
+            }
+            for i in 0..request.len() {
+                let mut corrupt = request.clone();
+                corrupt[i] = 0xff;
+                let _ = read(&corrupt);
+            }
+        }
+        // a size past socket.request.max.bytes is refused before reading
+        assert!(matches!(
+            Request::read(&mut [0xff; 4].as_slice()),
+            Err(Error::RequestTooLarge(u32::MAX, 104_857_600))
+        ));
+    }
 }
@@ -0,0 +1,24 @@
+use tracing::info;
+
+use crate::{metrics, ErrorCode, RequestHeader};
+
+/// One line per answered request on the `kafka.request.logger` target,
+/// which `RUST_LOG=kafka.request.logger=off` silences. The request is
+/// counted in the metrics too.
+pub fn log_request(header: &RequestHeader, error_code: ErrorCode) {
+    metrics().request(header, error_code);
+    info!(
+        target: "kafka.request.logger",
+        api_key = ?header.api_key(),
+        api_version = *header.api_version(),
+        correlation_id = *header.correlation_id(),
+        client_id = %**header.client_id(),
+        size = *header.message_size(),
+        latency_ms = %format_args!(
+            "{:.3}",
+            header.received().elapsed().as_secs_f64() * 1000.0
+        ),
+        error_code = error_code.name(),
+        "completed request"
+    );
+}
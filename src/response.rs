@@ -1,19 +1,91 @@
+use std::collections::BTreeMap;
 use std::ops::Deref;
+use std::time::{Duration, Instant};
 
 use crate::{
-    Api, ApiKey, CorrelationId, Error, ErrorCode, FetchPartitionResponse,
-    FetchResponse, Meta, Partition, PartitionIndex, PartitionRecordValue,
-    RecordValue, Request, RequestBody, Result, SessionId, TagBuffer,
-    ThrottleTime, Topic, VarInt, Version,
+    api_handler, fetch_sessions, metrics, quota_manager, Acks,
+    AclCreationResult, AclResourceType, AlterClientQuotasEntryResult,
+    AlterConfigsResourceResponse, Api, ApiKey, Authorizer, ClientQuotaEntry,
+    ClientSoftware, CorrelationId, CreatePartitionsTopicResult,
+    DeleteAclsFilterResult, DeleteRecordsResponse, DescribeAclsResource,
+    DescribeConfigsResult, Error, ErrorCode, FetchContext,
+    FetchPartitionResponse, FetchResponse, HandlerContext, ListOffsetsResponse,
+    Meta, Partition, PartitionIndex, PartitionRecordValue, ProduceResponse,
+    QuotaManager, Request, RequestBody, RequestHeader, Result, SaslState,
+    SessionId, TagBuffer, ThrottleTime, Topic, TopicName, Version,
+    BROKER_RESOURCE, CLUSTER_NAME, CONSUMER_BYTE_RATE, LOG_DIR, METADATA_LOG,
+    PRODUCER_BYTE_RATE, REQUEST_PERCENTAGE, TOPIC_RESOURCE,
 };
 use bytes::BufMut;
+use tracing::{error, warn};
 
 #[derive(Debug, Clone)]
 pub enum ResponseBody {
+    ListOffsets {
+        responses: Vec<ListOffsetsResponse>,
+        throttle_time: ThrottleTime,
+    },
+    DeleteRecords {
+        responses: Vec<DeleteRecordsResponse>,
+        throttle_time: ThrottleTime,
+    },
+    DescribeConfigs {
+        results: Vec<DescribeConfigsResult>,
+        throttle_time: ThrottleTime,
+    },
+    IncrementalAlterConfigs {
+        responses: Vec<AlterConfigsResourceResponse>,
+        throttle_time: ThrottleTime,
+    },
+    CreatePartitions {
+        results: Vec<CreatePartitionsTopicResult>,
+        throttle_time: ThrottleTime,
+    },
+    SaslHandshake {
+        error_code: ErrorCode,
+        mechanisms: Vec<String>,
+    },
+    DescribeAcls {
+        error_code: ErrorCode,
+        error_message: Option<String>,
+        resources: Vec<DescribeAclsResource>,
+        throttle_time: ThrottleTime,
+    },
+    CreateAcls {
+        results: Vec<AclCreationResult>,
+        throttle_time: ThrottleTime,
+    },
+    DeleteAcls {
+        filter_results: Vec<DeleteAclsFilterResult>,
+        throttle_time: ThrottleTime,
+    },
+    DescribeClientQuotas {
+        error_code: ErrorCode,
+        error_message: Option<String>,
+        entries: Option<Vec<ClientQuotaEntry>>,
+        throttle_time: ThrottleTime,
+    },
+    AlterClientQuotas {
+        entries: Vec<AlterClientQuotasEntryResult>,
+        throttle_time: ThrottleTime,
+    },
+    SaslAuthenticate {
+        error_code: ErrorCode,
+        error_message: Option<String>,
+        auth_bytes: Vec<u8>,
+        session_lifetime_ms: u64,
+    },
+    Produce {
+        acks: Acks,
+        responses: Vec<ProduceResponse>,
+        throttle_time: ThrottleTime,
+    },
     ApiVersions {
+        error_code: ErrorCode,
         api_versions: Vec<Api>,
         throttle_time: ThrottleTime,
-        tagged_fields: TagBuffer,
+        finalized_features: BTreeMap<String, i16>,
+        finalized_features_epoch: i64,
     },
     DescribeTopicPartitions {
         throttle_time: ThrottleTime,
@@ -22,11 +94,168 @@ pub enum ResponseBody {
     },
     Fetch {
         throttle_time: ThrottleTime,
+        error_code: ErrorCode,
         session_id: SessionId,
         responses: Vec<FetchResponse>,
+        // the fetch session context and every partition read, also those
+        // left out of an incremental response
+        context: Option<FetchContext>,
+        fetched: Vec<(TopicName, PartitionIndex)>,
     },
 }
 
+impl ResponseBody {
+    /// The API the response answers, whose registry entry encodes it.
+    pub fn api_key(&self) -> ApiKey {
+        match self {
+            ResponseBody::ListOffsets {
+                ..
+            } => ApiKey::ListOffsets,
+            ResponseBody::DeleteRecords {
+                ..
+            } => ApiKey::DeleteRecords,
+            ResponseBody::DescribeConfigs {
+                ..
+            } => ApiKey::DescribeConfigs,
+            ResponseBody::IncrementalAlterConfigs {
+                ..
+            } => ApiKey::IncrementalAlterConfigs,
+            ResponseBody::CreatePartitions {
+                ..
+            } => ApiKey::CreatePartitions,
+            ResponseBody::SaslHandshake {
+                ..
+            } => ApiKey::SaslHandshake,
+            ResponseBody::DescribeAcls {
+                ..
+            } => ApiKey::DescribeAcls,
+            ResponseBody::CreateAcls {
+                ..
+            } => ApiKey::CreateAcls,
+            ResponseBody::DeleteAcls {
+                ..
+            } => ApiKey::DeleteAcls,
+            ResponseBody::DescribeClientQuotas {
+                ..
+            } => ApiKey::DescribeClientQuotas,
+            ResponseBody::AlterClientQuotas {
+                ..
+            } => ApiKey::AlterClientQuotas,
+            ResponseBody::SaslAuthenticate {
+                ..
+            } => ApiKey::SaslAuthenticate,
+            ResponseBody::Produce {
+                ..
+            } => ApiKey::Produce,
+            ResponseBody::ApiVersions {
+                ..
+            } => ApiKey::ApiVersions,
+            ResponseBody::DescribeTopicPartitions {
+                ..
+            } => ApiKey::DescribeTopicPartitions,
+            ResponseBody::Fetch {
+                ..
+            } => ApiKey::Fetch,
+        }
+    }
+    /// NONE for responses that only have errors per resource.
+    fn error_code(&self) -> ErrorCode {
+        match self {
+            ResponseBody::SaslHandshake {
+                error_code,
+                ..
+            }
+            | ResponseBody::DescribeAcls {
+                error_code,
+                ..
+            }
+            | ResponseBody::DescribeClientQuotas {
+                error_code,
+                ..
+            }
+            | ResponseBody::SaslAuthenticate {
+                error_code,
+                ..
+            }
+            | ResponseBody::ApiVersions {
+                error_code,
+                ..
+            }
+            | ResponseBody::Fetch {
+                error_code,
+                ..
+            } => *error_code,
+            _ => ErrorCode::NoError,
+        }
+    }
+    fn throttle_time_mut(&mut self) -> Option<&mut ThrottleTime> {
+        match self {
+            ResponseBody::ListOffsets {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::DeleteRecords {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::DescribeConfigs {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::IncrementalAlterConfigs {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::CreatePartitions {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::DescribeAcls {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::CreateAcls {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::DeleteAcls {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::DescribeClientQuotas {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::AlterClientQuotas {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::Produce {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::ApiVersions {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::DescribeTopicPartitions {
+                throttle_time,
+                ..
+            }
+            | ResponseBody::Fetch {
+                throttle_time,
+                ..
+            } => Some(throttle_time),
+            ResponseBody::SaslHandshake {
+                ..
+            }
+            | ResponseBody::SaslAuthenticate {
+                ..
+            } => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Cursor(u8);
 impl Cursor {
@@ -48,186 +277,328 @@ impl Deref for Cursor {
 #[derive(Debug, Clone)]
 pub struct Response {
     correlation_id: CorrelationId,
+    api_version: Version,
     body: ResponseBody,
+    throttle_time: ThrottleTime,
+    // the error a failed request is answered with
+    error: Option<ErrorCode>,
 }
 
 impl Response {
-    pub fn new(correlation_id: CorrelationId, body: ResponseBody) -> Self {
+    pub fn new(
+        correlation_id: CorrelationId,
+        api_version: Version,
+        body: ResponseBody,
+    ) -> Self {
         Self {
             correlation_id,
+            api_version,
             body,
+            throttle_time: ThrottleTime::zero(),
+            error: None,
+        }
+    }
+    /// The error of a failed request, otherwise the top-level error code
+    /// of the responses that have one, for the request log.
+    pub fn error_code(&self) -> ErrorCode {
+        self.error.unwrap_or_else(|| self.body.error_code())
+    }
+    /// How long the connection stays muted after this response, also for
+    /// responses that cannot report a throttle time.
+    pub fn throttle_time(&self) -> ThrottleTime {
+        self.throttle_time
+    }
+    /// Record bytes of a Fetch response and the partitions it read. None
+    /// for other responses and for fetches with an error, which are
+    /// answered at once.
+    pub fn fetched(&self) -> Option<(usize, Vec<(TopicName, PartitionIndex)>)> {
+        let ResponseBody::Fetch {
+            error_code: ErrorCode::NoError,
+            responses,
+            fetched,
+            ..
+        } = &self.body
+        else {
+            return None;
+        };
+        let partitions: Vec<_> = responses
+            .iter()
+            .flat_map(|t| t.partitions().iter().map(move |p| (t, p)))
+            .collect();
+        if partitions.iter().any(|(_, p)| p.error_code() != ErrorCode::NoError)
+        {
+            return None;
+        }
+        Some((
+            partitions.iter().map(|(_, p)| p.records_size()).sum(),
+            fetched.clone(),
+        ))
+    }
+    /// Bytes of records a Fetch response carries, what the consumer byte
+    /// rate meters.
+    fn records_size(&self) -> usize {
+        match &self.body {
+            ResponseBody::Fetch {
+                responses,
+                ..
+            } => responses
+                .iter()
+                .flat_map(|t| t.partitions())
+                .map(FetchPartitionResponse::records_size)
+                .sum(),
+            _ => 0,
         }
     }
-    pub fn response(request: &Request) -> Result<Response> {
-        let body = match &request.body {
-            RequestBody::ApiVersions => Ok(ResponseBody::ApiVersions {
-                api_versions: vec![
-                    Api::new(
-                        ApiKey::ApiVersions,
-                        Version::V0,
-                        Version::V4,
-                        TagBuffer::new(0),
-                    ),
-                    Api::new(
-                        ApiKey::DescribeTopicPartitions,
-                        Version::V0,
-                        Version::V0,
-                        TagBuffer::new(0),
-                    ),
-                    Api::new(
-                        ApiKey::Fetch,
-                        Version::V0,
-                        Version::V16,
-                        TagBuffer::new(0),
-                    ),
-                ],
-                throttle_time: ThrottleTime::zero(),
-                tagged_fields: TagBuffer::zero(),
-            }),
-            RequestBody::DescribeTopicPartitions {
-                topics,
-                limit: _,
-                cursor: _,
-            } => match request.header.api_version() {
-                Version::V0 => {
-                    let meta = Meta::load("/tmp/kraft-combined-logs/__cluster_metadata-0/00000000000000000000.log")?;
-                    Ok(ResponseBody::DescribeTopicPartitions {
-                        throttle_time: ThrottleTime::zero(),
-                        topics: topics
-                            .into_iter()
-                            .map(|name| match meta.find_topic_id(name) {
-                                None => Topic::unknown(name.clone()),
-                                Some(topic_id) => {
-                                    let partitions = meta
-                                        .find_partitions(&topic_id)
-                                        .into_iter()
-                                        .flat_map(|v| {
-                                            v.clone().try_into().into_iter()
-                                        })
-                                        .collect();
-                                    Topic::new(
-                                        name.clone(),
-                                        topic_id.clone(),
-                                        partitions,
-                                    )
-                                }
-                            })
-                            .collect(),
-                        next_cursor: None,
-                    })
-                }
-                _ => Err(Error::UnsupportedApiVersion(
-                    2,
-                    Some(request.header.correlation_id()),
-                )),
-            },
-            RequestBody::Fetch {
-                session_id,
+    /// The fetch session context a Fetch response was read with.
+    pub fn fetch_context(&self) -> Option<&FetchContext> {
+        match &self.body {
+            ResponseBody::Fetch {
+                context,
+                ..
+            } => context.as_ref(),
+            _ => None,
+        }
+    }
+    /// Tells the fetch session of a Fetch response what the client has
+    /// now heard of its partitions, once the response goes out, and counts
+    /// the bytes fetched.
+    pub fn sent(&self) {
+        let ResponseBody::Fetch {
+            responses,
+            context,
+            ..
+        } = &self.body
+        else {
+            return;
+        };
+        if let Some(context) = context {
+            fetch_sessions().sent(context, responses);
+        }
+        responses.iter().for_each(|r| {
+            let bytes = r.partitions().iter().map(|p| p.records_size());
+            metrics().bytes_out(r.topic_name(), bytes.sum());
+        });
+    }
+    /// Meters the request the response answers, handled since `started`,
+    /// and sets the throttle time it gets.
+    pub fn throttled(self, request: &Request, started: Instant) -> Self {
+        let throttle_time = Self::throttle(request, &self, started);
+        self.with_throttle_time(throttle_time)
+    }
+    pub fn with_throttle_time(mut self, value: ThrottleTime) -> Self {
+        if let Some(throttle_time) = self.body.throttle_time_mut() {
+            *throttle_time = value;
+        }
+        self.throttle_time = value;
+        self
+    }
+    /// Answers a request with the handler registered for its API.
+    #[allow(clippy::self_named_constructors)]
+    pub fn response(
+        request: &Request,
+        sasl: &mut SaslState,
+        client_software: &mut Option<ClientSoftware>,
+    ) -> Result<Response> {
+        let started = Instant::now();
+        let handler = api_handler(request.header.api_key()).map_err(
+            Error::set_correlation_id(request.header.correlation_id()),
+        )?;
+        let mut cx = HandlerContext {
+            authorizer: Authorizer::load(METADATA_LOG),
+            sasl,
+            client_software,
+            log_dir: LOG_DIR,
+            metadata_log: METADATA_LOG,
+        };
+        let (body, error) = match (handler.handle)(request, &mut cx) {
+            Ok(body) => (body, None),
+            Err(e) => {
+                warn!(api_key = ?request.header.api_key(), "failed: {}", e);
+                ((handler.error)(Some(&request.body), &e), Some(e.error_code()))
+            }
+        };
+        let response = Response {
+            error,
+            ..Response::new(
+                request.header.correlation_id(),
+                request.header.api_version(),
+                body,
+            )
+        };
+        // a Fetch is metered once it is answered, it may be parked first
+        Ok(match request.header.api_key() {
+            ApiKey::Fetch => response,
+            _ => response.throttled(request, started),
+        })
+    }
 
-                topics,
-                ..
-            } => match request.header.api_version() {
-                Version::V16 => {
-                    let meta = Meta::load("/tmp/kraft-combined-logs/__cluster_metadata-0/00000000000000000000.log")?;
-                    Ok(ResponseBody::Fetch {
-                        throttle_time: ThrottleTime::zero(),
-                        session_id: session_id.clone(),
-                        responses: topics
-                            .iter()
-                            .map(|t| {
-                                let topic_log =
-                                    meta.find_log(&t.topic_id()).ok().flatten();
-                                let fpr = match topic_log {
-                                    None => FetchPartitionResponse::unknown(
-                                        PartitionIndex::new(0),
-                                    ),
-                                    Some(log) => FetchPartitionResponse::new(
-                                        PartitionIndex::new(0),
-                                        log.batches().clone(),
-                                    ),
-                                };
-                                FetchResponse::new(t.topic_id(), vec![fpr])
-                            })
-                            .collect(),
-                    })
-                }
-                _ => Err(Error::UnsupportedApiVersion(
-                    2,
-                    Some(request.header.correlation_id()),
-                )),
-            },
+    /// The error response to a request, for a malformed one without its
+    /// body.
+    pub fn failed(
+        header: &RequestHeader,
+        body: Option<&RequestBody>,
+        error: &Error,
+    ) -> Result<Response> {
+        let handler = api_handler(header.api_key())
+            .map_err(Error::set_correlation_id(header.correlation_id()))?;
+        Ok(Response {
+            error: Some(error.error_code()),
+            ..Response::new(
+                header.correlation_id(),
+                header.api_version(),
+                (handler.error)(body, error),
+            )
+        })
+    }
+
+    pub(crate) fn unexpected(request: &Request) -> Error {
+        Error::UnsupportedApiKey(
+            *request.header.api_key(),
+            Some(request.header.correlation_id()),
+        )
+    }
+
+    /// Meters the request against the client quotas. Produce counts the
+    /// request bytes, Fetch the record bytes of the response and every
+    /// request its handling time, the longest resulting throttle applies.
+    fn throttle(
+        request: &Request,
+        response: &Response,
+        started: Instant,
+    ) -> ThrottleTime {
+        let api_key = request.header.api_key();
+        // exempt so a throttled client can still connect and authenticate
+        if matches!(
+            api_key,
+            ApiKey::ApiVersions
+                | ApiKey::SaslHandshake
+                | ApiKey::SaslAuthenticate
+        ) {
+            return ThrottleTime::zero();
+        }
+        let quotas = QuotaManager::quotas(METADATA_LOG);
+        if quotas.is_empty() {
+            return ThrottleTime::zero();
+        }
+        let now = Instant::now();
+        let record = |key, value| {
+            quota_manager().record(&quotas, &request.header, key, value, now)
+        };
+        let elapsed = now.duration_since(started).as_secs_f64();
+        let bytes = match api_key {
+            ApiKey::Produce => Some((
+                PRODUCER_BYTE_RATE,
+                *request.header.message_size() as f64,
+            )),
+            ApiKey::Fetch =>
+                Some((CONSUMER_BYTE_RATE, response.records_size() as f64)),
+            _ => None,
         };
-        body.map(|b| Response::new(request.header.correlation_id(), b))
+        let throttle = bytes
+            .map_or(Duration::ZERO, |(key, value)| record(key, value))
+            .max(record(REQUEST_PERCENTAGE, elapsed * 100.0));
+        ThrottleTime::new(throttle.as_millis().min(u32::MAX as u128) as u32)
+    }
+
+    /// Reads from the log of a partition the metadata has.
+    pub(crate) fn partition_log<T>(
+        meta: &Meta,
+        topic_name: &TopicName,
+        partition: PartitionIndex,
+        read: impl FnOnce() -> Result<T>,
+    ) -> std::result::Result<T, ErrorCode> {
+        if !meta.has_partition(topic_name, partition) {
+            return Err(ErrorCode::UnknownTopicOrPartition);
+        }
+        read().map_err(|e| {
+            error!("loading {:?}-{}: {}", topic_name, *partition, e);
+            ErrorCode::UnknownServerError
+        })
     }
+
+    /// The error for a config resource the principal may not describe or
+    /// alter, broker configs are authorized on the cluster.
+    pub(crate) fn config_authorization(
+        allowed: &impl Fn(u8, u8, &str) -> bool,
+        operation: u8,
+        resource_type: u8,
+        resource_name: &str,
+    ) -> Option<ErrorCode> {
+        match resource_type {
+            TOPIC_RESOURCE
+                if !allowed(
+                    operation,
+                    AclResourceType::TOPIC,
+                    resource_name,
+                ) =>
+                Some(ErrorCode::TopicAuthorizationFailed),
+            BROKER_RESOURCE
+                if !allowed(
+                    operation,
+                    AclResourceType::CLUSTER,
+                    CLUSTER_NAME,
+                ) =>
+                Some(ErrorCode::ClusterAuthorizationFailed),
+            _ => None,
+        }
+    }
+
+    /// ACLs can only be managed with an authorizer, by principals allowed
+    /// on the cluster.
+    pub(crate) fn acl_authorization(
+        authorizer: &Authorizer,
+        allowed: &impl Fn(u8, u8, &str) -> bool,
+        operation: u8,
+    ) -> Option<(ErrorCode, String)> {
+        if !authorizer.enabled() {
+            return Some((
+                ErrorCode::SecurityDisabled,
+                "No authorizer is configured".to_string(),
+            ));
+        }
+        match allowed(operation, AclResourceType::CLUSTER, CLUSTER_NAME) {
+            true => None,
+            false => Some((
+                ErrorCode::ClusterAuthorizationFailed,
+                "Not authorized on the cluster".to_string(),
+            )),
+        }
+    }
+}
+
+/// The response header, with a tag buffer from the first flexible
+/// version, and whether the body is flexible.
+pub(crate) fn response_header(
+    api_key: ApiKey,
+    version: Version,
+    correlation_id: CorrelationId,
+) -> (Vec<u8>, bool) {
+    let flexible = api_key.is_flexible(version);
+    let mut bytes = vec![];
+    bytes.put_u32(*correlation_id);
+    if flexible {
+        bytes.put_u8(*TagBuffer::zero());
+    }
+    (bytes, flexible)
 }
 
-fn with_message_size(bytes: &[u8]) -> Vec<u8> {
+pub(crate) fn with_message_size(bytes: &[u8]) -> Vec<u8> {
     let mut result = (bytes.len() as u32).to_be_bytes().to_vec();
     result.extend(bytes);
     result
 }
 impl From<Response> for Vec<u8> {
+    /// Encodes the response with the encoder registered for its API.
     fn from(value: Response) -> Self {
-        match value.body {
-            ResponseBody::ApiVersions {
-                api_versions,
-                throttle_time,
-                tagged_fields,
-            } => {
-                let mut bytes: Vec<u8> = Vec::new();
-                bytes.put_u32(*value.correlation_id);
-                bytes.put_i16(*ErrorCode::NoError);
-                bytes.put_i8(api_versions.len() as i8 + 1);
-                let api_versions: Vec<u8> = api_versions
-                    .into_iter()
-                    .flat_map::<Vec<u8>, _>(|e| e.into())
-                    .collect();
-                bytes.extend(api_versions);
-                bytes.put_u32(*throttle_time);
-                bytes.put_u8(*tagged_fields);
-                with_message_size(&bytes)
-            }
-            ResponseBody::DescribeTopicPartitions {
-                throttle_time,
-                topics,
-                next_cursor,
-            } => {
-                let mut bytes: Vec<u8> = Vec::new();
-                bytes.put_u32(*value.correlation_id);
-                bytes.put_u8(*TagBuffer::zero());
-                bytes.put_u32(*throttle_time);
-                bytes.extend(VarInt::encode((topics.len() + 1) as u64));
-                let topics_bytes: Vec<u8> = topics
-                    .into_iter()
-                    .flat_map::<Vec<u8>, _>(|e| e.into())
-                    .collect();
-                bytes.extend(topics_bytes);
-                bytes.put_u8(next_cursor.map(|v| *v).unwrap_or_else(|| 0xff));
-                bytes.put_u8(*TagBuffer::zero());
-                with_message_size(&bytes)
-            }
-            ResponseBody::Fetch {
-                throttle_time,
-                session_id,
-                responses,
-            } => {
-                let mut bytes: Vec<u8> = Vec::new();
-                bytes.put_u32(*value.correlation_id);
-                bytes.put_u8(*TagBuffer::zero());
-                bytes.put_u32(*throttle_time);
-                bytes.put_i16(*ErrorCode::NoError);
-                bytes.put_u32(*session_id);
-                bytes.extend(VarInt::encode((responses.len() + 1) as u64));
-                let responses_bytes: Vec<u8> = responses
-                    .into_iter()
-                    .flat_map::<Vec<u8>, _>(|e| e.into())
-                    .collect();
-                let is_empty = responses_bytes.is_empty();
-                bytes.extend(responses_bytes);
-                if is_empty {
-                    bytes.put_u8(*TagBuffer::zero());
-                }
-                with_message_size(&bytes)
+        match api_handler(value.body.api_key()) {
+            Ok(handler) => (handler.encode)(
+                value.body,
+                value.api_version,
+                value.correlation_id,
+            ),
+            Err(e) => {
+                error!("no encoder: {}", e);
+                vec![]
             }
         }
     }
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::{error, info};
+
+use crate::{
+    read_dir_names, topic_config_values, Log, Meta, PartitionIndex, Result,
+    TopicName,
+};
+
+const CONSUMER_OFFSETS: &str = "__consumer_offsets";
+
+/// `cleanup.policy`: delete old segments, compact by key, or both.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CleanupPolicy {
+    #[default]
+    Delete,
+    Compact,
+    CompactDelete,
+}
+
+impl CleanupPolicy {
+    pub fn delete(&self) -> bool {
+        matches!(self, Self::Delete | Self::CompactDelete)
+    }
+    pub fn compact(&self) -> bool {
+        matches!(self, Self::Compact | Self::CompactDelete)
+    }
+}
+
+impl FromStr for CleanupPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let policies: Vec<&str> = s.split(',').map(str::trim).collect();
+        match (policies.contains(&"compact"), policies.contains(&"delete")) {
+            (true, true) => Ok(Self::CompactDelete),
+            (true, false) => Ok(Self::Compact),
+            (false, true) => Ok(Self::Delete),
+            (false, false) => Err(format!("unknown cleanup.policy {s}")),
+        }
+    }
+}
+
+/// Per topic log settings, topic configs override the broker defaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogConfig {
+    pub segment_bytes: u64,
+    pub retention_ms: i64,
+    pub retention_bytes: i64,
+    pub cleanup_policy: CleanupPolicy,
+    pub delete_retention_ms: i64,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            segment_bytes: 1024 * 1024 * 1024,
+            retention_ms: 7 * 24 * 60 * 60 * 1000,
+            retention_bytes: -1,
+            cleanup_policy: CleanupPolicy::Delete,
+            delete_retention_ms: 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn from_configs(configs: &HashMap<String, String>) -> Self {
+        fn get<T: FromStr>(
+            configs: &HashMap<String, String>,
+            name: &str,
+            default: T,
+        ) -> T {
+            configs.get(name).and_then(|v| v.parse().ok()).unwrap_or(default)
+        }
+        let default = Self::default();
+        Self {
+            segment_bytes: get(configs, "segment.bytes", default.segment_bytes),
+            retention_ms: get(configs, "retention.ms", default.retention_ms),
+            retention_bytes: get(
+                configs,
+                "retention.bytes",
+                default.retention_bytes,
+            ),
+            cleanup_policy: get(
+                configs,
+                "cleanup.policy",
+                default.cleanup_policy,
+            ),
+            delete_retention_ms: get(
+                configs,
+                "delete.retention.ms",
+                default.delete_retention_ms,
+            ),
+        }
+    }
+    /// Dynamic topic configs over server.properties over the defaults.
+    /// Committed offsets are compacted unless the topic says otherwise.
+    pub fn for_topic(meta: &Meta, topic_name: &TopicName) -> Self {
+        let mut configs = topic_config_values(meta, topic_name);
+        if **topic_name == CONSUMER_OFFSETS
+            && !meta.topic_configs(topic_name).contains_key("cleanup.policy")
+        {
+            configs.insert("cleanup.policy".to_string(), "compact".to_string());
+        }
+        Self::from_configs(&configs)
+    }
+}
+
+/// One cleaner pass over every partition in `log_dir`, returning the
+/// segments deleted by retention and the records removed by compaction.
+pub fn clean(log_dir: &str, meta: &Meta, now: u64) -> Result<(usize, usize)> {
+    let mut deleted = 0;
+    let mut compacted = 0;
+    for name in read_dir_names(log_dir)? {
+        let partition = name
+            .rsplit_once('-')
+            .and_then(|(t, p)| p.parse().ok().map(|p| (t.to_string(), p)));
+        match partition {
+            Some((topic, _)) if topic == "__cluster_metadata" => {}
+            Some((topic, partition)) => {
+                let topic_name = TopicName::new(topic);
+                let partition = PartitionIndex::new(partition);
+                let config = LogConfig::for_topic(meta, &topic_name);
+                if config.cleanup_policy.delete() {
+                    deleted += Log::retain(
+                        log_dir,
+                        &topic_name,
+                        partition,
+                        &config,
+                        now,
+                    )?;
+                }
+                if config.cleanup_policy.compact() {
+                    compacted += Log::compact(
+                        log_dir,
+                        &topic_name,
+                        partition,
+                        &config,
+                        now,
+                    )?;
+                }
+            }
+            None => {}
+        }
+    }
+    Ok((deleted, compacted))
+}
+
+/// The cleaner thread, stopped before the logs are closed so no
+/// retention or compaction is cut short by the exit.
+#[derive(Debug)]
+pub struct Cleaner {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    thread: JoinHandle<()>,
+}
+
+impl Cleaner {
+    /// Waits for a pass under way to finish.
+    pub fn stop(self) {
+        let (stopped, wake) = &*self.stopped;
+        *stopped.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        wake.notify_all();
+        let _ = self.thread.join();
+    }
+}
+
+/// Runs `clean` every `interval` on its own thread, like Kafka's
+/// `log.retention.check.interval.ms`.
+pub fn spawn_cleaner(
+    log_dir: &'static str,
+    metadata: &'static str,
+    interval: Duration,
+) -> Cleaner {
+    let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+    let stop = stopped.clone();
+    let thread = spawn(move || loop {
+        let (stopped, wake) = &*stop;
+        let guard = stopped.lock().unwrap_or_else(|e| e.into_inner());
+        let (guard, _) = wake
+            .wait_timeout_while(guard, interval, |stopped| !*stopped)
+            .unwrap_or_else(|e| e.into_inner());
+        if *guard {
+            return;
+        }
+        drop(guard);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|v| v.as_millis() as u64)
+            .unwrap_or(0);
+        if Path::new(metadata).exists() {
+            match Meta::load(metadata)
+                .and_then(|meta| clean(log_dir, &meta, now))
+            {
+                Ok((0, 0)) => {}
+                Ok((deleted, compacted)) => info!(
+                    "cleaner deleted {deleted} segments, compacted away \
+                     {compacted} records"
+                ),
+                Err(e) => error!("retention failed: {e}"),
+            }
+        }
+    });
+    Cleaner {
+        stopped,
+        thread,
+    }
+}
@@ -0,0 +1,613 @@
+use std::collections::HashMap;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::BufMut;
+use hmac::{Hmac, Mac};
+use newtype_macro::newtype;
+use sha2::{Digest, Sha256, Sha512};
+use uuid::Uuid;
+
+use crate::{
+    array_length, broker_config, response_header, with_message_size, ApiKey,
+    BytesOps, CorrelationId, Error, ErrorCode, HandlerContext, Meta, Request,
+    RequestBody, Response, ResponseBody, Result, TagBuffer, ToKafkaString,
+    ToNullableBytes, ToNullableString, VarInt, Version, METADATA_LOG,
+};
+
+/// The authenticated user of a connection, `User:<name>`.
+#[newtype]
+pub struct Principal(String);
+
+impl Principal {
+    pub fn user(name: &str) -> Self {
+        Self(format!("User:{name}"))
+    }
+    pub fn anonymous() -> Self {
+        Self::user("ANONYMOUS")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mechanism {
+    Plain,
+    ScramSha256,
+    ScramSha512,
+}
+
+impl Mechanism {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mechanism::Plain => "PLAIN",
+            Mechanism::ScramSha256 => "SCRAM-SHA-256",
+            Mechanism::ScramSha512 => "SCRAM-SHA-512",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Plain, Self::ScramSha256, Self::ScramSha512]
+            .into_iter()
+            .find(|v| v.name() == name)
+    }
+    /// The mechanism byte of a UserScramCredentialRecord.
+    fn from_scram_type(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(Self::ScramSha256),
+            2 => Some(Self::ScramSha512),
+            _ => None,
+        }
+    }
+    fn hmac(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        fn mac<M: Mac + hmac::digest::KeyInit>(
+            key: &[u8],
+            data: &[u8],
+        ) -> Vec<u8> {
+            let mut mac = <M as Mac>::new_from_slice(key)
+                .expect("HMAC takes keys of any length");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+        match self {
+            Mechanism::ScramSha512 => mac::<Hmac<Sha512>>(key, data),
+            _ => mac::<Hmac<Sha256>>(key, data),
+        }
+    }
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Mechanism::ScramSha512 => Sha512::digest(data).to_vec(),
+            _ => Sha256::digest(data).to_vec(),
+        }
+    }
+    fn salted_password(
+        &self,
+        password: &str,
+        salt: &[u8],
+        iterations: u32,
+    ) -> Vec<u8> {
+        match self {
+            Mechanism::ScramSha512 => pbkdf2::pbkdf2_hmac_array::<Sha512, 64>(
+                password.as_bytes(),
+                salt,
+                iterations,
+            )
+            .to_vec(),
+            _ => pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(
+                password.as_bytes(),
+                salt,
+                iterations,
+            )
+            .to_vec(),
+        }
+    }
+}
+
+/// Mechanisms of `sasl.enabled.mechanisms`, none means SASL is off.
+pub fn enabled_mechanisms() -> Vec<Mechanism> {
+    broker_config()
+        .get("sasl.enabled.mechanisms")
+        .map(|v| v.split(',').filter_map(|v| Mechanism::from_name(v.trim())))
+        .map(Iterator::collect)
+        .unwrap_or_default()
+}
+
+/// What the broker keeps of a SCRAM password, RFC 5802.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScramCredential {
+    pub salt: Vec<u8>,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+    pub iterations: u32,
+}
+
+impl ScramCredential {
+    pub fn derive(
+        mechanism: Mechanism,
+        password: &str,
+        salt: &[u8],
+        iterations: u32,
+    ) -> Self {
+        let salted = mechanism.salted_password(password, salt, iterations);
+        let client_key = mechanism.hmac(&salted, b"Client Key");
+        Self {
+            salt: salt.to_vec(),
+            stored_key: mechanism.hash(&client_key),
+            server_key: mechanism.hmac(&salted, b"Server Key"),
+            iterations,
+        }
+    }
+}
+
+/// PLAIN passwords from the `user_<name>="<password>"` entries of any
+/// `sasl.jaas.config` in server.properties, SCRAM credentials from the
+/// metadata log.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    passwords: HashMap<String, String>,
+    scram: HashMap<(String, Mechanism), ScramCredential>,
+}
+
+impl Credentials {
+    pub fn load(meta: Option<&Meta>) -> Self {
+        let passwords = broker_config()
+            .iter()
+            .filter(|(k, _)| k.ends_with("sasl.jaas.config"))
+            .flat_map(|(_, v)| v.split_whitespace())
+            .filter_map(|v| v.trim_end_matches(';').strip_prefix("user_"))
+            .filter_map(|v| v.split_once('='))
+            .map(|(user, password)| {
+                (user.to_string(), password.trim_matches('"').to_string())
+            })
+            .collect();
+        let scram = meta
+            .map(Meta::scram_credentials)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|v| {
+                let mechanism = Mechanism::from_scram_type(v.3)?;
+                let credential = ScramCredential {
+                    salt: v.4.clone(),
+                    stored_key: v.5.clone(),
+                    server_key: v.6.clone(),
+                    iterations: v.7,
+                };
+                Some(((v.2.clone(), mechanism), credential))
+            })
+            .collect();
+        Self {
+            passwords,
+            scram,
+        }
+    }
+    pub fn with_scram(
+        mut self,
+        user: &str,
+        mechanism: Mechanism,
+        credential: ScramCredential,
+    ) -> Self {
+        self.scram.insert((user.to_string(), mechanism), credential);
+        self
+    }
+    fn plain(&self, user: &str, password: &str) -> bool {
+        self.passwords.get(user).is_some_and(|v| v == password)
+    }
+    fn scram(
+        &self,
+        user: &str,
+        mechanism: Mechanism,
+    ) -> Option<&ScramCredential> {
+        self.scram.get(&(user.to_string(), mechanism))
+    }
+}
+
+/// Server side of a SCRAM exchange waiting for the client final message.
+#[derive(Debug, Clone)]
+struct ScramExchange {
+    user: String,
+    credential: ScramCredential,
+    nonce: String,
+    client_first_bare: String,
+    server_first: String,
+}
+
+type SaslResult<T> = std::result::Result<T, (ErrorCode, String)>;
+
+fn authentication_failed() -> (ErrorCode, String) {
+    (
+        ErrorCode::SaslAuthenticationFailed,
+        "Authentication failed: Invalid username or password".to_string(),
+    )
+}
+
+/// SASL state of one connection: the handshake picks a mechanism, then
+/// SaslAuthenticate runs it until a principal is set.
+#[derive(Debug, Clone, Default)]
+pub struct SaslState {
+    mechanism: Option<Mechanism>,
+    scram: Option<ScramExchange>,
+    principal: Option<Principal>,
+}
+
+impl SaslState {
+    /// A connection authenticated otherwise, by a TLS client certificate.
+    pub fn authenticated(principal: Principal) -> Self {
+        Self {
+            principal: Some(principal),
+            ..Self::default()
+        }
+    }
+    pub fn principal(&self) -> Option<&Principal> {
+        self.principal.as_ref()
+    }
+    /// With SASL on, only ApiVersions and the SASL requests are allowed
+    /// before authenticating. The request gets the principal.
+    pub fn authorize(&self, request: Request) -> Result<Request> {
+        let api_key = request.header.api_key();
+        let allowed = matches!(
+            api_key,
+            ApiKey::ApiVersions
+                | ApiKey::SaslHandshake
+                | ApiKey::SaslAuthenticate
+        );
+        match &self.principal {
+            Some(principal) => Ok(request.with_principal(principal.clone())),
+            None if enabled_mechanisms().is_empty() =>
+                Ok(request.with_principal(Principal::anonymous())),
+            None if allowed => Ok(request),
+            None => Err(Error::SaslAuthenticationRequired(*api_key)),
+        }
+    }
+    pub fn handshake(&mut self, mechanism: &str) -> SaslResult<()> {
+        if self.mechanism.is_some() {
+            return Err((
+                ErrorCode::IllegalSaslState,
+                "SaslHandshake already done".to_string(),
+            ));
+        }
+        match Mechanism::from_name(mechanism) {
+            Some(v) if enabled_mechanisms().contains(&v) => {
+                self.mechanism = Some(v);
+                Ok(())
+            }
+            _ => Err((
+                ErrorCode::UnsupportedSaslMechanism,
+                format!("Unsupported SASL mechanism {mechanism}"),
+            )),
+        }
+    }
+    /// One SaslAuthenticate round, returns the bytes for the client.
+    pub fn authenticate(
+        &mut self,
+        credentials: &Credentials,
+        auth_bytes: &[u8],
+    ) -> SaslResult<Vec<u8>> {
+        let message = String::from_utf8_lossy(auth_bytes).to_string();
+        match (self.mechanism, &self.principal) {
+            (None, _) | (_, Some(_)) => Err((
+                ErrorCode::IllegalSaslState,
+                "SaslAuthenticate without a pending SaslHandshake".to_string(),
+            )),
+            (Some(Mechanism::Plain), None) => {
+                // authorization id, user and password separated by NUL
+                let mut fields = message.split('\0').skip(1);
+                match (fields.next(), fields.next()) {
+                    (Some(user), Some(password))
+                        if credentials.plain(user, password) =>
+                    {
+                        self.principal = Some(Principal::user(user));
+                        Ok(vec![])
+                    }
+                    _ => Err(authentication_failed()),
+                }
+            }
+            (Some(mechanism), None) => match self.scram.take() {
+                None => self
+                    .scram_first(mechanism, credentials, &message)
+                    .map(String::into_bytes),
+                Some(exchange) => self
+                    .scram_final(mechanism, exchange, &message)
+                    .map(String::into_bytes),
+            },
+        }
+    }
+    fn scram_first(
+        &mut self,
+        mechanism: Mechanism,
+        credentials: &Credentials,
+        message: &str,
+    ) -> SaslResult<String> {
+        self.scram_first_with_nonce(
+            mechanism,
+            credentials,
+            message,
+            &Uuid::new_v4().simple().to_string(),
+        )
+    }
+    fn scram_first_with_nonce(
+        &mut self,
+        mechanism: Mechanism,
+        credentials: &Credentials,
+        message: &str,
+        server_nonce: &str,
+    ) -> SaslResult<String> {
+        let invalid = || {
+            (
+                ErrorCode::SaslAuthenticationFailed,
+                "Invalid SCRAM client first message".to_string(),
+            )
+        };
+        // gs2 header without channel binding, then n=user,r=nonce
+        let client_first_bare =
+            message.strip_prefix("n,,").ok_or_else(invalid)?;
+        let attribute = |name: &str| {
+            client_first_bare
+                .split(',')
+                .find_map(|v| v.strip_prefix(name))
+                .map(str::to_string)
+        };
+        let user = attribute("n=")
+            .ok_or_else(invalid)?
+            .replace("=2C", ",")
+            .replace("=3D", "=");
+        let client_nonce = attribute("r=").ok_or_else(invalid)?;
+        let credential = credentials
+            .scram(&user, mechanism)
+            .cloned()
+            .ok_or_else(authentication_failed)?;
+        let nonce = format!("{client_nonce}{server_nonce}");
+        let server_first = format!(
+            "r={nonce},s={},i={}",
+            STANDARD.encode(&credential.salt),
+            credential.iterations
+        );
+        self.scram = Some(ScramExchange {
+            user,
+            credential,
+            nonce,
+            client_first_bare: client_first_bare.to_string(),
+            server_first: server_first.clone(),
+        });
+        Ok(server_first)
+    }
+    fn scram_final(
+        &mut self,
+        mechanism: Mechanism,
+        exchange: ScramExchange,
+        message: &str,
+    ) -> SaslResult<String> {
+        let (without_proof, proof) =
+            message.rsplit_once(",p=").ok_or_else(authentication_failed)?;
+        let nonce = without_proof
+            .split(',')
+            .find_map(|v| v.strip_prefix("r="))
+            .ok_or_else(authentication_failed)?;
+        let proof =
+            STANDARD.decode(proof).map_err(|_| authentication_failed())?;
+        if nonce != exchange.nonce {
+            return Err(authentication_failed());
+        }
+        let auth_message = format!(
+            "{},{},{without_proof}",
+            exchange.client_first_bare, exchange.server_first
+        );
+        let credential = &exchange.credential;
+        let signature =
+            mechanism.hmac(&credential.stored_key, auth_message.as_bytes());
+        let client_key: Vec<u8> =
+            proof.iter().zip(&signature).map(|(a, b)| a ^ b).collect();
+        if proof.len() != signature.len()
+            || mechanism.hash(&client_key) != credential.stored_key
+        {
+            return Err(authentication_failed());
+        }
+        let server_signature =
+            mechanism.hmac(&credential.server_key, auth_message.as_bytes());
+        self.principal = Some(Principal::user(&exchange.user));
+        Ok(format!("v={}", STANDARD.encode(server_signature)))
+    }
+}
+
+impl RequestBody {
+    /// v0 sends the SASL tokens unframed and is not supported.
+    pub(crate) fn sasl_handshake(
+        body: &[u8],
+        _version: Version,
+    ) -> Result<Self> {
+        let (mechanism, _rest) = body.extract_string()?;
+        Ok(RequestBody::SaslHandshake {
+            mechanism,
+        })
+    }
+    pub(crate) fn sasl_authenticate(
+        body: &[u8],
+        version: Version,
+    ) -> Result<Self> {
+        let (auth_bytes, _rest) =
+            match ApiKey::SaslAuthenticate.is_flexible(version) {
+                true => body.extract_compact_nullable_bytes(),
+                false => body.extract_nullable_bytes(),
+            }?;
+        Ok(RequestBody::SaslAuthenticate {
+            auth_bytes: auth_bytes.unwrap_or_default().to_vec(),
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_sasl_handshake(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let RequestBody::SaslHandshake {
+            mechanism,
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        Ok(ResponseBody::SaslHandshake {
+            error_code: cx
+                .sasl
+                .handshake(mechanism)
+                .err()
+                .map_or(ErrorCode::NoError, |(code, _)| code),
+            mechanisms: enabled_mechanisms()
+                .iter()
+                .map(|v| v.name().to_string())
+                .collect(),
+        })
+    }
+
+    pub(crate) fn handle_sasl_authenticate(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let RequestBody::SaslAuthenticate {
+            auth_bytes,
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        let meta = Meta::load(METADATA_LOG).ok();
+        let credentials = Credentials::load(meta.as_ref());
+        Ok(match cx.sasl.authenticate(&credentials, auth_bytes) {
+            Ok(auth_bytes) => ResponseBody::SaslAuthenticate {
+                error_code: ErrorCode::NoError,
+                error_message: None,
+                auth_bytes,
+                session_lifetime_ms: 0,
+            },
+            Err((error_code, message)) => ResponseBody::SaslAuthenticate {
+                error_code,
+                error_message: Some(message),
+                auth_bytes: vec![],
+                session_lifetime_ms: 0,
+            },
+        })
+    }
+
+    pub(crate) fn error_sasl_handshake(
+        _body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        ResponseBody::SaslHandshake {
+            error_code: error.error_code(),
+            mechanisms: enabled_mechanisms()
+                .iter()
+                .map(|v| v.name().to_string())
+                .collect(),
+        }
+    }
+
+    pub(crate) fn error_sasl_authenticate(
+        _body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        ResponseBody::SaslAuthenticate {
+            error_code: error.error_code(),
+            error_message: Some(error.to_string()),
+            auth_bytes: vec![],
+            session_lifetime_ms: 0,
+        }
+    }
+
+    pub(crate) fn encode_sasl_handshake(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::SaslHandshake {
+            error_code,
+            mechanisms,
+        } = body
+        else {
+            unreachable!("not a SaslHandshake");
+        };
+        let (mut bytes, flexible) =
+            response_header(ApiKey::SaslHandshake, version, correlation_id);
+        bytes.put_i16(*error_code);
+        bytes.extend(array_length(flexible, mechanisms.len()));
+        mechanisms.into_iter().for_each(|m| bytes.extend(m.to_kafka_string()));
+        with_message_size(&bytes)
+    }
+
+    pub(crate) fn encode_sasl_authenticate(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::SaslAuthenticate {
+            error_code,
+            error_message,
+            auth_bytes,
+            session_lifetime_ms,
+        } = body
+        else {
+            unreachable!("not a SaslAuthenticate");
+        };
+        let (mut bytes, flexible) =
+            response_header(ApiKey::SaslAuthenticate, version, correlation_id);
+        bytes.put_i16(*error_code);
+        bytes.extend(error_message.to_nullable_string(flexible));
+        match flexible {
+            true => {
+                bytes.extend(VarInt::encode(auth_bytes.len() as u64 + 1));
+                bytes.extend(auth_bytes);
+            }
+            false => bytes.extend(Some(auth_bytes).to_nullable_bytes()),
+        }
+        if version >= Version::V1 {
+            bytes.put_u64(session_lifetime_ms);
+        }
+        if flexible {
+            bytes.put_u8(*TagBuffer::zero());
+        }
+        with_message_size(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scram_sha_256() {
+        // RFC 7677 section 3
+        let salt = STANDARD.decode("W22ZaJ0SNY7soEsUEjb6gQ==").unwrap();
+        let credential = ScramCredential::derive(
+            Mechanism::ScramSha256,
+            "pencil",
+            &salt,
+            4096,
+        );
+        let credentials = Credentials::default().with_scram(
+            "user",
+            Mechanism::ScramSha256,
+            credential,
+        );
+        let mut state = SaslState {
+            mechanism: Some(Mechanism::ScramSha256),
+            ..SaslState::default()
+        };
+
+        let server_first = state.scram_first_with_nonce(
+            Mechanism::ScramSha256,
+            &credentials,
+            "n,,n=user,r=rOprNGfwEbeRWgbNEkqO",
+            "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0",
+        );
+        assert_eq!(
+            server_first,
+            Ok("r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"
+                .to_string())
+        );
+        let server_final = state.authenticate(
+            &credentials,
+            b"c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+              p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=",
+        );
+        assert_eq!(
+            server_final,
+            Ok(b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=".to_vec())
+        );
+        assert_eq!(state.principal(), Some(&Principal::user("user")));
+    }
+}
@@ -1,7 +1,11 @@
-use crate::{Context, ErrorCode, Partition, Result, TagBuffer, VarInt};
+use crate::{
+    response_header, with_message_size, AclOperation, ApiKey, BytesOps,
+    Context, CorrelationId, Cursor, Error, ErrorCode, HandlerContext,
+    MapTupleTwo, Meta, Partition, Request, RequestBody, Response, ResponseBody,
+    ResponsePartitionLimit, Result, TagBuffer, ThrottleTime, VarInt, Version,
+};
 use bytes::BufMut;
 use newtype_macro::newtype;
-use std::ops::Deref;
 use uuid::*;
 
 #[derive(Debug, Clone)]
@@ -31,8 +35,11 @@ impl Topic {
         }
     }
     pub fn unknown(name: TopicName) -> Self {
+        Self::error(name, ErrorCode::UnknownTopicOrPartition)
+    }
+    pub fn error(name: TopicName, error_code: ErrorCode) -> Self {
         Self {
-            error_code: ErrorCode::UnknownTopicOrPartition,
+            error_code,
             name,
             id: TopicId::zero(),
             is_internal: false,
@@ -41,6 +48,19 @@ impl Topic {
             tag_buffer: TagBuffer::zero(),
         }
     }
+    /// Like an unknown topic, so its existence is not given away.
+    pub fn unauthorized(name: TopicName) -> Self {
+        Self {
+            error_code: ErrorCode::TopicAuthorizationFailed,
+            ..Self::unknown(name)
+        }
+    }
+    pub fn with_authorized_operations(self, operations: u32) -> Self {
+        Self {
+            topic_authorized_operations: TopicAuthorizedOperations(operations),
+            ..self
+        }
+    }
 }
 
 impl From<Topic> for Vec<u8> {
@@ -67,11 +87,18 @@ impl From<Topic> for Vec<u8> {
 pub struct TopicName(String);
 
 impl TopicName {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(name: &str) -> Self {
         Self::new(name.to_string())
     }
 }
 
+impl From<&str> for TopicName {
+    fn from(name: &str) -> Self {
+        Self::new(name.to_string())
+    }
+}
+
 #[newtype]
 pub struct TopicId(Uuid);
 impl TopicId {
@@ -80,10 +107,123 @@ impl TopicId {
     }
     pub fn zero() -> Self {
         Self::new(
-            Uuid::parse_str(&"00000000-0000-0000-0000-000000000000").unwrap(),
+            Uuid::parse_str("00000000-0000-0000-0000-000000000000").unwrap(),
         )
     }
 }
 
 #[newtype]
 pub struct TopicAuthorizedOperations(u32);
+
+impl RequestBody {
+    pub(crate) fn describe_topic_partitions(
+        body: &[u8],
+        _version: Version,
+    ) -> Result<Self> {
+        let (topics, rest) = body.extract_array_with(true, |v| {
+            let (name, rest) = v.extract_compact_str()?;
+            Ok((TopicName::new(name), rest.drop(1).second()?))
+        })?;
+        let (limit, rest) =
+            rest.drop(4).fmap_tuple(ResponsePartitionLimit::mk)?;
+        let cursor = rest.extract_u8().map_tuple(Cursor::mk).first()?;
+        Ok(RequestBody::DescribeTopicPartitions {
+            topics,
+            limit,
+            cursor,
+        })
+    }
+}
+
+impl Response {
+    pub(crate) fn handle_describe_topic_partitions(
+        request: &Request,
+        cx: &mut HandlerContext,
+    ) -> Result<ResponseBody> {
+        let authorizer = &cx.authorizer;
+        let topic_allowed = cx.topic_allowed(request);
+        let RequestBody::DescribeTopicPartitions {
+            topics,
+            ..
+        } = &request.body
+        else {
+            return Err(Self::unexpected(request));
+        };
+        let meta = Meta::load(cx.metadata_log)?;
+        Ok(ResponseBody::DescribeTopicPartitions {
+            throttle_time: ThrottleTime::zero(),
+            topics: topics
+                .iter()
+                .map(|name| match meta.find_topic_id(name) {
+                    _ if !topic_allowed(AclOperation::DESCRIBE, name) =>
+                        Topic::unauthorized(name.clone()),
+                    None => Topic::unknown(name.clone()),
+                    Some(topic_id) => {
+                        let partitions = meta
+                            .find_partitions(&topic_id)
+                            .into_iter()
+                            .flat_map(|v| v.clone().try_into().into_iter())
+                            .collect();
+                        Topic::new(name.clone(), topic_id, partitions)
+                            .with_authorized_operations(
+                                authorizer.topic_authorized_operations(
+                                    &request.header,
+                                    name,
+                                ),
+                            )
+                    }
+                })
+                .collect(),
+            next_cursor: None,
+        })
+    }
+
+    pub(crate) fn error_describe_topic_partitions(
+        body: Option<&RequestBody>,
+        error: &Error,
+    ) -> ResponseBody {
+        let topics = match body {
+            Some(RequestBody::DescribeTopicPartitions {
+                topics,
+                ..
+            }) => topics
+                .iter()
+                .map(|t| Topic::error(t.clone(), error.error_code()))
+                .collect(),
+            _ => vec![],
+        };
+        ResponseBody::DescribeTopicPartitions {
+            throttle_time: ThrottleTime::zero(),
+            topics,
+            next_cursor: None,
+        }
+    }
+
+    pub(crate) fn encode_describe_topic_partitions(
+        body: ResponseBody,
+        version: Version,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        let ResponseBody::DescribeTopicPartitions {
+            throttle_time,
+            topics,
+            next_cursor,
+        } = body
+        else {
+            unreachable!("not a DescribeTopicPartitions");
+        };
+        let (mut bytes, _) = response_header(
+            ApiKey::DescribeTopicPartitions,
+            version,
+            correlation_id,
+        );
+        bytes.put_u32(*throttle_time);
+        bytes.extend(VarInt::encode((topics.len() + 1) as u64));
+        let topics_bytes: Vec<u8> =
+            topics.into_iter().flat_map::<Vec<u8>, _>(|e| e.into()).collect();
+        bytes.extend(topics_bytes);
+        bytes.put_u8(next_cursor.map(|v| *v).unwrap_or_else(|| 0xff));
+        bytes.put_u8(*TagBuffer::zero());
+        with_message_size(&bytes)
+    }
+}
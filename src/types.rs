@@ -3,7 +3,7 @@ use bytes::BufMut;
 use newtype_macro::newtype;
 use std::convert::TryFrom;
 use std::ops::Deref;
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Version {
     V0,
     V1,
@@ -78,30 +78,97 @@ impl Deref for Version {
 }
 #[derive(Debug, Copy, Clone)]
 pub enum ApiKey {
+    Produce,
+    ListOffsets,
+    DeleteRecords,
     ApiVersions,
     DescribeTopicPartitions,
     Fetch,
+    DescribeConfigs,
+    IncrementalAlterConfigs,
+    CreatePartitions,
+    SaslHandshake,
+    SaslAuthenticate,
+    DescribeAcls,
+    CreateAcls,
+    DeleteAcls,
+    DescribeClientQuotas,
+    AlterClientQuotas,
 }
 
 impl TryFrom<u16> for ApiKey {
     type Error = Error;
     fn try_from(value: u16) -> Result<Self> {
         match value {
+            0 => Ok(ApiKey::Produce),
+            2 => Ok(ApiKey::ListOffsets),
+            21 => Ok(ApiKey::DeleteRecords),
             18 => Ok(ApiKey::ApiVersions),
             75 => Ok(ApiKey::DescribeTopicPartitions),
             1 => Ok(ApiKey::Fetch),
+            32 => Ok(ApiKey::DescribeConfigs),
+            44 => Ok(ApiKey::IncrementalAlterConfigs),
+            37 => Ok(ApiKey::CreatePartitions),
+            17 => Ok(ApiKey::SaslHandshake),
+            36 => Ok(ApiKey::SaslAuthenticate),
+            29 => Ok(ApiKey::DescribeAcls),
+            30 => Ok(ApiKey::CreateAcls),
+            31 => Ok(ApiKey::DeleteAcls),
+            48 => Ok(ApiKey::DescribeClientQuotas),
+            49 => Ok(ApiKey::AlterClientQuotas),
             _ => Err(Error::UnsupportedApiKey(value, None)),
         }
     }
 }
+impl ApiKey {
+    /// First version that uses compact types, tagged fields and request
+    /// header v2.
+    pub fn flexible_since(&self) -> Version {
+        match self {
+            ApiKey::Produce => Version::V9,
+            ApiKey::ListOffsets => Version::V6,
+            ApiKey::DeleteRecords => Version::V2,
+            ApiKey::ApiVersions => Version::V3,
+            ApiKey::DescribeTopicPartitions => Version::V0,
+            ApiKey::Fetch => Version::V12,
+            ApiKey::DescribeConfigs => Version::V4,
+            ApiKey::IncrementalAlterConfigs => Version::V1,
+            ApiKey::CreatePartitions => Version::V2,
+            // never flexible
+            ApiKey::SaslHandshake => Version::V16,
+            ApiKey::SaslAuthenticate => Version::V2,
+            ApiKey::DescribeAcls => Version::V2,
+            ApiKey::CreateAcls => Version::V2,
+            ApiKey::DeleteAcls => Version::V2,
+            ApiKey::DescribeClientQuotas => Version::V1,
+            ApiKey::AlterClientQuotas => Version::V1,
+        }
+    }
+    pub fn is_flexible(&self, version: Version) -> bool {
+        version >= self.flexible_since()
+    }
+}
 impl Deref for ApiKey {
     type Target = u16;
 
     fn deref(&self) -> &Self::Target {
         match &self {
+            ApiKey::Produce => &0u16,
+            ApiKey::ListOffsets => &2u16,
+            ApiKey::DeleteRecords => &21u16,
             ApiKey::ApiVersions => &18u16,
             ApiKey::DescribeTopicPartitions => &75u16,
             ApiKey::Fetch => &1u16,
+            ApiKey::DescribeConfigs => &32u16,
+            ApiKey::IncrementalAlterConfigs => &44u16,
+            ApiKey::CreatePartitions => &37u16,
+            ApiKey::SaslHandshake => &17u16,
+            ApiKey::SaslAuthenticate => &36u16,
+            ApiKey::DescribeAcls => &29u16,
+            ApiKey::CreateAcls => &30u16,
+            ApiKey::DeleteAcls => &31u16,
+            ApiKey::DescribeClientQuotas => &48u16,
+            ApiKey::AlterClientQuotas => &49u16,
         }
     }
 }
@@ -158,25 +225,6 @@ impl TagBuffer {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum ErrorCode {
-    UnsupportedVersion,
-    NoError,
-    UnknownTopicOrPartition,
-    UnknownTopic,
-}
-impl Deref for ErrorCode {
-    type Target = i16;
-
-    fn deref(&self) -> &Self::Target {
-        match &self {
-            ErrorCode::UnsupportedVersion => &35i16,
-            ErrorCode::NoError => &0i16,
-            ErrorCode::UnknownTopicOrPartition => &3i16,
-            ErrorCode::UnknownTopic => &100i16,
-        }
-    }
-}
 #[newtype]
 pub struct CorrelationId(u32);
 
@@ -211,6 +259,22 @@ pub struct IsolationLevel(u8);
 #[newtype]
 pub struct SessionEpoch(u32);
 
+impl SessionEpoch {
+    /// Opens a fetch session with a full fetch.
+    pub const INITIAL: Self = Self(0);
+    /// A full fetch without a session, closing the one given.
+    pub const FINAL: Self = Self(u32::MAX);
+
+    /// The epoch after this one, skipping the initial epoch once it
+    /// wraps.
+    pub fn next(&self) -> Self {
+        match self.0 {
+            v if v == i32::MAX as u32 => Self(1),
+            v => Self(v + 1),
+        }
+    }
+}
+
 #[newtype]
 pub struct HighWatermark(u64);
 